@@ -0,0 +1,408 @@
+#![allow(dead_code)]
+use crate::ast::{
+    BindingId, Block, Expr, ExprKind, FuncDecl, Given, PatternKind, Program, Stmt, StmtKind,
+    Storage,
+};
+use crate::diagnostic::Diagnostic;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/* Definite assignment: a variable declared without a value, as `int32 sum`, holds
+ * `nil` until it is assigned. Reading it where no path has assigned it yet is an
+ * error, and where only some paths have, a warning; reading it while it holds `nil`
+ * then fails at run time, unless its type admits `nil`.
+ *
+ * Runs after name resolution, which ties names to declarations. Each function body
+ * is analysed on its own: a function may be called once the variables it captures
+ * have been assigned, so those are not checked inside it. */
+pub fn check(program: &Program) -> Vec<Diagnostic> {
+    let mut analysis = Analysis {
+        report: true,
+        ..Analysis::default()
+    };
+    analysis.check_body(&program.stmts);
+    while let Some(func) = analysis.funcs.pop() {
+        analysis.check_body(&func.body);
+    }
+    analysis
+        .diagnostics
+        .sort_by_key(|diagnostic| diagnostic.span);
+    analysis.diagnostics
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Init {
+    Unassigned,
+    /* Assigned on some paths only. */
+    Maybe,
+    Assigned,
+}
+
+/* What is known about the variables declared without a value, or `None` where the
+ * code cannot be reached, as after a `return`. */
+type State = Option<HashMap<BindingId, Init>>;
+
+fn join(a: State, b: State) -> State {
+    let (Some(mut a), Some(b)) = (a.clone(), b.clone()) else {
+        return a.or(b);
+    };
+    for (id, init) in &mut a {
+        if b.get(id) != Some(init) {
+            *init = Init::Maybe;
+        }
+    }
+    for id in b.keys() {
+        a.entry(*id).or_insert(Init::Maybe);
+    }
+    Some(a)
+}
+
+#[derive(Default)]
+struct Analysis {
+    /* Off while a loop is iterated to a fixpoint, so that each read is reported once. */
+    report: bool,
+    /* The labels of the enclosing loops, with the states at their `break`s and at
+     * their `continue`s. */
+    loops: Vec<(Option<String>, State, State)>,
+    /* The states at the statements of each enclosing `try` body, any of which may
+     * fail and run its handler. */
+    catches: Vec<State>,
+    /* Nested functions still to analyse. */
+    funcs: Vec<Rc<FuncDecl>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Analysis {
+    fn check_body(&mut self, body: &Block) {
+        let loops = std::mem::take(&mut self.loops);
+        let catches = std::mem::take(&mut self.catches);
+        let mut state = Some(HashMap::new());
+        self.block(body, &mut state);
+        self.loops = loops;
+        self.catches = catches;
+    }
+
+    fn block(&mut self, block: &Block, state: &mut State) {
+        for stmt in block {
+            self.stmt(stmt, state);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, state: &mut State) {
+        if let Some(catch) = self.catches.last_mut() {
+            *catch = join(catch.take(), state.clone());
+        }
+        match &stmt.kind {
+            StmtKind::VarDecl(decl) => match &decl.value {
+                Some(value) => self.expr(value, state),
+                /* A global may have been assigned by the rest of the program. */
+                None if decl.storage == Some(Storage::Global) => (),
+                None => {
+                    if let (Some(vars), Some(id)) = (state.as_mut(), decl.binding.get()) {
+                        vars.insert(id, Init::Unassigned);
+                    }
+                }
+            },
+            StmtKind::Func(func) => self.funcs.push(Rc::clone(func)),
+            StmtKind::Struct(decl) => self.funcs.extend(decl.methods.iter().cloned()),
+            StmtKind::Enum(_) | StmtKind::TypeAlias(_) => (),
+            StmtKind::Expr(expr) => self.expr(expr, state),
+            StmtKind::Assign { target, op, value } => {
+                self.expr(value, state);
+                match &target.kind {
+                    ExprKind::Ident(_, binding) => {
+                        if op.is_some() {
+                            self.expr(target, state);
+                        }
+                        if let (Some(vars), Some(id)) = (state.as_mut(), binding.get()) {
+                            if let Some(init) = vars.get_mut(&id) {
+                                *init = Init::Assigned;
+                            }
+                        }
+                    }
+                    _ => self.expr(target, state),
+                }
+            }
+            StmtKind::If {
+                branches,
+                else_block,
+            } => {
+                let mut out = None;
+                for (cond, body) in branches {
+                    self.expr(cond, state);
+                    let mut branch = state.clone();
+                    self.block(body, &mut branch);
+                    out = join(out, branch);
+                }
+                let mut rest = state.clone();
+                if let Some(body) = else_block {
+                    self.block(body, &mut rest);
+                }
+                *state = join(out, rest);
+            }
+            StmtKind::Given(given) => self.given(given, state),
+            StmtKind::For {
+                iter, body, label, ..
+            } => {
+                self.expr(iter, state);
+                self.iterate(None, body, label, state, true);
+            }
+            StmtKind::While { cond, body, label } => {
+                self.iterate(Some(cond), body, label, state, true);
+            }
+            StmtKind::Loop { body, label } => self.iterate(None, body, label, state, false),
+            StmtKind::Break(label) | StmtKind::Continue(label) => {
+                let target = self
+                    .loops
+                    .iter_mut()
+                    .rev()
+                    .find(|(name, ..)| label.is_none() || name == label);
+                if let Some((_, breaks, continues)) = target {
+                    let exit = match stmt.kind {
+                        StmtKind::Break(_) => breaks,
+                        _ => continues,
+                    };
+                    *exit = join(exit.take(), state.take());
+                }
+                *state = None;
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expr(value, state);
+                }
+                *state = None;
+            }
+            StmtKind::Try { body, handler, .. } => {
+                self.catches.push(None);
+                self.block(body, state);
+                let mut failed = self.catches.pop().flatten();
+                self.block(handler, &mut failed);
+                *state = join(state.take(), failed);
+            }
+            StmtKind::Raise(value) => {
+                self.expr(value, state);
+                *state = None;
+            }
+        }
+    }
+
+    /* Unless the checker found its arms exhaustive, or it has an `else` arm, a
+     * `given` may match no arm at all. */
+    fn given(&mut self, given: &Given, state: &mut State) {
+        self.expr(&given.subject, state);
+        let mut out = None;
+        let mut exhaustive = given.exhaustive.get();
+        for arm in &given.arms {
+            exhaustive |= arm
+                .patterns
+                .iter()
+                .any(|pattern| pattern.kind == PatternKind::Wildcard);
+            let mut branch = state.clone();
+            self.block(&arm.body, &mut branch);
+            out = join(out, branch);
+        }
+        if !exhaustive {
+            out = join(out, state.clone());
+        }
+        *state = out;
+    }
+
+    /* A loop body runs after the code before the loop or after itself, so its state
+     * on entry is iterated to a fixpoint before the reads in it are reported. The
+     * loop is left when `cond` fails (or its items run out) and at each `break`. */
+    fn iterate(
+        &mut self,
+        cond: Option<&Expr>,
+        body: &Block,
+        label: &Option<String>,
+        state: &mut State,
+        exits: bool,
+    ) {
+        let entry = state.clone();
+        let mut head = entry.clone();
+        let report = std::mem::replace(&mut self.report, false);
+        loop {
+            let mut end = head.clone();
+            self.loop_body(cond, body, label, &mut end);
+            let next = join(entry.clone(), end);
+            if next == head {
+                break;
+            }
+            head = next;
+        }
+        self.report = report;
+        let mut end = head.clone();
+        let breaks = self.loop_body(cond, body, label, &mut end);
+        *state = join(if exits { head } else { None }, breaks);
+    }
+
+    /* Runs one iteration and returns the state at its `break`s. A `continue` ends the
+     * iteration as reaching the end of the body does. */
+    fn loop_body(
+        &mut self,
+        cond: Option<&Expr>,
+        body: &Block,
+        label: &Option<String>,
+        state: &mut State,
+    ) -> State {
+        if let Some(cond) = cond {
+            self.expr(cond, state);
+        }
+        self.loops.push((label.clone(), None, None));
+        self.block(body, state);
+        let (_, breaks, continues) = self.loops.pop().unwrap_or_default();
+        *state = join(state.take(), continues);
+        breaks
+    }
+
+    fn expr(&mut self, expr: &Expr, state: &State) {
+        match &expr.kind {
+            ExprKind::Ident(name, binding) => {
+                let init = state
+                    .as_ref()
+                    .zip(binding.get())
+                    .and_then(|(vars, id)| vars.get(&id));
+                match init {
+                    _ if !self.report => (),
+                    Some(Init::Unassigned) => self.diagnostics.push(Diagnostic::error(
+                        expr.span,
+                        format!("`{name}` is used before it is assigned."),
+                    )),
+                    Some(Init::Maybe) => self.diagnostics.push(Diagnostic::warning(
+                        expr.span,
+                        format!("`{name}` may be used before it is assigned."),
+                    )),
+                    _ => (),
+                }
+            }
+            ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::Bool(_)
+            | ExprKind::Char(_)
+            | ExprKind::Str(_)
+            | ExprKind::Nil
+            | ExprKind::SelF
+            | ExprKind::This => (),
+            ExprKind::Array(items) => {
+                for item in items {
+                    self.expr(item, state);
+                }
+            }
+            ExprKind::Table(entries) => {
+                for (key, value) in entries {
+                    self.expr(key, state);
+                    self.expr(value, state);
+                }
+            }
+            ExprKind::Unary(_, operand)
+            | ExprKind::Cast(_, operand)
+            | ExprKind::Try(operand)
+            | ExprKind::Field(operand, _)
+            | ExprKind::SafeField(operand, _) => self.expr(operand, state),
+            ExprKind::Yield(value) => {
+                if let Some(value) = value {
+                    self.expr(value, state);
+                }
+            }
+            ExprKind::Range {
+                start, end, step, ..
+            } => {
+                self.expr(start, state);
+                self.expr(end, state);
+                if let Some(step) = step {
+                    self.expr(step, state);
+                }
+            }
+            ExprKind::Binary(_, lhs, rhs)
+            | ExprKind::Index(lhs, rhs)
+            | ExprKind::Repeat(lhs, rhs) => {
+                self.expr(lhs, state);
+                self.expr(rhs, state);
+            }
+            ExprKind::Call(callee, args) => {
+                self.expr(callee, state);
+                for arg in args {
+                    self.expr(arg, state);
+                }
+            }
+            ExprKind::Func(func) => self.funcs.push(Rc::clone(func)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker;
+    use crate::parser::parse;
+    use crate::resolver::resolve;
+
+    fn messages(source: &str) -> Vec<String> {
+        let program = parse(source).unwrap();
+        assert!(resolve(&program, &[]).iter().all(|d| !d.is_error()));
+        check(&program).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_branches_and_returns() {
+        let source = "int32 a\nprintln(a)\nint32 b\nif true then\n\tb = 1\nelse\n\tb = 2\nend\nprintln(b)\nint32 c\nif true then\n\tc = 1\nend\nprintln(c)\nfunc f(bool flag)\n\tint32 d\n\tif flag then\n\t\treturn 0\n\telse\n\t\td = 1\n\tend\n\treturn d\nend\nint32 e\ngiven 1 do\n\twhen 1 => e = 1\n\telse =>\n\t\te = 2\nend\ne += 1\nprintln(e)";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:2:9: `a` is used before it is assigned.",
+                "Toolip:14:9: warning: `c` may be used before it is assigned.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exhaustive_given() {
+        let source = "enum Color Red Green end\nfunc f(bool flag, int32 n, Color c)\n\tint32 a\n\tgiven flag do\n\t\twhen true => a = 1\n\t\twhen false => a = 2\n\tend\n\tint32 b\n\tgiven n % 2 do\n\t\twhen 0 => b = 1\n\t\twhen 1 => b = 2\n\tend\n\tint32 c2\n\tgiven c do\n\t\twhen Red => c2 = 1\n\t\twhen Green => c2 = 2\n\tend\n\tint32 d\n\tgiven n do\n\t\twhen 0 => d = 1\n\tend\n\tprintln(a, b, c2, d)\nend";
+        let program = parse(source).unwrap();
+        let messages = checker::check(&program)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec!["Toolip:22:20: warning: `d` may be used before it is assigned."]
+        );
+    }
+
+    #[test]
+    fn test_loops() {
+        let source = "int32 a\nfor int32 i in 0 to 3 do\n\tif i == 0 then\n\t\ta = i\n\telse\n\t\ta += i\n\tend\nend\nprintln(a)\nint32 b\nloop\n\tb = 1\n\tbreak\nend\nprintln(b)\nint32 c\nwhile true do\n\tprintln(c)\n\tc = 1\nend";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:6:3: warning: `a` may be used before it is assigned.",
+                "Toolip:9:9: warning: `a` may be used before it is assigned.",
+                "Toolip:18:10: warning: `c` may be used before it is assigned.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_try() {
+        let source = "int32 a
+int32 b
+try
+	a = 1
+	b = 2
+catch
+	b = 0
+end
+println(a, b)
+int32 c
+try
+	c = 1
+catch
+	raise \"no c\"
+end
+println(c)";
+        assert_eq!(
+            messages(source),
+            vec!["Toolip:9:9: warning: `a` may be used before it is assigned."]
+        );
+    }
+}
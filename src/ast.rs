@@ -1 +1,462 @@
+#![allow(dead_code)]
+use crate::token::Span;
+use crate::types::Type;
+use std::cell::Cell;
+use std::fmt;
+use std::rc::Rc;
 
+pub type Block = Vec<Stmt>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub stmts: Block,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    VarDecl(VarDecl),
+    Func(Rc<FuncDecl>),
+    Enum(Rc<EnumDecl>),
+    Struct(Rc<StructDecl>),
+    TypeAlias(Rc<TypeAlias>),
+    Expr(Expr),
+    /* `op` is set for compound assignments such as `+=` and `..=`. */
+    Assign {
+        target: Expr,
+        op: Option<BinaryOp>,
+        value: Expr,
+    },
+    If {
+        branches: Vec<(Expr, Block)>,
+        else_block: Option<Block>,
+    },
+    Given(Given),
+    /* Loops may be labelled, as in `outer: for ...`, for the `break` and `continue`
+     * of a loop nested in them to name. */
+    For {
+        var_ty: Option<Type>,
+        var: String,
+        /* The second variable of `for key, value in table`, with its type if given. */
+        value_var: Option<(Option<Type>, String)>,
+        iter: Expr,
+        body: Block,
+        label: Option<String>,
+    },
+    While {
+        cond: Expr,
+        body: Block,
+        label: Option<String>,
+    },
+    Loop {
+        body: Block,
+        label: Option<String>,
+    },
+    /* Each leaves or goes on with the innermost loop, or the one labelled so. */
+    Break(Option<String>),
+    Continue(Option<String>),
+    Return(Option<Expr>),
+    /* `try ... catch e ... end`: runs `handler` with the error as `var` when `body`
+     * fails, by `raise` or by a runtime error. */
+    Try {
+        body: Block,
+        var: Option<String>,
+        handler: Block,
+    },
+    /* `raise e` fails with the error value `e`, or with an error whose message is
+     * the string `e`. */
+    Raise(Expr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Storage {
+    Global,
+    Const,
+    Static,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarDecl {
+    /* `Unknown` in `const x = 5`, where the type is inferred from the value. */
+    pub ty: Type,
+    pub name: String,
+    /* A variable declared without a value holds `nil` until it is assigned. */
+    pub value: Option<Expr>,
+    /* Set when the declaration starts with `global`, `const` or `static`. */
+    pub storage: Option<Storage>,
+    /* Filled in by the resolver. */
+    pub binding: Cell<Option<BindingId>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    /* `Unknown` for an untyped parameter of an anonymous function, as `a` in
+     * `func (a) return a * 2 end`, whose type is inferred from its uses. */
+    pub ty: Type,
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncDecl {
+    pub name: Option<String>,
+    /* `<T, U>` after `func`, as in `func<T> T first(list<T> xs)`. */
+    pub type_params: Vec<String>,
+    pub params: Vec<Param>,
+    pub ret: Type,
+    pub body: Block,
+    pub span: Span,
+    /* Whether the body mentions `self`, which then has to be bound on each call. */
+    pub uses_self: bool,
+    /* Marked `jit`: compiled to native code on its first call, if it can be. */
+    pub jit: bool,
+    /* Marked `coroutine`: a call makes a coroutine that runs the body when resumed,
+     * and `ret` is the type of the values it yields. */
+    pub coroutine: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDecl {
+    pub ty: Type,
+    pub name: String,
+    pub default: Option<Expr>,
+    pub span: Span,
+}
+
+/* Structs are values: assigning one, passing it as an argument or storing it in a
+ * field or element stores a copy. A method's `self` is the receiver itself, so
+ * methods can update it in place. A method named `new` is the constructor. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructDecl {
+    pub name: String,
+    /* Generic type parameters, as in `struct Pair<K, V>`. */
+    pub params: Vec<String>,
+    pub fields: Vec<FieldDecl>,
+    pub methods: Vec<Rc<FuncDecl>>,
+    pub span: Span,
+}
+
+impl StructDecl {
+    pub fn field_index(&self, name: &str) -> Option<usize> {
+        self.fields.iter().position(|field| field.name == name)
+    }
+
+    pub fn method(&self, name: &str) -> Option<&Rc<FuncDecl>> {
+        self.methods
+            .iter()
+            .find(|method| method.name.as_deref() == Some(name))
+    }
+}
+
+/* `type Name<T> = ...`: `Name` is interchangeable with the type it stands for. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeAlias {
+    pub name: String,
+    pub params: Vec<String>,
+    pub ty: Type,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumDecl {
+    pub name: String,
+    pub variants: Vec<Variant>,
+}
+
+impl EnumDecl {
+    /* Payload-less enums number their variants from 0 in declaration order. */
+    pub fn has_discriminants(&self) -> bool {
+        self.variants
+            .iter()
+            .all(|variant| variant.fields.is_empty())
+    }
+
+    pub fn variant_index(&self, name: &str) -> Option<usize> {
+        self.variants
+            .iter()
+            .position(|variant| variant.name == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub name: String,
+    pub fields: Vec<Type>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Given {
+    pub subject: Expr,
+    pub arms: Vec<WhenArm>,
+    /* Set by the checker when the arms match every value the subject can take. */
+    pub exhaustive: Cell<bool>,
+}
+
+/* A `when` arm, or the trailing `else` arm when its only pattern is a wildcard. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhenArm {
+    pub patterns: Vec<Pattern>,
+    pub body: Block,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+    /* Set by the resolver when a bare name refers to a `const`, as in `when LIMIT`. */
+    pub constant: Cell<Option<BindingId>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternKind {
+    Wildcard,
+    Int(i128),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Str(String),
+    Nil,
+    /* `1 to 5` or `1 through 4`: the integers of a range. */
+    Range {
+        start: i128,
+        end: i128,
+        inclusive: bool,
+    },
+    /* An enum variant such as `Red`, `Color.Red` or `Circle(r)`. */
+    Name {
+        qualifier: Option<String>,
+        name: String,
+        bindings: Option<Vec<String>>,
+    },
+}
+
+impl fmt::Display for PatternKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wildcard => write!(f, "else"),
+            Self::Int(int) => write!(f, "{int}"),
+            Self::Float(float) => write!(f, "{float}"),
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::Char(ch) => write!(f, "{ch:?}"),
+            Self::Str(string) => write!(f, "{string:?}"),
+            Self::Nil => write!(f, "nil"),
+            Self::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let word = if *inclusive { "through" } else { "to" };
+                write!(f, "{start} {word} {end}")
+            }
+            Self::Name {
+                qualifier,
+                name,
+                bindings,
+            } => {
+                if let Some(qualifier) = qualifier {
+                    write!(f, "{qualifier}.")?;
+                }
+                write!(f, "{name}")?;
+                if let Some(bindings) = bindings {
+                    write!(f, "({})", bindings.join(", "))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/* Identifies a declaration. The resolver numbers declarations as it meets them and
+ * records in each name the declaration it refers to. The definite assignment pass
+ * tracks variables by it, and the evaluator and compiler skip the variables of the
+ * same name that it does not refer to. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BindingId(pub u32);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    /* A literal whose type comes from the other operand or the declaration it
+     * initialises. */
+    pub fn is_untyped_literal(&self) -> bool {
+        match &self.kind {
+            ExprKind::Int(_) | ExprKind::Float(_) => true,
+            ExprKind::Unary(UnaryOp::Neg, operand) => {
+                matches!(operand.kind, ExprKind::Int(_) | ExprKind::Float(_))
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    /* Integer literals keep their magnitude; the sign is a unary minus. */
+    Int(u128),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Str(String),
+    Nil,
+    /* The binding is filled in by the resolver. */
+    Ident(String, Cell<Option<BindingId>>),
+    SelF,
+    This,
+    Array(Vec<Expr>),
+    /* `[key: value, ...]`, a table. An empty one is written `[]`. */
+    Table(Vec<(Expr, Expr)>),
+    /* `[value; count]`: an array of `count` copies of `value`. The count must be
+     * known at compile time. */
+    Repeat(Box<Expr>, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /* `start to end`, or `start to end step n`, which counts down for a negative `n`.
+     * The end is excluded, unless written `start through end`. */
+    Range {
+        start: Box<Expr>,
+        end: Box<Expr>,
+        step: Option<Box<Expr>>,
+        inclusive: bool,
+    },
+    Call(Box<Expr>, Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Field(Box<Expr>, String),
+    /* `a?.b`: `nil` when `a` is, and `a.b` otherwise. */
+    SafeField(Box<Expr>, String),
+    Func(Rc<FuncDecl>),
+    /* An explicit conversion such as `int32(x)`, `flt64(n)` or `char(65)`. */
+    Cast(Type, Box<Expr>),
+    /* `try f()`: the value of `f()`, which is raised if it is an error. */
+    Try(Box<Expr>),
+    /* `yield x` suspends the coroutine running, and is the value it is resumed
+     * with. Only a statement or the value of an assignment or declaration. */
+    Yield(Option<Box<Expr>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+    Len,
+}
+
+impl UnaryOp {
+    pub const fn symbol(self) -> &'static str {
+        match self {
+            Self::Neg => "-",
+            Self::Not => "not",
+            Self::BitNot => "~",
+            Self::Len => "#",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    FloorDiv,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+    Xor,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    NilCoalesce,
+    /* `x in r`: whether the range `r` visits `x`. */
+    In,
+}
+
+impl BinaryOp {
+    pub const fn symbol(self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::FloorDiv => "//",
+            Self::Mod => "%",
+            Self::Pow => "**",
+            Self::Concat => "..",
+            Self::Eq => "==",
+            Self::NotEq => "!=",
+            Self::Lt => "<",
+            Self::LtEq => "<=",
+            Self::Gt => ">",
+            Self::GtEq => ">=",
+            Self::And => "and",
+            Self::Or => "or",
+            Self::Xor => "xor",
+            Self::BitAnd => "&",
+            Self::BitOr => "|",
+            Self::BitXor => "^",
+            Self::Shl => "<<",
+            Self::Shr => ">>",
+            Self::NilCoalesce => "??",
+            Self::In => "in",
+        }
+    }
+
+    pub const fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            Self::Eq | Self::NotEq | Self::Lt | Self::LtEq | Self::Gt | Self::GtEq
+        )
+    }
+
+    pub const fn is_logical(self) -> bool {
+        matches!(self, Self::And | Self::Or | Self::Xor)
+    }
+
+    /* Whether the result has the type of the operands, so that a type expected of the
+     * result is expected of the operands too. */
+    pub const fn preserves_type(self) -> bool {
+        matches!(
+            self,
+            Self::Add
+                | Self::Sub
+                | Self::Mul
+                | Self::Div
+                | Self::FloorDiv
+                | Self::Mod
+                | Self::Pow
+                | Self::BitAnd
+                | Self::BitOr
+                | Self::BitXor
+                | Self::Shl
+                | Self::Shr
+                | Self::NilCoalesce
+        )
+    }
+
+    /* The type expected of the operands when `expected` is expected of the result.
+     * `..` on integers keeps their type, but joins anything else into a string. */
+    pub fn operand_hint(self, expected: Option<&Type>) -> Option<&Type> {
+        expected.filter(|ty| self.preserves_type() || (self == Self::Concat && ty.is_int()))
+    }
+}
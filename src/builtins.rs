@@ -0,0 +1,220 @@
+#![allow(dead_code)]
+use crate::format;
+use crate::ops;
+use crate::value::{Builtin, EnumValue, ErrorValue, Heap, Object, Value};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/* Where `print` and friends send their text. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    Stdout,
+    Captured(String),
+}
+
+impl Output {
+    pub fn write(&mut self, text: &str) {
+        match self {
+            Self::Stdout => {
+                let mut stdout = io::stdout().lock();
+                let _ = stdout.write_all(text.as_bytes());
+                let _ = stdout.flush();
+            }
+            Self::Captured(buffer) => buffer.push_str(text),
+        }
+    }
+
+    /* Captured error output is kept with the rest, in order. */
+    pub fn write_err(&mut self, text: &str) {
+        match self {
+            Self::Stdout => {
+                let mut stderr = io::stderr().lock();
+                let _ = stderr.write_all(text.as_bytes());
+                let _ = stderr.flush();
+            }
+            Self::Captured(buffer) => buffer.push_str(text),
+        }
+    }
+
+    pub fn take(&mut self) -> String {
+        match self {
+            Self::Stdout => String::new(),
+            Self::Captured(buffer) => std::mem::take(buffer),
+        }
+    }
+}
+
+/* `named` is given when the first argument is a format string, and holds the values
+ * of the variables named by its placeholders. */
+pub fn call(
+    builtin: Builtin,
+    heap: &mut Heap,
+    out: &mut Output,
+    args: &[Value],
+    named: Option<&HashMap<String, Value>>,
+) -> Result<Value, String> {
+    match builtin {
+        Builtin::Print | Builtin::Println | Builtin::Eprint | Builtin::Eprintln => {
+            let mut text = display_args(heap, args, named)?;
+            if matches!(builtin, Builtin::Println | Builtin::Eprintln) {
+                text.push('\n');
+            }
+            if matches!(builtin, Builtin::Print | Builtin::Println) {
+                out.write(&text);
+            } else {
+                out.write_err(&text);
+            }
+            Ok(Value::Nil)
+        }
+        Builtin::Format => match args {
+            [Value::Str(text), args @ ..] => {
+                let empty = HashMap::new();
+                let named = named.unwrap_or(&empty);
+                let text = format::render(&format::parse(text)?, args, named, heap)?;
+                Ok(Value::Str(text.into()))
+            }
+            [value, ..] => Err(format!(
+                "`format` expects a format string, found {}.",
+                heap.type_name(value)
+            )),
+            [] => Err(arity(builtin, 1, 0)),
+        },
+        Builtin::Discriminant => {
+            let [value] = args else {
+                return Err(arity(builtin, 1, args.len()));
+            };
+            let Some(EnumValue { ty, variant, .. }) = heap.enum_value(value) else {
+                return Err(format!(
+                    "`discriminant` expects an enum, found {}.",
+                    heap.type_name(value)
+                ));
+            };
+            match heap.enum_decl(*ty) {
+                Some(decl) if decl.has_discriminants() => i32::try_from(*variant)
+                    .map(Value::Int32)
+                    .map_err(|_| "Discriminant does not fit in int32.".to_owned()),
+                _ => Err(format!(
+                    "Enum `{}` has variants with payloads and no discriminants.",
+                    heap.type_name(value)
+                )),
+            }
+        }
+        Builtin::FromDiscriminant => {
+            let [Value::Obj(ty), value] = args else {
+                return Err(arity(builtin, 1, args.len().saturating_sub(1)));
+            };
+            let Some(decl) = heap.enum_decl(*ty) else {
+                return Err("`fromDiscriminant` must be called on an enum.".to_owned());
+            };
+            let name = &decl.name;
+            if !decl.has_discriminants() {
+                return Err(format!(
+                    "Enum `{name}` has variants with payloads and no discriminants."
+                ));
+            }
+            let Some((negative, magnitude)) = value.int_parts() else {
+                return Err(format!(
+                    "`fromDiscriminant` expects an integer, found {}.",
+                    heap.type_name(value)
+                ));
+            };
+            let len = decl.variants.len();
+            match usize::try_from(magnitude) {
+                Ok(variant) if !negative && variant < len => {
+                    Ok(Value::Obj(heap.alloc(Object::EnumValue(EnumValue {
+                        ty: *ty,
+                        variant,
+                        payload: vec![],
+                    }))))
+                }
+                _ => Ok(Value::Nil),
+            }
+        }
+        Builtin::Arithmetic(op, overflow) => {
+            let [lhs, rhs] = args else {
+                return Err(arity(builtin, 2, args.len()));
+            };
+            if lhs.int_kind().is_none() || lhs.int_kind() != rhs.int_kind() {
+                return Err(format!(
+                    "`{}` expects two integers of the same type, found {} and {}.",
+                    builtin.name(),
+                    heap.type_name(lhs),
+                    heap.type_name(rhs)
+                ));
+            }
+            ops::arithmetic(op, lhs, rhs, overflow)
+        }
+        Builtin::Error => {
+            let (kind, message) = match args {
+                [Value::Str(message)] => ("error".into(), message.clone()),
+                [Value::Str(kind), Value::Str(message)] => (kind.clone(), message.clone()),
+                [_] | [_, _] => {
+                    return Err(format!(
+                        "`error` expects strings, found {}.",
+                        args.iter()
+                            .map(|arg| heap.type_name(arg))
+                            .collect::<Vec<_>>()
+                            .join(" and ")
+                    ))
+                }
+                _ => return Err(arity(builtin, 2, args.len())),
+            };
+            let error = ErrorValue { kind, message };
+            Ok(Value::Obj(heap.alloc(Object::Error(error))))
+        }
+        Builtin::IsError => match args {
+            [value] => Ok(Value::Bool(heap.error(value).is_some())),
+            _ => Err(arity(builtin, 1, args.len())),
+        },
+        /* The caller resumes coroutines itself; anything left is not one. */
+        Builtin::Resume => match args {
+            [value] | [value, _] => Err(format!(
+                "`resume` expects a coroutine, found {}.",
+                heap.type_name(value)
+            )),
+            _ => Err(arity(builtin, 2, args.len())),
+        },
+        Builtin::Status => match args {
+            [value] => match heap.coroutine_status(value) {
+                Some(status) => Ok(Value::Str(status.name().into())),
+                None => Err(format!(
+                    "`status` expects a coroutine, found {}.",
+                    heap.type_name(value)
+                )),
+            },
+            _ => Err(arity(builtin, 1, args.len())),
+        },
+        /* The caller, which knows the roots, has already collected. */
+        Builtin::CollectGarbage | Builtin::HeapSize => match args {
+            [] => Ok(Value::Int64(heap.len() as i64)),
+            _ => Err(arity(builtin, 0, args.len())),
+        },
+    }
+}
+
+/* A format string followed by its arguments, or else the arguments separated by
+ * spaces. The first argument is only read as a format string when `named` is
+ * given. */
+fn display_args(
+    heap: &Heap,
+    args: &[Value],
+    named: Option<&HashMap<String, Value>>,
+) -> Result<String, String> {
+    if let (Some(named), [Value::Str(text), rest @ ..]) = (named, args) {
+        if format::is_format(text) {
+            return format::render(&format::parse(text)?, rest, named, heap);
+        }
+    }
+    Ok(args
+        .iter()
+        .map(|arg| heap.display(arg))
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+fn arity(builtin: Builtin, expected: usize, found: usize) -> String {
+    format!(
+        "`{}` expects {expected} argument(s), found {found}.",
+        builtin.name()
+    )
+}
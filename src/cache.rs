@@ -0,0 +1,1443 @@
+#![allow(dead_code)]
+use crate::ast::{
+    BinaryOp, BindingId, EnumDecl, FieldDecl, Pattern, PatternKind, Storage, StructDecl, UnaryOp,
+    Variant,
+};
+use crate::compiler::{
+    Bytecode, CallSite, Capture, Function, GlobalDecl, Hint, Literal, LiteralValue, Op,
+    PatternTest, StructDef,
+};
+use crate::token::Span;
+use crate::types::{FloatKind, IntKind, Type};
+use crate::value::Value;
+use std::cell::Cell;
+use std::rc::Rc;
+
+/* Bytecode cache files (`.toolc`): a compiled program saved by `toolip build`, so that
+ * running it again skips lexing, parsing, checking and compiling.
+ *
+ * A file starts with a header: the magic bytes, the format version, the version of
+ * the machine that wrote it and a hash of the source it was compiled from. A file
+ * whose header does not match the running machine or the current source is stale.
+ * Then come the names of the globals and the table of functions, the program itself
+ * first. Each function has its constant pool, its instructions and a line table
+ * giving the source position of each instruction, and refers to the functions it
+ * creates by their place in the table.
+ *
+ * Numbers are little-endian; lengths and operands are `u32`s.
+ *
+ * The machine trusts its code, so a loaded function is verified before it can run:
+ * every operand must be within the pools, slots and code of its function, and each
+ * instruction must find its operands on the stack, at the same height on every path
+ * that reaches it. A damaged file is then reported rather than crashing the machine. */
+
+const MAGIC: &[u8; 6] = b"TOOLC\0";
+/* Bumped whenever the layout of the file or the meaning of an instruction changes. */
+pub const FORMAT_VERSION: u32 = 14;
+pub const VM_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/* The FNV-1a hash of a source file, which is stable across builds of the machine. */
+pub fn source_hash(source: &str) -> u64 {
+    source.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+pub fn write(bytecode: &Bytecode, source: &str) -> Vec<u8> {
+    let mut writer = Writer::default();
+    writer.bytes.extend_from_slice(MAGIC);
+    writer.u32(FORMAT_VERSION);
+    writer.str(VM_VERSION);
+    writer.u64(source_hash(source));
+    writer.list(&bytecode.globals, Writer::string);
+    let mut table = vec![];
+    collect(&bytecode.script, &mut table);
+    writer.len(table.len());
+    for function in &table {
+        writer.function(function, &table);
+    }
+    writer.bytes
+}
+
+/* Loads a cache file, checking it against the current source when there is one. */
+pub fn read(bytes: &[u8], source: Option<&str>) -> Result<Bytecode, String> {
+    let mut reader = Reader { bytes, pos: 0 };
+    if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err("Not a Toolip bytecode file.".to_owned());
+    }
+    let format = reader.u32()?;
+    let version = reader.string()?;
+    if format != FORMAT_VERSION || version != VM_VERSION {
+        return Err(format!(
+            "The bytecode file was built by Toolip {version} (format {format}), not {VM_VERSION} (format {FORMAT_VERSION})."
+        ));
+    }
+    let hash = reader.u64()?;
+    if source.is_some_and(|source| source_hash(source) != hash) {
+        return Err("The bytecode file is out of date with its source.".to_owned());
+    }
+    let globals = reader.list(Reader::string)?;
+    let count = reader.len()?;
+    let mut table = Vec::with_capacity(count);
+    for _ in 0..count {
+        table.push(reader.function()?);
+    }
+    if reader.pos != bytes.len() {
+        return Err("The bytecode file has trailing bytes.".to_owned());
+    }
+    /* A function only creates functions after it in the table, so they are linked
+     * from the last one back. */
+    let mut linked: Vec<Option<Rc<Function>>> = vec![None; count];
+    for (index, (mut function, nested)) in table.into_iter().enumerate().rev() {
+        for inner in nested {
+            let inner = linked
+                .get(inner as usize)
+                .filter(|_| inner as usize > index)
+                .and_then(Clone::clone)
+                .ok_or("The bytecode file has a bad function table.")?;
+            function.functions.push(inner);
+        }
+        verify(&function, globals.len())?;
+        linked[index] = Some(Rc::new(function));
+    }
+    let script = linked
+        .into_iter()
+        .next()
+        .flatten()
+        .ok_or("The bytecode file has no program.")?;
+    /* Only closures keep `static` variables, and only they run as coroutines. */
+    if script.statics > 0 || script.coroutine {
+        return malformed("program");
+    }
+    Ok(Bytecode { script, globals })
+}
+
+/* Lists a function and those it creates, each before the functions it creates. */
+fn collect(function: &Rc<Function>, table: &mut Vec<Rc<Function>>) {
+    table.push(Rc::clone(function));
+    for inner in &function.functions {
+        collect(inner, table);
+    }
+}
+
+const BINARY_OPS: [BinaryOp; 24] = [
+    BinaryOp::Add,
+    BinaryOp::Sub,
+    BinaryOp::Mul,
+    BinaryOp::Div,
+    BinaryOp::FloorDiv,
+    BinaryOp::Mod,
+    BinaryOp::Pow,
+    BinaryOp::Concat,
+    BinaryOp::Eq,
+    BinaryOp::NotEq,
+    BinaryOp::Lt,
+    BinaryOp::LtEq,
+    BinaryOp::Gt,
+    BinaryOp::GtEq,
+    BinaryOp::And,
+    BinaryOp::Or,
+    BinaryOp::Xor,
+    BinaryOp::BitAnd,
+    BinaryOp::BitOr,
+    BinaryOp::BitXor,
+    BinaryOp::Shl,
+    BinaryOp::Shr,
+    BinaryOp::NilCoalesce,
+    BinaryOp::In,
+];
+const UNARY_OPS: [UnaryOp; 4] = [UnaryOp::Neg, UnaryOp::Not, UnaryOp::BitNot, UnaryOp::Len];
+const FLOAT_KINDS: [FloatKind; 2] = [FloatKind::F32, FloatKind::F64];
+
+fn position<T: PartialEq>(all: &[T], item: &T) -> u8 {
+    all.iter()
+        .position(|known| known == item)
+        .unwrap_or_default() as u8
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, byte: u8) {
+        self.bytes.push(byte);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(u8::from(value));
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u128(&mut self, value: u128) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u32(len as u32);
+    }
+
+    fn string(&mut self, string: &impl AsRef<str>) {
+        self.str(string.as_ref());
+    }
+
+    fn str(&mut self, string: &str) {
+        self.len(string.len());
+        self.bytes.extend_from_slice(string.as_bytes());
+    }
+
+    fn list<T>(&mut self, items: &[T], mut item: impl FnMut(&mut Self, &T)) {
+        self.len(items.len());
+        for value in items {
+            item(self, value);
+        }
+    }
+
+    fn option<T>(&mut self, value: Option<&T>, item: impl FnOnce(&mut Self, &T)) {
+        self.bool(value.is_some());
+        if let Some(value) = value {
+            item(self, value);
+        }
+    }
+
+    fn span(&mut self, span: &Span) {
+        self.len(span.line);
+        self.len(span.col);
+    }
+
+    fn function(&mut self, function: &Function, table: &[Rc<Function>]) {
+        self.option(function.name.as_ref(), Self::string);
+        self.list(&function.params, |writer, (name, ty)| {
+            writer.string(name);
+            writer.ty(ty);
+        });
+        self.ty(&function.ret);
+        self.bool(function.uses_self);
+        self.bool(function.coroutine);
+        self.bool(function.jit);
+        self.len(function.slots);
+        self.list(&function.constants, Self::value);
+        self.list(&function.code, Self::op);
+        self.lines(&function.spans);
+        self.list(&function.literals, Self::literal);
+        self.list(&function.hints, Self::hint);
+        self.list(&function.checks, |writer, (ty, what)| {
+            writer.ty(ty);
+            writer.string(what);
+        });
+        self.list(&function.types, Self::ty);
+        self.list(&function.names, Self::string);
+        self.list(&function.globals, Self::global);
+        self.list(&function.sites, Self::site);
+        self.list(&function.patterns, Self::pattern);
+        self.list(&function.structs, Self::struct_def);
+        self.list(&function.enums, |writer, decl| writer.enum_decl(decl));
+        self.len(function.statics);
+        self.list(&function.functions, |writer, inner| {
+            let index = table.iter().position(|known| Rc::ptr_eq(known, inner));
+            writer.len(index.unwrap_or_default());
+        });
+        self.list(&function.captures, |writer, capture| match capture {
+            Capture::Local(slot) => {
+                writer.u8(0);
+                writer.u32(*slot);
+            }
+            Capture::Capture(index) => {
+                writer.u8(1);
+                writer.u32(*index);
+            }
+        });
+    }
+
+    /* The line table: runs of instructions at the same source position. */
+    fn lines(&mut self, spans: &[Span]) {
+        let runs = spans.chunk_by(|a, b| a == b).collect::<Vec<_>>();
+        self.list(&runs, |writer, run| {
+            writer.len(run.len());
+            writer.span(&run[0]);
+        });
+    }
+
+    fn ty(&mut self, ty: &Type) {
+        match ty {
+            Type::Void => self.u8(0),
+            Type::Nil => self.u8(1),
+            Type::Bool => self.u8(2),
+            Type::Int(kind) => {
+                self.u8(3);
+                self.u8(position(&IntKind::ALL, kind));
+            }
+            Type::Float(kind) => {
+                self.u8(4);
+                self.u8(position(&FLOAT_KINDS, kind));
+            }
+            Type::Char => self.u8(5),
+            Type::String => self.u8(6),
+            Type::Array(elem) => {
+                self.u8(7);
+                self.ty(elem);
+            }
+            Type::List(elem) => {
+                self.u8(8);
+                self.ty(elem);
+            }
+            Type::Set(elem) => {
+                self.u8(9);
+                self.ty(elem);
+            }
+            Type::Coroutine(elem) => {
+                self.u8(16);
+                self.ty(elem);
+            }
+            Type::Range(elem) => {
+                self.u8(17);
+                self.ty(elem);
+            }
+            Type::Table(key, value) => {
+                self.u8(10);
+                self.ty(key);
+                self.ty(value);
+            }
+            Type::Func(params, ret) => {
+                self.u8(11);
+                self.list(params, Self::ty);
+                self.ty(ret);
+            }
+            Type::Optional(inner) => {
+                self.u8(12);
+                self.ty(inner);
+            }
+            Type::Named(name, args) => {
+                self.u8(13);
+                self.string(name);
+                self.list(args, Self::ty);
+            }
+            Type::Var(var) => {
+                self.u8(14);
+                self.len(*var);
+            }
+            Type::Unknown => self.u8(15),
+        }
+    }
+
+    /* Constants are the scalars the compiler folds literals into. */
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Nil => self.u8(0),
+            Value::Bool(value) => {
+                self.u8(1);
+                self.bool(*value);
+            }
+            Value::Char(ch) => {
+                self.u8(2);
+                self.u32(u32::from(*ch));
+            }
+            Value::Str(string) => {
+                self.u8(3);
+                self.str(string);
+            }
+            Value::Flt32(float) => {
+                self.u8(4);
+                self.u32(float.to_bits());
+            }
+            Value::Flt64(float) => {
+                self.u8(5);
+                self.u64(float.to_bits());
+            }
+            Value::Obj(_) | Value::Builtin(_) => unreachable!("constants are scalars"),
+            int => {
+                let kind = int.int_kind().expect("the other constants are integers");
+                let (negative, magnitude) = int.int_parts().unwrap_or_default();
+                self.u8(6);
+                self.u8(position(&IntKind::ALL, &kind));
+                self.bool(negative);
+                self.u128(magnitude);
+            }
+        }
+    }
+
+    fn op(&mut self, op: &Op) {
+        let (tag, operands): (u8, &[u32]) = match *op {
+            Op::Constant(a) => (0, &[a]),
+            Op::Literal(a) => (1, &[a]),
+            Op::Nil => (2, &[]),
+            Op::Pop => (3, &[]),
+            Op::Dup => (4, &[]),
+            Op::Swap => (5, &[]),
+            Op::GetLocal(a) => (6, &[a]),
+            Op::SetLocal(a) => (7, &[a]),
+            Op::NewCell(a) => (8, &[a]),
+            Op::GetCell(a) => (9, &[a]),
+            Op::SetCell(a) => (10, &[a]),
+            Op::GetCapture(a) => (11, &[a]),
+            Op::SetCapture(a) => (12, &[a]),
+            Op::GetGlobal(a) => (13, &[a]),
+            Op::AssignGlobal(a) => (14, &[a]),
+            Op::SetGlobal(a) => (15, &[a]),
+            Op::DefineGlobal(a) => (16, &[a]),
+            Op::Check(a) => (17, &[a]),
+            Op::Condition => (18, &[]),
+            Op::Unary(op) => {
+                self.u8(19);
+                self.u8(position(&UNARY_OPS, &op));
+                return;
+            }
+            Op::Binary(op) => {
+                self.u8(20);
+                self.u8(position(&BINARY_OPS, &op));
+                return;
+            }
+            Op::Cast(a) => (21, &[a]),
+            Op::Array(a) => (22, &[a]),
+            Op::Repeat => (23, &[]),
+            Op::Index => (24, &[]),
+            Op::IndexSlot => (25, &[]),
+            Op::StoreIndex => (26, &[]),
+            Op::Field(a) => (27, &[a]),
+            Op::SafeField(a, b) => (28, &[a, b]),
+            Op::FieldSlot(a) => (29, &[a]),
+            Op::Jump(a) => (30, &[a]),
+            Op::JumpIfFalse(a) => (31, &[a]),
+            Op::JumpIfTrue(a) => (32, &[a]),
+            Op::JumpIfNotNil(a) => (33, &[a]),
+            Op::Closure(a) => (34, &[a]),
+            Op::Call(a) => (35, &[a]),
+            Op::Return => (36, &[]),
+            Op::RangeStart(a) => (37, &[a]),
+            Op::RangeNext(a, b) => (38, &[a, b]),
+            Op::IterStart(a) => (39, &[a]),
+            Op::IterNext(a, b) => (40, &[a, b]),
+            Op::Match(a, b) => (41, &[a, b]),
+            Op::MatchValue(a) => (42, &[a]),
+            Op::Fail(a) => (43, &[a]),
+            Op::TailCall(a) => (44, &[a]),
+            Op::StoreField(a) => (45, &[a]),
+            Op::Copy => (46, &[]),
+            Op::Struct(a) => (47, &[a]),
+            Op::Enum(a) => (48, &[a]),
+            Op::Static(a, b) => (49, &[a, b]),
+            Op::StaticInit(a, b) => (50, &[a, b]),
+            Op::Payload(a, b) => (51, &[a, b]),
+            Op::Collection(a) => (52, &[a]),
+            Op::Table(a, b) => (53, &[a, b]),
+            Op::EntriesStart(a) => (54, &[a]),
+            Op::EntriesNext(a, b) => (55, &[a, b]),
+            Op::Assigned(a) => (56, &[a]),
+            Op::ThroughNext(a, b) => (58, &[a, b]),
+            Op::TryStart(a) => (59, &[a]),
+            Op::TryEnd => (60, &[]),
+            Op::Raise => (61, &[]),
+            Op::Unwrap => (62, &[]),
+            Op::Yield => (63, &[]),
+            Op::Range(inclusive, stepped) => {
+                self.u8(57);
+                self.bool(inclusive);
+                self.bool(stepped);
+                return;
+            }
+        };
+        self.u8(tag);
+        for &operand in operands {
+            self.u32(operand);
+        }
+    }
+
+    fn hint(&mut self, hint: &Hint) {
+        match hint {
+            Hint::None => self.u8(0),
+            Hint::Type(ty) => {
+                self.u8(1);
+                self.ty(ty);
+            }
+            Hint::Numeric(slot, otherwise) => {
+                self.u8(2);
+                self.u32(*slot);
+                self.hint(otherwise);
+            }
+            Hint::Param(slot, index) => {
+                self.u8(3);
+                self.u32(*slot);
+                self.u32(*index);
+            }
+            Hint::Global(global) => {
+                self.u8(4);
+                self.u32(*global);
+            }
+            Hint::Elem(inner) => {
+                self.u8(5);
+                self.hint(inner);
+            }
+            Hint::Operands(op, inner) => {
+                self.u8(6);
+                self.u8(position(&BINARY_OPS, op));
+                self.hint(inner);
+            }
+            Hint::Field(slot, name) => {
+                self.u8(7);
+                self.u32(*slot);
+                self.u32(*name);
+            }
+            Hint::Key(inner) => {
+                self.u8(8);
+                self.hint(inner);
+            }
+            Hint::Index(slot) => {
+                self.u8(9);
+                self.u32(*slot);
+            }
+            Hint::Entry(slot) => {
+                self.u8(10);
+                self.u32(*slot);
+            }
+            Hint::Item(slot) => {
+                self.u8(11);
+                self.u32(*slot);
+            }
+        }
+    }
+
+    fn literal(&mut self, literal: &Literal) {
+        match literal.value {
+            LiteralValue::Int {
+                negative,
+                magnitude,
+            } => {
+                self.u8(0);
+                self.bool(negative);
+                self.u128(magnitude);
+            }
+            LiteralValue::Float(float) => {
+                self.u8(1);
+                self.u64(float.to_bits());
+            }
+        }
+        self.hint(&literal.hint);
+    }
+
+    fn global(&mut self, decl: &GlobalDecl) {
+        self.u32(decl.global);
+        self.ty(&decl.ty);
+        self.u8(match decl.storage {
+            None => 0,
+            Some(Storage::Global) => 1,
+            Some(Storage::Const) => 2,
+            Some(Storage::Static) => 3,
+        });
+        self.bool(decl.has_value);
+    }
+
+    fn site(&mut self, site: &CallSite) {
+        self.u32(site.argc);
+        self.list(&site.arg_spans, Self::span);
+        self.bool(site.format);
+        self.list(&site.named, Self::string);
+        self.list(&site.type_args, Self::ty);
+    }
+
+    /* Struct and enum declarations keep only what the machine uses. */
+    fn struct_def(&mut self, def: &StructDef) {
+        let decl = &def.decl;
+        self.string(&decl.name);
+        self.list(&decl.params, Self::string);
+        self.list(&decl.fields, |writer, field| {
+            writer.ty(&field.ty);
+            writer.string(&field.name);
+            writer.span(&field.span);
+        });
+        self.span(&decl.span);
+        self.u32(def.init);
+        self.list(&def.methods, |writer, method| writer.u32(*method));
+    }
+
+    fn enum_decl(&mut self, decl: &EnumDecl) {
+        self.string(&decl.name);
+        self.list(&decl.variants, |writer, variant| {
+            writer.string(&variant.name);
+            writer.list(&variant.fields, Self::ty);
+            writer.span(&variant.span);
+        });
+    }
+
+    fn pattern(&mut self, test: &PatternTest) {
+        let pattern = &test.pattern;
+        match &pattern.kind {
+            PatternKind::Wildcard => self.u8(0),
+            PatternKind::Int(int) => {
+                self.u8(1);
+                self.u128(*int as u128);
+            }
+            PatternKind::Float(float) => {
+                self.u8(2);
+                self.u64(float.to_bits());
+            }
+            PatternKind::Bool(value) => {
+                self.u8(3);
+                self.bool(*value);
+            }
+            PatternKind::Char(ch) => {
+                self.u8(4);
+                self.u32(u32::from(*ch));
+            }
+            PatternKind::Str(string) => {
+                self.u8(5);
+                self.string(string);
+            }
+            PatternKind::Nil => self.u8(6),
+            PatternKind::Name {
+                qualifier,
+                name,
+                bindings,
+            } => {
+                self.u8(7);
+                self.option(qualifier.as_ref(), Self::string);
+                self.string(name);
+                self.option(bindings.as_ref(), |writer, bindings| {
+                    writer.list(bindings, Self::string);
+                });
+            }
+            PatternKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                self.u8(8);
+                self.u128(*start as u128);
+                self.u128(*end as u128);
+                self.bool(*inclusive);
+            }
+        }
+        self.span(&pattern.span);
+        self.option(pattern.constant.get().as_ref(), |writer, id| {
+            writer.u32(id.0)
+        });
+        self.option(test.global.as_ref(), |writer, global| writer.u32(*global));
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+type ReadResult<T> = Result<T, String>;
+
+fn malformed<T>(what: &str) -> ReadResult<T> {
+    Err(format!("The bytecode file has a bad {what}."))
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> ReadResult<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or("The bytecode file is truncated.")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> ReadResult<[u8; N]> {
+        let bytes = self.take(N)?;
+        Ok(bytes.try_into().unwrap_or([0; N]))
+    }
+
+    fn u8(&mut self) -> ReadResult<u8> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn bool(&mut self) -> ReadResult<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => malformed("flag"),
+        }
+    }
+
+    fn u32(&mut self) -> ReadResult<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> ReadResult<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn u128(&mut self) -> ReadResult<u128> {
+        Ok(u128::from_le_bytes(self.array()?))
+    }
+
+    fn len(&mut self) -> ReadResult<usize> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn string(&mut self) -> ReadResult<String> {
+        let len = self.len()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).or_else(|_| malformed("string"))
+    }
+
+    fn char(&mut self) -> ReadResult<char> {
+        char::from_u32(self.u32()?).map_or_else(|| malformed("character"), Ok)
+    }
+
+    fn list<T>(&mut self, mut item: impl FnMut(&mut Self) -> ReadResult<T>) -> ReadResult<Vec<T>> {
+        let len = self.len()?;
+        /* Every item takes at least a byte, which bounds the allocation. */
+        let mut items = Vec::with_capacity(len.min(self.bytes.len() - self.pos));
+        for _ in 0..len {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    fn option<T>(
+        &mut self,
+        item: impl FnOnce(&mut Self) -> ReadResult<T>,
+    ) -> ReadResult<Option<T>> {
+        if self.bool()? {
+            Ok(Some(item(self)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn span(&mut self) -> ReadResult<Span> {
+        Ok(Span::new(self.len()?, self.len()?))
+    }
+
+    fn kind<T: Copy>(&mut self, all: &[T], what: &str) -> ReadResult<T> {
+        let index = self.u8()?;
+        all.get(index as usize)
+            .copied()
+            .map_or_else(|| malformed(what), Ok)
+    }
+
+    /* A function, with the places in the table of the functions it creates. */
+    fn function(&mut self) -> ReadResult<(Function, Vec<u32>)> {
+        let name = self.option(Self::string)?;
+        let params = self.list(|reader| Ok((reader.string()?, reader.ty()?)))?;
+        let ret = self.ty()?;
+        let uses_self = self.bool()?;
+        let coroutine = self.bool()?;
+        let jit = self.bool()?;
+        let slots = self.len()?;
+        let constants = self.list(Self::value)?;
+        let code = self.list(Self::op)?;
+        let spans = self.lines()?;
+        if spans.len() != code.len() {
+            return malformed("line table");
+        }
+        let function = Function {
+            name,
+            params,
+            ret,
+            uses_self,
+            coroutine,
+            jit,
+            slots,
+            code,
+            spans,
+            constants,
+            literals: self.list(Self::literal)?,
+            hints: self.list(Self::hint)?,
+            checks: self.list(|reader| Ok((reader.ty()?, reader.string()?)))?,
+            types: self.list(Self::ty)?,
+            names: self.list(Self::string)?,
+            globals: self.list(Self::global)?,
+            sites: self.list(Self::site)?,
+            patterns: self.list(Self::pattern)?,
+            structs: self.list(Self::struct_def)?,
+            enums: self.list(|reader| Ok(Rc::new(reader.enum_decl()?)))?,
+            statics: self.len()?,
+            functions: vec![],
+            captures: vec![],
+        };
+        let functions = self.list(Self::u32)?;
+        let captures = self.list(|reader| match reader.u8()? {
+            0 => Ok(Capture::Local(reader.u32()?)),
+            1 => Ok(Capture::Capture(reader.u32()?)),
+            _ => malformed("capture"),
+        })?;
+        Ok((
+            Function {
+                captures,
+                ..function
+            },
+            functions,
+        ))
+    }
+
+    fn lines(&mut self) -> ReadResult<Vec<Span>> {
+        let runs = self.list(|reader| Ok((reader.len()?, reader.span()?)))?;
+        let mut spans = vec![];
+        for (len, span) in runs {
+            if len > self.bytes.len() {
+                return malformed("line table");
+            }
+            spans.extend(std::iter::repeat_n(span, len));
+        }
+        Ok(spans)
+    }
+
+    fn ty(&mut self) -> ReadResult<Type> {
+        Ok(match self.u8()? {
+            0 => Type::Void,
+            1 => Type::Nil,
+            2 => Type::Bool,
+            3 => Type::Int(self.kind(&IntKind::ALL, "type")?),
+            4 => Type::Float(self.kind(&FLOAT_KINDS, "type")?),
+            5 => Type::Char,
+            6 => Type::String,
+            7 => Type::Array(Box::new(self.ty()?)),
+            8 => Type::List(Box::new(self.ty()?)),
+            9 => Type::Set(Box::new(self.ty()?)),
+            10 => Type::Table(Box::new(self.ty()?), Box::new(self.ty()?)),
+            11 => Type::Func(self.list(Self::ty)?, Box::new(self.ty()?)),
+            12 => Type::Optional(Box::new(self.ty()?)),
+            13 => Type::Named(self.string()?, self.list(Self::ty)?),
+            14 => Type::Var(self.len()?),
+            15 => Type::Unknown,
+            16 => Type::Coroutine(Box::new(self.ty()?)),
+            17 => Type::Range(Box::new(self.ty()?)),
+            _ => return malformed("type"),
+        })
+    }
+
+    fn value(&mut self) -> ReadResult<Value> {
+        Ok(match self.u8()? {
+            0 => Value::Nil,
+            1 => Value::Bool(self.bool()?),
+            2 => Value::Char(self.char()?),
+            3 => Value::Str(self.string()?.into()),
+            4 => Value::Flt32(f32::from_bits(self.u32()?)),
+            5 => Value::Flt64(f64::from_bits(self.u64()?)),
+            6 => {
+                let kind = self.kind(&IntKind::ALL, "constant")?;
+                let negative = self.bool()?;
+                let magnitude = self.u128()?;
+                return Value::from_int_parts(kind, negative, magnitude)
+                    .map_or_else(|| malformed("constant"), Ok);
+            }
+            _ => return malformed("constant"),
+        })
+    }
+
+    fn op(&mut self) -> ReadResult<Op> {
+        Ok(match self.u8()? {
+            0 => Op::Constant(self.u32()?),
+            1 => Op::Literal(self.u32()?),
+            2 => Op::Nil,
+            3 => Op::Pop,
+            4 => Op::Dup,
+            5 => Op::Swap,
+            6 => Op::GetLocal(self.u32()?),
+            7 => Op::SetLocal(self.u32()?),
+            8 => Op::NewCell(self.u32()?),
+            9 => Op::GetCell(self.u32()?),
+            10 => Op::SetCell(self.u32()?),
+            11 => Op::GetCapture(self.u32()?),
+            12 => Op::SetCapture(self.u32()?),
+            13 => Op::GetGlobal(self.u32()?),
+            14 => Op::AssignGlobal(self.u32()?),
+            15 => Op::SetGlobal(self.u32()?),
+            16 => Op::DefineGlobal(self.u32()?),
+            17 => Op::Check(self.u32()?),
+            18 => Op::Condition,
+            19 => Op::Unary(self.kind(&UNARY_OPS, "instruction")?),
+            20 => Op::Binary(self.kind(&BINARY_OPS, "instruction")?),
+            21 => Op::Cast(self.u32()?),
+            22 => Op::Array(self.u32()?),
+            23 => Op::Repeat,
+            24 => Op::Index,
+            25 => Op::IndexSlot,
+            26 => Op::StoreIndex,
+            27 => Op::Field(self.u32()?),
+            28 => Op::SafeField(self.u32()?, self.u32()?),
+            29 => Op::FieldSlot(self.u32()?),
+            30 => Op::Jump(self.u32()?),
+            31 => Op::JumpIfFalse(self.u32()?),
+            32 => Op::JumpIfTrue(self.u32()?),
+            33 => Op::JumpIfNotNil(self.u32()?),
+            34 => Op::Closure(self.u32()?),
+            35 => Op::Call(self.u32()?),
+            36 => Op::Return,
+            37 => Op::RangeStart(self.u32()?),
+            38 => Op::RangeNext(self.u32()?, self.u32()?),
+            39 => Op::IterStart(self.u32()?),
+            40 => Op::IterNext(self.u32()?, self.u32()?),
+            41 => Op::Match(self.u32()?, self.u32()?),
+            42 => Op::MatchValue(self.u32()?),
+            43 => Op::Fail(self.u32()?),
+            44 => Op::TailCall(self.u32()?),
+            45 => Op::StoreField(self.u32()?),
+            46 => Op::Copy,
+            47 => Op::Struct(self.u32()?),
+            48 => Op::Enum(self.u32()?),
+            49 => Op::Static(self.u32()?, self.u32()?),
+            50 => Op::StaticInit(self.u32()?, self.u32()?),
+            51 => Op::Payload(self.u32()?, self.u32()?),
+            52 => Op::Collection(self.u32()?),
+            53 => Op::Table(self.u32()?, self.u32()?),
+            54 => Op::EntriesStart(self.u32()?),
+            55 => Op::EntriesNext(self.u32()?, self.u32()?),
+            56 => Op::Assigned(self.u32()?),
+            57 => Op::Range(self.bool()?, self.bool()?),
+            58 => Op::ThroughNext(self.u32()?, self.u32()?),
+            59 => Op::TryStart(self.u32()?),
+            60 => Op::TryEnd,
+            61 => Op::Raise,
+            62 => Op::Unwrap,
+            63 => Op::Yield,
+            _ => return malformed("instruction"),
+        })
+    }
+
+    fn hint(&mut self) -> ReadResult<Hint> {
+        Ok(match self.u8()? {
+            0 => Hint::None,
+            1 => Hint::Type(self.ty()?),
+            2 => Hint::Numeric(self.u32()?, Box::new(self.hint()?)),
+            3 => Hint::Param(self.u32()?, self.u32()?),
+            4 => Hint::Global(self.u32()?),
+            5 => Hint::Elem(Box::new(self.hint()?)),
+            6 => Hint::Operands(self.kind(&BINARY_OPS, "hint")?, Box::new(self.hint()?)),
+            7 => Hint::Field(self.u32()?, self.u32()?),
+            8 => Hint::Key(Box::new(self.hint()?)),
+            9 => Hint::Index(self.u32()?),
+            10 => Hint::Entry(self.u32()?),
+            11 => Hint::Item(self.u32()?),
+            _ => return malformed("hint"),
+        })
+    }
+
+    fn literal(&mut self) -> ReadResult<Literal> {
+        let value = match self.u8()? {
+            0 => LiteralValue::Int {
+                negative: self.bool()?,
+                magnitude: self.u128()?,
+            },
+            1 => LiteralValue::Float(f64::from_bits(self.u64()?)),
+            _ => return malformed("literal"),
+        };
+        Ok(Literal {
+            value,
+            hint: self.hint()?,
+        })
+    }
+
+    fn global(&mut self) -> ReadResult<GlobalDecl> {
+        Ok(GlobalDecl {
+            global: self.u32()?,
+            ty: self.ty()?,
+            storage: match self.u8()? {
+                0 => None,
+                1 => Some(Storage::Global),
+                2 => Some(Storage::Const),
+                3 => Some(Storage::Static),
+                _ => return malformed("declaration"),
+            },
+            has_value: self.bool()?,
+        })
+    }
+
+    fn site(&mut self) -> ReadResult<CallSite> {
+        Ok(CallSite {
+            argc: self.u32()?,
+            arg_spans: self.list(Self::span)?,
+            format: self.bool()?,
+            named: self.list(Self::string)?,
+            type_args: self.list(Self::ty)?,
+        })
+    }
+
+    fn struct_def(&mut self) -> ReadResult<StructDef> {
+        let decl = StructDecl {
+            name: self.string()?,
+            params: self.list(Self::string)?,
+            fields: self.list(|reader| {
+                Ok(FieldDecl {
+                    ty: reader.ty()?,
+                    name: reader.string()?,
+                    default: None,
+                    span: reader.span()?,
+                })
+            })?,
+            methods: vec![],
+            span: self.span()?,
+        };
+        Ok(StructDef {
+            decl: Rc::new(decl),
+            init: self.u32()?,
+            methods: self.list(Self::u32)?,
+        })
+    }
+
+    fn enum_decl(&mut self) -> ReadResult<EnumDecl> {
+        Ok(EnumDecl {
+            name: self.string()?,
+            variants: self.list(|reader| {
+                Ok(Variant {
+                    name: reader.string()?,
+                    fields: reader.list(Self::ty)?,
+                    span: reader.span()?,
+                })
+            })?,
+        })
+    }
+
+    fn pattern(&mut self) -> ReadResult<PatternTest> {
+        let kind = match self.u8()? {
+            0 => PatternKind::Wildcard,
+            1 => PatternKind::Int(self.u128()? as i128),
+            2 => PatternKind::Float(f64::from_bits(self.u64()?)),
+            3 => PatternKind::Bool(self.bool()?),
+            4 => PatternKind::Char(self.char()?),
+            5 => PatternKind::Str(self.string()?),
+            6 => PatternKind::Nil,
+            7 => PatternKind::Name {
+                qualifier: self.option(Self::string)?,
+                name: self.string()?,
+                bindings: self.option(|reader| reader.list(Self::string))?,
+            },
+            8 => PatternKind::Range {
+                start: self.u128()? as i128,
+                end: self.u128()? as i128,
+                inclusive: self.bool()?,
+            },
+            _ => return malformed("pattern"),
+        };
+        let pattern = Pattern {
+            kind,
+            span: self.span()?,
+            constant: Cell::new(self.option(Self::u32)?.map(BindingId)),
+        };
+        Ok(PatternTest {
+            pattern,
+            global: self.option(Self::u32)?,
+        })
+    }
+}
+
+/* What a local slot holds, as far as the instructions that expect a cell or the
+ * state of a loop in it are concerned. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SlotKind {
+    Value,
+    Cell,
+    Loop,
+    /* The entries of a table and the position in them. */
+    Entries,
+    /* Different things on different paths. */
+    Mixed,
+}
+
+/* Checks that running `function` cannot index outside of it or its stack, nor find
+ * something other than a cell or a loop where it expects one. */
+/* The handler of each `try` being run and the height of the stack at its start,
+ * innermost last. */
+type Tries = Vec<(u32, usize)>;
+
+fn verify(function: &Function, globals: usize) -> ReadResult<()> {
+    let code = &function.code;
+    if function.slots < function.params.len() + usize::from(function.uses_self) {
+        return malformed("function");
+    }
+    /* The height of the stack above the local slots before each instruction, what
+     * the slots hold then, and the handler and height of each `try` being run. */
+    let mut states: Vec<Option<(usize, Vec<SlotKind>, Tries)>> = vec![None; code.len()];
+    let mut pending = vec![(0, 0, vec![SlotKind::Value; function.slots], vec![])];
+    while let Some((at, height, mut slots, mut tries)) = pending.pop() {
+        match states.get_mut(at) {
+            None => return malformed("jump"),
+            Some(state @ None) => *state = Some((height, slots.clone(), tries.clone())),
+            Some(Some((known, _, _))) if *known != height => return malformed("stack"),
+            Some(Some((_, _, known))) if *known != tries => return malformed("try"),
+            Some(Some((_, known, _))) => {
+                let joined = known
+                    .iter()
+                    .zip(&slots)
+                    .map(|(a, b)| if a == b { *a } else { SlotKind::Mixed })
+                    .collect::<Vec<_>>();
+                if joined == *known {
+                    continue;
+                }
+                known.clone_from(&joined);
+                slots = joined;
+            }
+        }
+        let op = code[at];
+        let (pops, pushes) = operands(function, op, globals, height)?;
+        let Some(rest) = height.checked_sub(pops) else {
+            return malformed("stack");
+        };
+        /* An error goes back to the height of the innermost `try`, so the stack
+         * cannot drop below it. */
+        if tries.last().is_some_and(|&(_, floor)| rest < floor) {
+            return malformed("stack");
+        }
+        let next = rest + pushes;
+        let holds = |slot: u32, kind| slots[slot as usize] == kind;
+        let captures_cells = |index: u32| {
+            function.functions[index as usize]
+                .captures
+                .iter()
+                .all(|capture| match capture {
+                    Capture::Local(slot) => holds(*slot, SlotKind::Cell),
+                    Capture::Capture(_) => true,
+                })
+        };
+        let expected = match op {
+            Op::GetCell(slot) | Op::SetCell(slot) => holds(slot, SlotKind::Cell),
+            Op::IterNext(slot, _) => holds(slot, SlotKind::Loop) && holds(slot + 1, SlotKind::Loop),
+            Op::EntriesNext(slot, _) => {
+                holds(slot, SlotKind::Entries) && holds(slot + 1, SlotKind::Entries)
+            }
+            Op::Closure(index) => captures_cells(index),
+            Op::Struct(index) => {
+                let def = &function.structs[index as usize];
+                captures_cells(def.init) && def.methods.iter().all(|&method| captures_cells(method))
+            }
+            _ => true,
+        };
+        if !expected {
+            return malformed("local slot");
+        }
+        /* The handler runs with the error on the stack, whether `op` fails before
+         * or after it changes the slots. */
+        let handler = |slots: &Vec<SlotKind>| {
+            let (&(target, floor), outer) = tries.split_last()?;
+            Some((target as usize, floor + 1, slots.clone(), outer.to_vec()))
+        };
+        pending.extend(handler(&slots));
+        match op {
+            Op::SetLocal(slot) => slots[slot as usize] = SlotKind::Value,
+            Op::NewCell(slot) | Op::Static(_, slot) => slots[slot as usize] = SlotKind::Cell,
+            Op::RangeStart(slot) => {
+                slots[slot as usize..][..2].fill(SlotKind::Value);
+            }
+            Op::IterStart(slot) => {
+                slots[slot as usize..][..2].fill(SlotKind::Loop);
+            }
+            Op::EntriesStart(slot) => {
+                slots[slot as usize..][..2].fill(SlotKind::Entries);
+            }
+            _ => (),
+        }
+        pending.extend(handler(&slots));
+        match op {
+            Op::TryStart(target) => tries.push((target, height)),
+            Op::TryEnd if tries.pop().is_none() => return malformed("try"),
+            _ => (),
+        }
+        match op {
+            Op::Jump(target) => pending.push((target as usize, next, slots, tries)),
+            Op::JumpIfFalse(target)
+            | Op::JumpIfTrue(target)
+            | Op::JumpIfNotNil(target)
+            | Op::SafeField(_, target)
+            | Op::StaticInit(_, target) => {
+                pending.push((target as usize, next, slots.clone(), tries.clone()));
+                pending.push((at + 1, next, slots, tries));
+            }
+            /* They push the next item, or jump without one. */
+            Op::RangeNext(_, target)
+            | Op::ThroughNext(_, target)
+            | Op::IterNext(_, target)
+            | Op::EntriesNext(_, target) => {
+                pending.push((target as usize, height, slots.clone(), tries.clone()));
+                pending.push((at + 1, next, slots, tries));
+            }
+            Op::Return | Op::Raise | Op::Fail(_) => (),
+            _ => pending.push((at + 1, next, slots, tries)),
+        }
+    }
+    Ok(())
+}
+
+/* How many values `op` pops and pushes, once its operands are found to be in range.
+ * `height` is that of the stack above the local slots. */
+fn operands(
+    function: &Function,
+    op: Op,
+    globals: usize,
+    height: usize,
+) -> ReadResult<(usize, usize)> {
+    let outside = |index: u32, len: usize| index as usize >= len;
+    let no_slot = |slot: u32| outside(slot, function.slots);
+    /* Loops keep their state in two slots. */
+    let no_pair = |slot: u32| outside(slot.saturating_add(1), function.slots);
+    /* A closure made of a function must find the variables it captures. */
+    let no_closure = |index: u32| match function.functions.get(index as usize) {
+        Some(inner) => inner.captures.iter().any(|capture| match capture {
+            Capture::Local(index) => no_slot(*index),
+            Capture::Capture(index) => outside(*index, function.captures.len()),
+        }),
+        None => true,
+    };
+    let bad = match op {
+        Op::Constant(index) => outside(index, function.constants.len()),
+        Op::Literal(index) => match function.literals.get(index as usize) {
+            Some(literal) => !hint_in_range(&literal.hint, function, globals, height),
+            None => true,
+        },
+        Op::Collection(index) | Op::Table(_, index) => match function.hints.get(index as usize) {
+            Some(hint) => !hint_in_range(hint, function, globals, height),
+            None => true,
+        },
+        Op::GetLocal(index)
+        | Op::SetLocal(index)
+        | Op::NewCell(index)
+        | Op::GetCell(index)
+        | Op::SetCell(index)
+        | Op::MatchValue(index)
+        | Op::Payload(index, _) => no_slot(index),
+        Op::GetCapture(index) | Op::SetCapture(index) => outside(index, function.captures.len()),
+        Op::GetGlobal(global) | Op::AssignGlobal(global) | Op::SetGlobal(global) => {
+            outside(global, globals)
+        }
+        Op::DefineGlobal(index) => function
+            .globals
+            .get(index as usize)
+            .is_none_or(|decl| outside(decl.global, globals)),
+        Op::Check(index) => outside(index, function.checks.len()),
+        Op::Yield => !function.coroutine,
+        Op::Cast(index) => outside(index, function.types.len()),
+        Op::Field(name)
+        | Op::SafeField(name, _)
+        | Op::FieldSlot(name)
+        | Op::StoreField(name)
+        | Op::Assigned(name)
+        | Op::Fail(name) => outside(name, function.names.len()),
+        Op::Closure(index) => no_closure(index),
+        Op::Struct(index) => match function.structs.get(index as usize) {
+            Some(def) => {
+                no_closure(def.init) || def.methods.iter().any(|&method| no_closure(method))
+            }
+            None => true,
+        },
+        Op::Enum(index) => outside(index, function.enums.len()),
+        Op::Static(index, slot) => outside(index, function.statics) || no_slot(slot),
+        Op::StaticInit(index, _) => outside(index, function.statics),
+        Op::Call(site) | Op::TailCall(site) => outside(site, function.sites.len()),
+        Op::RangeStart(index)
+        | Op::RangeNext(index, _)
+        | Op::ThroughNext(index, _)
+        | Op::IterStart(index)
+        | Op::IterNext(index, _)
+        | Op::EntriesStart(index)
+        | Op::EntriesNext(index, _) => no_pair(index),
+        Op::Match(index, subject) => match function.patterns.get(index as usize) {
+            Some(test) => {
+                no_slot(subject) || test.global.is_some_and(|global| outside(global, globals))
+            }
+            None => true,
+        },
+        _ => false,
+    };
+    if bad {
+        return malformed("operand");
+    }
+    Ok(match op {
+        Op::Constant(_)
+        | Op::Literal(_)
+        | Op::Nil
+        | Op::GetLocal(_)
+        | Op::GetCell(_)
+        | Op::GetCapture(_)
+        | Op::GetGlobal(_)
+        | Op::AssignGlobal(_)
+        | Op::Closure(_)
+        | Op::Struct(_)
+        | Op::Enum(_)
+        | Op::Payload(..)
+        | Op::RangeNext(..)
+        | Op::ThroughNext(..)
+        | Op::IterNext(..)
+        | Op::Match(..) => (0, 1),
+        Op::Pop
+        | Op::SetLocal(_)
+        | Op::SetCell(_)
+        | Op::SetCapture(_)
+        | Op::SetGlobal(_)
+        | Op::JumpIfFalse(_)
+        | Op::JumpIfTrue(_)
+        | Op::Return
+        | Op::Raise
+        | Op::IterStart(_)
+        | Op::EntriesStart(_) => (1, 0),
+        Op::EntriesNext(..) => (0, 2),
+        Op::Dup => (1, 2),
+        Op::Swap => (2, 2),
+        Op::IndexSlot => (2, 3),
+        Op::Binary(_) | Op::Repeat | Op::Index | Op::Range(_, false) => (2, 1),
+        Op::Range(_, true) => (3, 1),
+        Op::StoreIndex => (3, 0),
+        Op::FieldSlot(_) => (1, 2),
+        Op::StoreField(_) => (2, 0),
+        Op::RangeStart(_) => (2, 0),
+        Op::Array(len) => (len as usize, 1),
+        Op::Table(len, _) => (2 * len as usize, 1),
+        Op::DefineGlobal(index) => (usize::from(function.globals[index as usize].has_value), 0),
+        Op::Call(site) | Op::TailCall(site) => {
+            let site = &function.sites[site as usize];
+            (site.argc as usize + site.named.len() + 1, 1)
+        }
+        Op::Check(_)
+        | Op::Assigned(_)
+        | Op::Copy
+        | Op::Collection(_)
+        | Op::Condition
+        | Op::Unary(_)
+        | Op::Cast(_)
+        | Op::Field(_)
+        | Op::SafeField(..)
+        | Op::JumpIfNotNil(_)
+        | Op::Unwrap
+        | Op::Yield
+        | Op::MatchValue(_) => (1, 1),
+        Op::NewCell(_)
+        | Op::Jump(_)
+        | Op::Fail(_)
+        | Op::Static(..)
+        | Op::StaticInit(..)
+        | Op::TryStart(_)
+        | Op::TryEnd => (0, 0),
+    })
+}
+
+/* Whether the temporaries, names and globals a hint reads exist. */
+fn hint_in_range(hint: &Hint, function: &Function, globals: usize, height: usize) -> bool {
+    match hint {
+        Hint::None | Hint::Type(_) => true,
+        Hint::Numeric(slot, otherwise) => {
+            (*slot as usize) < height && hint_in_range(otherwise, function, globals, height)
+        }
+        Hint::Param(slot, _) | Hint::Index(slot) | Hint::Entry(slot) | Hint::Item(slot) => {
+            (*slot as usize) < height
+        }
+        Hint::Field(slot, name) => {
+            (*slot as usize) < height && (*name as usize) < function.names.len()
+        }
+        Hint::Global(global) => (*global as usize) < globals,
+        Hint::Elem(inner) | Hint::Key(inner) | Hint::Operands(_, inner) => {
+            hint_in_range(inner, function, globals, height)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::parser::parse;
+    use crate::vm::Vm;
+    use std::fs;
+    use std::path::Path;
+
+    fn build(source: &str) -> Bytecode {
+        compile(&parse(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_examples_round_trip() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "tool") {
+                continue;
+            }
+            let source = fs::read_to_string(&path).unwrap();
+            let bytecode = build(&source);
+            let loaded = read(&write(&bytecode, &source), Some(&source)).unwrap();
+            assert_eq!(loaded, bytecode, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn test_loaded_program_runs() {
+        let source = "func counter()\n\tint32 n = 0\n\tglobal c = func ()\n\t\tn += 1\n\t\treturn n\n\tend\nend\ncounter()\nc()\nconst LIMIT = 2\ngiven c() do\n\twhen LIMIT => print(\"at {LIMIT} \")\n\telse => print(\"other \")\nend\nprintln(1.5, -3)\nint8 x = 100\nx += 28";
+        let bytes = write(&build(source), source);
+        let mut vm = Vm::new();
+        vm.capture_output();
+        let error = vm.run(&read(&bytes, None).unwrap()).unwrap_err();
+        assert_eq!(vm.take_output(), "at 2 1.5 -3\n");
+        assert_eq!(
+            error.to_string(),
+            "Toolip:17:6: Runtime error: Integer overflow: `+` on int8 operands."
+        );
+    }
+
+    #[test]
+    fn test_loaded_types_run() {
+        let source = "struct Box\n\tint32 value\n\tfunc int32 next()\n\t\tstatic int32 n = 0\n\t\tn += 1\n\t\treturn self.value + n\n\tend\nend\nenum Shape Circle(flt64) Dot end\nBox b = Box(10)\nb.next()\ngiven Shape.Circle(2.0) do\n\twhen Circle(r) => println(b.next(), r)\n\telse => println(b)\nend";
+        let bytecode = build(source);
+        let loaded = read(&write(&bytecode, source), Some(source)).unwrap();
+        assert_eq!(loaded, bytecode);
+        let mut vm = Vm::new();
+        vm.capture_output();
+        vm.run(&loaded).unwrap();
+        assert_eq!(vm.take_output(), "12 2.0\n");
+    }
+
+    #[test]
+    fn test_stale_and_damaged_files() {
+        let source = "println(1)";
+        let bytes = write(&build(source), source);
+        assert_eq!(
+            read(&bytes, Some("println(2)")),
+            Err("The bytecode file is out of date with its source.".to_owned())
+        );
+        let mut old = bytes.clone();
+        old[MAGIC.len()] = old[MAGIC.len()].wrapping_add(1);
+        assert!(read(&old, None)
+            .unwrap_err()
+            .contains("was built by Toolip"));
+        assert_eq!(
+            read(b"println(1)", None),
+            Err("Not a Toolip bytecode file.".to_owned())
+        );
+        for len in MAGIC.len()..bytes.len() {
+            assert!(read(&bytes[..len], None).is_err());
+        }
+        let damaged = |op, at| {
+            let bytecode =
+                build("func f()\n\tint32 n = 1\n\treturn func ()\n\t\treturn n\n\tend\nend\nf()");
+            let mut script = (*bytecode.script.functions[0]).clone();
+            script.code[at] = op;
+            let mut program = (*bytecode.script).clone();
+            program.functions[0] = Rc::new(script);
+            let bytecode = Bytecode {
+                script: Rc::new(program),
+                ..bytecode
+            };
+            read(&write(&bytecode, ""), None).unwrap_err()
+        };
+        assert_eq!(
+            damaged(Op::Closure(1), 2),
+            "The bytecode file has a bad operand."
+        );
+        assert_eq!(
+            damaged(Op::GetLocal(7), 0),
+            "The bytecode file has a bad operand."
+        );
+        assert_eq!(
+            damaged(Op::Jump(99), 0),
+            "The bytecode file has a bad jump."
+        );
+        assert_eq!(damaged(Op::Pop, 0), "The bytecode file has a bad stack.");
+        assert_eq!(
+            damaged(Op::Yield, 1),
+            "The bytecode file has a bad operand."
+        );
+        assert_eq!(
+            damaged(Op::Nil, 0),
+            "The bytecode file has a bad local slot."
+        );
+    }
+}
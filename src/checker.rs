@@ -0,0 +1,2534 @@
+#![allow(dead_code)]
+use crate::assignment;
+use crate::ast::{
+    BinaryOp, BindingId, Block, EnumDecl, Expr, ExprKind, FuncDecl, Given, Pattern, PatternKind,
+    Program, Stmt, StmtKind, Storage, StructDecl, TypeAlias, UnaryOp, VarDecl,
+};
+use crate::diagnostic::Diagnostic;
+use crate::exhaustive::{self, Cover, Domain, Reason};
+use crate::fold::{self, Constant};
+use crate::format;
+use crate::ops;
+use crate::resolver;
+use crate::token::Span;
+use crate::types::{FloatKind, IntKind, Type};
+use crate::value::{Builtin, Value};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/* Static checks over a parsed program; nothing is executed.
+ *
+ * Every expression is given the type the evaluator would give its value, and a
+ * value that does not fit the declared type of a variable, parameter, field or
+ * return is reported. `<unknown>` (builtins, erased generics, `self.args`, ...)
+ * fits anything. */
+pub fn check(program: &Program) -> Vec<Diagnostic> {
+    check_with_globals(program, &[])
+}
+
+/* `globals` are the names defined before `program`, as by earlier REPL inputs.
+ * Names are resolved before assignments and types are checked. */
+pub fn check_with_globals(program: &Program, globals: &[String]) -> Vec<Diagnostic> {
+    let mut diagnostics = resolver::resolve(program, globals);
+    let mut checker = Checker::new();
+    checker.check_block(&program.stmts);
+    /* After the checker, which tells which `given`s are exhaustive. */
+    diagnostics.extend(assignment::check(program));
+    diagnostics.extend(checker.diagnostics);
+    diagnostics
+}
+
+const MAX_ALIAS_DEPTH: usize = 64;
+
+/* The scope of the top level of the program, after that of the checker itself. */
+const PROGRAM_SCOPE: usize = 1;
+
+/* What a type name refers to. */
+#[derive(Debug, Clone)]
+enum TypeDef {
+    Struct(Rc<StructDecl>),
+    Enum(Rc<EnumDecl>),
+    Alias(Rc<TypeAlias>),
+    /* A type parameter of the enclosing generic function or struct. */
+    Param,
+}
+
+#[derive(Debug, Default)]
+struct Scope {
+    vars: HashMap<String, Type>,
+    types: HashMap<String, TypeDef>,
+    /* The named functions declared in this scope, with their type parameters. */
+    funcs: HashMap<String, (Rc<FuncDecl>, Vec<String>)>,
+    /* Variables of optional types known not to be `nil` here, with their types
+     * without the `?`. */
+    narrowed: HashMap<BindingId, Type>,
+}
+
+/* What a call calls, for diagnostics. */
+enum Callee {
+    Func(String),
+    Struct(String),
+    Variant(String),
+    /* A function value whose declaration is not known statically. */
+    Value(String),
+}
+
+/* The parameter and return types of a call. The type parameters are bound from the
+ * context first and then from the types of the arguments. */
+struct Signature {
+    callee: Callee,
+    params: Vec<(String, Type)>,
+    ret: Type,
+    type_params: Vec<String>,
+    type_args: Vec<Type>,
+}
+
+impl Signature {
+    fn into_type(self) -> Type {
+        Type::Func(
+            self.params.into_iter().map(|(_, ty)| ty).collect(),
+            Box::new(self.ret),
+        )
+    }
+
+    fn describe_param(&self, index: usize) -> String {
+        let name = &self.params[index].0;
+        match &self.callee {
+            Callee::Func(func) => format!("parameter `{name}` of `{func}`"),
+            Callee::Struct(_) => format!("field `{name}`"),
+            Callee::Variant(variant) => format!("field {index} of `{variant}`"),
+            Callee::Value(func) => format!("argument {} of `{func}`", index + 1),
+        }
+    }
+
+    /* Structs without a `new` method may be constructed with fewer arguments than
+     * fields; the rest take their defaults. */
+    fn arity_error(&self, found: usize) -> Option<String> {
+        let expected = self.params.len();
+        match &self.callee {
+            Callee::Func(func) | Callee::Value(func) if found != expected => Some(format!(
+                "Function `{func}` expects {expected} argument(s), found {found}."
+            )),
+            Callee::Struct(name) if found > expected => Some(format!(
+                "Struct `{name}` has {expected} field(s), found {found} argument(s)."
+            )),
+            Callee::Variant(variant) if found != expected => Some(format!(
+                "Variant `{variant}` has {expected} field(s), found {found} argument(s)."
+            )),
+            _ => None,
+        }
+    }
+}
+
+/* How many `iter` methods a `for` loop follows to the one with `next`. */
+const MAX_ITER_DEPTH: usize = 8;
+
+struct Checker {
+    diagnostics: Vec<Diagnostic>,
+    scopes: Vec<Scope>,
+    enums: HashMap<String, Rc<EnumDecl>>,
+    /* Names and declared return types of the functions being checked, innermost last. */
+    returns: Vec<(String, Type)>,
+    /* Whether each of those functions is a coroutine. */
+    coroutines: Vec<bool>,
+    /* The `yield` a statement consists of, or assigns, which is the only place one
+     * may be. */
+    statement_yield: Option<*const Expr>,
+    /* What each `Type::Var` has been unified with so far. */
+    solutions: Vec<Option<Type>>,
+    /* The values of the constant expressions checked so far, and of the `const`s. */
+    folded: HashMap<*const Expr, Constant>,
+    consts: HashMap<BindingId, Constant>,
+    /* How many right sides of `and`, `or` and `??` that may not be evaluated the
+     * expression being checked is in. A constant operation there that would trap is
+     * left to the run time. */
+    unevaluated: usize,
+}
+
+impl Checker {
+    fn new() -> Self {
+        Self {
+            diagnostics: vec![],
+            scopes: vec![Scope::default()],
+            enums: HashMap::new(),
+            returns: vec![],
+            coroutines: vec![],
+            statement_yield: None,
+            solutions: vec![],
+            folded: HashMap::new(),
+            consts: HashMap::new(),
+            unevaluated: 0,
+        }
+    }
+
+    fn error(&mut self, span: Span, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic::error(span, message));
+    }
+
+    fn declare(&mut self, name: &str, ty: Type) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.funcs.remove(name);
+            scope.vars.insert(name.to_owned(), ty);
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Type> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.vars.get(name))
+    }
+
+    /* The function declaration `name` refers to, unless a variable shadows it. */
+    fn lookup_func(&self, name: &str) -> Option<&(Rc<FuncDecl>, Vec<String>)> {
+        let scope = self
+            .scopes
+            .iter()
+            .rev()
+            .find(|scope| scope.vars.contains_key(name))?;
+        scope.funcs.get(name)
+    }
+
+    /* The struct `name` refers to as a value, as in `Point(1.0, 2.0)`. */
+    fn lookup_struct(&self, name: &str) -> Option<Rc<StructDecl>> {
+        let scope = self
+            .scopes
+            .iter()
+            .rev()
+            .find(|scope| scope.vars.contains_key(name))?;
+        match scope.types.get(name) {
+            Some(TypeDef::Struct(decl)) => Some(Rc::clone(decl)),
+            _ => None,
+        }
+    }
+
+    /* The enum `expr` names, as `Color` in `Color.Red`. */
+    fn enum_named(&self, expr: &Expr) -> Option<Rc<EnumDecl>> {
+        match &expr.kind {
+            ExprKind::Ident(name, _) if self.lookup(name).is_none() => {
+                self.enums.get(name).cloned()
+            }
+            _ => None,
+        }
+    }
+
+    fn declare_type(&mut self, name: &str, def: TypeDef) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.types.insert(name.to_owned(), def);
+        }
+    }
+
+    fn lookup_type(&self, name: &str) -> Option<&TypeDef> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.types.get(name))
+    }
+
+    /* Narrowing */
+
+    fn narrowed(&self, id: BindingId) -> Option<&Type> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.narrowed.get(&id))
+    }
+
+    /* The variables known not to be `nil` here. */
+    fn known(&self) -> HashMap<BindingId, Type> {
+        self.scopes
+            .iter()
+            .flat_map(|scope| scope.narrowed.clone())
+            .collect()
+    }
+
+    /* Records that the variable `expr` names is not `nil` for the rest of the current
+     * scope, if it is of an optional type. */
+    fn narrow(&mut self, expr: &Expr) {
+        let ExprKind::Ident(name, binding) = &expr.kind else {
+            return;
+        };
+        let Some(id) = binding.get() else {
+            return;
+        };
+        if let Some(Type::Optional(inner)) = self.lookup(name).map(|ty| self.apply(ty)) {
+            if let Some(scope) = self.scopes.last_mut() {
+                scope.narrowed.insert(id, *inner);
+            }
+        }
+    }
+
+    /* Forgets everything known about the variable `id`, in every scope: it may have
+     * been set to `nil`. */
+    fn widen(&mut self, id: BindingId) {
+        for scope in &mut self.scopes {
+            scope.narrowed.remove(&id);
+        }
+    }
+
+    /* Updates what is known about the variable `name` after a value of type `actual`
+     * is stored in it. */
+    fn assigned(&mut self, name: &str, id: Option<BindingId>, actual: &Type) {
+        let Some(id) = id else {
+            return;
+        };
+        self.widen(id);
+        let never_nil = !matches!(
+            actual,
+            Type::Nil | Type::Optional(_) | Type::Unknown | Type::Var(_)
+        );
+        if let (true, Some(Type::Optional(inner))) = (never_nil, self.lookup(name).cloned()) {
+            if let Some(scope) = self.scopes.last_mut() {
+                scope.narrowed.insert(id, *inner);
+            }
+        }
+    }
+
+    /* Reports a value of an optional type used where `nil` is not allowed, and gives
+     * the type it has otherwise, so that the error is reported once. */
+    fn non_nil(&mut self, ty: Type, span: Span) -> Type {
+        let Type::Optional(inner) = ty else {
+            return ty;
+        };
+        self.error(
+            span,
+            format!(
+                "A value of type {inner}? may be nil here; compare it with `nil` or use `??` first."
+            ),
+        );
+        *inner
+    }
+
+    /* Expands aliases in `ty`, reporting unknown names and wrong numbers of type
+     * arguments at `span`. */
+    fn resolve(&mut self, ty: &Type, span: Span) -> Type {
+        let mut errors = vec![];
+        let resolved = self.resolve_inner(ty, &mut errors, 0);
+        for message in errors {
+            self.error(span, message);
+        }
+        resolved
+    }
+
+    fn resolve_inner(&self, ty: &Type, errors: &mut Vec<String>, depth: usize) -> Type {
+        ty.map_named(&mut |name, args| {
+            let def = self.lookup_type(name);
+            let params = match def {
+                Some(TypeDef::Struct(decl)) => decl.params.len(),
+                Some(TypeDef::Alias(alias)) => alias.params.len(),
+                Some(TypeDef::Enum(_) | TypeDef::Param) => 0,
+                None => {
+                    errors.push(format!("Unknown type `{name}`."));
+                    return Some(Type::Unknown);
+                }
+            };
+            if !args.is_empty() && args.len() != params {
+                errors.push(format!(
+                    "Type `{name}` takes {params} type argument(s), found {}.",
+                    args.len()
+                ));
+                return Some(Type::Unknown);
+            }
+            let Some(TypeDef::Alias(alias)) = def else {
+                return None;
+            };
+            if depth >= MAX_ALIAS_DEPTH {
+                errors.push(format!("Type alias `{name}` refers to itself."));
+                return Some(Type::Unknown);
+            }
+            let body = alias.ty.substitute(&alias.params, args);
+            Some(self.resolve_inner(&body, errors, depth + 1))
+        })
+    }
+
+    /* Resolves `ty` without reporting errors, keeping the type parameters `params`.
+     * Errors in declared types are reported where they are declared. */
+    fn resolve_generic(&mut self, ty: &Type, params: &[String]) -> Type {
+        self.scopes.push(Scope::default());
+        for param in params {
+            self.declare_type(param, TypeDef::Param);
+        }
+        let resolved = self.resolve_inner(ty, &mut vec![], 0);
+        self.scopes.pop();
+        resolved
+    }
+
+    /* Functions, structs, enums and type aliases may be used before the statement that
+     * declares them. Types come first so that function signatures can use them. */
+    fn hoist(&mut self, block: &Block) {
+        for stmt in block {
+            match &stmt.kind {
+                StmtKind::Enum(decl) => {
+                    self.declare_enum(decl);
+                    self.declare_type(&decl.name, TypeDef::Enum(Rc::clone(decl)));
+                }
+                StmtKind::Struct(decl) => {
+                    self.declare(&decl.name, Type::Named(decl.name.clone(), vec![]));
+                    self.declare_type(&decl.name, TypeDef::Struct(Rc::clone(decl)));
+                }
+                StmtKind::TypeAlias(alias) => {
+                    self.declare_type(&alias.name, TypeDef::Alias(Rc::clone(alias)));
+                }
+                _ => (),
+            }
+        }
+        for stmt in block {
+            let StmtKind::Func(func) = &stmt.kind else {
+                continue;
+            };
+            let Some(name) = &func.name else {
+                continue;
+            };
+            let params = func.type_params.clone();
+            let ty = self
+                .func_signature(func, params.clone(), Callee::Func(name.clone()))
+                .into_type();
+            self.declare(name, ty);
+            if let Some(scope) = self.scopes.last_mut() {
+                scope.funcs.insert(name.clone(), (Rc::clone(func), params));
+            }
+        }
+    }
+
+    fn declare_enum(&mut self, decl: &Rc<EnumDecl>) {
+        self.enums.insert(decl.name.clone(), Rc::clone(decl));
+    }
+
+    fn check_block(&mut self, block: &Block) {
+        self.scopes.push(Scope::default());
+        self.hoist(block);
+        for stmt in block {
+            self.check_stmt(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) {
+        self.statement_yield = match &stmt.kind {
+            StmtKind::Expr(value)
+            | StmtKind::Assign { value, .. }
+            | StmtKind::VarDecl(VarDecl {
+                value: Some(value), ..
+            }) if matches!(value.kind, ExprKind::Yield(_)) => Some(value as *const Expr),
+            _ => None,
+        };
+        match &stmt.kind {
+            StmtKind::VarDecl(decl)
+                if decl.storage == Some(Storage::Global)
+                    && self.scopes.len() > PROGRAM_SCOPE + 1 =>
+            {
+                self.check_global(decl, stmt.span);
+            }
+            StmtKind::VarDecl(decl) if decl.ty == Type::Unknown => {
+                let mut ty = Type::Unknown;
+                if let Some(value) = &decl.value {
+                    ty = self.check_expr(value, None);
+                    if matches!(ty, Type::Nil | Type::Void) {
+                        self.error(
+                            value.span,
+                            format!("Cannot infer the type of `{}` from {ty}.", decl.name),
+                        );
+                        ty = Type::Unknown;
+                    }
+                }
+                self.define_const(decl, &ty);
+                self.declare(&decl.name, ty);
+            }
+            StmtKind::VarDecl(decl) => {
+                let ty = self.resolve(&decl.ty, stmt.span);
+                let mut actual = Type::Nil;
+                if let Some(value) = &decl.value {
+                    actual = self.check_expr(value, Some(&ty));
+                    self.expect(&actual, &ty, value.span, || {
+                        format!("variable `{}`", decl.name)
+                    });
+                }
+                self.define_const(decl, &ty);
+                self.declare(&decl.name, ty);
+                self.assigned(&decl.name, decl.binding.get(), &actual);
+            }
+            StmtKind::Func(func) => {
+                self.check_func(func, Type::Unknown);
+            }
+            StmtKind::Struct(decl) => self.check_struct(decl),
+            StmtKind::Enum(decl) => {
+                for variant in &decl.variants {
+                    for field in &variant.fields {
+                        self.resolve(field, variant.span);
+                    }
+                }
+            }
+            StmtKind::TypeAlias(alias) => {
+                self.scopes.push(Scope::default());
+                for param in &alias.params {
+                    self.declare_type(param, TypeDef::Param);
+                }
+                self.resolve(&alias.ty, alias.span);
+                self.scopes.pop();
+            }
+            StmtKind::Break(_) | StmtKind::Continue(_) => (),
+            StmtKind::Expr(expr) => {
+                self.check_expr(expr, None);
+            }
+            StmtKind::Return(value) => self.check_return(value.as_ref(), stmt.span),
+            StmtKind::Assign { target, op, value } => self.check_assign(target, *op, value),
+            StmtKind::If {
+                branches,
+                else_block,
+            } => self.check_if(branches, else_block.as_ref()),
+            StmtKind::Given(given) => self.check_given(given, stmt.span),
+            StmtKind::For {
+                var_ty,
+                var,
+                value_var,
+                iter,
+                body,
+                ..
+            } => {
+                let declared = var_ty.as_ref().map(|ty| self.resolve(ty, stmt.span));
+                let (iterated, item) = self.check_iter(iter, declared.as_ref());
+                let ty = self.loop_var(var, declared, item, iter.span);
+                let value = value_var.as_ref().map(|(value_ty, name)| {
+                    let declared = value_ty.as_ref().map(|ty| self.resolve(ty, stmt.span));
+                    let item = self.entry_value(&iterated, iter.span);
+                    (name, self.loop_var(name, declared, item, iter.span))
+                });
+                self.forget_assigned(body);
+                self.scopes.push(Scope::default());
+                self.declare(var, ty);
+                if let Some((name, ty)) = value {
+                    self.declare(name, ty);
+                }
+                self.check_block(body);
+                self.scopes.pop();
+            }
+            StmtKind::While { cond, body, .. } => {
+                self.forget_assigned(body);
+                self.check_condition(cond);
+                self.scopes.push(Scope::default());
+                self.narrow_when(cond, true);
+                self.check_block(body);
+                self.scopes.pop();
+            }
+            StmtKind::Loop { body, .. } => {
+                self.forget_assigned(body);
+                self.check_block(body);
+            }
+            StmtKind::Try { body, var, handler } => {
+                self.check_block(body);
+                /* The handler may run after any statement of the body. */
+                self.forget_assigned(body);
+                self.scopes.push(Scope::default());
+                if let Some(var) = var {
+                    self.declare(var, Type::Unknown);
+                }
+                self.check_block(handler);
+                self.scopes.pop();
+            }
+            StmtKind::Raise(value) => {
+                let ty = self.check_expr(value, None);
+                if !matches!(self.apply(&ty), Type::String | Type::Unknown | Type::Var(_)) {
+                    self.error(
+                        value.span,
+                        format!("Only errors and strings can be raised, found {ty}."),
+                    );
+                }
+            }
+        }
+    }
+
+    /* Each branch knows the conditions before it to be false, and its own to be true.
+     * After the `if`, a variable is known not to be `nil` when it is at the end of
+     * every path that gets there, as after `if x == nil then return end`. */
+    fn check_if(&mut self, branches: &[(Expr, Block)], else_block: Option<&Block>) {
+        self.scopes.push(Scope::default());
+        let mut ends = vec![];
+        for (cond, body) in branches {
+            self.check_condition(cond);
+            self.scopes.push(Scope::default());
+            self.narrow_when(cond, true);
+            ends.push(self.check_branch(body));
+            self.scopes.pop();
+            self.narrow_when(cond, false);
+        }
+        ends.push(match else_block {
+            Some(body) => self.check_branch(body),
+            None => Some(self.known()),
+        });
+        self.scopes.pop();
+        let mut ends = ends.into_iter().flatten();
+        if let Some(first) = ends.next() {
+            let joined = ends.fold(first, |joined, end| {
+                joined
+                    .into_iter()
+                    .filter(|(id, _)| end.contains_key(id))
+                    .collect()
+            });
+            if let Some(scope) = self.scopes.last_mut() {
+                scope.narrowed.extend(joined);
+            }
+        }
+    }
+
+    /* Checks a branch and gives what is known at its end, or `None` if it never gets
+     * there. */
+    fn check_branch(&mut self, block: &Block) -> Option<HashMap<BindingId, Type>> {
+        self.scopes.push(Scope::default());
+        self.hoist(block);
+        for stmt in block {
+            self.check_stmt(stmt);
+        }
+        let known = (!always_exits(block)).then(|| self.known());
+        self.scopes.pop();
+        known
+    }
+
+    /* Narrows the variables that `cond` evaluating to `value` shows not to be `nil`,
+     * as `x` in `x != nil`, `x != nil and y != nil` or `not (x == nil)`. */
+    fn narrow_when(&mut self, cond: &Expr, value: bool) {
+        match &cond.kind {
+            ExprKind::Binary(op @ (BinaryOp::Eq | BinaryOp::NotEq), lhs, rhs)
+                if value == (*op == BinaryOp::NotEq) =>
+            {
+                match (&lhs.kind, &rhs.kind) {
+                    (_, ExprKind::Nil) => self.narrow(lhs),
+                    (ExprKind::Nil, _) => self.narrow(rhs),
+                    _ => (),
+                }
+            }
+            ExprKind::Binary(op @ (BinaryOp::And | BinaryOp::Or), lhs, rhs)
+                if value == (*op == BinaryOp::And) =>
+            {
+                self.narrow_when(lhs, value);
+                self.narrow_when(rhs, value);
+            }
+            ExprKind::Unary(UnaryOp::Not, operand) => self.narrow_when(operand, !value),
+            _ => (),
+        }
+    }
+
+    /* A loop body may run after itself, so what is known before the loop about the
+     * variables it assigns does not hold in it. */
+    fn forget_assigned(&mut self, body: &Block) {
+        let mut ids = vec![];
+        assigned_bindings(body, &mut ids);
+        for id in ids {
+            self.widen(id);
+        }
+    }
+
+    /* `global` below the top level declares a variable at the top level of the
+     * program, or assigns one declared there already. */
+    fn check_global(&mut self, decl: &VarDecl, span: Span) {
+        let declared = match &decl.ty {
+            Type::Unknown => self.scopes[PROGRAM_SCOPE].vars.get(&decl.name).cloned(),
+            ty => Some(self.resolve(ty, span)),
+        };
+        let ty = match (&decl.value, declared) {
+            (Some(value), Some(ty)) => {
+                let actual = self.check_expr(value, Some(&ty));
+                self.expect(&actual, &ty, value.span, || {
+                    format!("variable `{}`", decl.name)
+                });
+                ty
+            }
+            (Some(value), None) => self.check_expr(value, None),
+            (None, declared) => declared.unwrap_or(Type::Unknown),
+        };
+        self.scopes[PROGRAM_SCOPE]
+            .vars
+            .insert(decl.name.clone(), ty);
+    }
+
+    /* Records the value of a `const`, converted to its declared type. */
+    fn define_const(&mut self, decl: &VarDecl, ty: &Type) {
+        let (Some(Storage::Const), Some(id), Some(value)) =
+            (decl.storage, decl.binding.get(), decl.value.as_ref())
+        else {
+            return;
+        };
+        let Some(mut constant) = self.folded.get(&(value as *const Expr)).cloned() else {
+            return;
+        };
+        if let Constant::Value(value) = &constant {
+            if let Some(widened) = ops::widen(value, ty.non_optional()) {
+                constant = Constant::Value(widened);
+            }
+        }
+        self.consts.insert(id, constant);
+    }
+
+    /* Checks a function and returns its type. `self` is the receiver in a method and
+     * the call itself elsewhere. The untyped parameters and the return type of an
+     * anonymous function are type variables, solved by the body and by the calls. */
+    fn check_func(&mut self, func: &FuncDecl, self_ty: Type) -> Type {
+        self.scopes.push(Scope::default());
+        let type_params = &func.type_params;
+        for param in type_params {
+            self.declare_type(param, TypeDef::Param);
+        }
+        self.declare("self", self_ty);
+        let mut params = Vec::with_capacity(func.params.len());
+        for param in &func.params {
+            let ty = match param.ty {
+                Type::Unknown => self.fresh_var(),
+                _ => self.resolve(&param.ty, param.span),
+            };
+            self.declare(&param.name, ty.clone());
+            params.push(ty);
+        }
+        let ret = match func.ret {
+            Type::Unknown => self.fresh_var(),
+            _ => self.resolve(&func.ret, func.span),
+        };
+        let name = func
+            .name
+            .clone()
+            .unwrap_or_else(|| "<anonymous>".to_owned());
+        /* The function may be called once the variables it captures are `nil` again. */
+        let known = self
+            .scopes
+            .iter_mut()
+            .map(|scope| std::mem::take(&mut scope.narrowed))
+            .collect::<Vec<_>>();
+        self.returns.push((name, ret.clone()));
+        self.coroutines.push(func.coroutine);
+        self.check_block(&func.body);
+        self.coroutines.pop();
+        self.returns.pop();
+        for (scope, known) in self.scopes.iter_mut().zip(known) {
+            scope.narrowed = known;
+        }
+        self.scopes.pop();
+        let ret = match func.coroutine {
+            true => Type::Coroutine(Box::new(ret)),
+            false => ret,
+        };
+        let ty = self.apply(&Type::Func(params, Box::new(ret)));
+        ty.substitute(type_params, &[])
+    }
+
+    /* Inside a struct, `this` names the struct itself. */
+    fn check_struct(&mut self, decl: &Rc<StructDecl>) {
+        self.scopes.push(Scope::default());
+        for param in &decl.params {
+            self.declare_type(param, TypeDef::Param);
+        }
+        self.declare("this", Type::Named(decl.name.clone(), vec![]));
+        self.declare_type("this", TypeDef::Struct(Rc::clone(decl)));
+        for field in &decl.fields {
+            let ty = self.resolve(&field.ty, field.span);
+            if let Some(default) = &field.default {
+                let actual = self.check_expr(default, Some(&ty));
+                self.expect(&actual, &ty, default.span, || {
+                    format!("field `{}`", field.name)
+                });
+            }
+        }
+        let receiver = Type::Named(
+            decl.name.clone(),
+            decl.params
+                .iter()
+                .map(|param| Type::Named(param.clone(), vec![]))
+                .collect(),
+        );
+        for method in &decl.methods {
+            self.check_func(method, receiver.clone());
+        }
+        self.scopes.pop();
+    }
+
+    fn check_return(&mut self, value: Option<&Expr>, span: Span) {
+        let Some((name, ret)) = self.returns.last().cloned() else {
+            if let Some(value) = value {
+                self.check_expr(value, None);
+            }
+            return;
+        };
+        match value {
+            Some(value) => {
+                let actual = self.check_expr(value, Some(&ret));
+                self.expect(&actual, &ret, value.span, || {
+                    format!("the return value of `{name}`")
+                });
+            }
+            /* A coroutine may finish without a last value. */
+            None if self.coroutines.last() == Some(&true) => (),
+            None => match self.apply(&ret) {
+                Type::Void | Type::Unknown => (),
+                Type::Var(_) => {
+                    self.assignable(&Type::Void, &ret);
+                }
+                ret => self.error(
+                    span,
+                    format!("`return` without a value in `{name}`, which returns {ret}."),
+                ),
+            },
+        }
+    }
+
+    /* A variable of an optional type may be set to `nil` even where it is known not
+     * to be, and is known not to be after a value that cannot be `nil` is stored. */
+    fn check_assign(&mut self, target: &Expr, op: Option<BinaryOp>, value: &Expr) {
+        let current = self.check_expr(target, None);
+        let declared = match &target.kind {
+            ExprKind::Ident(name, _) => self.lookup(name).cloned().unwrap_or(Type::Unknown),
+            _ => current.clone(),
+        };
+        let actual = match op {
+            None => self.check_expr(value, Some(&declared)),
+            Some(op) => {
+                let hint = current.non_optional();
+                let hint = hint.is_numeric().then_some(hint);
+                let value_ty = self.check_expr(value, hint);
+                self.binary_type(op, &current, &value_ty, value.span)
+            }
+        };
+        self.expect(&actual, &declared, value.span, || match &target.kind {
+            ExprKind::Ident(name, _) => format!("variable `{name}`"),
+            ExprKind::Field(_, name) => format!("field `{name}`"),
+            _ => "an element".to_owned(),
+        });
+        if let ExprKind::Ident(name, binding) = &target.kind {
+            self.assigned(name, binding.get(), &actual);
+        }
+    }
+
+    fn check_condition(&mut self, cond: &Expr) {
+        let ty = self.check_expr(cond, Some(&Type::Bool));
+        let fits = match ty {
+            Type::Var(_) => self.assignable(&ty, &Type::Bool),
+            Type::Bool | Type::Unknown => true,
+            _ => false,
+        };
+        if !fits {
+            self.error(cond.span, format!("Condition must be a bool, found {ty}."));
+        }
+    }
+
+    /* The type of `iter` and of the items a `for` loop over it visits. */
+    fn check_iter(&mut self, iter: &Expr, expected: Option<&Type>) -> (Type, Type) {
+        let ExprKind::Range {
+            start, end, step, ..
+        } = &iter.kind
+        else {
+            let ty = self.check_expr(iter, None);
+            let ty = self.non_nil(ty, iter.span);
+            return (ty.clone(), self.item_type(ty, iter.span, 0));
+        };
+        let item = self.check_range(start, end, step.as_deref(), expected, iter.span);
+        (Type::Range(Box::new(item.clone())), item)
+    }
+
+    /* The type of a loop variable: the type of the items it takes, or the declared
+     * one they must have. */
+    fn loop_var(&mut self, var: &str, declared: Option<Type>, item: Type, span: Span) -> Type {
+        match declared {
+            Some(ty) => {
+                self.expect(&item, &ty, span, || format!("loop variable `{var}`"));
+                ty
+            }
+            None => item,
+        }
+    }
+
+    /* The type of the values `for key, value in iter` visits, which only a table has. */
+    fn entry_value(&mut self, iterated: &Type, span: Span) -> Type {
+        match self.apply(iterated) {
+            Type::Table(_, value) => *value,
+            Type::Unknown | Type::Var(_) => Type::Unknown,
+            ty => {
+                self.error(
+                    span,
+                    format!("Only a table has a key and a value to iterate over, found {ty}."),
+                );
+                Type::Unknown
+            }
+        }
+    }
+
+    /* The type of the integers of a range, `expected` if the bounds are literals. */
+    fn check_range(
+        &mut self,
+        start: &Expr,
+        end: &Expr,
+        step: Option<&Expr>,
+        expected: Option<&Type>,
+        span: Span,
+    ) -> Type {
+        let (start_ty, end_ty) = self.check_operands(start, end, expected);
+        if let Some(step) = step {
+            let hint = Some(&start_ty).filter(|ty| ty.is_int());
+            let step_ty = self.check_expr(step, hint.or(expected));
+            if hint.is_some() && !matches!(step_ty, Type::Unknown | Type::Var(_)) {
+                self.expect(&step_ty, &start_ty, step.span, || {
+                    "the step of a range".to_owned()
+                });
+            }
+        }
+        let known = [&start_ty, &end_ty]
+            .into_iter()
+            .filter(|ty| !matches!(ty, Type::Unknown | Type::Var(_)))
+            .collect::<Vec<_>>();
+        if known.iter().any(|ty| !ty.is_int()) || known.windows(2).any(|pair| pair[0] != pair[1]) {
+            self.error(
+                span,
+                format!(
+                    "Range bounds must be integers of the same type, found {start_ty} and {end_ty}."
+                ),
+            );
+            return Type::Unknown;
+        }
+        known.first().map_or(Type::Unknown, |ty| (*ty).clone())
+    }
+
+    /* The items of a value of type `ty`. A struct gives them by its `next` method,
+     * which returns `nil` after the last, or else by the value its `iter` method
+     * returns. */
+    fn item_type(&mut self, ty: Type, span: Span, depth: usize) -> Type {
+        match ty {
+            Type::String => return Type::Char,
+            Type::Table(key, _) => return *key,
+            Type::Array(elem)
+            | Type::List(elem)
+            | Type::Set(elem)
+            | Type::Coroutine(elem)
+            | Type::Range(elem) => return *elem,
+            Type::Unknown | Type::Var(_) => return Type::Unknown,
+            Type::Named(ref name, ref args) if depth < MAX_ITER_DEPTH => {
+                if let Some(TypeDef::Struct(decl)) = self.lookup_type(name).cloned() {
+                    let method = decl.method("next").or_else(|| decl.method("iter"));
+                    if let Some(method) = method.filter(|method| method.params.is_empty()) {
+                        let method = Rc::clone(method);
+                        let sig = self.method_signature(&decl, &method, args);
+                        let ret = sig.ret.substitute(&sig.type_params, &sig.type_args);
+                        let ret = self.apply(&ret);
+                        return match method.name.as_deref() {
+                            Some("next") => ret.non_optional().clone(),
+                            _ => self.item_type(ret, span, depth + 1),
+                        };
+                    }
+                }
+            }
+            _ => (),
+        }
+        self.error(span, format!("Cannot iterate over {ty}."));
+        Type::Unknown
+    }
+
+    /* Reports a value of type `actual` where one of type `expected` is required. */
+    fn expect(
+        &mut self,
+        actual: &Type,
+        expected: &Type,
+        span: Span,
+        what: impl FnOnce() -> String,
+    ) {
+        if !self.assignable(actual, expected) {
+            let (actual, expected) = (self.apply(actual), self.apply(expected));
+            self.error(
+                span,
+                format!("Expected {expected} for {}, found {actual}.", what()),
+            );
+        }
+    }
+
+    /* Whether a value of type `actual` fits where `expected` is required. Type
+     * variables on either side are solved so that it does, if they can be. */
+    fn assignable(&mut self, actual: &Type, expected: &Type) -> bool {
+        let (actual, expected) = (self.apply(actual), self.apply(expected));
+        match (&actual, &expected) {
+            (Type::Var(a), Type::Var(e)) if a == e => true,
+            (Type::Var(var), ty) | (ty, Type::Var(var)) => self.bind(*var, ty),
+            (Type::Unknown, _)
+            | (_, Type::Unknown)
+            | (Type::Nil, Type::Void | Type::Optional(_)) => true,
+            (Type::Optional(a), Type::Optional(e)) => self.assignable(a, e),
+            (_, Type::Optional(e)) => self.assignable(&actual, e),
+            (Type::Array(a), Type::Array(e))
+            | (Type::List(a), Type::List(e))
+            | (Type::Set(a), Type::Set(e))
+            | (Type::Coroutine(a), Type::Coroutine(e))
+            | (Type::Range(a), Type::Range(e)) => self.assignable(a, e),
+            (Type::Table(ak, av), Type::Table(ek, ev)) => {
+                self.assignable(ak, ek) && self.assignable(av, ev)
+            }
+            (Type::Func(ap, ar), Type::Func(ep, er)) => {
+                ap.len() == ep.len()
+                    && ap.iter().zip(ep).all(|(a, e)| self.assignable(e, a))
+                    && self.assignable(ar, er)
+            }
+            /* `int32 f` may also name a function that returns an `int32`, or one whose
+             * return type is inferred. */
+            (Type::Func(_, ret), _) if expected != Type::Void => self.assignable(ret, &expected),
+            (Type::Named(a, a_args), Type::Named(e, e_args)) => {
+                a == e
+                    && (a_args.is_empty()
+                        || e_args.is_empty()
+                        || a_args
+                            .iter()
+                            .zip(e_args)
+                            .all(|(a, e)| self.assignable(a, e)))
+            }
+            _ if actual.widens_to(&expected) => true,
+            _ => actual == expected,
+        }
+    }
+
+    /* Type variables */
+
+    fn fresh_var(&mut self) -> Type {
+        self.solutions.push(None);
+        Type::Var(self.solutions.len() - 1)
+    }
+
+    /* `ty` with the type variables solved so far replaced by their solutions. */
+    fn apply(&self, ty: &Type) -> Type {
+        ty.map_vars(&mut |var| {
+            self.solutions[var]
+                .as_ref()
+                .map(|solution| self.apply(solution))
+        })
+    }
+
+    /* Solves the unsolved variable `var` as `ty`. Nothing is learnt from an unknown
+     * type, and a type cannot contain itself. */
+    fn bind(&mut self, var: usize, ty: &Type) -> bool {
+        if *ty == Type::Unknown {
+            return true;
+        }
+        if ty.contains_var(var) {
+            return false;
+        }
+        self.solutions[var] = Some(ty.clone());
+        true
+    }
+
+    /* Expressions */
+
+    /* The type of `expr`, reporting the errors in it. `expected` is the type the
+     * context asks for, which untyped literals take as they do when evaluated; of an
+     * optional type, only the values other than `nil` matter for it. */
+    fn check_expr(&mut self, expr: &Expr, expected: Option<&Type>) -> Type {
+        let expected = expected.map(|ty| self.apply(ty).non_optional().clone());
+        let ty = self.expr_type(expr, expected.as_ref());
+        let ty = self.apply(&ty);
+        self.fold(expr, &ty);
+        ty
+    }
+
+    /* Folds `expr` when its operands have been, reporting what would trap at run
+     * time. An expression whose type did not check is left alone. */
+    fn fold(&mut self, expr: &Expr, ty: &Type) {
+        if matches!(ty, Type::Unknown | Type::Var(_)) {
+            return;
+        }
+        let folded = |operand: &Expr| self.folded.get(&(operand as *const Expr));
+        let result = match &expr.kind {
+            ExprKind::Int(magnitude) => Ok(fold::int_literal(false, *magnitude, ty)),
+            ExprKind::Float(float) => Ok(fold::float_literal(*float, ty)),
+            ExprKind::Unary(UnaryOp::Neg, operand) => match operand.kind {
+                ExprKind::Int(magnitude) => Ok(fold::int_literal(true, magnitude, ty)),
+                ExprKind::Float(float) => Ok(fold::float_literal(-float, ty)),
+                _ => folded(operand).map_or(Ok(None), |operand| fold::unary(UnaryOp::Neg, operand)),
+            },
+            ExprKind::Bool(value) => Ok(Some(Constant::Value(Value::Bool(*value)))),
+            ExprKind::Char(ch) => Ok(Some(Constant::Value(Value::Char(*ch)))),
+            ExprKind::Str(string) => Ok(Some(Constant::Value(Value::Str(string.as_str().into())))),
+            ExprKind::Nil => Ok(Some(Constant::Value(Value::Nil))),
+            ExprKind::Ident(_, binding) => {
+                Ok(binding.get().and_then(|id| self.consts.get(&id)).cloned())
+            }
+            ExprKind::Array(items) if !matches!(ty, Type::Set(_)) => Ok(items
+                .iter()
+                .all(|item| folded(item).is_some())
+                .then_some(Constant::Array(items.len()))),
+            ExprKind::Repeat(item, count) if !matches!(ty, Type::Set(_)) => Ok(folded(item)
+                .zip(folded(count))
+                .and_then(|(_, count)| match count {
+                    Constant::Value(count) => match count.int_parts()? {
+                        (false, count) => usize::try_from(count).ok().map(Constant::Array),
+                        _ => None,
+                    },
+                    Constant::Array(_) => None,
+                })),
+            ExprKind::Unary(op, operand) => {
+                folded(operand).map_or(Ok(None), |operand| fold::unary(*op, operand))
+            }
+            ExprKind::Binary(op, lhs, rhs) => match (folded(lhs), folded(rhs)) {
+                (Some(lhs), Some(rhs)) => fold::binary(*op, lhs, rhs),
+                _ => Ok(None),
+            },
+            ExprKind::Cast(ty, operand) => {
+                folded(operand).map_or(Ok(None), |operand| fold::cast(operand, ty))
+            }
+            _ => Ok(None),
+        };
+        match result {
+            Ok(Some(constant)) => {
+                self.folded.insert(expr, constant);
+            }
+            Ok(None) => (),
+            Err(_) if self.unevaluated > 0 => (),
+            Err(message) => self.error(expr.span, message),
+        }
+    }
+
+    fn expr_type(&mut self, expr: &Expr, expected: Option<&Type>) -> Type {
+        let span = expr.span;
+        match &expr.kind {
+            ExprKind::Int(magnitude) => self.int_literal(false, *magnitude, expected, span),
+            ExprKind::Float(_) => Self::float_literal(expected),
+            ExprKind::Bool(_) => Type::Bool,
+            ExprKind::Char(_) => Type::Char,
+            ExprKind::Str(_) => Type::String,
+            ExprKind::Nil => Type::Nil,
+            ExprKind::Ident(name, binding) => binding
+                .get()
+                .and_then(|id| self.narrowed(id))
+                .or_else(|| self.lookup(name))
+                .cloned()
+                .unwrap_or(Type::Unknown),
+            ExprKind::SelF => self.lookup("self").cloned().unwrap_or(Type::Unknown),
+            ExprKind::This => self.lookup("this").cloned().unwrap_or(Type::Unknown),
+            ExprKind::Array(items) => self.check_array(items, expected),
+            ExprKind::Table(entries) => self.check_table(entries, expected),
+            ExprKind::Repeat(item, count) => self.check_repeat(item, count, expected),
+            ExprKind::Unary(op, operand) => self.check_unary(*op, operand, expected, span),
+            ExprKind::Binary(op, lhs, rhs) => self.check_binary(*op, lhs, rhs, expected, span),
+            ExprKind::Range {
+                start, end, step, ..
+            } => {
+                let expected = match expected {
+                    Some(Type::Range(elem)) => Some(&**elem),
+                    _ => None,
+                };
+                let item = self.check_range(start, end, step.as_deref(), expected, span);
+                Type::Range(Box::new(item))
+            }
+            ExprKind::Call(callee, args) => self.check_call(callee, args, expected, span),
+            ExprKind::Index(target, index) => self.check_index(target, index),
+            ExprKind::Field(target, name) => match self.enum_named(target) {
+                Some(decl) => self.variant_type(&decl, name, span),
+                None => {
+                    let target_ty = self.check_expr(target, None);
+                    let target_ty = self.non_nil(target_ty, target.span);
+                    self.field_type(&target_ty, name, span)
+                }
+            },
+            ExprKind::SafeField(target, name) => {
+                let target = self.check_expr(target, None);
+                let ty = self.field_type(target.non_optional(), name, span);
+                match target {
+                    Type::Optional(_) => ty.optional(),
+                    _ => ty,
+                }
+            }
+            ExprKind::Func(func) => self.check_func(func, Type::Unknown),
+            ExprKind::Try(value) => self.check_expr(value, expected),
+            ExprKind::Yield(value) => self.check_yield(expr, value.as_deref()),
+            ExprKind::Cast(ty, value) => {
+                let from = self.check_expr(value, None);
+                let valid = matches!(
+                    (&from, ty),
+                    (Type::Unknown | Type::Var(_), _)
+                        | (
+                            Type::Int(_) | Type::Float(_) | Type::Bool | Type::Char,
+                            Type::Int(_)
+                        )
+                        | (Type::Int(_) | Type::Float(_), Type::Float(_))
+                        | (Type::Int(_) | Type::Char, Type::Char)
+                        | (Type::Bool, Type::Bool)
+                );
+                if !valid {
+                    self.error(span, format!("Cannot convert {from} to {ty}."));
+                }
+                ty.clone()
+            }
+        }
+    }
+
+    /* A `yield` gives the value the coroutine is next resumed with, which may be of
+     * any type. */
+    fn check_yield(&mut self, expr: &Expr, value: Option<&Expr>) -> Type {
+        let at_statement = self.statement_yield.take() == Some(expr as *const Expr);
+        if self.coroutines.last() != Some(&true) {
+            self.error(expr.span, "`yield` outside of a coroutine.");
+        } else if !at_statement {
+            self.error(
+                expr.span,
+                "`yield` can only be a statement, or the value of an assignment or declaration.",
+            );
+        }
+        if let Some(value) = value {
+            let (name, ret) = self.returns.last().cloned().unwrap_or_default();
+            let actual = self.check_expr(value, Some(&ret));
+            if self.coroutines.last() == Some(&true) {
+                self.expect(&actual, &ret, value.span, || {
+                    format!("the value yielded by `{name}`")
+                });
+            }
+        }
+        Type::Unknown
+    }
+
+    /* Integer literals take the type they are used as, defaulting to `int32`. */
+    fn int_literal(
+        &mut self,
+        negative: bool,
+        magnitude: u128,
+        expected: Option<&Type>,
+        span: Span,
+    ) -> Type {
+        let kind = match expected {
+            Some(Type::Int(kind)) => *kind,
+            Some(Type::Float(kind)) => return Type::Float(*kind),
+            _ => [IntKind::I32, IntKind::I64, IntKind::I128]
+                .into_iter()
+                .find(|kind| kind.fits_literal(negative, magnitude))
+                .unwrap_or(IntKind::U128),
+        };
+        if !kind.fits_literal(negative, magnitude) {
+            self.error(
+                span,
+                format!(
+                    "Integer literal {}{magnitude} does not fit in {}.",
+                    if negative { "-" } else { "" },
+                    kind.name()
+                ),
+            );
+        }
+        Type::Int(kind)
+    }
+
+    fn float_literal(expected: Option<&Type>) -> Type {
+        match expected {
+            Some(Type::Float(kind)) => Type::Float(*kind),
+            _ => Type::Float(FloatKind::F64),
+        }
+    }
+
+    /* An array literal takes the container type it is used as; its elements must all
+     * have the type of the first. */
+    fn check_array(&mut self, items: &[Expr], expected: Option<&Type>) -> Type {
+        if let (Some(table @ Type::Table(..)), []) = (expected, items) {
+            return table.clone();
+        }
+        let mut elem = match expected {
+            Some(Type::Array(elem) | Type::List(elem) | Type::Set(elem)) => (**elem).clone(),
+            _ => Type::Unknown,
+        };
+        for (i, item) in items.iter().enumerate() {
+            if elem == Type::Unknown {
+                elem = self.check_expr(item, None);
+                continue;
+            }
+            let actual = self.check_expr(item, Some(&elem));
+            self.expect(&actual, &elem, item.span, || format!("element {i}"));
+        }
+        let elem = Box::new(elem);
+        match expected {
+            Some(Type::List(_)) => Type::List(elem),
+            Some(Type::Set(_)) => Type::Set(elem),
+            _ => Type::Array(elem),
+        }
+    }
+
+    /* A table literal, like an array literal, takes the types of the first entry
+     * unless the context gives them. */
+    fn check_table(&mut self, entries: &[(Expr, Expr)], expected: Option<&Type>) -> Type {
+        let (mut key, mut value) = match expected {
+            Some(Type::Table(key, value)) => ((**key).clone(), (**value).clone()),
+            _ => (Type::Unknown, Type::Unknown),
+        };
+        for (i, (key_expr, value_expr)) in entries.iter().enumerate() {
+            for (expr, ty, what) in [
+                (key_expr, &mut key, "key"),
+                (value_expr, &mut value, "value"),
+            ] {
+                if *ty == Type::Unknown {
+                    *ty = self.check_expr(expr, None);
+                    continue;
+                }
+                let actual = self.check_expr(expr, Some(ty));
+                self.expect(&actual, ty, expr.span, || {
+                    format!("the {what} of entry {i}")
+                });
+            }
+        }
+        Type::Table(Box::new(key), Box::new(value))
+    }
+
+    /* `[item; count]` is typed as an array literal of one item. The count must fold
+     * to an integer that is not negative. */
+    fn check_repeat(&mut self, item: &Expr, count: &Expr, expected: Option<&Type>) -> Type {
+        let ty = self.check_array(std::slice::from_ref(item), expected);
+        let count_ty = self.check_expr(count, Some(&Type::Int(IntKind::I32)));
+        match (&count_ty, self.folded.get(&(count as *const Expr))) {
+            (Type::Unknown | Type::Var(_), _) => (),
+            (Type::Int(_), Some(Constant::Value(value))) => {
+                if let Some((true, magnitude)) = value.int_parts() {
+                    self.error(
+                        count.span,
+                        format!("The size of an array cannot be negative, found -{magnitude}."),
+                    );
+                }
+            }
+            (Type::Int(_), _) => (),
+            _ => self.error(
+                count.span,
+                format!("The size of an array must be an integer, found {count_ty}."),
+            ),
+        }
+        ty
+    }
+
+    fn check_unary(
+        &mut self,
+        op: UnaryOp,
+        operand: &Expr,
+        expected: Option<&Type>,
+        span: Span,
+    ) -> Type {
+        match (op, &operand.kind) {
+            (UnaryOp::Neg, ExprKind::Int(magnitude)) => {
+                return self.int_literal(true, *magnitude, expected, span)
+            }
+            (UnaryOp::Neg, ExprKind::Float(_)) => return Self::float_literal(expected),
+            _ => (),
+        }
+        let ty = match op {
+            UnaryOp::Not => self.check_expr(operand, Some(&Type::Bool)),
+            UnaryOp::Len => self.check_expr(operand, None),
+            UnaryOp::Neg | UnaryOp::BitNot => self.check_expr(operand, expected),
+        };
+        let ty = self.non_nil(ty, operand.span);
+        let valid = match (op, &ty) {
+            (UnaryOp::Not, Type::Var(_)) => self.assignable(&ty, &Type::Bool),
+            (_, Type::Unknown | Type::Var(_))
+            | (UnaryOp::Not, Type::Bool)
+            | (UnaryOp::BitNot, Type::Int(_)) => true,
+            (UnaryOp::Neg, Type::Int(kind)) => kind.is_signed(),
+            (UnaryOp::Neg, Type::Float(_)) => true,
+            (UnaryOp::Len, _) => {
+                if !matches!(
+                    ty,
+                    Type::String
+                        | Type::Array(_)
+                        | Type::List(_)
+                        | Type::Set(_)
+                        | Type::Table(..)
+                        | Type::Var(_)
+                ) {
+                    self.error(span, format!("Cannot take the length of {ty}."));
+                }
+                return Type::Int(IntKind::I32);
+            }
+            _ => false,
+        };
+        if !valid {
+            self.error(
+                span,
+                format!("Operator `{}` cannot be applied to {ty}.", op.symbol()),
+            );
+        }
+        match op {
+            UnaryOp::Not => Type::Bool,
+            _ if valid => ty,
+            _ => Type::Unknown,
+        }
+    }
+
+    /* Checks both operands, giving an untyped literal the type of the other side. */
+    fn check_operands(&mut self, lhs: &Expr, rhs: &Expr, expected: Option<&Type>) -> (Type, Type) {
+        if lhs.is_untyped_literal() && !rhs.is_untyped_literal() {
+            let rhs = self.check_expr(rhs, expected);
+            let hint = rhs.is_numeric().then_some(&rhs).or(expected);
+            let lhs = self.check_expr(lhs, hint);
+            return (lhs, rhs);
+        }
+        let lhs = self.check_expr(lhs, expected);
+        let hint = lhs.is_numeric().then_some(&lhs).or(expected);
+        let rhs = self.check_expr(rhs, hint);
+        (lhs, rhs)
+    }
+
+    fn check_binary(
+        &mut self,
+        op: BinaryOp,
+        lhs: &Expr,
+        rhs: &Expr,
+        expected: Option<&Type>,
+        span: Span,
+    ) -> Type {
+        let expected = op.operand_hint(expected);
+        match op {
+            /* The right side of `x != nil and x > 0` is only evaluated when `x` is not
+             * `nil`, and that of `x == nil or x > 0` too. */
+            BinaryOp::And | BinaryOp::Or | BinaryOp::Xor => {
+                self.check_condition(lhs);
+                self.scopes.push(Scope::default());
+                if op != BinaryOp::Xor {
+                    self.narrow_when(lhs, op == BinaryOp::And);
+                }
+                self.check_right(op, lhs, |checker| checker.check_condition(rhs));
+                self.scopes.pop();
+                Type::Bool
+            }
+            BinaryOp::NilCoalesce => {
+                let lhs_ty = self.check_expr(lhs, expected);
+                if matches!(lhs_ty, Type::Unknown | Type::Nil) {
+                    return self.check_right(op, lhs, |checker| checker.check_expr(rhs, expected));
+                }
+                let inner = lhs_ty.non_optional().clone();
+                let rhs_ty =
+                    self.check_right(op, lhs, |checker| checker.check_expr(rhs, Some(&inner)));
+                self.expect(&rhs_ty, &inner.clone().optional(), rhs.span, || {
+                    "the right side of `??`".to_owned()
+                });
+                self.binary_type(op, &lhs_ty, &rhs_ty, span)
+            }
+            BinaryOp::In => self.check_membership(lhs, rhs, span),
+            _ => {
+                let (lhs, rhs) = self.check_operands(lhs, rhs, expected);
+                self.binary_type(op, &lhs, &rhs, span)
+            }
+        }
+    }
+
+    /* Checks the right side of `op`, which is only certain to be evaluated when the
+     * left side is a constant that does not decide the result. */
+    fn check_right<T>(
+        &mut self,
+        op: BinaryOp,
+        lhs: &Expr,
+        check: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        let evaluated = match (op, self.folded.get(&(lhs as *const Expr))) {
+            (BinaryOp::Xor, _) => true,
+            (BinaryOp::And, Some(Constant::Value(Value::Bool(lhs)))) => *lhs,
+            (BinaryOp::Or, Some(Constant::Value(Value::Bool(lhs)))) => !*lhs,
+            (BinaryOp::NilCoalesce, Some(Constant::Value(lhs))) => lhs.is_nil(),
+            _ => false,
+        };
+        if evaluated {
+            return check(self);
+        }
+        self.unevaluated += 1;
+        let result = check(self);
+        self.unevaluated -= 1;
+        result
+    }
+
+    /* `value in range`. An untyped literal takes the type of the integers of the range,
+     * and a range literal the type of the value. */
+    fn check_membership(&mut self, value: &Expr, range: &Expr, span: Span) -> Type {
+        let (value_ty, range_ty) = if value.is_untyped_literal() {
+            let range_ty = self.check_expr(range, None);
+            let item = match self.apply(&range_ty) {
+                Type::Range(elem) => Some(*elem),
+                _ => None,
+            };
+            (self.check_expr(value, item.as_ref()), range_ty)
+        } else {
+            let value_ty = self.check_expr(value, None);
+            let hint = Type::Range(Box::new(value_ty.clone()));
+            (value_ty, self.check_expr(range, Some(&hint)))
+        };
+        match self.apply(&range_ty) {
+            Type::Range(elem) => self.expect(&value_ty, &elem, value.span, || {
+                "the value tested with `in`".to_owned()
+            }),
+            Type::Unknown | Type::Var(_) => (),
+            range_ty => self.error(
+                span,
+                format!("Operator `in` cannot be applied to {value_ty} and {range_ty}."),
+            ),
+        }
+        Type::Bool
+    }
+
+    /* The type of `lhs op rhs`. Arithmetic and comparisons need operands of the same
+     * type, as the evaluator never converts between numeric types implicitly. */
+    fn binary_type(&mut self, op: BinaryOp, lhs: &Type, rhs: &Type, span: Span) -> Type {
+        /* `a ?? b` may be `nil` only when `b` may be. */
+        if op == BinaryOp::NilCoalesce {
+            return match rhs {
+                Type::Nil | Type::Optional(_) => lhs.clone().optional(),
+                _ => lhs.non_optional().clone(),
+            };
+        }
+        let (lhs, rhs) = match op {
+            BinaryOp::Eq | BinaryOp::NotEq => (lhs.clone(), rhs.clone()),
+            _ => (
+                self.non_nil(lhs.clone(), span),
+                self.non_nil(rhs.clone(), span),
+            ),
+        };
+        let (lhs, rhs) = (&lhs, &rhs);
+        let is_text = |ty: &Type| matches!(ty, Type::String | Type::Char);
+        /* An operand still being inferred takes the type of the other side, or `bool`
+         * for the logical operators. */
+        if matches!(lhs, Type::Var(_)) || matches!(rhs, Type::Var(_)) {
+            if op.is_logical() {
+                self.assignable(lhs, &Type::Bool);
+                self.assignable(rhs, &Type::Bool);
+            } else if !matches!(op, BinaryOp::Concat | BinaryOp::Shl | BinaryOp::Shr) {
+                self.assignable(lhs, rhs);
+            }
+        }
+        let (lhs, rhs) = (&self.apply(lhs), &self.apply(rhs));
+        let pending = |ty: &Type| matches!(ty, Type::Unknown | Type::Var(_));
+        if pending(lhs) || pending(rhs) {
+            return match op {
+                _ if op.is_comparison() || op.is_logical() => Type::Bool,
+                BinaryOp::Concat if is_text(lhs) || is_text(rhs) => Type::String,
+                BinaryOp::Concat => Type::Unknown,
+                BinaryOp::Shl | BinaryOp::Shr => lhs.clone(),
+                _ if pending(lhs) => rhs.clone(),
+                _ => lhs.clone(),
+            };
+        }
+        /* A narrower number is widened to the type of the other operand. */
+        let (lhs, rhs) = match op {
+            BinaryOp::Shl | BinaryOp::Shr => (lhs, rhs),
+            _ if lhs.widens_to(rhs) => (rhs, rhs),
+            _ if rhs.widens_to(lhs) => (lhs, lhs),
+            _ => (lhs, rhs),
+        };
+        let same = lhs == rhs;
+        let result = match op {
+            BinaryOp::Eq | BinaryOp::NotEq => (self.assignable(lhs, rhs)
+                || self.assignable(rhs, lhs)
+                || *lhs == Type::Nil
+                || *rhs == Type::Nil)
+                .then_some(Type::Bool),
+            BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq => (same
+                && matches!(
+                    lhs,
+                    Type::Int(_) | Type::Float(_) | Type::Char | Type::String | Type::Bool
+                ))
+            .then_some(Type::Bool),
+            BinaryOp::Add
+            | BinaryOp::Sub
+            | BinaryOp::Mul
+            | BinaryOp::Div
+            | BinaryOp::FloorDiv
+            | BinaryOp::Mod
+            | BinaryOp::Pow => (same && lhs.is_numeric()).then(|| lhs.clone()),
+            BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor => {
+                (same && (lhs.is_int() || *lhs == Type::Bool)).then(|| lhs.clone())
+            }
+            BinaryOp::Shl | BinaryOp::Shr => (lhs.is_int() && rhs.is_int()).then(|| lhs.clone()),
+            BinaryOp::Concat => match (lhs, rhs) {
+                _ if is_text(lhs) || is_text(rhs) => Some(Type::String),
+                (Type::Int(_), _) if same => Some(lhs.clone()),
+                (Type::Array(_), Type::Array(_)) | (Type::List(_), Type::List(_)) => {
+                    (self.assignable(rhs, lhs) || self.assignable(lhs, rhs)).then(|| lhs.clone())
+                }
+                _ => None,
+            },
+            BinaryOp::And | BinaryOp::Or | BinaryOp::Xor => {
+                (same && *lhs == Type::Bool).then_some(Type::Bool)
+            }
+            BinaryOp::NilCoalesce | BinaryOp::In => {
+                unreachable!("`{}` is typed above", op.symbol())
+            }
+        };
+        result.unwrap_or_else(|| {
+            self.error(
+                span,
+                format!(
+                    "Operator `{}` cannot be applied to {lhs} and {rhs}.",
+                    op.symbol()
+                ),
+            );
+            Type::Unknown
+        })
+    }
+
+    fn check_index(&mut self, target: &Expr, index: &Expr) -> Type {
+        let target_ty = self.check_expr(target, None);
+        let target_ty = self.non_nil(target_ty, target.span);
+        let int = Type::Int(IntKind::I32);
+        let (key, item) = match target_ty.clone() {
+            Type::String => (int, Type::Char),
+            Type::Array(elem) | Type::List(elem) | Type::Set(elem) => (int, *elem),
+            Type::Table(key, value) => (*key, *value),
+            Type::Unknown | Type::Var(_) => (int, Type::Unknown),
+            ty => {
+                self.error(index.span, format!("Cannot index into {ty}."));
+                (int, Type::Unknown)
+            }
+        };
+        /* A range of indices slices a string or an array into one of the same type. */
+        let hint = match index.kind {
+            ExprKind::Range { .. } if key.is_int() => Type::Range(Box::new(key.clone())),
+            _ => key.clone(),
+        };
+        let index_ty = self.check_expr(index, Some(&hint));
+        if matches!(index_ty, Type::Range(_)) && key.is_int() {
+            return target_ty;
+        }
+        if !key.is_int() {
+            self.expect(&index_ty, &key, index.span, || "the key".to_owned());
+        } else if !matches!(index_ty, Type::Int(_) | Type::Unknown) {
+            self.error(
+                index.span,
+                format!("Index must be an integer, found {index_ty}."),
+            );
+        }
+        item
+    }
+
+    /* `Color.Red`, the constructor `Shape.Circle`, or `Color.fromDiscriminant`. */
+    fn variant_type(&mut self, decl: &EnumDecl, name: &str, span: Span) -> Type {
+        let ty = Type::Named(decl.name.clone(), vec![]);
+        if name == Builtin::FromDiscriminant.name() {
+            return Type::Func(vec![Type::Unknown], Box::new(ty));
+        }
+        match self.variant_signature(decl, name) {
+            Some(sig) if sig.params.is_empty() => ty,
+            Some(sig) => sig.into_type(),
+            None => {
+                self.error(
+                    span,
+                    format!("Enum `{}` has no variant `{name}`.", decl.name),
+                );
+                Type::Unknown
+            }
+        }
+    }
+
+    /* The type of the field or method `name` of a value of type `target`. */
+    fn field_type(&mut self, target: &Type, name: &str, span: Span) -> Type {
+        if matches!(target, Type::Unknown | Type::Var(_)) {
+            return Type::Unknown;
+        }
+        if let Type::Named(struct_name, args) = target {
+            if let Some(TypeDef::Struct(decl)) = self.lookup_type(struct_name).cloned() {
+                if let Some(field) = decl.fields.iter().find(|field| field.name == name) {
+                    return self
+                        .resolve_generic(&field.ty, &decl.params)
+                        .substitute(&decl.params, args);
+                }
+                if let Some(method) = decl.method(name) {
+                    let method = Rc::clone(method);
+                    let sig = self.method_signature(&decl, &method, args);
+                    let ret = sig.ret.substitute(&sig.type_params, &sig.type_args);
+                    let params = sig
+                        .params
+                        .iter()
+                        .map(|(_, ty)| ty.substitute(&sig.type_params, &sig.type_args))
+                        .collect();
+                    return Type::Func(params, Box::new(ret));
+                }
+            }
+        }
+        self.error(span, format!("{target} has no field `{name}`."));
+        Type::Unknown
+    }
+
+    /* Calls */
+
+    fn check_call(
+        &mut self,
+        callee: &Expr,
+        args: &[Expr],
+        expected: Option<&Type>,
+        span: Span,
+    ) -> Type {
+        if let ExprKind::Ident(name, _) = &callee.kind {
+            let builtin = Builtin::ALL.iter().find(|builtin| builtin.name() == *name);
+            if let Some(&builtin) = builtin.filter(|builtin| builtin.formats()) {
+                if self.lookup(name).is_none() && self.lookup_func(name).is_none() {
+                    return self.check_format_call(builtin, args, span);
+                }
+            }
+        }
+        let Some(mut sig) = self.signature(callee, expected, args.len()) else {
+            for arg in args {
+                self.check_expr(arg, None);
+            }
+            return Type::Unknown;
+        };
+        if let Some(message) = sig.arity_error(args.len()) {
+            self.error(span, message);
+        }
+        let mut arg_types = Vec::with_capacity(args.len());
+        for (i, arg) in args.iter().enumerate() {
+            let param = sig
+                .params
+                .get(i)
+                .map(|(_, ty)| ty.substitute(&sig.type_params, &sig.type_args));
+            arg_types.push(self.check_expr(arg, param.as_ref()));
+        }
+        for ((_, param), arg) in sig.params.iter().zip(&arg_types) {
+            Self::infer(param, arg, &sig.type_params, &mut sig.type_args);
+        }
+        for (i, (arg, actual)) in args.iter().zip(&arg_types).enumerate() {
+            let Some((_, param)) = sig.params.get(i) else {
+                break;
+            };
+            let param = param.substitute(&sig.type_params, &sig.type_args);
+            self.expect(actual, &param, arg.span, || sig.describe_param(i));
+        }
+        sig.ret.substitute(&sig.type_params, &sig.type_args)
+    }
+
+    /* A format string known at compile time must be valid, and its placeholders
+     * must take as many arguments as follow it. */
+    fn check_format_call(&mut self, builtin: Builtin, args: &[Expr], span: Span) -> Type {
+        let arg_types = args
+            .iter()
+            .map(|arg| self.check_expr(arg, None))
+            .collect::<Vec<_>>();
+        let ret = match builtin {
+            Builtin::Format => Type::String,
+            _ => Type::Unknown,
+        };
+        let (Some(format), Some(ty)) = (args.first(), arg_types.first()) else {
+            return ret;
+        };
+        if builtin == Builtin::Format && !matches!(ty, Type::String | Type::Unknown | Type::Var(_))
+        {
+            self.error(
+                format.span,
+                format!("`format` expects a format string, found {ty}."),
+            );
+        }
+        /* `print` and friends only read a literal or a constant with a brace in it as
+         * a format string. */
+        let literal = matches!(format.kind, ExprKind::Str(_) | ExprKind::Ident(..));
+        let Some(Constant::Value(Value::Str(text))) = self.folded.get(&(format as *const Expr))
+        else {
+            return ret;
+        };
+        if builtin != Builtin::Format && !(literal && format::is_format(text)) {
+            return ret;
+        }
+        match format::parse(text) {
+            Ok(pieces) => {
+                if let Some(message) = format::arity_error(&pieces, args.len() - 1) {
+                    self.error(span, message);
+                }
+            }
+            Err(message) => self.error(format.span, message),
+        }
+        ret
+    }
+
+    /* What calling `callee` expects, or `None` when that is not known statically. */
+    fn signature(
+        &mut self,
+        callee: &Expr,
+        expected: Option<&Type>,
+        arity: usize,
+    ) -> Option<Signature> {
+        match &callee.kind {
+            ExprKind::Ident(name, _) => {
+                if let Some((func, params)) = self.lookup_func(name).cloned() {
+                    return Some(self.func_signature(&func, params, Callee::Func(name.clone())));
+                }
+                if let Some(decl) = self.lookup_struct(name) {
+                    return Some(self.constructor_signature(&decl, expected));
+                }
+            }
+            ExprKind::This => {
+                if let Some(decl) = self.lookup_struct("this") {
+                    return Some(self.constructor_signature(&decl, expected));
+                }
+            }
+            ExprKind::Field(target, name) => match self.enum_named(target) {
+                Some(decl) => {
+                    if let Some(sig) = self.variant_signature(&decl, name) {
+                        if !sig.params.is_empty() {
+                            return Some(sig);
+                        }
+                    }
+                }
+                None => return self.member_signature(target, name, false, arity, callee.span),
+            },
+            ExprKind::SafeField(target, name) => {
+                return self.member_signature(target, name, true, arity, callee.span)
+            }
+            _ => (),
+        }
+        let ty = self.check_expr(callee, None);
+        let name = match &callee.kind {
+            ExprKind::Ident(name, _) => name.clone(),
+            _ => "<anonymous>".to_owned(),
+        };
+        self.value_signature(ty, name, arity, callee.span)
+    }
+
+    /* Calling `target.name(...)`, or `target?.name(...)` when `safe` is set, which is
+     * `nil` when `target` is. Methods take precedence over fields holding functions. */
+    fn member_signature(
+        &mut self,
+        target: &Expr,
+        name: &str,
+        safe: bool,
+        arity: usize,
+        span: Span,
+    ) -> Option<Signature> {
+        let target_ty = self.check_expr(target, None);
+        let optional = safe && matches!(target_ty, Type::Optional(_));
+        let target_ty = if safe {
+            target_ty.non_optional().clone()
+        } else {
+            self.non_nil(target_ty, target.span)
+        };
+        let mut sig = None;
+        if let Type::Named(struct_name, args) = &target_ty {
+            if let Some(TypeDef::Struct(decl)) = self.lookup_type(struct_name).cloned() {
+                if let Some(method) = decl.method(name) {
+                    let method = Rc::clone(method);
+                    sig = Some(self.method_signature(&decl, &method, args));
+                }
+            }
+        }
+        let mut sig = match sig {
+            Some(sig) => sig,
+            None => {
+                let ty = self.field_type(&target_ty, name, span);
+                self.value_signature(ty, name.to_owned(), arity, span)?
+            }
+        };
+        if optional {
+            sig.ret = sig.ret.optional();
+        }
+        Some(sig)
+    }
+
+    /* Calling a value whose type is still being inferred makes it a function of
+     * `arity` parameters. */
+    fn value_signature(
+        &mut self,
+        ty: Type,
+        name: String,
+        arity: usize,
+        span: Span,
+    ) -> Option<Signature> {
+        let ty = self.non_nil(ty, span);
+        let ty = match ty {
+            Type::Var(_) => {
+                let params = (0..arity).map(|_| self.fresh_var()).collect();
+                let func = Type::Func(params, Box::new(self.fresh_var()));
+                self.assignable(&ty, &func);
+                func
+            }
+            ty => ty,
+        };
+        match ty {
+            Type::Func(params, ret) => Some(Signature {
+                callee: Callee::Value(name),
+                params: params.into_iter().map(|ty| (String::new(), ty)).collect(),
+                ret: *ret,
+                type_params: vec![],
+                type_args: vec![],
+            }),
+            Type::Unknown => None,
+            ty => {
+                self.error(span, format!("{ty} is not callable."));
+                None
+            }
+        }
+    }
+
+    fn func_signature(
+        &mut self,
+        func: &FuncDecl,
+        type_params: Vec<String>,
+        callee: Callee,
+    ) -> Signature {
+        let mut params = Vec::with_capacity(func.params.len());
+        for param in &func.params {
+            let ty = self.resolve_generic(&param.ty, &type_params);
+            params.push((param.name.clone(), ty));
+        }
+        let mut ret = self.resolve_generic(&func.ret, &type_params);
+        if func.coroutine {
+            ret = Type::Coroutine(Box::new(ret));
+        }
+        Signature {
+            callee,
+            params,
+            ret,
+            type_args: vec![Type::Unknown; type_params.len()],
+            type_params,
+        }
+    }
+
+    /* A method of a struct whose type parameters are bound to `type_args`. */
+    fn method_signature(
+        &mut self,
+        decl: &StructDecl,
+        method: &FuncDecl,
+        type_args: &[Type],
+    ) -> Signature {
+        let mut type_params = decl.params.clone();
+        for param in &method.type_params {
+            if !type_params.contains(param) {
+                type_params.push(param.clone());
+            }
+        }
+        let name = method.name.clone().unwrap_or_default();
+        let mut sig = self.func_signature(method, type_params, Callee::Func(name));
+        for (slot, arg) in sig.type_args.iter_mut().zip(type_args) {
+            *slot = arg.clone();
+        }
+        sig
+    }
+
+    /* `Point(1.0, 2.0)` fills fields in order, or calls `new` if the struct has one.
+     * `Box<int8> b = Box(1)` binds the type arguments from the declaration. */
+    fn constructor_signature(&mut self, decl: &StructDecl, expected: Option<&Type>) -> Signature {
+        let type_args = match expected {
+            Some(Type::Named(name, args)) if *name == decl.name => args.clone(),
+            _ => vec![],
+        };
+        let mut sig = match decl.method("new") {
+            Some(new) => {
+                let new = Rc::clone(new);
+                self.method_signature(decl, &new, &type_args)
+            }
+            None => {
+                let mut params = Vec::with_capacity(decl.fields.len());
+                for field in &decl.fields {
+                    let ty = self.resolve_generic(&field.ty, &decl.params);
+                    params.push((field.name.clone(), ty));
+                }
+                let mut args = vec![Type::Unknown; decl.params.len()];
+                for (slot, arg) in args.iter_mut().zip(type_args) {
+                    *slot = arg;
+                }
+                Signature {
+                    callee: Callee::Struct(decl.name.clone()),
+                    params,
+                    ret: Type::Unknown,
+                    type_params: decl.params.clone(),
+                    type_args: args,
+                }
+            }
+        };
+        sig.ret = Type::Named(
+            decl.name.clone(),
+            decl.params
+                .iter()
+                .map(|param| Type::Named(param.clone(), vec![]))
+                .collect(),
+        );
+        sig
+    }
+
+    fn variant_signature(&mut self, decl: &EnumDecl, name: &str) -> Option<Signature> {
+        let variant = &decl.variants[decl.variant_index(name)?];
+        let params = variant
+            .fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| (i.to_string(), self.resolve_generic(field, &[])))
+            .collect();
+        Some(Signature {
+            callee: Callee::Variant(format!("{}.{name}", decl.name)),
+            params,
+            ret: Type::Named(decl.name.clone(), vec![]),
+            type_params: vec![],
+            type_args: vec![],
+        })
+    }
+
+    /* Binds the type parameters `params` that occur in `param` from the type of the
+     * argument passed for it; the first binding wins. */
+    fn infer(param: &Type, arg: &Type, params: &[String], args: &mut [Type]) {
+        match (param, arg) {
+            (Type::Named(name, _), _) if params.contains(name) => {
+                if let Some(index) = params.iter().position(|param| param == name) {
+                    if args[index] == Type::Unknown {
+                        args[index] = arg.clone();
+                    }
+                }
+            }
+            (
+                Type::Array(p) | Type::List(p) | Type::Set(p),
+                Type::Array(a) | Type::List(a) | Type::Set(a),
+            ) => {
+                Self::infer(p, a, params, args);
+            }
+            (Type::Table(pk, pv), Type::Table(ak, av)) => {
+                Self::infer(pk, ak, params, args);
+                Self::infer(pv, av, params, args);
+            }
+            (Type::Named(_, p), Type::Named(_, a)) => {
+                for (p, a) in p.iter().zip(a) {
+                    Self::infer(p, a, params, args);
+                }
+            }
+            (Type::Func(pp, pr), Type::Func(ap, ar)) => {
+                for (p, a) in pp.iter().zip(ap) {
+                    Self::infer(p, a, params, args);
+                }
+                Self::infer(pr, ar, params, args);
+            }
+            _ => (),
+        }
+    }
+
+    /* `given` */
+
+    /* `%` is a floored modulo, so `x % n` with a constant `n > 0` is always in `0..n`. */
+    fn domain_of(&self, subject: &Expr, ty: &Type) -> Domain {
+        if let ExprKind::Binary(BinaryOp::Mod, _, rhs) = &subject.kind {
+            if let ExprKind::Int(modulus) = rhs.kind {
+                if modulus > 0 && ty.is_int() {
+                    let hi = i128::try_from(modulus - 1).unwrap_or(i128::MAX);
+                    return Domain::Ints {
+                        lo: 0,
+                        hi,
+                        total: true,
+                    };
+                }
+            }
+        }
+        match ty {
+            Type::Bool => Domain::Bool,
+            Type::Int(kind) => Domain::Ints {
+                lo: kind.min(),
+                hi: i128::try_from(kind.max()).unwrap_or(i128::MAX),
+                total: false,
+            },
+            Type::Named(name, _) => match self.enums.get(name) {
+                Some(decl) => Domain::Enum {
+                    name: name.clone(),
+                    variants: decl
+                        .variants
+                        .iter()
+                        .map(|variant| variant.name.clone())
+                        .collect(),
+                },
+                None => Domain::Open,
+            },
+            _ => Domain::Open,
+        }
+    }
+
+    /* A `const` pattern covers what the literal of its value would. */
+    fn pattern_cover(&mut self, pattern: &Pattern, domain: &Domain) -> Cover {
+        let Some(id) = pattern.constant.get() else {
+            return self.cover_of(&pattern.kind, domain, pattern.span);
+        };
+        match self.consts.get(&id).and_then(fold::pattern) {
+            Some(kind) => self.cover_of(&kind, domain, pattern.span),
+            None => Cover::Opaque,
+        }
+    }
+
+    fn cover_of(&mut self, kind: &PatternKind, domain: &Domain, span: Span) -> Cover {
+        match kind {
+            PatternKind::Wildcard => Cover::All,
+            PatternKind::Int(int) => Cover::Ints(*int, *int),
+            PatternKind::Range {
+                start,
+                end,
+                inclusive,
+            } => Cover::Ints(*start, if *inclusive { *end } else { end - 1 }),
+            PatternKind::Bool(value) => Cover::Bool(*value),
+            PatternKind::Float(_)
+            | PatternKind::Char(_)
+            | PatternKind::Str(_)
+            | PatternKind::Nil => Cover::Literal(kind.to_string()),
+            PatternKind::Name {
+                qualifier,
+                name,
+                bindings,
+            } => {
+                let Domain::Enum {
+                    name: enum_name,
+                    variants,
+                } = domain
+                else {
+                    return Cover::Opaque;
+                };
+                if qualifier
+                    .as_ref()
+                    .is_some_and(|qualifier| qualifier != enum_name)
+                {
+                    self.diagnostics.push(Diagnostic::error(
+                        span,
+                        format!("Pattern `{kind}` does not belong to enum `{enum_name}`."),
+                    ));
+                    return Cover::Opaque;
+                }
+                match variants.iter().position(|variant| variant == name) {
+                    Some(index) => {
+                        let fields = self.enums[enum_name].variants[index].fields.len();
+                        match bindings {
+                            Some(bindings) if bindings.len() != fields => {
+                                self.diagnostics.push(Diagnostic::error(
+                                    span,
+                                    format!(
+                                        "Variant `{name}` has {fields} field(s), found {} binding(s).",
+                                        bindings.len()
+                                    ),
+                                ));
+                                Cover::Opaque
+                            }
+                            _ => Cover::Variant(index),
+                        }
+                    }
+                    None => {
+                        self.diagnostics.push(Diagnostic::error(
+                            span,
+                            format!("Enum `{enum_name}` has no variant `{name}`."),
+                        ));
+                        Cover::Opaque
+                    }
+                }
+            }
+        }
+    }
+
+    fn describe_domain(domain: &Domain) -> String {
+        match domain {
+            Domain::Bool => "a `bool`".to_owned(),
+            Domain::Ints { lo, hi, .. } => format!("a value in {lo} through {hi}"),
+            Domain::Enum { name, .. } => format!("a `{name}`"),
+            Domain::Open => "the subject".to_owned(),
+        }
+    }
+
+    fn check_given(&mut self, given: &Given, span: Span) {
+        let subject = self.check_expr(&given.subject, None);
+        let domain = self.domain_of(&given.subject, &subject);
+        let covers = given
+            .arms
+            .iter()
+            .map(|arm| {
+                arm.patterns
+                    .iter()
+                    .map(|pattern| self.pattern_cover(pattern, &domain))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let report = exhaustive::analyze(&domain, &covers);
+        given.exhaustive.set(report.exhaustive);
+        for (arm, index, reason) in report.unreachable {
+            let pattern = &given.arms[arm].patterns[index];
+            let message = match (reason, &pattern.kind) {
+                (Reason::Covered, PatternKind::Wildcard) => {
+                    "Unreachable `else` arm: every value is already matched by an earlier arm."
+                        .to_owned()
+                }
+                (Reason::Covered, kind) => format!(
+                    "Unreachable pattern `{kind}`: it is already matched by an earlier arm."
+                ),
+                (Reason::OutOfDomain, kind) => format!(
+                    "Unreachable pattern `{kind}`: the subject is always {}.",
+                    Self::describe_domain(&domain)
+                ),
+            };
+            self.diagnostics
+                .push(Diagnostic::warning(pattern.span, message));
+        }
+        if !report.missing.is_empty() {
+            self.diagnostics.push(Diagnostic::warning(
+                span,
+                format!(
+                    "Non-exhaustive `given`: no arm matches {}.",
+                    report.missing.join(", ")
+                ),
+            ));
+        }
+
+        let fields_of = |name: &str| match &domain {
+            Domain::Enum {
+                name: enum_name, ..
+            } => self.enums[enum_name]
+                .variants
+                .iter()
+                .find(|variant| variant.name == name)
+                .map(|variant| variant.fields.clone()),
+            _ => None,
+        };
+        let arm_bindings = given
+            .arms
+            .iter()
+            .map(|arm| {
+                let mut declared = vec![];
+                for pattern in &arm.patterns {
+                    if let PatternKind::Name {
+                        name,
+                        bindings: Some(bindings),
+                        ..
+                    } = &pattern.kind
+                    {
+                        let fields = fields_of(name).unwrap_or_default();
+                        for (i, binding) in bindings.iter().enumerate() {
+                            let ty = fields.get(i).cloned().unwrap_or(Type::Unknown);
+                            declared.push((binding.clone(), ty));
+                        }
+                    }
+                }
+                declared
+            })
+            .collect::<Vec<_>>();
+        for (arm, declared) in given.arms.iter().zip(arm_bindings) {
+            self.scopes.push(Scope::default());
+            for (binding, ty) in declared {
+                let ty = self.resolve_generic(&ty, &[]);
+                self.declare(&binding, ty);
+            }
+            self.check_block(&arm.body);
+            self.scopes.pop();
+        }
+    }
+}
+
+/* Whether running `block` never reaches its end, as when it ends in a `return`. */
+fn always_exits(block: &Block) -> bool {
+    match block.last().map(|stmt| &stmt.kind) {
+        Some(
+            StmtKind::Return(_) | StmtKind::Break(_) | StmtKind::Continue(_) | StmtKind::Raise(_),
+        ) => true,
+        Some(StmtKind::Try { body, handler, .. }) => always_exits(body) && always_exits(handler),
+        Some(StmtKind::If {
+            branches,
+            else_block: Some(else_block),
+        }) => branches.iter().all(|(_, body)| always_exits(body)) && always_exits(else_block),
+        _ => false,
+    }
+}
+
+/* The variables assigned in `block`, outside the functions declared in it. */
+fn assigned_bindings(block: &Block, ids: &mut Vec<BindingId>) {
+    for stmt in block {
+        match &stmt.kind {
+            StmtKind::Assign { target, .. } => {
+                if let ExprKind::Ident(_, binding) = &target.kind {
+                    ids.extend(binding.get());
+                }
+            }
+            StmtKind::If {
+                branches,
+                else_block,
+            } => {
+                for (_, body) in branches {
+                    assigned_bindings(body, ids);
+                }
+                if let Some(body) = else_block {
+                    assigned_bindings(body, ids);
+                }
+            }
+            StmtKind::Given(given) => {
+                for arm in &given.arms {
+                    assigned_bindings(&arm.body, ids);
+                }
+            }
+            StmtKind::For { body, .. }
+            | StmtKind::While { body, .. }
+            | StmtKind::Loop { body, .. } => assigned_bindings(body, ids),
+            StmtKind::Try { body, handler, .. } => {
+                assigned_bindings(body, ids);
+                assigned_bindings(handler, ids);
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn messages(source: &str) -> Vec<String> {
+        let program = parse(source).unwrap();
+        check(&program).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_given_example_is_exhaustive() {
+        let source = std::fs::read_to_string("examples/givenTest.tool").unwrap();
+        assert!(messages(&source).is_empty());
+    }
+
+    #[test]
+    fn test_given_modulo_missing_and_unreachable() {
+        let source = "func f(int32 x)\n\tgiven x % 3 do\n\t\twhen 0 => f(x)\n\t\twhen 0, 5 => f(x)\n\tend\nend";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:4:8: warning: Unreachable pattern `0`: it is already matched by an earlier arm.",
+                "Toolip:4:11: warning: Unreachable pattern `5`: the subject is always a value in 0 through 2.",
+                "Toolip:2:2: warning: Non-exhaustive `given`: no arm matches 1, 2.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_given_bool() {
+        let source = "bool b = true\ngiven b do\n\twhen true => b = false\n\twhen false => b = true\n\telse => b = true\nend";
+        assert_eq!(
+            messages(source),
+            vec!["Toolip:5:2: warning: Unreachable `else` arm: every value is already matched by an earlier arm."]
+        );
+    }
+
+    #[test]
+    fn test_given_enum() {
+        let source = "enum Color Red Green Blue end\nColor c = Color.Red\ngiven c do\n\twhen Red => c = Color.Blue\n\twhen Color.Purple => c = Color.Blue\nend";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:5:7: Enum `Color` has no variant `Purple`.",
+                "Toolip:3:1: warning: Non-exhaustive `given`: no arm matches Green, Blue.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_given_enum_payload_bindings() {
+        let source = "enum Shape Circle(flt64) Rect(flt64, flt64) end\nfunc flt64 area(Shape s)\n\tgiven s do\n\t\twhen Circle(r) => return r\n\t\twhen Rect(w) => return w\n\tend\nend";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:5:8: Variant `Rect` has 2 field(s), found 1 binding(s).",
+                "Toolip:3:2: warning: Non-exhaustive `given`: no arm matches Rect.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_type_errors() {
+        let source = "type Pair<A, B> = table<A, B>\ntype Loop = Loop\nPair<int32> p\nPoint q\nLoop l\nfunc<T> T first(list<T> xs)\n\treturn xs[0]\nend\nfunc f(Strin a)\nend\nf(1)\nfunc Strng g()\n\treturn \"g\"\nend";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:2:1: Type alias `Loop` refers to itself.",
+                "Toolip:3:1: Type `Pair` takes 2 type argument(s), found 1.",
+                "Toolip:4:1: Unknown type `Point`.",
+                "Toolip:5:1: Type alias `Loop` refers to itself.",
+                "Toolip:9:8: Unknown type `Strin`.",
+                "Toolip:12:1: Unknown type `Strng`.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_generic_call_inference() {
+        let source = "type Flags = list<bool>\nfunc<T> T first(list<T> xs)\n\treturn xs[0]\nend\nFlags flags = [true]\ngiven first(flags) do\n\twhen true => flags = [false]\nend";
+        assert_eq!(
+            messages(source),
+            vec!["Toolip:6:1: warning: Non-exhaustive `given`: no arm matches false."]
+        );
+    }
+
+    #[test]
+    fn test_examples_type_check() {
+        for example in ["boolIfTest", "intTest", "stringTest"] {
+            let source = std::fs::read_to_string(format!("examples/{example}.tool")).unwrap();
+            assert!(messages(&source).is_empty(), "{example}");
+        }
+    }
+
+    #[test]
+    fn test_call_and_return_mismatches() {
+        let source = "func int32 f(int32 a, string s)\n\treturn s\nend\nf(\"x\", 1)\nf(1)\nfunc g()\n\treturn 5\nend";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:2:9: Expected int32 for the return value of `f`, found string.",
+                "Toolip:4:3: Expected int32 for parameter `a` of `f`, found string.",
+                "Toolip:4:8: Expected string for parameter `s` of `f`, found int32.",
+                "Toolip:5:2: Function `f` expects 2 argument(s), found 1.",
+                "Toolip:7:9: Expected void for the return value of `g`, found int32.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_operator_applicability() {
+        let source = "flt64 a = 1.5 .. 2.5\nstring s = \"n\" .. 1\nflt64 r = 1.5 % 2.0\nbool b = 1 < \"a\"\nuint8 u = 3\nint8 v = u + 1\nint8 w = 300\nif u then\nend";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:1:15: Operator `..` cannot be applied to flt64 and flt64.",
+                "Toolip:4:12: Operator `<` cannot be applied to int32 and string.",
+                "Toolip:6:12: Expected int8 for variable `v`, found uint8.",
+                "Toolip:7:10: Integer literal 300 does not fit in int8.",
+                "Toolip:8:4: Condition must be a bool, found uint8.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_struct_and_generic_types() {
+        let source = "struct Box<T>\n\tT value\n\tfunc T get()\n\t\treturn self.value\n\tend\nend\nBox<uint8> b = Box(200)\nuint8 x = b.get() + 55\nstring s = b.get()\nb.size = 1\nBox<int8> c = Box(\"big\")";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:9:17: Expected string for variable `s`, found uint8.",
+                "Toolip:10:2: Box<uint8> has no field `size`.",
+                "Toolip:11:19: Expected int8 for field `value`, found string.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_local_inference() {
+        let source = "global x = 5\nx = \"s\"\nglobal y = \"a\" .. \"b\"\nint64 z = y\nconst add = func (a, b) return a + b end\nstring s = add(1, 2)\nadd(1, \"x\")\nconst twice = func (f, v) return f(f(v)) end\nint32 r = twice(func (n) return n * 2 end, 4)\nglobal w = nil";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:2:5: Expected int32 for variable `x`, found string.",
+                "Toolip:4:11: Expected int64 for variable `z`, found string.",
+                "Toolip:6:15: Expected string for variable `s`, found int32.",
+                "Toolip:7:8: Expected int32 for argument 2 of `add`, found string.",
+                "Toolip:10:12: Cannot infer the type of `w` from nil.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_numeric_conversions() {
+        let source = "int16 a = 1\nint64 b = a + 2\nint8 c = a\nuint64 w = a\nflt64 d = a\nflt32 g = b\nint8 n = int8(b)\nstring s = int32(\"5\")\nbool z = a < b";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:3:10: Expected int8 for variable `c`, found int16.",
+                "Toolip:4:12: Expected uint64 for variable `w`, found int16.",
+                "Toolip:6:11: Expected flt32 for variable `g`, found int64.",
+                "Toolip:8:12: Cannot convert string to int32.",
+                "Toolip:8:12: Expected string for variable `s`, found int32.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nil_safety() {
+        let source = "int32? a = nil\nprintln(a + 1, a ?? 0)\nint32 b = nil\nif a != nil and a > 0 then\n\tprintln(-a)\n\ta = nil\n\tprintln(-a)\nend\nfunc f(int32? x)\n\tif x == nil then\n\t\treturn\n\tend\n\tprintln(x * 2)\n\tconst g = func () return x + 1 end\n\twhile true do\n\t\tprintln(x + 1)\n\t\tx = nil\n\tend\nend\nint32? c = nil\nif c == nil then\n\tc = 4\nend\nc ??= 5\nint32 d = c + (a ?? 1)";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:2:11: A value of type int32? may be nil here; compare it with `nil` or use `??` first.",
+                "Toolip:3:11: Expected int32 for variable `b`, found nil.",
+                "Toolip:7:11: A value of type int32? may be nil here; compare it with `nil` or use `??` first.",
+                "Toolip:14:29: A value of type int32? may be nil here; compare it with `nil` or use `??` first.",
+                "Toolip:16:13: A value of type int32? may be nil here; compare it with `nil` or use `??` first.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_safe_navigation() {
+        let source = "struct Node\n\tint32 value\n\tNode? next\n\tfunc int32 double()\n\t\treturn self.value * 2\n\tend\nend\nNode n = Node(1)\nint32 a = n.next?.value\nint32? b = n.next?.double()\nint32 c = n.next.value";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:9:17: Expected int32 for variable `a`, found int32?.",
+                "Toolip:11:12: A value of type Node? may be nil here; compare it with `nil` or use `??` first.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        let source = "const int8 LIMIT = 100\nconst int8 BIG = LIMIT + 28\nconst SIZE = #[1, 2, 3] * 2\nprintln(1 // 0, int8(300), \"n\" .. SIZE .. 1 / 2)\nuint64 u = 18446744073709551615 + 1\nprintln([0; SIZE], [0; 1 - 2], [0; \"a\"])\ngiven 6 do\n\twhen SIZE => println(LIMIT)\n\twhen 6 => println(0)\n\telse => println(1)\nend\nbool b = false and 1 // 0 == 1 or true\nint32? n = 3\nprintln(n ?? 1 // 0, 5 ?? 1 // 0, true and 2 // 0 == 1)";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:2:24: Integer overflow: `+` on int8 operands.",
+                "Toolip:4:11: Division by zero.",
+                "Toolip:4:17: 300 does not fit in int8.",
+                "Toolip:5:33: Integer overflow: `+` on uint64 operands.",
+                "Toolip:6:26: The size of an array cannot be negative, found -1.",
+                "Toolip:6:36: The size of an array must be an integer, found string.",
+                "Toolip:9:7: warning: Unreachable pattern `6`: it is already matched by an earlier arm.",
+                "Toolip:14:46: Division by zero.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_strings() {
+        let source = "const GREETING = \"Hi {}, you are {}\"\nstring name = \"Ada\"\nprintln(GREETING, name)\nprintln(\"{name} {missing} {0:>4}\", 1)\nprintln(\"{0} {2}\", 1, 2)\nprint(\"bad {:q}\", 1)\nstring s = format(5)\nprintln(1, 2)\nprintln(\"label\", 1)\nprintln(\"{\" .. \"}\", 1)";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:4:9: Undefined name `missing` in format string.",
+                "Toolip:3:8: The format string takes 2 argument(s), found 1.",
+                "Toolip:5:8: The format string takes 3 argument(s), found 2.",
+                "Toolip:6:7: Invalid format spec `{:q}`.",
+                "Toolip:7:19: `format` expects a format string, found int32.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_try_and_raise() {
+        let source = "func int32 parse(string s)\n\ttry\n\t\treturn 1\n\tcatch e\n\t\traise e\n\tend\nend\nraise \"bad\"\nraise 3";
+        assert_eq!(
+            messages(source),
+            vec!["Toolip:9:7: Only errors and strings can be raised, found int32."]
+        );
+    }
+
+    #[test]
+    fn test_coroutines() {
+        let source = "coroutine func int32 count(int32 n)\n\tfor int32 i in 0 to n do\n\t\tint32 sent = yield i\n\tend\n\treturn\nend\nfor int32 x in count(3) do\n\tprintln(x)\nend\ncoroutine<int32> c = count(2)\nstring s = status(c)\nfunc f()\n\tyield 1\nend\ncoroutine func int32 g()\n\tprintln(yield 1)\n\tyield \"x\"\nend\nfor string t in count(1) do\nend";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:13:2: `yield` outside of a coroutine.",
+                "Toolip:16:10: `yield` can only be a statement, or the value of an assignment or declaration.",
+                "Toolip:17:8: Expected int32 for the value yielded by `g`, found string.",
+                "Toolip:19:22: Expected string for loop variable `t`, found int32.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ranges() {
+        let source = "range<int8> r = 0 to 100 step 5\nbool b = 5 in r\nint8 k = 3\nbool c = k in 0 through 9\nbool d = \"x\" in r\nbool e = 1 in [1]\narray<int32> xs = [1, 2, 3]\narray<int32> ys = xs[1 to 3]\nstring s = \"abc\"[0 to 2]\nchar ch = s[r]\nuint8 u = 7\ngiven u % 8 do\n\twhen 0 to 4 => println(1)\n\twhen 4 through 6 => println(2)\n\twhen 5 => println(3)\nend";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:5:10: Expected int8 for the value tested with `in`, found string.",
+                "Toolip:6:12: Operator `in` cannot be applied to int32 and array<int32>.",
+                "Toolip:10:12: Expected char for variable `ch`, found string.",
+                "Toolip:15:7: warning: Unreachable pattern `5`: it is already matched by an earlier arm.",
+                "Toolip:12:1: warning: Non-exhaustive `given`: no arm matches 7.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_steps_and_iterators() {
+        let source = "struct Letters\n\tfunc string? next()\n\t\treturn nil\n\tend\nend\nstruct Wrapper\n\tfunc Letters iter()\n\t\treturn Letters()\n\tend\nend\nstruct Plain\nend\nfor string s in Wrapper() do\nend\nfor int32 i in Letters() do\nend\nfor int32 i in Plain() do\nend\nfor int32 i in 0 to 9 step 1.5 do\nend";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:15:23: Expected int32 for loop variable `i`, found string.",
+                "Toolip:17:21: Cannot iterate over Plain.",
+                "Toolip:19:28: Expected int32 for the step of a range, found flt64.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sets_and_tables() {
+        let source = "set<int32> s = [1, 1.5]\ntable<string, int32> t = [\"a\": 1, 2: 3]\nfor string k, int32 v in t do\nend\nfor k, v in s do\nend\nfor i, j in 0 to 3 do\nend\nint32 n = t[\"a\"]\nstring m = t[\"a\"]";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:1:20: Expected int32 for element 1, found flt64.",
+                "Toolip:2:35: Expected string for the key of entry 1, found int32.",
+                "Toolip:5:13: Only a table has a key and a value to iterate over, found set<int32>.",
+                "Toolip:7:15: Only a table has a key and a value to iterate over, found range<int32>.",
+                "Toolip:10:13: Expected string for variable `m`, found int32.",
+            ]
+        );
+    }
+}
@@ -0,0 +1,2753 @@
+#![allow(dead_code)]
+use crate::ast::{
+    BinaryOp, BindingId, Block, EnumDecl, Expr, ExprKind, FieldDecl, FuncDecl, Pattern,
+    PatternKind, Program, Stmt, StmtKind, Storage, StructDecl, TypeAlias, UnaryOp, VarDecl,
+    Variant,
+};
+use crate::diagnostic::Diagnostic;
+use crate::evaluator::{float_literal, func_type, inferred_type, int_literal};
+use crate::format;
+use crate::token::Span;
+use crate::types::{IntKind, Type};
+use crate::value::{Builtin, Value};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/* The bytecode compiler: translates a checked program into functions of compact
+ * instructions for the stack machine in `vm.rs`, which computes what the evaluator
+ * would.
+ *
+ * Each function has a fixed number of local variable slots, parameters first, with
+ * its temporaries on the stack above them. A variable that a nested function refers
+ * to lives in a cell, which the closure shares. Variables at the top level of the
+ * program are globals, numbered across the whole program.
+ *
+ * An untyped literal takes its type from its context as in the evaluator. Where the
+ * context is only known at run time, as the other operand of `+`, the instruction
+ * carries a `Hint` for the machine to resolve. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /* Pushes `constants[i]`. */
+    Constant(u32),
+    /* Pushes the untyped literal `literals[i]`. */
+    Literal(u32),
+    Nil,
+    Pop,
+    Dup,
+    Swap,
+    GetLocal(u32),
+    SetLocal(u32),
+    /* Puts a new cell holding `nil` in a local slot. */
+    NewCell(u32),
+    GetCell(u32),
+    SetCell(u32),
+    GetCapture(u32),
+    SetCapture(u32),
+    GetGlobal(u32),
+    /* Pushes the value of a global about to be assigned, failing if it is a constant. */
+    AssignGlobal(u32),
+    SetGlobal(u32),
+    /* Declares the global `globals[i]`, with the value on the stack if it has one. */
+    DefineGlobal(u32),
+    /* Checks the value on the stack against `checks[i]`, widening it if need be. */
+    Check(u32),
+    /* Fails if the value on the stack is `nil`, as the variable `names[i]` holds until
+     * it is assigned when its type does not admit `nil`. */
+    Assigned(u32),
+    /* Replaces the struct on the stack with a copy, as it is stored. */
+    Copy,
+    /* Fails unless the value on the stack is a `bool`. */
+    Condition,
+    Unary(UnaryOp),
+    Binary(BinaryOp),
+    /* Converts the value on the stack to `types[i]`. */
+    Cast(u32),
+    Array(u32),
+    Repeat,
+    /* Replaces a start, an end and, with the second flag, a step on the stack with a
+     * range, which includes the end with the first. */
+    Range(bool, bool),
+    /* Makes the array on the stack the set or empty table `hints[i]` asks for, if it
+     * asks for one. */
+    Collection(u32),
+    /* Replaces `n` keys and values on the stack with a table, typed as `hints[i]`
+     * asks or else by its first entry. */
+    Table(u32, u32),
+    Index,
+    /* `array[index]` about to be assigned: keeps both and pushes the element. */
+    IndexSlot,
+    StoreIndex,
+    /* The field `names[i]` of the value on the stack. */
+    Field(u32),
+    /* Like `Field`, but jumps to the target leaving `nil` when the value is `nil`. */
+    SafeField(u32, u32),
+    /* The field `names[i]` of a struct about to be assigned: keeps the struct and
+     * pushes the field. */
+    FieldSlot(u32),
+    StoreField(u32),
+    Jump(u32),
+    JumpIfFalse(u32),
+    JumpIfTrue(u32),
+    JumpIfNotNil(u32),
+    /* Creates a closure of `functions[i]`. */
+    Closure(u32),
+    /* Creates the struct type `structs[i]`, with closures of its functions. */
+    Struct(u32),
+    /* Creates the enum type `enums[i]`. */
+    Enum(u32),
+    /* Puts the cell of the closure's `static` variable `i` in a local slot. */
+    Static(u32, u32),
+    /* Jumps to the target if `static` variable `i` was initialised, marking it if not. */
+    StaticInit(u32, u32),
+    /* Calls a function with the arguments above it, as described by `sites[i]`. */
+    Call(u32),
+    /* `return f(x)`: a call that replaces the frame of the function returning. */
+    TailCall(u32),
+    Return,
+    /* Starts a `for` loop over a range, kept in two local slots from the given one. */
+    RangeStart(u32),
+    /* Pushes the next item of a loop or jumps to the target when there is none. */
+    RangeNext(u32, u32),
+    /* `RangeNext` for a range that includes its end, after which it leaves 1 and 0 in
+     * the slots, so that the largest value of the type can end it. */
+    ThroughNext(u32, u32),
+    /* Starts a `for` loop over the items of an array, string or range value. */
+    IterStart(u32),
+    IterNext(u32, u32),
+    /* Starts a `for key, value` loop over the entries of a table. */
+    EntriesStart(u32),
+    /* Pushes the value and then the key of the next entry, or jumps to the target. */
+    EntriesNext(u32, u32),
+    /* Starts a `try` block, an error in which jumps to the target with the error on
+     * the stack, once the stack is back to its height here. */
+    TryStart(u32),
+    TryEnd,
+    /* Fails with the error on the stack, or with an error whose message is the string. */
+    Raise,
+    /* `try x`: raises the value on the stack if it is an error. */
+    Unwrap,
+    /* Suspends the coroutine with the value on the stack, replaced by the value it is
+     * resumed with. */
+    Yield,
+    /* Pushes whether the subject in a local slot matches `patterns[i]`. */
+    Match(u32, u32),
+    /* Replaces the value on the stack with whether it equals the subject in a slot. */
+    MatchValue(u32),
+    /* Pushes item `i` of the payload of the enum value in a slot, which matched a
+     * pattern binding it. */
+    Payload(u32, u32),
+    /* Fails with the message `names[i]`. */
+    Fail(u32),
+}
+
+/* The type an untyped literal is used as, where the compiler cannot tell. */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Hint {
+    None,
+    Type(Type),
+    /* The numeric type of the temporary in a slot, or else the other hint. */
+    Numeric(u32, Box<Hint>),
+    /* The type of a parameter of the function in a temporary slot. */
+    Param(u32, u32),
+    /* The type of a global variable. */
+    Global(u32),
+    /* The element type of an array or set type, or the value type of a table type. */
+    Elem(Box<Hint>),
+    /* The key type of a table type. */
+    Key(Box<Hint>),
+    /* The type of the indices of the value in a temporary slot: the key type of a
+     * table, or else `int32`. */
+    Index(u32),
+    /* The value type of the table in a temporary slot. */
+    Entry(u32),
+    /* The type of the integers of the range in a temporary slot. */
+    Item(u32),
+    /* The hint for the operands of an operator whose result has this hint. */
+    Operands(BinaryOp, Box<Hint>),
+    /* The type of the field `names[i]` of the struct in a temporary slot. */
+    Field(u32, u32),
+}
+
+impl Hint {
+    fn of(ty: &Type) -> Self {
+        match ty.non_optional() {
+            Type::Unknown => Self::None,
+            ty => Self::Type(ty.clone()),
+        }
+    }
+
+    /* The hint when it does not depend on the run time. */
+    fn known(&self) -> Option<Option<&Type>> {
+        match self {
+            Self::None => Some(None),
+            Self::Type(ty) => Some(Some(ty)),
+            _ => None,
+        }
+    }
+
+    fn elem(&self) -> Self {
+        match self {
+            Self::None => Self::None,
+            Self::Type(
+                Type::Array(elem) | Type::List(elem) | Type::Set(elem) | Type::Table(_, elem),
+            ) => Self::of(elem),
+            Self::Type(_) => Self::None,
+            hint => Self::Elem(Box::new(hint.clone())),
+        }
+    }
+
+    fn key(&self) -> Self {
+        match self {
+            Self::None => Self::None,
+            Self::Type(Type::Table(key, _)) => Self::of(key),
+            Self::Type(_) => Self::None,
+            hint => Self::Key(Box::new(hint.clone())),
+        }
+    }
+
+    /* Whether an array literal with this hint may be made a set or table. */
+    fn collects(&self) -> bool {
+        match self.known() {
+            Some(ty) => matches!(ty, Some(Type::Set(_) | Type::Table(..))),
+            None => true,
+        }
+    }
+
+    fn operands(&self, op: BinaryOp) -> Self {
+        match self {
+            Self::None => Self::None,
+            Self::Type(ty) => match op.operand_hint(Some(ty)) {
+                Some(ty) => Self::Type(ty.clone()),
+                None => Self::None,
+            },
+            hint => Self::Operands(op, Box::new(hint.clone())),
+        }
+    }
+
+    /* The hint for an operand of a binary operator: the numeric type of the other
+     * operand, which is in temporary `slot` and may be known already. */
+    fn numeric(other: Option<&Type>, slot: u32, otherwise: &Self) -> Self {
+        match other {
+            Some(ty @ (Type::Int(_) | Type::Float(_))) => Self::Type(ty.clone()),
+            Some(_) => otherwise.clone(),
+            None => Self::Numeric(slot, Box::new(otherwise.clone())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LiteralValue {
+    Int { negative: bool, magnitude: u128 },
+    Float(f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Literal {
+    pub value: LiteralValue,
+    pub hint: Hint,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallSite {
+    pub argc: u32,
+    pub arg_spans: Vec<Span>,
+    /* Whether the first argument is read as a format string by `print` and friends:
+     * a string literal or a constant. */
+    pub format: bool,
+    /* The variables named by the placeholders of a literal format string, whose
+     * values follow the arguments. Globals are looked up when the call is made. */
+    pub named: Vec<String>,
+    /* The type arguments of the struct constructed, as in `Box<int8> b = Box(1)`. */
+    pub type_args: Vec<Type>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalDecl {
+    pub global: u32,
+    pub ty: Type,
+    pub storage: Option<Storage>,
+    pub has_value: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternTest {
+    pub pattern: Pattern,
+    /* The global a bare name refers to, which matches when it is a constant. */
+    pub global: Option<u32>,
+}
+
+/* A struct declaration as the machine needs it: the declaration has its name and
+ * fields, typed as the machine checks them, while the function returning the
+ * defaults of the fields and the methods are in `functions`. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructDef {
+    pub decl: Rc<StructDecl>,
+    pub init: u32,
+    pub methods: Vec<u32>,
+}
+
+/* Where a closure finds a variable it captures when it is created. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    /* A cell in a local slot of the enclosing function. */
+    Local(u32),
+    /* A variable the enclosing function captures itself. */
+    Capture(u32),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Function {
+    /* `None` for anonymous functions and the program itself. */
+    pub name: Option<String>,
+    pub params: Vec<(String, Type)>,
+    pub ret: Type,
+    pub uses_self: bool,
+    /* Called to make a coroutine, which runs it when resumed. */
+    pub coroutine: bool,
+    /* Compiled to native code on its first call, if it can be. */
+    pub jit: bool,
+    /* The number of local variable slots. */
+    pub slots: usize,
+    pub code: Vec<Op>,
+    /* The source position of each instruction, for its errors. */
+    pub spans: Vec<Span>,
+    pub constants: Vec<Value>,
+    pub literals: Vec<Literal>,
+    /* The hints of the sets and tables made at run time. */
+    pub hints: Vec<Hint>,
+    pub checks: Vec<(Type, String)>,
+    pub types: Vec<Type>,
+    pub names: Vec<String>,
+    pub globals: Vec<GlobalDecl>,
+    pub sites: Vec<CallSite>,
+    pub patterns: Vec<PatternTest>,
+    pub structs: Vec<StructDef>,
+    /* Enum declarations, their payloads typed as the machine checks them. */
+    pub enums: Vec<Rc<EnumDecl>>,
+    /* The number of `static` variables, which each closure of it keeps. */
+    pub statics: usize,
+    pub functions: Vec<Rc<Function>>,
+    pub captures: Vec<Capture>,
+}
+
+impl Function {
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or("<anonymous>")
+    }
+}
+
+/* A compiled program: the code of its top level and the names of its globals, the
+ * builtins first in the order of `Builtin::ALL`. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bytecode {
+    pub script: Rc<Function>,
+    pub globals: Vec<String>,
+}
+
+/* Compiles a program, or reports the first construct the machine does not support
+ * yet, in which case the evaluator runs the program instead. */
+pub fn compile(program: &Program) -> Result<Bytecode, Diagnostic> {
+    let mut declared = HashSet::new();
+    walk(&program.stmts, &mut |node| match node {
+        Node::Stmt(Stmt {
+            kind: StmtKind::VarDecl(decl),
+            ..
+        }) => {
+            declared.insert(decl.name.clone());
+        }
+        Node::Stmt(Stmt {
+            kind: StmtKind::Func(decl),
+            ..
+        }) => declared.extend(decl.name.clone()),
+        Node::Stmt(Stmt {
+            kind: StmtKind::Struct(decl),
+            ..
+        }) => {
+            declared.insert(decl.name.clone());
+        }
+        Node::Stmt(Stmt {
+            kind: StmtKind::Enum(decl),
+            ..
+        }) => {
+            declared.insert(decl.name.clone());
+        }
+        _ => (),
+    });
+    /* The functions are compiled first, so they know the constants up front. */
+    let constants = program
+        .stmts
+        .iter()
+        .filter_map(|stmt| match &stmt.kind {
+            StmtKind::VarDecl(decl) if decl.storage == Some(Storage::Const) => {
+                Some((decl.name.clone(), constant_text(decl)))
+            }
+            _ => None,
+        })
+        .collect();
+    let mut compiler = Compiler {
+        states: vec![],
+        globals: Builtin::ALL
+            .iter()
+            .map(|builtin| builtin.name().to_owned())
+            .collect(),
+        declared,
+        constants,
+        types: vec![vec![]],
+        statement_yield: None,
+    };
+    compiler.begin_function(Function::default(), &program.stmts);
+    compiler.stmts(&program.stmts)?;
+    compiler.emit(Op::Nil, Span::default());
+    compiler.emit(Op::Return, Span::default());
+    let script = compiler.end_function();
+    Ok(Bytecode {
+        script: Rc::new(script),
+        globals: compiler.globals,
+    })
+}
+
+type CompileResult<T> = Result<T, Diagnostic>;
+
+fn unsupported(span: Span, what: &str) -> Diagnostic {
+    Diagnostic::error(
+        span,
+        format!("{what} are not supported by the bytecode compiler."),
+    )
+}
+
+/* The nesting of type aliases past which a type is left unknown, as in the
+ * evaluator. */
+const MAX_ALIAS_DEPTH: usize = 64;
+
+/* The name a function, struct or enum declaration defines, with its type. */
+fn hoisted(stmt: &Stmt) -> Option<(&String, Type)> {
+    match &stmt.kind {
+        StmtKind::Func(decl) => Some((decl.name.as_ref()?, func_type(decl))),
+        StmtKind::Struct(decl) => Some((&decl.name, Type::Unknown)),
+        StmtKind::Enum(decl) => Some((&decl.name, Type::Unknown)),
+        _ => None,
+    }
+}
+
+/* The type every value of `ty` has exactly at run time, for the primitive types. */
+fn exact(ty: &Type) -> Option<Type> {
+    match ty {
+        Type::Bool | Type::Int(_) | Type::Float(_) | Type::Char | Type::String => Some(ty.clone()),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Node<'a> {
+    Stmt(&'a Stmt),
+    Expr(&'a Expr),
+    Pattern(&'a Pattern),
+}
+
+/* Visits every statement, expression and pattern of `block`, nested functions
+ * included. */
+fn walk<'a>(block: &'a Block, visit: &mut impl FnMut(Node<'a>)) {
+    for stmt in block {
+        visit(Node::Stmt(stmt));
+        match &stmt.kind {
+            StmtKind::VarDecl(decl) => {
+                if let Some(value) = &decl.value {
+                    walk_expr(value, visit);
+                }
+            }
+            StmtKind::Func(decl) => walk(&decl.body, visit),
+            StmtKind::Struct(decl) => {
+                for field in &decl.fields {
+                    if let Some(default) = &field.default {
+                        walk_expr(default, visit);
+                    }
+                }
+                for method in &decl.methods {
+                    walk(&method.body, visit);
+                }
+            }
+            StmtKind::Enum(_)
+            | StmtKind::TypeAlias(_)
+            | StmtKind::Break(_)
+            | StmtKind::Continue(_) => (),
+            StmtKind::Expr(expr) | StmtKind::Return(Some(expr)) => walk_expr(expr, visit),
+            StmtKind::Return(None) => (),
+            StmtKind::Assign { target, value, .. } => {
+                walk_expr(target, visit);
+                walk_expr(value, visit);
+            }
+            StmtKind::If {
+                branches,
+                else_block,
+            } => {
+                for (cond, body) in branches {
+                    walk_expr(cond, visit);
+                    walk(body, visit);
+                }
+                if let Some(body) = else_block {
+                    walk(body, visit);
+                }
+            }
+            StmtKind::Given(given) => {
+                walk_expr(&given.subject, visit);
+                for arm in &given.arms {
+                    for pattern in &arm.patterns {
+                        visit(Node::Pattern(pattern));
+                    }
+                    walk(&arm.body, visit);
+                }
+            }
+            StmtKind::For { iter, body, .. } => {
+                walk_expr(iter, visit);
+                walk(body, visit);
+            }
+            StmtKind::While { cond, body, .. } => {
+                walk_expr(cond, visit);
+                walk(body, visit);
+            }
+            StmtKind::Loop { body, .. } => walk(body, visit),
+            StmtKind::Try { body, handler, .. } => {
+                walk(body, visit);
+                walk(handler, visit);
+            }
+            StmtKind::Raise(value) => walk_expr(value, visit),
+        }
+    }
+}
+
+fn walk_expr<'a>(expr: &'a Expr, visit: &mut impl FnMut(Node<'a>)) {
+    visit(Node::Expr(expr));
+    match &expr.kind {
+        ExprKind::Int(_)
+        | ExprKind::Float(_)
+        | ExprKind::Bool(_)
+        | ExprKind::Char(_)
+        | ExprKind::Str(_)
+        | ExprKind::Nil
+        | ExprKind::Ident(..)
+        | ExprKind::SelF
+        | ExprKind::This => (),
+        ExprKind::Array(items) => {
+            for item in items {
+                walk_expr(item, visit);
+            }
+        }
+        ExprKind::Table(entries) => {
+            for (key, value) in entries {
+                walk_expr(key, visit);
+                walk_expr(value, visit);
+            }
+        }
+        ExprKind::Unary(_, operand)
+        | ExprKind::Cast(_, operand)
+        | ExprKind::Try(operand)
+        | ExprKind::Field(operand, _)
+        | ExprKind::SafeField(operand, _) => walk_expr(operand, visit),
+        ExprKind::Yield(value) => {
+            if let Some(value) = value {
+                walk_expr(value, visit);
+            }
+        }
+        ExprKind::Binary(_, lhs, rhs) | ExprKind::Index(lhs, rhs) | ExprKind::Repeat(lhs, rhs) => {
+            walk_expr(lhs, visit);
+            walk_expr(rhs, visit);
+        }
+        ExprKind::Range {
+            start, end, step, ..
+        } => {
+            walk_expr(start, visit);
+            walk_expr(end, visit);
+            if let Some(step) = step {
+                walk_expr(step, visit);
+            }
+        }
+        ExprKind::Call(callee, args) => {
+            walk_expr(callee, visit);
+            for arg in args {
+                walk_expr(arg, visit);
+            }
+        }
+        ExprKind::Func(decl) => walk(&decl.body, visit),
+    }
+}
+
+/* The names used inside the functions nested in `body`, the methods and field
+ * defaults of its structs included. A variable of `body` by one of these names may be
+ * captured, so it is kept in a cell. */
+fn captured_names(body: &Block) -> HashSet<String> {
+    let mut nested = vec![];
+    let mut defaults = vec![];
+    walk(body, &mut |node| match node {
+        Node::Stmt(Stmt {
+            kind: StmtKind::Func(decl),
+            ..
+        })
+        | Node::Expr(Expr {
+            kind: ExprKind::Func(decl),
+            ..
+        }) => nested.push(decl),
+        Node::Stmt(Stmt {
+            kind: StmtKind::Struct(decl),
+            ..
+        }) => {
+            nested.extend(&decl.methods);
+            defaults.extend(
+                decl.fields
+                    .iter()
+                    .filter_map(|field| field.default.as_ref()),
+            );
+        }
+        _ => (),
+    });
+    let mut names = HashSet::new();
+    let mut note = |node: Node| match node {
+        Node::Expr(expr) => match &expr.kind {
+            ExprKind::Ident(name, _) => {
+                names.insert(name.clone());
+            }
+            ExprKind::SelF => {
+                names.insert("self".to_owned());
+            }
+            ExprKind::This => {
+                names.insert("this".to_owned());
+            }
+            ExprKind::Str(text) => {
+                if let Ok(pieces) = format::parse(text) {
+                    names.extend(format::names(&pieces).map(str::to_owned));
+                }
+            }
+            _ => (),
+        },
+        Node::Pattern(Pattern {
+            kind:
+                PatternKind::Name {
+                    qualifier: None,
+                    name,
+                    ..
+                },
+            ..
+        }) => {
+            names.insert(name.clone());
+        }
+        _ => (),
+    };
+    for decl in nested {
+        walk(&decl.body, &mut note);
+    }
+    for default in defaults {
+        walk_expr(default, &mut note);
+    }
+    names
+}
+
+/* The `static` variables of a function, in the blocks of its body. As in the
+ * evaluator, they only outlive a call when its body declares one directly. */
+fn statics(body: &Block) -> Vec<&VarDecl> {
+    fn collect<'a>(block: &'a Block, out: &mut Vec<&'a VarDecl>) {
+        for stmt in block {
+            match &stmt.kind {
+                StmtKind::VarDecl(decl) if decl.storage == Some(Storage::Static) => out.push(decl),
+                StmtKind::If {
+                    branches,
+                    else_block,
+                } => {
+                    for (_, body) in branches {
+                        collect(body, out);
+                    }
+                    if let Some(body) = else_block {
+                        collect(body, out);
+                    }
+                }
+                StmtKind::Given(given) => {
+                    for arm in &given.arms {
+                        collect(&arm.body, out);
+                    }
+                }
+                StmtKind::For { body, .. }
+                | StmtKind::While { body, .. }
+                | StmtKind::Loop { body, .. } => collect(body, out),
+                StmtKind::Try { body, handler, .. } => {
+                    collect(body, out);
+                    collect(handler, out);
+                }
+                _ => (),
+            }
+        }
+    }
+    let direct = body.iter().any(|stmt| {
+        matches!(&stmt.kind, StmtKind::VarDecl(decl) if decl.storage == Some(Storage::Static))
+    });
+    let mut out = vec![];
+    if direct {
+        collect(body, &mut out);
+    }
+    out
+}
+
+/* The text of a constant declared with a string literal. */
+fn constant_text(decl: &VarDecl) -> Option<String> {
+    match &decl.value {
+        Some(Expr {
+            kind: ExprKind::Str(text),
+            ..
+        }) if decl.storage == Some(Storage::Const) => Some(text.clone()),
+        _ => None,
+    }
+}
+
+/* What the compiler knows of a local variable. */
+#[derive(Debug, Clone)]
+struct Variable {
+    ty: Type,
+    constant: bool,
+    /* The exact type of its value, when it always has one. */
+    exact: Option<Type>,
+    /* The text of a constant string literal, which may be a format string. */
+    text: Option<String>,
+    /* Declared without a value, which its type does not admit, so that reading it
+     * fails until it is assigned. */
+    unassigned: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Local {
+    name: String,
+    slot: u32,
+    boxed: bool,
+    /* A cell made when its block is entered, for the nested functions declared before
+     * the variable itself; the code of the block only sees it once declared. */
+    declared: bool,
+    var: Variable,
+    /* Set for `global x` inside a function, which refers to the global. */
+    global: Option<u32>,
+    /* The declaration of a variable; names the resolver tied to another skip it. */
+    id: Option<BindingId>,
+}
+
+enum Place {
+    Local(u32, bool, Variable),
+    Capture(u32, Variable),
+    Global(u32),
+}
+
+struct FunctionState {
+    function: Function,
+    scopes: Vec<Vec<Local>>,
+    captured: HashSet<String>,
+    /* The names of the captured variables, by capture index. */
+    capture_names: Vec<(String, Variable)>,
+    next_slot: u32,
+    /* The number of temporaries on the stack at the current instruction. */
+    temps: u32,
+    /* The enclosing loops, innermost last. */
+    loops: Vec<Loop>,
+    /* The number of `try` blocks around the code being compiled. */
+    tries: u32,
+    /* The declarations of its `static` variables, by index, with their slots. */
+    statics: Vec<(*const VarDecl, u32)>,
+}
+
+/* A loop being compiled: its label, the start of each iteration, which `continue`
+ * jumps to, the `break` jumps to patch at its exit, and the number of `try` blocks
+ * around it. */
+struct Loop {
+    label: Option<String>,
+    head: u32,
+    breaks: Vec<usize>,
+    tries: u32,
+}
+
+struct Compiler {
+    /* The functions being compiled, innermost last; the program itself first. */
+    states: Vec<FunctionState>,
+    globals: Vec<String>,
+    /* The names declared anywhere, which may shadow a builtin. */
+    declared: HashSet<String>,
+    /* The globals declared `const`, with the text of those that are string literals. */
+    constants: HashMap<String, Option<String>>,
+    /* The types declared in each scope being compiled, the top level first. */
+    types: Vec<Vec<(String, TypeDecl)>>,
+    /* The `yield` the statement being compiled is made of, the only place one can
+     * suspend a coroutine. */
+    statement_yield: Option<*const Expr>,
+}
+
+/* What a type name declared in the program stands for. */
+#[derive(Debug, Clone)]
+enum TypeDecl {
+    Struct(Rc<StructDecl>),
+    Enum,
+    Alias(Rc<TypeAlias>),
+}
+
+impl Compiler {
+    fn state(&mut self) -> &mut FunctionState {
+        self.states
+            .last_mut()
+            .expect("a function is being compiled")
+    }
+
+    fn function(&mut self) -> &mut Function {
+        &mut self.state().function
+    }
+
+    fn begin_function(&mut self, function: Function, body: &Block) {
+        self.states.push(FunctionState {
+            function,
+            scopes: vec![],
+            captured: captured_names(body),
+            capture_names: vec![],
+            next_slot: 0,
+            temps: 0,
+            loops: vec![],
+            tries: 0,
+            statics: vec![],
+        });
+    }
+
+    fn end_function(&mut self) -> Function {
+        let state = self.states.pop().expect("a function is being compiled");
+        state.function
+    }
+
+    /* Ends the function being compiled, adding it to the functions of the enclosing
+     * one, and returns its index there. */
+    fn end_inner_function(&mut self) -> u32 {
+        let function = self.end_function();
+        let parent = self.function();
+        parent.functions.push(Rc::new(function));
+        parent.functions.len() as u32 - 1
+    }
+
+    fn at_top_level(&self) -> bool {
+        self.states.len() == 1 && self.states[0].scopes.is_empty()
+    }
+
+    fn global(&mut self, name: &str) -> u32 {
+        let index = match self.globals.iter().position(|global| global == name) {
+            Some(index) => index,
+            None => {
+                self.globals.push(name.to_owned());
+                self.globals.len() - 1
+            }
+        };
+        index as u32
+    }
+
+    /* Emitting code */
+
+    fn effect(&self, op: Op) -> i64 {
+        let function = &self.states[self.states.len() - 1].function;
+        match op {
+            Op::Constant(_)
+            | Op::Literal(_)
+            | Op::Nil
+            | Op::Dup
+            | Op::GetLocal(_)
+            | Op::GetCell(_)
+            | Op::GetCapture(_)
+            | Op::GetGlobal(_)
+            | Op::AssignGlobal(_)
+            | Op::IndexSlot
+            | Op::Closure(_)
+            | Op::Struct(_)
+            | Op::Enum(_)
+            | Op::FieldSlot(_)
+            | Op::Payload(..)
+            | Op::RangeNext(..)
+            | Op::ThroughNext(..)
+            | Op::IterNext(..)
+            | Op::Match(..) => 1,
+            Op::Pop
+            | Op::SetLocal(_)
+            | Op::SetCell(_)
+            | Op::SetCapture(_)
+            | Op::SetGlobal(_)
+            | Op::Binary(_)
+            | Op::Repeat
+            | Op::Index
+            | Op::JumpIfFalse(_)
+            | Op::JumpIfTrue(_)
+            | Op::Return
+            | Op::IterStart(_)
+            | Op::EntriesStart(_)
+            | Op::Raise
+            | Op::Range(_, false) => -1,
+            Op::Range(_, true) => -2,
+            Op::DefineGlobal(index) => -i64::from(function.globals[index as usize].has_value),
+            Op::Array(len) => 1 - i64::from(len),
+            Op::Table(len, _) => 1 - 2 * i64::from(len),
+            Op::EntriesNext(..) => 2,
+            Op::StoreIndex => -3,
+            Op::StoreField(_) => -2,
+            Op::RangeStart(_) => -2,
+            Op::Call(site) | Op::TailCall(site) => {
+                let site = &function.sites[site as usize];
+                -(i64::from(site.argc) + site.named.len() as i64)
+            }
+            Op::Swap
+            | Op::NewCell(_)
+            | Op::TryStart(_)
+            | Op::TryEnd
+            | Op::Unwrap
+            | Op::Yield
+            | Op::Check(_)
+            | Op::Assigned(_)
+            | Op::Copy
+            | Op::Collection(_)
+            | Op::Static(..)
+            | Op::StaticInit(..)
+            | Op::Condition
+            | Op::Unary(_)
+            | Op::Cast(_)
+            | Op::Field(_)
+            | Op::SafeField(..)
+            | Op::Jump(_)
+            | Op::JumpIfNotNil(_)
+            | Op::MatchValue(_)
+            | Op::Fail(_) => 0,
+        }
+    }
+
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        let effect = self.effect(op);
+        let state = self.state();
+        state.temps = u32::try_from(i64::from(state.temps) + effect).unwrap_or(0);
+        state.function.code.push(op);
+        state.function.spans.push(span);
+        state.function.code.len() - 1
+    }
+
+    fn here(&mut self) -> u32 {
+        self.function().code.len() as u32
+    }
+
+    /* Points the jump at `at` to the next instruction. */
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.function().code[at] {
+            Op::Jump(to)
+            | Op::JumpIfFalse(to)
+            | Op::JumpIfTrue(to)
+            | Op::JumpIfNotNil(to)
+            | Op::SafeField(_, to)
+            | Op::StaticInit(_, to)
+            | Op::TryStart(to)
+            | Op::RangeNext(_, to)
+            | Op::ThroughNext(_, to)
+            | Op::IterNext(_, to)
+            | Op::EntriesNext(_, to) => *to = target,
+            _ => unreachable!("only jumps are patched"),
+        }
+    }
+
+    /* The slot of the temporary on top of the stack. */
+    fn top(&mut self) -> u32 {
+        self.state().temps - 1
+    }
+
+    fn constant(&mut self, value: Value, span: Span) {
+        let function = self.function();
+        function.constants.push(value);
+        let index = function.constants.len() as u32 - 1;
+        self.emit(Op::Constant(index), span);
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        let function = self.function();
+        match function.names.iter().position(|known| known == name) {
+            Some(index) => index as u32,
+            None => {
+                function.names.push(name.to_owned());
+                function.names.len() as u32 - 1
+            }
+        }
+    }
+
+    fn fail(&mut self, message: &str, span: Span) {
+        let message = self.name(message);
+        self.emit(Op::Fail(message), span);
+    }
+
+    fn check(&mut self, ty: &Type, what: String, span: Span) {
+        if *ty == Type::Unknown {
+            return;
+        }
+        let function = self.function();
+        function.checks.push((ty.clone(), what));
+        let index = function.checks.len() as u32 - 1;
+        self.emit(Op::Check(index), span);
+    }
+
+    /* Scopes */
+
+    fn begin_scope(&mut self) {
+        self.state().scopes.push(vec![]);
+        self.types.push(vec![]);
+    }
+
+    fn end_scope(&mut self) {
+        self.types.pop();
+        let state = self.state();
+        let scope = state.scopes.pop().expect("scopes are balanced");
+        let slots = scope.iter().filter(|local| local.global.is_none());
+        if let Some(first) = slots.map(|local| local.slot).min() {
+            state.next_slot = state.next_slot.min(first);
+        }
+    }
+
+    fn alloc_slot(&mut self) -> u32 {
+        let state = self.state();
+        let slot = state.next_slot;
+        state.next_slot += 1;
+        state.function.slots = state.function.slots.max(state.next_slot as usize);
+        slot
+    }
+
+    /* Declares a variable in the innermost scope, reusing the slot of one by the same
+     * name there. Returns its slot and whether it lives in a cell. */
+    fn declare(
+        &mut self,
+        name: &str,
+        id: Option<BindingId>,
+        var: Variable,
+        span: Span,
+    ) -> (u32, bool) {
+        let state = self.state();
+        let scope = state
+            .scopes
+            .last_mut()
+            .expect("locals are declared in a scope");
+        if let Some(local) = scope.iter_mut().rev().find(|local| local.name == name) {
+            if local.global.is_none() {
+                local.declared = true;
+                local.var = var;
+                local.id = id;
+                return (local.slot, local.boxed);
+            }
+        }
+        let boxed = state.captured.contains(name);
+        let slot = self.alloc_slot();
+        self.state()
+            .scopes
+            .last_mut()
+            .expect("locals are declared in a scope")
+            .push(Local {
+                name: name.to_owned(),
+                slot,
+                boxed,
+                declared: true,
+                var,
+                global: None,
+                id,
+            });
+        if boxed {
+            self.emit(Op::NewCell(slot), span);
+        }
+        (slot, boxed)
+    }
+
+    fn store_local(&mut self, slot: u32, boxed: bool, span: Span) {
+        let op = if boxed {
+            Op::SetCell(slot)
+        } else {
+            Op::SetLocal(slot)
+        };
+        self.emit(op, span);
+    }
+
+    /* The local `name` refers to in function `depth`. A variable declared after a
+     * nested function, in a scope they share, has another id than the resolver
+     * recorded and is skipped; `pending` includes cells made for variables not yet
+     * declared. */
+    fn find_local(
+        &self,
+        depth: usize,
+        name: &str,
+        id: Option<BindingId>,
+        pending: bool,
+    ) -> Option<&Local> {
+        self.states[depth]
+            .scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|local| {
+                local.name == name
+                    && (local.declared || pending)
+                    && (id.is_none() || local.id.is_none() || local.id == id)
+            })
+    }
+
+    /* Whether `print` and friends read `arg` as a format string, when it comes first:
+     * a string literal or a constant. Also its text, when that is known. */
+    fn format_string(&self, arg: Option<&Expr>) -> (bool, Option<String>) {
+        let name = match arg.map(|arg| &arg.kind) {
+            Some(ExprKind::Str(text)) => return (true, Some(text.clone())),
+            Some(ExprKind::Ident(name, _)) => name,
+            _ => return (false, None),
+        };
+        let depth = self.states.len() - 1;
+        let local = (0..=depth)
+            .rev()
+            .find_map(|at| self.find_local(at, name, None, at != depth));
+        match local {
+            Some(Local {
+                global: None, var, ..
+            }) => (var.constant, var.text.clone()),
+            _ => match self.constants.get(name) {
+                Some(text) => (true, text.clone()),
+                None => (false, None),
+            },
+        }
+    }
+
+    fn place(&mut self, name: &str, id: Option<BindingId>, span: Span) -> CompileResult<Place> {
+        let depth = self.states.len() - 1;
+        if let Some(local) = self.find_local(depth, name, id, false) {
+            return Ok(match local.global {
+                Some(global) => Place::Global(global),
+                None => Place::Local(local.slot, local.boxed, local.var.clone()),
+            });
+        }
+        match self.capture(depth, name, id, span)? {
+            Some(place) => Ok(place),
+            None => Ok(Place::Global(self.global(name))),
+        }
+    }
+
+    /* Captures `name` from the functions enclosing function `depth`, or `None` if it
+     * is a global. */
+    fn capture(
+        &mut self,
+        depth: usize,
+        name: &str,
+        id: Option<BindingId>,
+        span: Span,
+    ) -> CompileResult<Option<Place>> {
+        if depth == 0 {
+            return Ok(None);
+        }
+        let state = &self.states[depth];
+        if let Some(index) = state.capture_names.iter().position(|(n, _)| n == name) {
+            let var = state.capture_names[index].1.clone();
+            return Ok(Some(Place::Capture(index as u32, var)));
+        }
+        let (source, var) = match self.find_local(depth - 1, name, id, true) {
+            Some(Local {
+                global: Some(global),
+                ..
+            }) => return Ok(Some(Place::Global(*global))),
+            Some(local) if !local.boxed => {
+                return Err(unsupported(span, "Variables captured this way"))
+            }
+            Some(local) => (Capture::Local(local.slot), local.var.clone()),
+            None => match self.capture(depth - 1, name, id, span)? {
+                Some(Place::Capture(index, var)) => (Capture::Capture(index), var),
+                place => return Ok(place),
+            },
+        };
+        let state = &mut self.states[depth];
+        state.capture_names.push((name.to_owned(), var.clone()));
+        state.function.captures.push(source);
+        Ok(Some(Place::Capture(
+            state.function.captures.len() as u32 - 1,
+            var,
+        )))
+    }
+
+    /* Pushes the value of a variable, and returns its exact type if known. */
+    fn get(&mut self, place: &Place, name: &str, span: Span) -> Option<Type> {
+        let exact = self.load(place, span);
+        if let Place::Local(_, _, var) | Place::Capture(_, var) = place {
+            if var.unassigned {
+                let name = self.name(name);
+                self.emit(Op::Assigned(name), span);
+            }
+        }
+        exact
+    }
+
+    /* Pushes the value of a variable as it is, `nil` if it has not been assigned. */
+    fn load(&mut self, place: &Place, span: Span) -> Option<Type> {
+        match place {
+            Place::Local(slot, boxed, var) => {
+                let op = if *boxed {
+                    Op::GetCell(*slot)
+                } else {
+                    Op::GetLocal(*slot)
+                };
+                self.emit(op, span);
+                var.exact.clone()
+            }
+            Place::Capture(index, var) => {
+                self.emit(Op::GetCapture(*index), span);
+                var.exact.clone()
+            }
+            Place::Global(global) => {
+                self.emit(Op::GetGlobal(*global), span);
+                None
+            }
+        }
+    }
+
+    /* Types */
+
+    fn type_decl(&self, name: &str) -> Option<&TypeDecl> {
+        self.types
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(known, _)| known == name)
+            .map(|(_, decl)| decl)
+    }
+
+    /* The types `block` declares, which can be used anywhere in it. */
+    fn declare_types(&mut self, block: &Block) {
+        let scope = self.types.last_mut().expect("the top level has a scope");
+        for stmt in block {
+            let (name, decl) = match &stmt.kind {
+                StmtKind::Struct(decl) => (&decl.name, TypeDecl::Struct(Rc::clone(decl))),
+                StmtKind::Enum(decl) => (&decl.name, TypeDecl::Enum),
+                StmtKind::TypeAlias(alias) => (&alias.name, TypeDecl::Alias(Rc::clone(alias))),
+                _ => continue,
+            };
+            scope.push((name.clone(), decl));
+        }
+    }
+
+    /* The runtime view of `ty`, as in the evaluator: aliases are expanded, and the
+     * names that are not types in scope, as generic type parameters, are erased to
+     * unknown. */
+    fn resolve(&self, ty: &Type) -> Type {
+        self.resolve_except(ty, &[], 0)
+    }
+
+    /* Like `resolve`, but leaves the names in `keep` alone for substitution. */
+    fn resolve_except(&self, ty: &Type, keep: &[String], depth: usize) -> Type {
+        ty.map_named(&mut |name, args| {
+            if keep.iter().any(|param| param == name) {
+                return None;
+            }
+            match self.type_decl(name) {
+                Some(TypeDecl::Struct(_) | TypeDecl::Enum) => None,
+                Some(TypeDecl::Alias(alias)) if depth < MAX_ALIAS_DEPTH => {
+                    let body = self.resolve_except(&alias.ty, &alias.params, depth + 1);
+                    Some(body.substitute(&alias.params, args))
+                }
+                _ => Some(Type::Unknown),
+            }
+        })
+    }
+
+    /* Statements */
+
+    fn block(&mut self, block: &Block) -> CompileResult<()> {
+        self.begin_scope();
+        self.stmts(block)?;
+        self.end_scope();
+        Ok(())
+    }
+
+    /* Compiles `block` in the current scope. As in the evaluator, its functions,
+     * structs and enums can be used anywhere in it, so they are created first. */
+    fn stmts(&mut self, block: &Block) -> CompileResult<()> {
+        self.declare_types(block);
+        if self.at_top_level() {
+            self.hoist_globals(block)?;
+        } else {
+            self.hoist_locals(block)?;
+        }
+        for stmt in block {
+            self.stmt(stmt)?;
+            debug_assert_eq!(
+                self.state().temps,
+                0,
+                "statements leave the stack as it was"
+            );
+        }
+        Ok(())
+    }
+
+    fn hoist_globals(&mut self, block: &Block) -> CompileResult<()> {
+        for stmt in block {
+            let Some((name, ty)) = hoisted(stmt) else {
+                continue;
+            };
+            let global = self.global(name);
+            self.hoisted_value(stmt)?;
+            let function = self.function();
+            function.globals.push(GlobalDecl {
+                global,
+                ty,
+                storage: None,
+                has_value: true,
+            });
+            let index = function.globals.len() as u32 - 1;
+            self.emit(Op::DefineGlobal(index), stmt.span);
+        }
+        Ok(())
+    }
+
+    /* Pushes the function, struct type or enum type `stmt` declares. */
+    fn hoisted_value(&mut self, stmt: &Stmt) -> CompileResult<()> {
+        match &stmt.kind {
+            StmtKind::Func(decl) => self.closure(decl),
+            StmtKind::Struct(decl) => self.struct_type(decl),
+            StmtKind::Enum(decl) => {
+                self.enum_type(decl, stmt.span);
+                Ok(())
+            }
+            _ => unreachable!("only functions and types are hoisted"),
+        }
+    }
+
+    fn hoist_locals(&mut self, block: &Block) -> CompileResult<()> {
+        for stmt in block {
+            if let StmtKind::VarDecl(decl) = &stmt.kind {
+                if self.static_slot(decl).is_some() {
+                    continue;
+                }
+                let state = self.state();
+                let scope = state.scopes.last().expect("blocks have a scope");
+                if decl.storage == Some(Storage::Global)
+                    || !state.captured.contains(&decl.name)
+                    || scope.iter().any(|local| local.name == decl.name)
+                {
+                    continue;
+                }
+                let slot = self.alloc_slot();
+                /* The cell holds `nil` until the declaration is run. */
+                let unassigned = !self.resolve(&decl.ty).admits_nil();
+                self.state()
+                    .scopes
+                    .last_mut()
+                    .expect("blocks have a scope")
+                    .push(Local {
+                        name: decl.name.clone(),
+                        slot,
+                        boxed: true,
+                        declared: false,
+                        var: Variable {
+                            ty: decl.ty.clone(),
+                            constant: decl.storage == Some(Storage::Const),
+                            exact: None,
+                            text: None,
+                            unassigned,
+                        },
+                        global: None,
+                        id: decl.binding.get(),
+                    });
+                self.emit(Op::NewCell(slot), stmt.span);
+            }
+        }
+        let mut values = vec![];
+        for stmt in block {
+            if let Some((name, ty)) = hoisted(stmt) {
+                let var = Variable {
+                    ty,
+                    constant: false,
+                    exact: None,
+                    text: None,
+                    unassigned: false,
+                };
+                values.push((stmt, self.declare(name, None, var, stmt.span)));
+            }
+        }
+        for (stmt, (slot, boxed)) in values {
+            self.hoisted_value(stmt)?;
+            self.store_local(slot, boxed, stmt.span);
+        }
+        Ok(())
+    }
+
+    /* The index and slot of `decl` if it is a `static` variable of the function. */
+    fn static_slot(&mut self, decl: &VarDecl) -> Option<(u32, u32)> {
+        let statics = &self.state().statics;
+        let index = statics
+            .iter()
+            .position(|(known, _)| std::ptr::eq(*known, decl))?;
+        Some((index as u32, statics[index].1))
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> CompileResult<()> {
+        let span = stmt.span;
+        self.statement_yield = match &stmt.kind {
+            StmtKind::Expr(value)
+            | StmtKind::Assign { value, .. }
+            | StmtKind::VarDecl(VarDecl {
+                value: Some(value), ..
+            }) if matches!(value.kind, ExprKind::Yield(_)) => Some(value as *const Expr),
+            _ => None,
+        };
+        match &stmt.kind {
+            StmtKind::VarDecl(decl) => self.var_decl(decl, span)?,
+            StmtKind::Func(_)
+            | StmtKind::Struct(_)
+            | StmtKind::Enum(_)
+            | StmtKind::TypeAlias(_) => (),
+            StmtKind::Expr(expr) => {
+                self.expr(expr, &Hint::None)?;
+                self.emit(Op::Pop, span);
+            }
+            StmtKind::Assign { target, op, value } => self.assign(target, *op, value)?,
+            StmtKind::If {
+                branches,
+                else_block,
+            } => {
+                let mut ends = vec![];
+                for (cond, body) in branches {
+                    self.condition(cond)?;
+                    let next = self.emit(Op::JumpIfFalse(0), cond.span);
+                    self.block(body)?;
+                    ends.push(self.emit(Op::Jump(0), span));
+                    self.patch(next);
+                }
+                if let Some(body) = else_block {
+                    self.block(body)?;
+                }
+                for end in ends {
+                    self.patch(end);
+                }
+            }
+            StmtKind::Given(given) => {
+                self.begin_scope();
+                let subject = self.alloc_slot();
+                self.state()
+                    .scopes
+                    .last_mut()
+                    .expect("given has a scope")
+                    .push(Local {
+                        name: String::new(),
+                        slot: subject,
+                        boxed: false,
+                        declared: false,
+                        var: Variable {
+                            ty: Type::Unknown,
+                            constant: true,
+                            exact: None,
+                            text: None,
+                            unassigned: false,
+                        },
+                        global: None,
+                        id: None,
+                    });
+                self.expr(&given.subject, &Hint::None)?;
+                self.emit(Op::SetLocal(subject), given.subject.span);
+                let mut ends = vec![];
+                for arm in &given.arms {
+                    /* The variables the patterns bind are in the scope of the body. */
+                    self.begin_scope();
+                    let mut bound = HashMap::new();
+                    for pattern in &arm.patterns {
+                        let PatternKind::Name {
+                            bindings: Some(names),
+                            ..
+                        } = &pattern.kind
+                        else {
+                            continue;
+                        };
+                        for name in names {
+                            if !bound.contains_key(name) {
+                                let var = Variable {
+                                    ty: Type::Unknown,
+                                    constant: false,
+                                    exact: None,
+                                    text: None,
+                                    unassigned: false,
+                                };
+                                bound.insert(name, self.declare(name, None, var, pattern.span));
+                            }
+                        }
+                    }
+                    let mut bodies = vec![];
+                    for pattern in &arm.patterns {
+                        match &pattern.kind {
+                            PatternKind::Wildcard => {
+                                bodies.push(self.emit(Op::Jump(0), pattern.span));
+                            }
+                            PatternKind::Name {
+                                bindings: Some(names),
+                                ..
+                            } => {
+                                self.pattern(pattern, subject)?;
+                                let next = self.emit(Op::JumpIfFalse(0), pattern.span);
+                                for (i, name) in names.iter().enumerate() {
+                                    let (slot, boxed) = bound[name];
+                                    self.emit(Op::Payload(subject, i as u32), pattern.span);
+                                    self.store_local(slot, boxed, pattern.span);
+                                }
+                                bodies.push(self.emit(Op::Jump(0), pattern.span));
+                                self.patch(next);
+                            }
+                            _ => {
+                                self.pattern(pattern, subject)?;
+                                bodies.push(self.emit(Op::JumpIfTrue(0), pattern.span));
+                            }
+                        }
+                    }
+                    let next = self.emit(Op::Jump(0), arm.span);
+                    for body in bodies {
+                        self.patch(body);
+                    }
+                    self.stmts(&arm.body)?;
+                    self.end_scope();
+                    ends.push(self.emit(Op::Jump(0), arm.span));
+                    self.patch(next);
+                }
+                for end in ends {
+                    self.patch(end);
+                }
+                self.end_scope();
+            }
+            StmtKind::For {
+                var_ty,
+                var,
+                value_var,
+                iter,
+                body,
+                label,
+            } => self.for_loop(var_ty.as_ref(), var, value_var.as_ref(), iter, body, label)?,
+            StmtKind::While { cond, body, label } => {
+                let head = self.here();
+                self.condition(cond)?;
+                let exit = self.emit(Op::JumpIfFalse(0), cond.span);
+                self.loop_body(body, head, label, span)?;
+                self.patch(exit);
+            }
+            StmtKind::Loop { body, label } => {
+                let head = self.here();
+                self.loop_body(body, head, label, span)?;
+            }
+            StmtKind::Break(label) | StmtKind::Continue(label) => {
+                let target = self
+                    .state()
+                    .loops
+                    .iter()
+                    .rposition(|enclosing| label.is_none() || enclosing.label == *label);
+                let is_break = matches!(stmt.kind, StmtKind::Break(_));
+                /* Leaving the `try` blocks inside the loop. */
+                if let Some(index) = target {
+                    for _ in self.state().loops[index].tries..self.state().tries {
+                        self.emit(Op::TryEnd, span);
+                    }
+                }
+                match target {
+                    Some(index) if is_break => {
+                        let jump = self.emit(Op::Jump(0), span);
+                        self.state().loops[index].breaks.push(jump);
+                    }
+                    Some(index) => {
+                        let head = self.state().loops[index].head;
+                        self.emit(Op::Jump(head), span);
+                    }
+                    None if is_break => self.fail("`break` outside of a loop.", span),
+                    None => self.fail("`continue` outside of a loop.", span),
+                }
+            }
+            StmtKind::Return(value) => {
+                let ret = self.function().ret.clone();
+                match value {
+                    /* Not in a `try` block or a coroutine, whose frame the call must
+                     * keep. */
+                    Some(Expr {
+                        kind: ExprKind::Call(callee, args),
+                        span,
+                    }) if self.states.len() > 1
+                        && self.states[self.states.len() - 1].tries == 0
+                        && !self.states[self.states.len() - 1].function.coroutine =>
+                    {
+                        self.call(callee, args, &Hint::of(&ret), *span, true)?;
+                    }
+                    Some(expr) => {
+                        self.expr(expr, &Hint::of(&ret))?;
+                    }
+                    None => {
+                        self.emit(Op::Nil, span);
+                    }
+                }
+                if self.states.len() == 1 {
+                    self.emit(Op::Pop, span);
+                    self.fail("`return` outside of a function.", span);
+                } else {
+                    self.emit(Op::Return, span);
+                }
+            }
+            StmtKind::Try { body, var, handler } => {
+                let start = self.emit(Op::TryStart(0), span);
+                self.state().tries += 1;
+                self.block(body)?;
+                self.state().tries -= 1;
+                self.emit(Op::TryEnd, span);
+                let end = self.emit(Op::Jump(0), span);
+                /* The handler starts with the error on the stack. */
+                self.patch(start);
+                self.state().temps += 1;
+                self.begin_scope();
+                match var {
+                    Some(var) => {
+                        let variable = Variable {
+                            ty: Type::Unknown,
+                            constant: false,
+                            exact: None,
+                            text: None,
+                            unassigned: false,
+                        };
+                        let (slot, boxed) = self.declare(var, None, variable, span);
+                        self.store_local(slot, boxed, span);
+                    }
+                    None => {
+                        self.emit(Op::Pop, span);
+                    }
+                }
+                self.stmts(handler)?;
+                self.end_scope();
+                self.patch(end);
+            }
+            StmtKind::Raise(value) => {
+                self.expr(value, &Hint::None)?;
+                self.emit(Op::Raise, span);
+            }
+        }
+        Ok(())
+    }
+
+    fn condition(&mut self, cond: &Expr) -> CompileResult<()> {
+        self.expr(cond, &Hint::Type(Type::Bool))?;
+        self.emit(Op::Condition, cond.span);
+        Ok(())
+    }
+
+    /* The body of a loop that starts at `head`, and the exit its `break`s jump to. */
+    fn loop_body(
+        &mut self,
+        body: &Block,
+        head: u32,
+        label: &Option<String>,
+        span: Span,
+    ) -> CompileResult<()> {
+        let tries = self.state().tries;
+        self.state().loops.push(Loop {
+            label: label.clone(),
+            head,
+            breaks: vec![],
+            tries,
+        });
+        self.block(body)?;
+        self.emit(Op::Jump(head), span);
+        self.end_loop();
+        Ok(())
+    }
+
+    fn end_loop(&mut self) {
+        if let Some(enclosing) = self.state().loops.pop() {
+            for jump in enclosing.breaks {
+                self.patch(jump);
+            }
+        }
+    }
+
+    fn var_decl(&mut self, decl: &VarDecl, span: Span) -> CompileResult<()> {
+        let ty = self.resolve(&decl.ty);
+        if let Some((index, slot)) = self.static_slot(decl) {
+            return self.static_decl(decl, &ty, index, slot, span);
+        }
+        let global = decl.storage == Some(Storage::Global);
+        if global || self.at_top_level() {
+            let index = self.global(&decl.name);
+            let hint = match (&ty, global) {
+                (Type::Unknown, true) => Hint::Global(index),
+                (ty, _) => Hint::of(ty),
+            };
+            let mut value_span = span;
+            if let Some(value) = &decl.value {
+                let known = self.expr(value, &hint)?;
+                if known.is_none() && exact(&ty).is_none() {
+                    self.emit(Op::Copy, value.span);
+                }
+                value_span = value.span;
+            }
+            let function = self.function();
+            function.globals.push(GlobalDecl {
+                global: index,
+                ty,
+                storage: decl.storage,
+                has_value: decl.value.is_some(),
+            });
+            let define = function.globals.len() as u32 - 1;
+            self.emit(Op::DefineGlobal(define), value_span);
+            if global && !self.at_top_level() {
+                let scope = self.state().scopes.last_mut();
+                let scope = scope.expect("a global declared in a function is in a scope");
+                scope.push(Local {
+                    name: decl.name.clone(),
+                    slot: 0,
+                    boxed: false,
+                    declared: true,
+                    var: Variable {
+                        ty: Type::Unknown,
+                        constant: false,
+                        exact: None,
+                        text: None,
+                        unassigned: false,
+                    },
+                    global: Some(index),
+                    id: None,
+                });
+            }
+            return Ok(());
+        }
+        let mut exact = None;
+        match &decl.value {
+            Some(value) => {
+                let known = self.expr(value, &Hint::of(&ty))?;
+                self.check(&ty, format!("variable `{}`", decl.name), value.span);
+                exact = match ty {
+                    Type::Unknown => known,
+                    ref ty => self::exact(ty),
+                };
+                if exact.is_none() {
+                    self.emit(Op::Copy, value.span);
+                }
+            }
+            None => {
+                self.emit(Op::Nil, span);
+            }
+        }
+        let var = Variable {
+            unassigned: decl.value.is_none() && !ty.admits_nil(),
+            ty,
+            constant: decl.storage == Some(Storage::Const),
+            exact,
+            text: constant_text(decl),
+        };
+        let (slot, boxed) = self.declare(&decl.name, decl.binding.get(), var, span);
+        self.store_local(slot, boxed, span);
+        Ok(())
+    }
+
+    /* A `static` variable is only initialised by the first call of its closure. */
+    fn static_decl(
+        &mut self,
+        decl: &VarDecl,
+        ty: &Type,
+        index: u32,
+        slot: u32,
+        span: Span,
+    ) -> CompileResult<()> {
+        let skip = self.emit(Op::StaticInit(index, 0), span);
+        match &decl.value {
+            Some(value) => {
+                self.expr(value, &Hint::of(ty))?;
+                self.check(ty, format!("variable `{}`", decl.name), value.span);
+                if exact(ty).is_none() {
+                    self.emit(Op::Copy, value.span);
+                }
+            }
+            None => {
+                self.emit(Op::Nil, span);
+            }
+        }
+        self.store_local(slot, true, span);
+        self.patch(skip);
+        let local = self
+            .state()
+            .scopes
+            .iter_mut()
+            .flatten()
+            .find(|local| local.slot == slot && local.name == decl.name);
+        if let Some(local) = local {
+            local.declared = true;
+        }
+        Ok(())
+    }
+
+    fn assign(&mut self, target: &Expr, op: Option<BinaryOp>, value: &Expr) -> CompileResult<()> {
+        match &target.kind {
+            ExprKind::Ident(name, id) => {
+                let place = self.place(name, id.get(), target.span)?;
+                let (ty, known) = match &place {
+                    Place::Local(_, _, var) | Place::Capture(_, var) => {
+                        if var.constant {
+                            let message = format!("Cannot assign to the constant `{name}`.");
+                            self.fail(&message, target.span);
+                            return Ok(());
+                        }
+                        let known = match op {
+                            Some(BinaryOp::NilCoalesce) => self.load(&place, target.span),
+                            Some(_) => self.get(&place, name, target.span),
+                            None => None,
+                        };
+                        (Some(var.ty.clone()), known)
+                    }
+                    Place::Global(global) => {
+                        self.emit(Op::AssignGlobal(*global), target.span);
+                        /* Reading it again fails if it has not been assigned. */
+                        if !matches!(op, None | Some(BinaryOp::NilCoalesce)) {
+                            self.emit(Op::Pop, target.span);
+                            self.emit(Op::GetGlobal(*global), target.span);
+                        }
+                        (None, None)
+                    }
+                };
+                let hint = match (&ty, &place) {
+                    (Some(ty), _) => Hint::of(ty),
+                    (None, Place::Global(global)) => Hint::Global(*global),
+                    (None, _) => Hint::None,
+                };
+                let local = ty.is_some();
+                let primitive = ty.as_ref().and_then(exact).is_some();
+                self.assigned_value(op, value, &hint, known, local)?;
+                if !primitive && matches!(op, None | Some(BinaryOp::NilCoalesce)) {
+                    self.emit(Op::Copy, value.span);
+                }
+                match place {
+                    Place::Local(slot, boxed, var) => {
+                        self.check(&var.ty, format!("variable `{name}`"), value.span);
+                        self.store_local(slot, boxed, value.span);
+                    }
+                    Place::Capture(index, var) => {
+                        self.check(&var.ty, format!("variable `{name}`"), value.span);
+                        self.emit(Op::SetCapture(index), value.span);
+                    }
+                    Place::Global(global) => {
+                        self.emit(Op::SetGlobal(global), value.span);
+                    }
+                }
+            }
+            ExprKind::Field(object, field) => {
+                self.expr(object, &Hint::None)?;
+                let object = self.top();
+                let field = self.name(field);
+                self.emit(Op::FieldSlot(field), target.span);
+                let hint = Hint::Field(object, field);
+                self.assigned_value(op, value, &hint, None, false)?;
+                self.emit(Op::StoreField(field), value.span);
+            }
+            ExprKind::Index(array, index) => {
+                self.expr(array, &Hint::None)?;
+                let (key, value_hint) = self.index_hints(array)?;
+                self.expr(index, &key)?;
+                self.emit(Op::IndexSlot, index.span);
+                /* `??=` pops the current value before it evaluates `value`, and it was
+                 * nil then anyway. */
+                let hint = match op {
+                    Some(BinaryOp::NilCoalesce) => value_hint,
+                    _ => Hint::Numeric(self.top(), Box::new(value_hint)),
+                };
+                self.assigned_value(op, value, &hint, None, false)?;
+                self.emit(Op::StoreIndex, index.span);
+            }
+            _ => self.fail("Invalid assignment target.", target.span),
+        }
+        Ok(())
+    }
+
+    /* Pushes the value an assignment stores: `value` itself, or `current op value`.
+     * The current value is on the stack, except for a plain assignment to a local
+     * variable, and `hint` is for `value` when it replaces it. */
+    fn assigned_value(
+        &mut self,
+        op: Option<BinaryOp>,
+        value: &Expr,
+        hint: &Hint,
+        known: Option<Type>,
+        local: bool,
+    ) -> CompileResult<()> {
+        match op {
+            None => {
+                self.expr(value, hint)?;
+                if !local {
+                    self.emit(Op::Swap, value.span);
+                    self.emit(Op::Pop, value.span);
+                }
+            }
+            Some(BinaryOp::NilCoalesce) => {
+                let end = self.emit(Op::JumpIfNotNil(0), value.span);
+                self.emit(Op::Pop, value.span);
+                self.expr(value, hint)?;
+                self.patch(end);
+            }
+            Some(op) => {
+                let current = self.top();
+                let hint = Hint::numeric(known.as_ref(), current, &Hint::None);
+                self.expr(value, &hint)?;
+                self.emit(Op::Binary(op), value.span);
+            }
+        }
+        Ok(())
+    }
+
+    fn pattern(&mut self, pattern: &Pattern, subject: u32) -> CompileResult<()> {
+        let mut global = None;
+        if let PatternKind::Name {
+            qualifier: None,
+            name,
+            bindings: None,
+        } = &pattern.kind
+        {
+            match self.place(name, None, pattern.span)? {
+                place @ (Place::Local(_, _, Variable { constant: true, .. })
+                | Place::Capture(_, Variable { constant: true, .. })) => {
+                    self.load(&place, pattern.span);
+                    self.emit(Op::MatchValue(subject), pattern.span);
+                    return Ok(());
+                }
+                Place::Global(index) => global = Some(index),
+                _ => (),
+            }
+        }
+        let function = self.function();
+        function.patterns.push(PatternTest {
+            pattern: pattern.clone(),
+            global,
+        });
+        let index = function.patterns.len() as u32 - 1;
+        self.emit(Op::Match(index, subject), pattern.span);
+        Ok(())
+    }
+
+    fn for_loop(
+        &mut self,
+        var_ty: Option<&Type>,
+        var: &str,
+        value_var: Option<&(Option<Type>, String)>,
+        iter: &Expr,
+        body: &Block,
+        label: &Option<String>,
+    ) -> CompileResult<()> {
+        let ty = var_ty.map_or(Type::Unknown, |ty| self.resolve(ty));
+        self.begin_scope();
+        let state = self.alloc_slot();
+        self.alloc_slot();
+        let hidden = |slot| Local {
+            name: String::new(),
+            slot,
+            boxed: false,
+            declared: false,
+            var: Variable {
+                ty: Type::Unknown,
+                constant: true,
+                exact: None,
+                text: None,
+                unassigned: false,
+            },
+            global: None,
+            id: None,
+        };
+        let scope = self.state().scopes.last_mut().expect("loops have a scope");
+        scope.extend([hidden(state), hidden(state + 1)]);
+        let hint = match var_ty {
+            Some(_) => Hint::of(&ty),
+            None => Hint::None,
+        };
+        /* Ranges with a step are iterated as values. */
+        let (head, next) = if let ExprKind::Range {
+            start,
+            end,
+            step: None,
+            inclusive,
+        } = &iter.kind
+        {
+            self.operands(start, end, &hint)?;
+            self.emit(Op::RangeStart(state), iter.span);
+            if value_var.is_some() {
+                let message = "Only a table has a key and a value to iterate over, found range.";
+                self.fail(message, iter.span);
+                self.end_scope();
+                return Ok(());
+            }
+            let head = self.here();
+            let next = match inclusive {
+                true => Op::ThroughNext(state, 0),
+                false => Op::RangeNext(state, 0),
+            };
+            (head, self.emit(next, iter.span))
+        } else if value_var.is_some() {
+            self.expr(iter, &Hint::None)?;
+            self.emit(Op::EntriesStart(state), iter.span);
+            let head = self.here();
+            (head, self.emit(Op::EntriesNext(state, 0), iter.span))
+        } else {
+            match iter.kind {
+                ExprKind::Range { .. } => self.range(iter, &hint)?,
+                _ => {
+                    self.expr(iter, &Hint::None)?;
+                }
+            }
+            self.emit(Op::IterStart(state), iter.span);
+            let head = self.here();
+            (head, self.emit(Op::IterNext(state, 0), iter.span))
+        };
+        self.check(&ty, format!("loop variable `{var}`"), iter.span);
+        let value_var = value_var.map(|(value_ty, name)| {
+            let value_ty = value_ty
+                .as_ref()
+                .map_or(Type::Unknown, |ty| self.resolve(ty));
+            self.emit(Op::Swap, iter.span);
+            self.check(&value_ty, format!("loop variable `{name}`"), iter.span);
+            (value_ty, name)
+        });
+        let tries = self.state().tries;
+        self.state().loops.push(Loop {
+            label: label.clone(),
+            head,
+            breaks: vec![],
+            tries,
+        });
+        self.begin_scope();
+        let variable = Variable {
+            ty: ty.clone(),
+            constant: false,
+            exact: exact(&ty),
+            text: None,
+            unassigned: false,
+        };
+        let (slot, boxed) = self.declare(var, None, variable, iter.span);
+        if let Some((value_ty, name)) = value_var {
+            let variable = Variable {
+                ty: value_ty.clone(),
+                constant: false,
+                exact: exact(&value_ty),
+                text: None,
+                unassigned: false,
+            };
+            let (value_slot, value_boxed) = self.declare(name, None, variable, iter.span);
+            self.store_local(value_slot, value_boxed, iter.span);
+        }
+        self.store_local(slot, boxed, iter.span);
+        self.stmts(body)?;
+        self.end_scope();
+        self.emit(Op::Jump(head), iter.span);
+        self.patch(next);
+        self.end_loop();
+        self.end_scope();
+        Ok(())
+    }
+
+    /* Functions */
+
+    /* Pushes a closure of `decl`. */
+    fn closure(&mut self, decl: &FuncDecl) -> CompileResult<()> {
+        let index = self.function_decl(decl)?;
+        self.emit(Op::Closure(index), decl.span);
+        Ok(())
+    }
+
+    /* Compiles `decl` into the functions of the enclosing one and returns its index. */
+    fn function_decl(&mut self, decl: &FuncDecl) -> CompileResult<u32> {
+        let function = Function {
+            name: decl.name.clone(),
+            params: decl
+                .params
+                .iter()
+                .map(|param| (param.name.clone(), self.resolve(&param.ty)))
+                .collect(),
+            ret: self.resolve(&decl.ret),
+            uses_self: decl.uses_self,
+            coroutine: decl.coroutine,
+            jit: decl.jit,
+            ..Function::default()
+        };
+        self.begin_function(function, &decl.body);
+        self.begin_scope();
+        let mut params = decl
+            .params
+            .iter()
+            .map(|param| (param.name.as_str(), self.resolve(&param.ty), param.span))
+            .collect::<Vec<_>>();
+        if decl.uses_self {
+            params.push(("self", Type::Unknown, decl.span));
+        }
+        for (name, ty, span) in params {
+            let slot = self.alloc_slot();
+            let boxed = self.state().captured.contains(name);
+            let var = Variable {
+                exact: exact(&ty),
+                ty,
+                constant: false,
+                text: None,
+                unassigned: false,
+            };
+            self.state()
+                .scopes
+                .last_mut()
+                .expect("parameters have a scope")
+                .push(Local {
+                    name: name.to_owned(),
+                    slot,
+                    boxed,
+                    declared: true,
+                    var,
+                    global: None,
+                    id: None,
+                });
+            if boxed {
+                self.emit(Op::GetLocal(slot), span);
+                self.emit(Op::NewCell(slot), span);
+                self.emit(Op::SetCell(slot), span);
+            }
+        }
+        let statics = statics(&decl.body);
+        for (index, var) in statics.iter().enumerate() {
+            let slot = self.alloc_slot();
+            let ty = self.resolve(&var.ty);
+            let state = self.state();
+            state.statics.push((*var as *const VarDecl, slot));
+            state
+                .scopes
+                .last_mut()
+                .expect("parameters have a scope")
+                .push(Local {
+                    name: var.name.clone(),
+                    slot,
+                    boxed: true,
+                    declared: false,
+                    var: Variable {
+                        exact: exact(&ty),
+                        ty,
+                        constant: false,
+                        text: None,
+                        unassigned: false,
+                    },
+                    global: None,
+                    id: None,
+                });
+            self.emit(Op::Static(index as u32, slot), decl.span);
+        }
+        self.function().statics = statics.len();
+        self.stmts(&decl.body)?;
+        self.end_scope();
+        self.emit(Op::Nil, decl.span);
+        self.emit(Op::Return, decl.span);
+        Ok(self.end_inner_function())
+    }
+
+    /* Pushes the struct type `decl`. Its methods and field defaults close over `this`,
+     * the type itself. */
+    fn struct_type(&mut self, decl: &StructDecl) -> CompileResult<()> {
+        self.begin_scope();
+        let this = Variable {
+            ty: Type::Unknown,
+            constant: false,
+            exact: None,
+            text: None,
+            unassigned: false,
+        };
+        let (slot, boxed) = self.declare("this", None, this, decl.span);
+        let fields = decl
+            .fields
+            .iter()
+            .map(|field| FieldDecl {
+                ty: self.resolve_except(&field.ty, &decl.params, 0),
+                name: field.name.clone(),
+                default: None,
+                span: field.span,
+            })
+            .collect::<Vec<_>>();
+        let init = self.field_defaults(decl, &fields)?;
+        let methods = decl
+            .methods
+            .iter()
+            .map(|method| self.function_decl(method))
+            .collect::<CompileResult<Vec<_>>>()?;
+        let function = self.function();
+        function.structs.push(StructDef {
+            decl: Rc::new(StructDecl {
+                name: decl.name.clone(),
+                params: decl.params.clone(),
+                fields,
+                methods: vec![],
+                span: decl.span,
+            }),
+            init,
+            methods,
+        });
+        let index = function.structs.len() as u32 - 1;
+        self.emit(Op::Struct(index), decl.span);
+        if boxed {
+            self.emit(Op::Dup, decl.span);
+            self.emit(Op::SetCell(slot), decl.span);
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    /* Compiles the function returning the defaults of the fields of a struct, typed
+     * as `fields`, in an array. Returns its index. */
+    fn field_defaults(&mut self, decl: &StructDecl, fields: &[FieldDecl]) -> CompileResult<u32> {
+        let function = Function {
+            name: Some(decl.name.clone()),
+            ..Function::default()
+        };
+        self.begin_function(function, &Vec::new());
+        self.begin_scope();
+        for (field, resolved) in decl.fields.iter().zip(fields) {
+            let ty = resolved.ty.substitute(&decl.params, &[]);
+            match &field.default {
+                Some(default) => {
+                    self.expr(default, &Hint::of(&ty))?;
+                    self.check(&ty, format!("field `{}`", field.name), default.span);
+                }
+                None => {
+                    self.emit(Op::Nil, field.span);
+                }
+            }
+        }
+        self.emit(Op::Array(fields.len() as u32), decl.span);
+        self.emit(Op::Return, decl.span);
+        self.end_scope();
+        Ok(self.end_inner_function())
+    }
+
+    /* Pushes the enum type `decl`. */
+    fn enum_type(&mut self, decl: &EnumDecl, span: Span) {
+        let variants = decl
+            .variants
+            .iter()
+            .map(|variant| Variant {
+                name: variant.name.clone(),
+                fields: variant.fields.iter().map(|ty| self.resolve(ty)).collect(),
+                span: variant.span,
+            })
+            .collect();
+        let function = self.function();
+        function.enums.push(Rc::new(EnumDecl {
+            name: decl.name.clone(),
+            variants,
+        }));
+        let index = function.enums.len() as u32 - 1;
+        self.emit(Op::Enum(index), span);
+    }
+
+    /* Expressions */
+
+    /* Pushes the value of `expr`, and returns its exact type if it is known. */
+    fn expr(&mut self, expr: &Expr, hint: &Hint) -> CompileResult<Option<Type>> {
+        let span = expr.span;
+        Ok(match &expr.kind {
+            ExprKind::Int(magnitude) => self.literal(
+                LiteralValue::Int {
+                    negative: false,
+                    magnitude: *magnitude,
+                },
+                hint,
+                span,
+            ),
+            ExprKind::Float(float) => self.literal(LiteralValue::Float(*float), hint, span),
+            ExprKind::Bool(value) => {
+                self.constant(Value::Bool(*value), span);
+                Some(Type::Bool)
+            }
+            ExprKind::Char(ch) => {
+                self.constant(Value::Char(*ch), span);
+                Some(Type::Char)
+            }
+            ExprKind::Str(string) => {
+                self.constant(Value::Str(string.as_str().into()), span);
+                Some(Type::String)
+            }
+            ExprKind::Nil => {
+                self.emit(Op::Nil, span);
+                None
+            }
+            ExprKind::Ident(name, id) => {
+                let place = self.place(name, id.get(), span)?;
+                if let (Place::Global(_), Some(TypeDecl::Alias(_))) = (&place, self.type_decl(name))
+                {
+                    return Err(unsupported(span, "Type aliases used as values"));
+                }
+                self.get(&place, name, span)
+            }
+            ExprKind::SelF => {
+                let place = self.place("self", None, span)?;
+                self.load(&place, span)
+            }
+            ExprKind::This => {
+                let place = self.place("this", None, span)?;
+                self.load(&place, span)
+            }
+            ExprKind::Array(items) => {
+                let elem = hint.elem();
+                for item in items {
+                    self.expr(item, &elem)?;
+                }
+                self.emit(Op::Array(items.len() as u32), span);
+                self.collection(hint, span);
+                None
+            }
+            ExprKind::Table(entries) => {
+                let (mut key_hint, mut value_hint) = (hint.key(), hint.elem());
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    let key_type = self.expr(key, &key_hint)?;
+                    let key_slot = self.top();
+                    let value_type = self.expr(value, &value_hint)?;
+                    /* Without the types from the context, the first entry gives them. */
+                    if i == 0 && key_hint == Hint::None {
+                        key_hint = Hint::numeric(key_type.as_ref(), key_slot, &Hint::None);
+                        value_hint = Hint::numeric(value_type.as_ref(), self.top(), &Hint::None);
+                    }
+                }
+                let index = self.hint(hint);
+                self.emit(Op::Table(entries.len() as u32, index), span);
+                None
+            }
+            ExprKind::Repeat(value, count) => {
+                self.expr(value, &hint.elem())?;
+                self.expr(count, &Hint::None)?;
+                self.emit(Op::Repeat, span);
+                self.collection(hint, span);
+                None
+            }
+            ExprKind::Unary(UnaryOp::Neg, operand) => match operand.kind {
+                ExprKind::Int(magnitude) => {
+                    let value = LiteralValue::Int {
+                        negative: true,
+                        magnitude,
+                    };
+                    self.literal(value, hint, span)
+                }
+                ExprKind::Float(float) => self.literal(LiteralValue::Float(-float), hint, span),
+                _ => {
+                    self.expr(operand, hint)?;
+                    self.emit(Op::Unary(UnaryOp::Neg), span);
+                    None
+                }
+            },
+            ExprKind::Unary(op, operand) => {
+                self.expr(operand, hint)?;
+                self.emit(Op::Unary(*op), span);
+                match op {
+                    UnaryOp::Not => Some(Type::Bool),
+                    UnaryOp::Len => Some(Type::Int(IntKind::I32)),
+                    _ => None,
+                }
+            }
+            ExprKind::Binary(op, lhs, rhs) => self.binary(*op, lhs, rhs, hint, span)?,
+            ExprKind::Range { .. } => {
+                let item = match hint {
+                    Hint::Type(Type::Range(item)) => Hint::of(item),
+                    _ => Hint::None,
+                };
+                self.range(expr, &item)?;
+                None
+            }
+            ExprKind::Call(callee, args) => {
+                self.call(callee, args, hint, span, false)?;
+                None
+            }
+            ExprKind::Index(target, index) => {
+                self.expr(target, &Hint::None)?;
+                let (key, _) = self.index_hints(target)?;
+                /* A range slices, with indices of the type of the others. */
+                match index.kind {
+                    ExprKind::Range { .. } => self.range(index, &key)?,
+                    _ => {
+                        self.expr(index, &key)?;
+                    }
+                }
+                self.emit(Op::Index, index.span);
+                None
+            }
+            ExprKind::Field(target, name) => {
+                self.expr(target, &Hint::None)?;
+                let name = self.name(name);
+                self.emit(Op::Field(name), span);
+                None
+            }
+            ExprKind::SafeField(target, name) => {
+                self.expr(target, &Hint::None)?;
+                let name = self.name(name);
+                let end = self.emit(Op::SafeField(name, 0), span);
+                self.patch(end);
+                None
+            }
+            ExprKind::Func(decl) => {
+                self.closure(decl)?;
+                None
+            }
+            ExprKind::Try(value) => {
+                let known = self.expr(value, hint)?;
+                self.emit(Op::Unwrap, span);
+                known
+            }
+            ExprKind::Yield(value) => {
+                let at_statement = self.statement_yield.take() == Some(expr as *const Expr);
+                if !self.function().coroutine {
+                    self.fail("`yield` outside of a coroutine.", span);
+                    self.emit(Op::Nil, span);
+                    return Ok(None);
+                }
+                if !at_statement {
+                    let message =
+                        "`yield` can only be a statement, or the value of an assignment or declaration.";
+                    self.fail(message, span);
+                    self.emit(Op::Nil, span);
+                    return Ok(None);
+                }
+                let ret = self.function().ret.clone();
+                match value {
+                    Some(value) => {
+                        self.expr(value, &Hint::of(&ret))?;
+                    }
+                    None => {
+                        self.emit(Op::Nil, span);
+                    }
+                }
+                self.emit(Op::Yield, span);
+                None
+            }
+            ExprKind::Cast(ty, value) => {
+                let ty = self.resolve(ty);
+                self.expr(value, &Hint::None)?;
+                let exact = exact(&ty);
+                let function = self.function();
+                function.types.push(ty);
+                let index = function.types.len() as u32 - 1;
+                self.emit(Op::Cast(index), span);
+                exact
+            }
+        })
+    }
+
+    /* An untyped literal, typed now if its hint is known before the program runs. */
+    /* Makes the array just pushed a set or table, if `hint` may ask for one. */
+    fn collection(&mut self, hint: &Hint, span: Span) {
+        if hint.collects() {
+            let index = self.hint(hint);
+            self.emit(Op::Collection(index), span);
+        }
+    }
+
+    fn hint(&mut self, hint: &Hint) -> u32 {
+        let function = self.function();
+        function.hints.push(hint.clone());
+        function.hints.len() as u32 - 1
+    }
+
+    /* The hints for an index of `target`, just pushed, and for the value stored at it:
+     * the key and value types of a table, or else `int32` and the element's type.
+     * They are known here when `target` is a variable declared with its type. */
+    fn index_hints(&mut self, target: &Expr) -> CompileResult<(Hint, Hint)> {
+        let slot = self.top();
+        let declared = match &target.kind {
+            ExprKind::Ident(name, id) => match self.place(name, id.get(), target.span)? {
+                Place::Local(_, _, var) | Place::Capture(_, var) => var.ty,
+                Place::Global(_) => Type::Unknown,
+            },
+            _ => Type::Unknown,
+        };
+        Ok(match declared.non_optional() {
+            Type::Table(key, value) => (Hint::of(key), Hint::of(value)),
+            Type::Unknown | Type::Var(_) => (Hint::Index(slot), Hint::Entry(slot)),
+            _ => (Hint::Type(Type::Int(IntKind::I32)), Hint::None),
+        })
+    }
+
+    fn literal(&mut self, value: LiteralValue, hint: &Hint, span: Span) -> Option<Type> {
+        let Some(ty) = hint.known() else {
+            let function = self.function();
+            function.literals.push(Literal {
+                value,
+                hint: hint.clone(),
+            });
+            let index = function.literals.len() as u32 - 1;
+            self.emit(Op::Literal(index), span);
+            return None;
+        };
+        let value = match value {
+            LiteralValue::Int {
+                negative,
+                magnitude,
+            } => int_literal(negative, magnitude, ty, span),
+            LiteralValue::Float(float) => Ok(float_literal(float, ty)),
+        };
+        match value {
+            Ok(value) => {
+                let ty = inferred_type(&value);
+                self.constant(value, span);
+                Some(ty)
+            }
+            Err(error) => {
+                self.fail(&error.message, span);
+                self.emit(Op::Nil, span);
+                None
+            }
+        }
+    }
+
+    fn binary(
+        &mut self,
+        op: BinaryOp,
+        lhs: &Expr,
+        rhs: &Expr,
+        hint: &Hint,
+        span: Span,
+    ) -> CompileResult<Option<Type>> {
+        let hint = hint.operands(op);
+        match op {
+            BinaryOp::And | BinaryOp::Or => {
+                self.condition(lhs)?;
+                self.emit(Op::Dup, span);
+                let end = match op {
+                    BinaryOp::And => self.emit(Op::JumpIfFalse(0), span),
+                    _ => self.emit(Op::JumpIfTrue(0), span),
+                };
+                self.emit(Op::Pop, span);
+                self.condition(rhs)?;
+                self.patch(end);
+                Ok(Some(Type::Bool))
+            }
+            BinaryOp::Xor => {
+                self.condition(lhs)?;
+                self.condition(rhs)?;
+                self.emit(Op::Binary(BinaryOp::NotEq), span);
+                Ok(Some(Type::Bool))
+            }
+            /* A literal takes the type of the integers of the range, and a range
+             * literal the type of the value. */
+            BinaryOp::In if lhs.is_untyped_literal() => {
+                self.expr(rhs, &Hint::None)?;
+                let slot = self.top();
+                self.expr(lhs, &Hint::Item(slot))?;
+                self.emit(Op::Swap, lhs.span);
+                self.emit(Op::Binary(op), span);
+                Ok(Some(Type::Bool))
+            }
+            BinaryOp::In => {
+                let known = self.expr(lhs, &Hint::None)?;
+                let slot = self.top();
+                let item = Hint::numeric(known.as_ref(), slot, &Hint::None);
+                match rhs.kind {
+                    ExprKind::Range { .. } => self.range(rhs, &item)?,
+                    _ => {
+                        self.expr(rhs, &Hint::None)?;
+                    }
+                }
+                self.emit(Op::Binary(op), span);
+                Ok(Some(Type::Bool))
+            }
+            BinaryOp::NilCoalesce => {
+                self.expr(lhs, &hint)?;
+                let end = self.emit(Op::JumpIfNotNil(0), span);
+                self.emit(Op::Pop, span);
+                self.expr(rhs, &hint)?;
+                self.patch(end);
+                Ok(None)
+            }
+            _ => {
+                self.operands(lhs, rhs, &hint)?;
+                self.emit(Op::Binary(op), span);
+                Ok(op.is_comparison().then_some(Type::Bool))
+            }
+        }
+    }
+
+    /* Pushes the range `expr`, whose bounds are typed by `item` if they are literals,
+     * and its step like the start. */
+    fn range(&mut self, expr: &Expr, item: &Hint) -> CompileResult<()> {
+        let ExprKind::Range {
+            start,
+            end,
+            step,
+            inclusive,
+        } = &expr.kind
+        else {
+            unreachable!("only ranges are compiled as ranges");
+        };
+        self.operands(start, end, item)?;
+        if let Some(step) = step {
+            let start = self.top() - 1;
+            self.expr(step, &Hint::Numeric(start, Box::new(Hint::None)))?;
+        }
+        self.emit(Op::Range(*inclusive, step.is_some()), expr.span);
+        Ok(())
+    }
+
+    /* Pushes both operands, giving an untyped literal the type of the other side. */
+    fn operands(&mut self, lhs: &Expr, rhs: &Expr, hint: &Hint) -> CompileResult<()> {
+        if lhs.is_untyped_literal() && !rhs.is_untyped_literal() {
+            let known = self.expr(rhs, hint)?;
+            let slot = self.top();
+            self.expr(lhs, &Hint::numeric(known.as_ref(), slot, hint))?;
+            self.emit(Op::Swap, lhs.span);
+            return Ok(());
+        }
+        let known = self.expr(lhs, hint)?;
+        let slot = self.top();
+        self.expr(rhs, &Hint::numeric(known.as_ref(), slot, hint))?;
+        Ok(())
+    }
+
+    /* `Box<int8> b = Box(1)` constructs a `Box<int8>`: the type arguments of the
+     * struct `callee` names, if `hint` gives them, with the types of the fields its
+     * arguments fill when it has no constructor. */
+    fn constructed(&self, callee: &Expr, hint: &Hint) -> (Vec<Type>, Vec<Type>) {
+        let (ExprKind::Ident(name, _), Hint::Type(Type::Named(hinted, args))) =
+            (&callee.kind, hint)
+        else {
+            return (vec![], vec![]);
+        };
+        let Some(TypeDecl::Struct(decl)) = self.type_decl(name) else {
+            return (vec![], vec![]);
+        };
+        if name != hinted || args.is_empty() {
+            return (vec![], vec![]);
+        }
+        if decl.method("new").is_some() {
+            return (args.clone(), vec![]);
+        }
+        let fields = decl
+            .fields
+            .iter()
+            .map(|field| {
+                self.resolve_except(&field.ty, &decl.params, 0)
+                    .substitute(&decl.params, args)
+            })
+            .collect();
+        (args.clone(), fields)
+    }
+
+    fn call(
+        &mut self,
+        callee: &Expr,
+        args: &[Expr],
+        hint: &Hint,
+        span: Span,
+        tail: bool,
+    ) -> CompileResult<()> {
+        let mut end = None;
+        let mut arithmetic = false;
+        match &callee.kind {
+            ExprKind::Field(target, name) => {
+                self.expr(target, &Hint::None)?;
+                let name = self.name(name);
+                self.emit(Op::Field(name), callee.span);
+            }
+            /* `a?.f(x)` is `nil` without evaluating `x` when `a` is `nil`. */
+            ExprKind::SafeField(target, name) => {
+                self.expr(target, &Hint::None)?;
+                let name = self.name(name);
+                end = Some(self.emit(Op::SafeField(name, 0), callee.span));
+            }
+            ExprKind::Ident(name, id) => {
+                let place = self.place(name, id.get(), callee.span)?;
+                /* The operands of `wrappingAdd` and friends are typed like those of
+                 * the operator. */
+                if let Place::Global(global) = place {
+                    arithmetic = !self.declared.contains(name)
+                        && matches!(
+                            Builtin::ALL.get(global as usize),
+                            Some(Builtin::Arithmetic(..))
+                        );
+                }
+                self.get(&place, name, callee.span);
+            }
+            _ => {
+                self.expr(callee, &Hint::None)?;
+            }
+        }
+        let function = self.top();
+        let (type_args, fields) = self.constructed(callee, hint);
+        if let (true, [lhs, rhs]) = (arithmetic, args) {
+            self.operands(lhs, rhs, hint)?;
+        } else {
+            for (i, arg) in args.iter().enumerate() {
+                let hint = match fields.get(i) {
+                    Some(ty) => Hint::of(ty),
+                    None => Hint::Param(function, i as u32),
+                };
+                self.expr(arg, &hint)?;
+            }
+        }
+        let mut named = vec![];
+        let (format, text) = self.format_string(args.first());
+        if let Some(text) = text {
+            if let Ok(pieces) = format::parse(&text) {
+                for name in format::names(&pieces) {
+                    if named.iter().any(|known| known == name) {
+                        continue;
+                    }
+                    let place = self.place(name, None, span)?;
+                    if !matches!(place, Place::Global(_)) {
+                        self.load(&place, span);
+                        named.push(name.to_owned());
+                    }
+                }
+            }
+        }
+        let function = self.function();
+        function.sites.push(CallSite {
+            argc: args.len() as u32,
+            arg_spans: args.iter().map(|arg| arg.span).collect(),
+            format,
+            named,
+            type_args,
+        });
+        let site = function.sites.len() as u32 - 1;
+        let op = if tail {
+            Op::TailCall(site)
+        } else {
+            Op::Call(site)
+        };
+        self.emit(op, span);
+        if let Some(end) = end {
+            self.patch(end);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn compile_source(source: &str) -> Result<Bytecode, String> {
+        compile(&parse(source).unwrap()).map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn test_unsupported_constructs() {
+        assert_eq!(
+            compile_source("type Id = int32\nprintln(Id)").unwrap_err(),
+            "Toolip:2:9: Type aliases used as values are not supported by the bytecode compiler."
+        );
+    }
+
+    #[test]
+    fn test_slots_and_captures() {
+        let bytecode = compile_source(
+            "func counter()\n\tint32 n = 0\n\tint32 unused = 1\n\treturn func ()\n\t\tn += 1\n\t\treturn n\n\tend\nend",
+        )
+        .unwrap();
+        let counter = &bytecode.script.functions[0];
+        assert_eq!(counter.slots, 2);
+        assert!(counter.code.contains(&Op::NewCell(0)));
+        assert_eq!(counter.functions[0].captures, vec![Capture::Local(0)]);
+        assert_eq!(bytecode.globals.last().map(String::as_str), Some("counter"));
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let bytecode =
+            compile_source("func int32 f(int32 n)\n\tprintln(f(n))\n\treturn f(n)\nend\nf(1)")
+                .unwrap();
+        let calls = |function: &Function| {
+            let tail = function
+                .code
+                .iter()
+                .filter(|op| matches!(op, Op::TailCall(_)));
+            let other = function.code.iter().filter(|op| matches!(op, Op::Call(_)));
+            (tail.count(), other.count())
+        };
+        assert_eq!(calls(&bytecode.script.functions[0]), (1, 2));
+        assert_eq!(calls(&bytecode.script), (0, 1));
+        /* The `try` would end before the call. */
+        let bytecode = compile_source(
+            "func int32 f(int32 n)\n\ttry\n\t\treturn f(n)\n\tcatch\n\tend\n\treturn 0\nend",
+        )
+        .unwrap();
+        assert_eq!(calls(&bytecode.script.functions[0]), (0, 1));
+    }
+}
@@ -1 +1,3273 @@
+#![allow(dead_code)]
+use crate::ast::{
+    BinaryOp, BindingId, Block, Expr, ExprKind, FuncDecl, Given, Pattern, PatternKind, Program,
+    Stmt, StmtKind, Storage, StructDecl, UnaryOp, VarDecl, Variant,
+};
+use crate::builtins::{self, Output};
+use crate::format;
+use crate::ops;
+use crate::token::Span;
+use crate::types::{FloatKind, IntKind, Type};
+use crate::value::{
+    Binding, Builtin, Closure, Coroutine, CoroutineStatus, Cursor, EnumType, EnumValue, Env,
+    ErrorValue, Heap, Instance, Iteration, ObjRef, Object, RangeValue, StructType, Table, Value,
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
 
+/* The default limit on the calls being executed at once. `return f(x)` does not
+ * count, as the call replaces the function returning. */
+pub const MAX_CALL_DEPTH: usize = 256;
+/* The host stack the evaluator takes per call, with room for the expressions the
+ * call is nested in. Calls nested deeper in expressions are caught by measuring the
+ * stack itself. */
+const STACK_PER_CALL: usize = 128 * 1024;
+const MAX_STACK_SIZE: usize = 1 << 30;
+const MAX_ALIAS_DEPTH: usize = 64;
+/* How many `iter` methods a `for` loop follows to the value it iterates over. */
+pub const MAX_ITER_DEPTH: usize = 8;
+
+/* A call that a runtime error unwound out of. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFrame {
+    pub function: String,
+    /* Where it was called from. */
+    pub call: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeError {
+    pub message: String,
+    pub span: Span,
+    /* The `kind` of the error value `catch` receives. */
+    pub kind: Rc<str>,
+    /* The calls being executed when it happened, innermost first. */
+    pub trace: Vec<TraceFrame>,
+}
+
+/* The kind of an error of the language itself, from the operation that failed,
+ * which its message starts with. */
+fn kind_of(message: &str) -> &'static str {
+    if message.starts_with("Integer overflow") {
+        "overflow"
+    } else if message.starts_with("Division by zero") {
+        "divisionByZero"
+    } else if message.starts_with("Index ") {
+        "index"
+    } else if message.starts_with("Stack overflow") {
+        "stackOverflow"
+    } else if message.starts_with("Cannot convert") || message.contains(" does not fit in ") {
+        "conversion"
+    } else if message.starts_with("Expected ") {
+        "type"
+    } else {
+        "runtime"
+    }
+}
+
+impl RuntimeError {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        let message = message.into();
+        Self {
+            kind: kind_of(&message).into(),
+            message,
+            span,
+            trace: vec![],
+        }
+    }
+
+    /* An error value raised by the program. */
+    pub fn raised(span: Span, error: &ErrorValue) -> Self {
+        Self {
+            message: error.message.to_string(),
+            span,
+            kind: Rc::clone(&error.kind),
+            trace: vec![],
+        }
+    }
+
+    /* Records that the error happened inside a call of `function` made at `call`. */
+    pub fn called(mut self, function: &str, call: Span) -> Self {
+        self.trace.push(TraceFrame {
+            function: function.to_owned(),
+            call,
+        });
+        self
+    }
+
+    /* The error followed by a stack trace giving the position reached in each
+     * function, innermost first. Runs of the same line, as in a deep recursion, are
+     * printed once. */
+    pub fn report(&self, file: &str) -> String {
+        let names = self
+            .trace
+            .iter()
+            .map(|frame| format!("`{}`", frame.function))
+            .chain(["the program".to_owned()]);
+        let spans = std::iter::once(self.span).chain(self.trace.iter().map(|frame| frame.call));
+        let mut lines: Vec<(String, usize)> = vec![];
+        for (name, span) in names.zip(spans) {
+            let line = format!("  in {name} at {file}:{span}");
+            match lines.last_mut() {
+                Some((last, repeats)) if *last == line => *repeats += 1,
+                _ => lines.push((line, 0)),
+            }
+        }
+        let mut report = format!("{self}\nStack trace (most recent call first):");
+        for (line, repeats) in lines {
+            report += &format!("\n{line}");
+            if repeats > 0 {
+                report += &format!("\n  ... repeated {repeats} more time(s)");
+            }
+        }
+        report
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Toolip:{}: Runtime error: {}", self.span, self.message)
+    }
+}
+
+pub type EvalResult<T> = Result<T, RuntimeError>;
+
+enum Flow {
+    Next,
+    /* Out of the innermost loop, or the one with the label. */
+    Break(Option<String>),
+    Continue(Option<String>),
+    Return(Value),
+    /* `return f(x)`, for the caller of the function returning to make. */
+    TailCall(Box<Call>),
+}
+
+/* How far a statement of a coroutine got: to the end, or to a `yield` of a value. */
+enum Step {
+    Flow(Flow),
+    Yield(Value),
+}
+
+/* A call of a closure whose arguments are evaluated. */
+struct Call {
+    closure: Closure,
+    params: Vec<Type>,
+    ret: Type,
+    values: Vec<Value>,
+    self_value: Option<Value>,
+}
+
+/* What a call expression calls. */
+enum Callee {
+    Method(ObjRef, Value),
+    Value(Value),
+    /* `a?.f(x)` with a `nil` receiver, which is not called. */
+    Nil,
+}
+
+/* The host stack size the evaluator needs to make `max_depth` calls at once, up to
+ * a gigabyte. */
+pub fn stack_size(max_depth: usize) -> usize {
+    max_depth
+        .saturating_add(2)
+        .saturating_mul(STACK_PER_CALL)
+        .min(MAX_STACK_SIZE)
+}
+
+/* The address of the host stack at the caller, which is where it grows from. */
+fn stack_address() -> usize {
+    let marker = 0u8;
+    std::ptr::addr_of!(marker) as usize
+}
+
+/* The type an inferred declaration such as `const x = 5` takes from its value, so
+ * that later assignments are checked against it. Objects stay unchecked. */
+pub fn inferred_type(value: &Value) -> Type {
+    match value {
+        Value::Bool(_) => Type::Bool,
+        Value::Char(_) => Type::Char,
+        Value::Str(_) => Type::String,
+        _ => value.numeric_type().unwrap_or(Type::Unknown),
+    }
+}
+
+pub fn func_type(decl: &FuncDecl) -> Type {
+    let ret = match decl.coroutine {
+        true => Type::Coroutine(Box::new(decl.ret.clone())),
+        false => decl.ret.clone(),
+    };
+    Type::Func(
+        decl.params.iter().map(|param| param.ty.clone()).collect(),
+        Box::new(ret),
+    )
+}
+
+/* What a loop labelled `label` does after its body ends with `flow`: `None` to go
+ * on with the next iteration, or else the flow to leave the loop with. */
+fn after_iteration(flow: Flow, label: &Option<String>) -> Option<Flow> {
+    match flow {
+        Flow::Next => None,
+        Flow::Continue(target) if target.is_none() || target == *label => None,
+        Flow::Break(target) if target.is_none() || target == *label => Some(Flow::Next),
+        flow => Some(flow),
+    }
+}
+
+/* Whether a coroutine can be suspended in `stmt`, at a `yield` statement in it. */
+fn yields(stmt: &Stmt) -> bool {
+    let block = |block: &Block| block.iter().any(yields);
+    match &stmt.kind {
+        StmtKind::Expr(value)
+        | StmtKind::Assign { value, .. }
+        | StmtKind::VarDecl(VarDecl {
+            value: Some(value), ..
+        }) => matches!(value.kind, ExprKind::Yield(_)),
+        StmtKind::If {
+            branches,
+            else_block,
+        } => branches.iter().any(|(_, body)| block(body)) || else_block.as_ref().is_some_and(block),
+        StmtKind::Given(given) => given.arms.iter().any(|arm| block(&arm.body)),
+        StmtKind::For { body, .. } | StmtKind::While { body, .. } | StmtKind::Loop { body, .. } => {
+            block(body)
+        }
+        StmtKind::Try { body, handler, .. } => block(body) || block(handler),
+        _ => false,
+    }
+}
+
+/* The operations below are shared with the bytecode virtual machine, so that both
+ * backends compute the same values and report the same errors. */
+
+/* The error `raise value` reports: an error value itself, or a string as the
+ * message of an "error". */
+pub fn raise(heap: &Heap, value: &Value, span: Span) -> RuntimeError {
+    match (value, heap.error(value)) {
+        (_, Some(error)) => RuntimeError::raised(span, error),
+        (Value::Str(message), _) => RuntimeError::raised(
+            span,
+            &ErrorValue {
+                kind: "error".into(),
+                message: Rc::clone(message),
+            },
+        ),
+        _ => RuntimeError::new(
+            span,
+            format!(
+                "Only errors and strings can be raised, found {}.",
+                heap.type_name(value)
+            ),
+        ),
+    }
+}
+
+/* `value` if it has type `ty`, widened to `ty` if it is a narrower number. */
+pub fn check_type(
+    heap: &Heap,
+    value: Value,
+    ty: &Type,
+    span: Span,
+    what: impl FnOnce() -> String,
+) -> EvalResult<Value> {
+    if heap.conforms(&value, ty) {
+        return Ok(value);
+    }
+    if let Some(value) = ops::widen(&value, ty.non_optional()) {
+        return Ok(value);
+    }
+    Err(RuntimeError::new(
+        span,
+        format!(
+            "Expected {ty} for {}, found {}.",
+            what(),
+            heap.type_name(&value)
+        ),
+    ))
+}
+
+/* A variable declared without a value holds `nil` until it is assigned, so a variable
+ * whose type does not admit `nil` must not be read while it holds it. */
+pub fn check_assigned(name: &str, value: &Value, ty: &Type, span: Span) -> EvalResult<()> {
+    if value.is_nil() && !ty.admits_nil() {
+        return Err(RuntimeError::new(
+            span,
+            format!("`{name}` is used before it is assigned."),
+        ));
+    }
+    Ok(())
+}
+
+/* Integer literals take the type they are used as, defaulting to `int32`. */
+pub fn int_literal(
+    negative: bool,
+    magnitude: u128,
+    hint: Option<&Type>,
+    span: Span,
+) -> EvalResult<Value> {
+    let kind = match hint {
+        Some(Type::Int(kind)) => *kind,
+        Some(Type::Float(kind)) => {
+            let float = if negative {
+                -(magnitude as f64)
+            } else {
+                magnitude as f64
+            };
+            return Ok(float_literal(float, Some(&Type::Float(*kind))));
+        }
+        _ => [IntKind::I32, IntKind::I64, IntKind::I128]
+            .into_iter()
+            .find(|kind| kind.fits_literal(negative, magnitude))
+            .unwrap_or(IntKind::U128),
+    };
+    Value::from_int_parts(kind, negative, magnitude).ok_or_else(|| {
+        RuntimeError::new(
+            span,
+            format!(
+                "Integer literal {}{magnitude} does not fit in {}.",
+                if negative { "-" } else { "" },
+                kind.name()
+            ),
+        )
+    })
+}
+
+pub fn float_literal(float: f64, hint: Option<&Type>) -> Value {
+    match hint {
+        Some(Type::Float(FloatKind::F32)) => Value::Flt32(float as f32),
+        _ => Value::Flt64(float),
+    }
+}
+
+/* `#value`, the number of elements of an array or characters of a string. */
+pub fn length(heap: &Heap, value: &Value, span: Span) -> EvalResult<Value> {
+    let len = match value {
+        Value::Str(string) => Some(string.chars().count()),
+        Value::Obj(obj) => match heap.get(*obj) {
+            Object::Array(items) | Object::Set(items) => Some(items.len()),
+            Object::Table(table) => Some(table.entries.len()),
+            _ => None,
+        },
+        _ => None,
+    };
+    let Some(len) = len else {
+        return Err(RuntimeError::new(
+            span,
+            format!("Cannot take the length of {}.", heap.type_name(value)),
+        ));
+    };
+    i32::try_from(len)
+        .map(Value::Int32)
+        .map_err(|_| RuntimeError::new(span, "Length does not fit in int32."))
+}
+
+pub fn binary_values(
+    heap: &mut Heap,
+    op: BinaryOp,
+    lhs: &Value,
+    rhs: &Value,
+    span: Span,
+) -> EvalResult<Value> {
+    let widened;
+    let (lhs, rhs) = match ops::widen_operands(op, lhs, rhs) {
+        Some(operands) => {
+            widened = operands;
+            (&widened.0, &widened.1)
+        }
+        None => (lhs, rhs),
+    };
+    match op {
+        BinaryOp::Eq => Ok(Value::Bool(heap.values_equal(lhs, rhs))),
+        BinaryOp::NotEq => Ok(Value::Bool(!heap.values_equal(lhs, rhs))),
+        BinaryOp::In => match heap.range(rhs) {
+            Some(range) if lhs.int_kind().is_some() && lhs.int_kind() == range.start.int_kind() => {
+                Ok(Value::Bool(range.contains(lhs)))
+            }
+            _ => Err(RuntimeError::new(
+                span,
+                format!(
+                    "Operator `in` cannot be applied to {} and {}.",
+                    heap.type_name(lhs),
+                    heap.type_name(rhs)
+                ),
+            )),
+        },
+        BinaryOp::Concat => match (lhs, rhs) {
+            (Value::Str(_) | Value::Char(_), _) | (_, Value::Str(_) | Value::Char(_)) => {
+                let text = heap.display(lhs) + &heap.display(rhs);
+                Ok(Value::Str(text.into()))
+            }
+            (Value::Obj(a), Value::Obj(b)) => {
+                let (Object::Array(a), Object::Array(b)) = (heap.get(*a), heap.get(*b)) else {
+                    return Err(concat_error(heap, lhs, rhs, span));
+                };
+                let items = a.iter().chain(b).cloned().collect::<Vec<_>>();
+                let items = items
+                    .into_iter()
+                    .map(|item| heap.copy_value(item))
+                    .collect();
+                Ok(Value::Obj(heap.alloc(Object::Array(items))))
+            }
+            _ if lhs.int_kind().is_some() => {
+                ops::binary(op, lhs, rhs).map_err(|message| RuntimeError::new(span, message))
+            }
+            _ => Err(concat_error(heap, lhs, rhs, span)),
+        },
+        _ => ops::binary(op, lhs, rhs).map_err(|message| RuntimeError::new(span, message)),
+    }
+}
+
+fn concat_error(heap: &Heap, lhs: &Value, rhs: &Value, span: Span) -> RuntimeError {
+    RuntimeError::new(
+        span,
+        format!(
+            "Operator `..` cannot be applied to {} and {}.",
+            heap.type_name(lhs),
+            heap.type_name(rhs)
+        ),
+    )
+}
+
+pub fn no_entries(heap: &Heap, value: &Value, span: Span) -> RuntimeError {
+    RuntimeError::new(
+        span,
+        format!(
+            "Only a table has a key and a value to iterate over, found {}.",
+            heap.type_name(value)
+        ),
+    )
+}
+
+pub fn not_iterable(heap: &Heap, value: &Value, span: Span) -> RuntimeError {
+    RuntimeError::new(
+        span,
+        format!("Cannot iterate over {}.", heap.type_name(value)),
+    )
+}
+
+pub fn index_of(heap: &Heap, index: &Value, len: usize, span: Span) -> EvalResult<usize> {
+    let Some((negative, magnitude)) = index.int_parts() else {
+        return Err(RuntimeError::new(
+            span,
+            format!("Index must be an integer, found {}.", heap.type_name(index)),
+        ));
+    };
+    match usize::try_from(magnitude) {
+        Ok(slot) if !negative && slot < len => Ok(slot),
+        _ => Err(RuntimeError::new(
+            span,
+            format!(
+                "Index {}{magnitude} is out of bounds for length {len}.",
+                if negative { "-" } else { "" }
+            ),
+        )),
+    }
+}
+
+pub fn array_slot(
+    heap: &Heap,
+    target: &Value,
+    index: &Value,
+    span: Span,
+) -> EvalResult<(ObjRef, usize)> {
+    if let Value::Obj(obj) = target {
+        if let Object::Array(items) = heap.get(*obj) {
+            return Ok((*obj, index_of(heap, index, items.len(), span)?));
+        }
+    }
+    Err(RuntimeError::new(
+        span,
+        format!("Cannot index into {}.", heap.type_name(target)),
+    ))
+}
+
+/* An array literal as the type `hint` asks for: a set of its distinct items, or an
+ * empty table. */
+pub fn collection(heap: &mut Heap, items: Vec<Value>, hint: Option<&Type>) -> Value {
+    let object = match hint {
+        Some(Type::Set(_)) => heap.set(items),
+        Some(Type::Table(key, value)) if items.is_empty() => Object::Table(Table {
+            key: (**key).clone(),
+            value: (**value).clone(),
+            entries: vec![],
+        }),
+        _ => Object::Array(items),
+    };
+    Value::Obj(heap.alloc(object))
+}
+
+/* A table of `pairs`, in which a key given again replaces the value it had. */
+pub fn table(heap: &mut Heap, key: Type, value: Type, pairs: Vec<(Value, Value)>) -> Value {
+    let mut entries: Vec<(Value, Value)> = Vec::with_capacity(pairs.len());
+    for (key, value) in pairs {
+        match entries
+            .iter()
+            .position(|(kept, _)| heap.values_equal(kept, &key))
+        {
+            Some(slot) => entries[slot].1 = value,
+            None => entries.push((key, value)),
+        }
+    }
+    Value::Obj(heap.alloc(Object::Table(Table {
+        key,
+        value,
+        entries,
+    })))
+}
+
+/* The hint of a type, which `Unknown` does not give. */
+fn known(ty: &Type) -> Option<&Type> {
+    Some(ty).filter(|ty| **ty != Type::Unknown)
+}
+
+/* `target[index]`: a character of a string, an item of an array or set, or the value
+ * of a key of a table. */
+pub fn element(heap: &Heap, target: &Value, index: &Value, span: Span) -> EvalResult<Value> {
+    if let Value::Str(string) = target {
+        let slot = index_of(heap, index, string.chars().count(), span)?;
+        return Ok(Value::Char(string.chars().nth(slot).unwrap_or_default()));
+    }
+    if let Value::Obj(obj) = target {
+        match heap.get(*obj) {
+            Object::Set(items) => {
+                return Ok(items[index_of(heap, index, items.len(), span)?].clone())
+            }
+            Object::Table(table) => {
+                return table
+                    .entries
+                    .iter()
+                    .find(|(key, _)| heap.values_equal(key, index))
+                    .map(|(_, value)| value.clone())
+                    .ok_or_else(|| {
+                        RuntimeError::new(
+                            span,
+                            format!("The table has no key `{}`.", heap.display(index)),
+                        )
+                    });
+            }
+            _ => (),
+        }
+    }
+    let (obj, slot) = array_slot(heap, target, index, span)?;
+    match heap.get(obj) {
+        Object::Array(items) => Ok(items[slot].clone()),
+        _ => unreachable!("array slots belong to arrays"),
+    }
+}
+
+/* The value `target[index]` has as it is assigned: that of an array slot, or of a key
+ * of a table, which is `nil` until it has been given one. */
+pub fn assigned_element(
+    heap: &Heap,
+    target: &Value,
+    index: &Value,
+    span: Span,
+) -> EvalResult<Value> {
+    if let Some(table) = heap.table(target) {
+        let entry = table
+            .entries
+            .iter()
+            .find(|(key, _)| heap.values_equal(key, index));
+        return Ok(entry.map_or(Value::Nil, |(_, value)| value.clone()));
+    }
+    if matches!(target, Value::Obj(obj) if matches!(heap.get(*obj), Object::Set(_))) {
+        return Err(RuntimeError::new(
+            span,
+            "Cannot assign to an item of a set.",
+        ));
+    }
+    let (obj, slot) = array_slot(heap, target, index, span)?;
+    match heap.get(obj) {
+        Object::Array(items) => Ok(items[slot].clone()),
+        _ => unreachable!("array slots belong to arrays"),
+    }
+}
+
+/* Stores `value` at `target[index]`, which `assigned_element` has checked. */
+pub fn store_element(
+    heap: &mut Heap,
+    target: &Value,
+    index: Value,
+    value: Value,
+    span: Span,
+) -> EvalResult<()> {
+    if let (Some(table), Value::Obj(obj)) = (heap.table(target), target) {
+        let slot = table
+            .entries
+            .iter()
+            .position(|(key, _)| heap.values_equal(key, &index));
+        if let Object::Table(table) = heap.get_mut(*obj) {
+            match slot {
+                Some(slot) => table.entries[slot].1 = value,
+                None => table.entries.push((index, value)),
+            }
+        }
+        return Ok(());
+    }
+    let (obj, slot) = array_slot(heap, target, &index, span)?;
+    if let Object::Array(items) = heap.get_mut(obj) {
+        items[slot] = value;
+    }
+    Ok(())
+}
+
+/* `start to end step n`, or `start through end step n` when `inclusive`; without a
+ * step, the range counts up by one. */
+pub fn range(
+    heap: &Heap,
+    start: Value,
+    end: Value,
+    step: Option<Value>,
+    inclusive: bool,
+    span: Span,
+) -> EvalResult<RangeValue> {
+    let (Some(kind), Some(_)) = (start.int_kind(), end.int_kind()) else {
+        return Err(RuntimeError::new(
+            span,
+            format!(
+                "Range bounds must be integers of the same type, found {} and {}.",
+                heap.type_name(&start),
+                heap.type_name(&end)
+            ),
+        ));
+    };
+    let step = step.unwrap_or_else(|| Value::from_int_parts(kind, false, 1).unwrap_or(Value::Nil));
+    match step.int_parts() {
+        Some((_, 0)) => Err(RuntimeError::new(span, "The step of a range cannot be 0.")),
+        Some(_) => Ok(RangeValue {
+            start,
+            end,
+            step,
+            inclusive,
+        }),
+        None => Err(RuntimeError::new(
+            span,
+            format!(
+                "The step of a range must be an integer, found {}.",
+                heap.type_name(&step)
+            ),
+        )),
+    }
+}
+
+/* The characters of a string or the items of an array at the indices `range` visits,
+ * in the order it visits them. */
+pub fn slice(heap: &mut Heap, target: &Value, range: &RangeValue, span: Span) -> EvalResult<Value> {
+    if let Value::Str(string) = target {
+        let chars = string.chars().collect::<Vec<_>>();
+        let sliced = range
+            .iter()
+            .map(|index| Ok(chars[index_of(heap, &index, chars.len(), span)?]))
+            .collect::<EvalResult<String>>()?;
+        return Ok(Value::Str(sliced.into()));
+    }
+    let items = match target {
+        Value::Obj(obj) => match heap.get(*obj) {
+            Object::Array(items) => Some(items.clone()),
+            _ => None,
+        },
+        _ => None,
+    };
+    let Some(items) = items else {
+        return Err(RuntimeError::new(
+            span,
+            format!("Cannot slice {}.", heap.type_name(target)),
+        ));
+    };
+    let sliced = range
+        .iter()
+        .map(|index| Ok(items[index_of(heap, &index, items.len(), span)?].clone()))
+        .collect::<EvalResult<Vec<_>>>()?;
+    let sliced = sliced
+        .into_iter()
+        .map(|item| heap.copy_value(item))
+        .collect();
+    Ok(Value::Obj(heap.alloc(Object::Array(sliced))))
+}
+
+/* A function call being executed. */
+struct Frame {
+    /* The declared return type. */
+    ret: Type,
+    /* The environment of the function's `static` variables, if it has any. */
+    statics: Option<ObjRef>,
+    /* The `try` blocks being executed, in which calls are not tail calls. */
+    tries: usize,
+    /* Whether this runs a coroutine, whose calls are not tail calls either. */
+    coroutine: bool,
+}
+
+pub struct Evaluator {
+    pub heap: Heap,
+    globals: ObjRef,
+    env: ObjRef,
+    /* The calls being executed, innermost last. */
+    frames: Vec<Frame>,
+    /* The environments of the `static` variables of each function, by declaration
+     * and by the environment it closes over. The declaration is kept so that its
+     * address is not reused. */
+    statics: HashMap<(*const FuncDecl, ObjRef), (Rc<FuncDecl>, ObjRef)>,
+    /* The objects held by Rust code while a statement runs: the values evaluated
+     * so far and the scopes to return to. Each statement drops its own. */
+    temps: Vec<Value>,
+    /* Where each coroutine being run is, innermost last. */
+    running: Vec<Vec<Cursor>>,
+    /* The value a coroutine is resumed with, for the `yield` it was suspended at. */
+    resumed: Option<Value>,
+    max_depth: usize,
+    /* The host stack address when the program started running. */
+    stack_start: usize,
+    output: Output,
+}
+
+impl Default for Evaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Evaluator {
+    pub fn new() -> Self {
+        let mut heap = Heap::default();
+        let globals = heap.alloc(Object::Env(Env {
+            vars: HashMap::new(),
+            parent: None,
+        }));
+        let mut evaluator = Self {
+            heap,
+            globals,
+            env: globals,
+            frames: vec![],
+            statics: HashMap::new(),
+            temps: vec![],
+            running: vec![],
+            resumed: None,
+            max_depth: MAX_CALL_DEPTH,
+            stack_start: 0,
+            output: Output::Stdout,
+        };
+        for &builtin in Builtin::ALL {
+            evaluator.define(builtin.name(), Value::Builtin(builtin), Type::Unknown);
+        }
+        evaluator
+    }
+
+    /* Collects everything the program prints instead of writing it to stdout. */
+    pub fn capture_output(&mut self) {
+        self.output = Output::Captured(String::new());
+    }
+
+    pub fn take_output(&mut self) -> String {
+        self.output.take()
+    }
+
+    /* Limits the calls being executed at once, past which a call is a stack overflow.
+     * The host stack must be `stack_size(max_depth)`. */
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /* The names defined in the global scope by the programs run so far. */
+    pub fn global_names(&self) -> Vec<String> {
+        self.env_ref(self.globals).vars.keys().cloned().collect()
+    }
+
+    /* Runs a program in the global scope, which persists between calls. Returns the
+     * value of the last statement if it is an expression. */
+    pub fn run(&mut self, program: &Program) -> EvalResult<Value> {
+        self.stack_start = stack_address();
+        self.env = self.globals;
+        self.frames.clear();
+        self.temps.clear();
+        self.running.clear();
+        self.hoist(&program.stmts);
+        let mut last = Value::Nil;
+        for stmt in &program.stmts {
+            last = Value::Nil;
+            self.safe_point();
+            if let StmtKind::Expr(expr) = &stmt.kind {
+                last = self.eval(expr, None)?;
+                self.temps.clear();
+                continue;
+            }
+            let flow = self.exec_stmt(stmt);
+            self.temps.clear();
+            match flow? {
+                Flow::Next => (),
+                Flow::Break(_) => {
+                    return Err(RuntimeError::new(stmt.span, "`break` outside of a loop."))
+                }
+                Flow::Continue(_) => {
+                    return Err(RuntimeError::new(
+                        stmt.span,
+                        "`continue` outside of a loop.",
+                    ))
+                }
+                Flow::Return(_) | Flow::TailCall(_) => {
+                    return Err(RuntimeError::new(
+                        stmt.span,
+                        "`return` outside of a function.",
+                    ))
+                }
+            }
+        }
+        Ok(last)
+    }
+
+    /* Garbage collection */
+
+    /* Statements start at safe points: every object the evaluator still needs is
+     * reachable from the scopes, the `static` variables or `temps`. */
+    fn safe_point(&mut self) {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+    }
+
+    fn collect_garbage(&mut self) -> usize {
+        let mut temps: Vec<ObjRef> = self
+            .temps
+            .iter()
+            .filter_map(|value| match value {
+                Value::Obj(obj) => Some(*obj),
+                _ => None,
+            })
+            .collect();
+        for cursor in self.running.iter().flatten() {
+            cursor.trace(&mut temps);
+        }
+        let statics = self
+            .statics
+            .iter()
+            .flat_map(|((_, env), (_, statics))| [*env, *statics]);
+        let roots: Vec<ObjRef> = [self.globals, self.env]
+            .into_iter()
+            .chain(temps)
+            .chain(statics)
+            .collect();
+        self.heap.collect(roots)
+    }
+
+    /* Keeps `value` alive until the end of the current statement. */
+    fn root(&mut self, value: &Value) {
+        if let Value::Obj(_) = value {
+            self.temps.push(value.clone());
+        }
+    }
+
+    fn copy_value(&mut self, value: Value) -> Value {
+        let value = self.heap.copy_value(value);
+        self.root(&value);
+        value
+    }
+
+    /* Environments */
+
+    fn env_ref(&self, env: ObjRef) -> &Env {
+        match self.heap.get(env) {
+            Object::Env(env) => env,
+            _ => unreachable!("scopes are always environments"),
+        }
+    }
+
+    fn env_mut(&mut self, env: ObjRef) -> &mut Env {
+        match self.heap.get_mut(env) {
+            Object::Env(env) => env,
+            _ => unreachable!("scopes are always environments"),
+        }
+    }
+
+    fn new_env(&mut self, parent: ObjRef) -> ObjRef {
+        self.heap.alloc(Object::Env(Env {
+            vars: HashMap::new(),
+            parent: Some(parent),
+        }))
+    }
+
+    fn define(&mut self, name: &str, value: Value, ty: Type) {
+        let binding = Binding {
+            value,
+            ty,
+            constant: false,
+            id: None,
+        };
+        self.define_in(self.env, name, binding);
+    }
+
+    fn define_in(&mut self, env: ObjRef, name: &str, binding: Binding) {
+        self.env_mut(env).vars.insert(name.to_owned(), binding);
+    }
+
+    fn find_env(&self, name: &str) -> Option<ObjRef> {
+        self.find_env_from(self.env, name)
+    }
+
+    fn find_env_from(&self, start: ObjRef, name: &str) -> Option<ObjRef> {
+        let mut env = Some(start);
+        while let Some(current) = env {
+            let scope = self.env_ref(current);
+            if scope.vars.contains_key(name) {
+                return Some(current);
+            }
+            env = scope.parent;
+        }
+        None
+    }
+
+    /* The environment of the variable an identifier refers to. A variable declared
+     * in a function or block carries the `BindingId` of its declaration, and is
+     * skipped when the resolver tied the identifier to another: it was declared
+     * after the closure reading it, in a scope they share. Other names are found by
+     * their text. */
+    fn find_var(&self, name: &str, id: Option<BindingId>) -> Option<ObjRef> {
+        let mut env = Some(self.env);
+        while let Some(current) = env {
+            let scope = self.env_ref(current);
+            match scope.vars.get(name) {
+                Some(binding) if id.is_none() || binding.id.is_none() || binding.id == id => {
+                    return Some(current);
+                }
+                _ => env = scope.parent,
+            }
+        }
+        None
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        let env = self.find_env(name)?;
+        self.env_ref(env).vars.get(name)
+    }
+
+    /* Types */
+
+    /* The runtime view of `ty` as written in scope `env`: aliases are expanded, and
+     * generic type parameters (names that are not types in scope) are erased to
+     * unknown. The checker verifies generic code statically, so the evaluator only
+     * checks the parts of a type it can see. */
+    fn resolve_type(&self, env: ObjRef, ty: &Type) -> Type {
+        self.resolve_type_except(env, ty, &[], 0)
+    }
+
+    /* Like `resolve_type`, but leaves the names in `keep` alone for substitution. */
+    fn resolve_type_except(&self, env: ObjRef, ty: &Type, keep: &[String], depth: usize) -> Type {
+        ty.map_named(&mut |name, args| {
+            if keep.iter().any(|param| param == name) {
+                return None;
+            }
+            let Some(scope) = self.find_env_from(env, name) else {
+                return Some(Type::Unknown);
+            };
+            let Some(Binding {
+                value: Value::Obj(obj),
+                ..
+            }) = self.env_ref(scope).vars.get(name)
+            else {
+                return Some(Type::Unknown);
+            };
+            match self.heap.get(*obj) {
+                Object::StructType(_) | Object::EnumType(_) => None,
+                Object::TypeAlias { alias, env } if depth < MAX_ALIAS_DEPTH => {
+                    let body = self.resolve_type_except(*env, &alias.ty, &alias.params, depth + 1);
+                    Some(body.substitute(&alias.params, args))
+                }
+                _ => Some(Type::Unknown),
+            }
+        })
+    }
+
+    /* Runs `f` in a fresh scope nested in `parent`, restoring the current scope after. */
+    fn scoped<T>(
+        &mut self,
+        parent: ObjRef,
+        f: impl FnOnce(&mut Self) -> EvalResult<T>,
+    ) -> EvalResult<T> {
+        let previous = self.env;
+        let mark = self.temps.len();
+        self.temps.push(Value::Obj(previous));
+        self.env = self.new_env(parent);
+        let result = f(self);
+        self.env = previous;
+        self.temps.truncate(mark);
+        result
+    }
+
+    /* Statements */
+
+    /* Functions, structs, enums and type aliases can be used anywhere in the block
+     * that declares them. */
+    fn hoist(&mut self, block: &Block) {
+        for stmt in block {
+            match &stmt.kind {
+                StmtKind::Func(decl) => {
+                    let Some(name) = &decl.name else {
+                        continue;
+                    };
+                    let closure = self.heap.alloc(Object::Closure(Closure {
+                        decl: Rc::clone(decl),
+                        env: self.env,
+                        owner: None,
+                    }));
+                    self.define(name, Value::Obj(closure), func_type(decl));
+                }
+                StmtKind::Struct(decl) => self.declare_struct(decl),
+                StmtKind::Enum(decl) => {
+                    let ty = self.heap.alloc(Object::EnumType(EnumType {
+                        decl: Rc::clone(decl),
+                        env: self.env,
+                    }));
+                    self.define(&decl.name, Value::Obj(ty), Type::Unknown);
+                }
+                StmtKind::TypeAlias(alias) => {
+                    let obj = self.heap.alloc(Object::TypeAlias {
+                        alias: Rc::clone(alias),
+                        env: self.env,
+                    });
+                    self.define(&alias.name, Value::Obj(obj), Type::Unknown);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn declare_struct(&mut self, decl: &Rc<StructDecl>) {
+        let ty = self.heap.alloc(Object::StructType(StructType {
+            decl: Rc::clone(decl),
+            env: self.env,
+        }));
+        self.define(&decl.name, Value::Obj(ty), Type::Unknown);
+    }
+
+    fn exec_block(&mut self, block: &Block) -> EvalResult<Flow> {
+        self.scoped(self.env, |ev| ev.exec_stmts(block))
+    }
+
+    fn exec_stmts(&mut self, block: &Block) -> EvalResult<Flow> {
+        self.hoist(block);
+        for stmt in block {
+            self.safe_point();
+            let mark = self.temps.len();
+            let flow = self.exec_stmt(stmt);
+            self.temps.truncate(mark);
+            match flow? {
+                Flow::Next => (),
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn exec_stmt(&mut self, stmt: &Stmt) -> EvalResult<Flow> {
+        match &stmt.kind {
+            StmtKind::VarDecl(decl) => self.exec_var_decl(decl)?,
+            StmtKind::Func(_)
+            | StmtKind::Struct(_)
+            | StmtKind::Enum(_)
+            | StmtKind::TypeAlias(_) => (),
+            StmtKind::Expr(expr) => {
+                self.eval(expr, None)?;
+            }
+            StmtKind::Assign { target, op, value } => self.exec_assign(target, *op, value)?,
+            StmtKind::If {
+                branches,
+                else_block,
+            } => {
+                for (cond, body) in branches {
+                    if self.eval_condition(cond)? {
+                        return self.exec_block(body);
+                    }
+                }
+                if let Some(body) = else_block {
+                    return self.exec_block(body);
+                }
+            }
+            StmtKind::Given(given) => return self.exec_given(given),
+            StmtKind::For {
+                var_ty,
+                var,
+                value_var,
+                iter,
+                body,
+                label,
+            } => return self.exec_for(var_ty.as_ref(), var, value_var.as_ref(), iter, body, label),
+            StmtKind::While { cond, body, label } => {
+                let mark = self.temps.len();
+                while self.eval_condition(cond)? {
+                    self.temps.truncate(mark);
+                    let flow = self.exec_block(body)?;
+                    if let Some(flow) = after_iteration(flow, label) {
+                        return Ok(flow);
+                    }
+                }
+            }
+            StmtKind::Loop { body, label } => loop {
+                let flow = self.exec_block(body)?;
+                if let Some(flow) = after_iteration(flow, label) {
+                    return Ok(flow);
+                }
+            },
+            StmtKind::Break(label) => return Ok(Flow::Break(label.clone())),
+            StmtKind::Continue(label) => return Ok(Flow::Continue(label.clone())),
+            StmtKind::Return(value) => {
+                let ret = self
+                    .frames
+                    .last()
+                    .map_or(Type::Unknown, |frame| frame.ret.clone());
+                let value = match value {
+                    Some(Expr {
+                        kind: ExprKind::Call(callee, args),
+                        span,
+                    }) if self
+                        .frames
+                        .last()
+                        .is_some_and(|frame| frame.tries == 0 && !frame.coroutine) =>
+                    {
+                        return self.tail_call(callee, args, Some(&ret), *span);
+                    }
+                    Some(expr) => self.eval(expr, Some(&ret))?,
+                    None => Value::Nil,
+                };
+                return Ok(Flow::Return(value));
+            }
+            StmtKind::Try { body, var, handler } => {
+                return self.exec_try(body, var.as_deref(), handler)
+            }
+            StmtKind::Raise(value) => {
+                let value = self.eval(value, None)?;
+                return Err(raise(&self.heap, &value, stmt.span));
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /* Any error in `body` runs `handler`, by then back in the scope of the `try`. */
+    fn exec_try(&mut self, body: &Block, var: Option<&str>, handler: &Block) -> EvalResult<Flow> {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.tries += 1;
+        }
+        let flow = self.exec_block(body);
+        if let Some(frame) = self.frames.last_mut() {
+            frame.tries -= 1;
+        }
+        let error = match flow {
+            Err(error) => error,
+            flow => return flow,
+        };
+        let error = ErrorValue {
+            kind: error.kind,
+            message: error.message.into(),
+        };
+        let error = self.heap.alloc(Object::Error(error));
+        self.scoped(self.env, |ev| {
+            if let Some(var) = var {
+                ev.define(var, Value::Obj(error), Type::Unknown);
+            }
+            ev.exec_stmts(handler)
+        })
+    }
+
+    /* A `static` variable lives in the environment of its function's statics and is
+     * only initialised by the first call. A `global` one lives at the top level of
+     * the program, where declaring it again assigns it. */
+    fn exec_var_decl(&mut self, decl: &VarDecl) -> EvalResult<()> {
+        let env = match decl.storage {
+            Some(Storage::Static) => self.frames.last().and_then(|frame| frame.statics),
+            Some(Storage::Global) => Some(self.globals),
+            _ => None,
+        }
+        .unwrap_or(self.env);
+        let existing = self.env_ref(env).vars.get(&decl.name).cloned();
+        if existing.is_some() && decl.storage == Some(Storage::Static) {
+            return Ok(());
+        }
+        let existing = existing.filter(|_| decl.storage == Some(Storage::Global));
+        let ty = match (&decl.ty, &existing) {
+            (Type::Unknown, Some(existing)) => existing.ty.clone(),
+            _ => self.resolve_type(self.env, &decl.ty),
+        };
+        let value = match &decl.value {
+            Some(expr) => {
+                let value = self.eval(expr, Some(&ty))?;
+                let value = check_type(&self.heap, value, &ty, expr.span, || {
+                    format!("variable `{}`", decl.name)
+                })?;
+                self.copy_value(value)
+            }
+            None => existing.map_or(Value::Nil, |existing| existing.value),
+        };
+        let ty = match ty {
+            Type::Unknown => inferred_type(&value),
+            ty => ty,
+        };
+        let binding = Binding {
+            value,
+            ty,
+            constant: decl.storage == Some(Storage::Const),
+            id: decl.binding.get().filter(|_| env != self.globals),
+        };
+        self.define_in(env, &decl.name, binding);
+        Ok(())
+    }
+
+    fn eval_condition(&mut self, cond: &Expr) -> EvalResult<bool> {
+        match self.eval(cond, Some(&Type::Bool))? {
+            Value::Bool(value) => Ok(value),
+            other => Err(RuntimeError::new(
+                cond.span,
+                format!(
+                    "Condition must be a bool, found {}.",
+                    self.heap.type_name(&other)
+                ),
+            )),
+        }
+    }
+
+    fn exec_assign(&mut self, target: &Expr, op: Option<BinaryOp>, value: &Expr) -> EvalResult<()> {
+        match &target.kind {
+            ExprKind::Ident(name, id) => {
+                let Some(env) = self.find_var(name, id.get()) else {
+                    return Err(RuntimeError::new(
+                        target.span,
+                        format!("Undefined variable `{name}`."),
+                    ));
+                };
+                let binding = self.env_ref(env).vars[name].clone();
+                self.root(&binding.value);
+                if binding.constant {
+                    return Err(RuntimeError::new(
+                        target.span,
+                        format!("Cannot assign to the constant `{name}`."),
+                    ));
+                }
+                if !matches!(op, None | Some(BinaryOp::NilCoalesce)) {
+                    check_assigned(name, &binding.value, &binding.ty, target.span)?;
+                }
+                let new_value = self.assigned_value(&binding.value, &binding.ty, op, value)?;
+                let new_value = check_type(&self.heap, new_value, &binding.ty, value.span, || {
+                    format!("variable `{name}`")
+                })?;
+                let new_value = self.copy_value(new_value);
+                if let Some(binding) = self.env_mut(env).vars.get_mut(name) {
+                    binding.value = new_value;
+                }
+                Ok(())
+            }
+            ExprKind::Field(object, field) => {
+                let object = self.eval(object, None)?;
+                let (obj, index, ty) = self.field_slot(&object, field, target.span)?;
+                let current = match self.heap.get(obj) {
+                    Object::Instance(instance) => instance.fields[index].clone(),
+                    _ => unreachable!("field slots belong to instances"),
+                };
+                self.root(&current);
+                let new_value = self.assigned_value(&current, &ty, op, value)?;
+                let new_value = check_type(&self.heap, new_value, &ty, value.span, || {
+                    format!("field `{field}`")
+                })?;
+                let new_value = self.copy_value(new_value);
+                if let Object::Instance(instance) = self.heap.get_mut(obj) {
+                    instance.fields[index] = new_value;
+                }
+                Ok(())
+            }
+            ExprKind::Index(array, index) => {
+                let array = self.eval(array, None)?;
+                let (key_ty, value_ty) = match self.heap.table(&array) {
+                    Some(table) => (table.key.clone(), table.value.clone()),
+                    None => (Type::Int(IntKind::I32), Type::Unknown),
+                };
+                let index_value = self.eval(index, known(&key_ty))?;
+                let current = assigned_element(&self.heap, &array, &index_value, index.span)?;
+                self.root(&current);
+                let hint = current.numeric_type().unwrap_or(value_ty);
+                let new_value = self.assigned_value(&current, &hint, op, value)?;
+                let (index_value, new_value) =
+                    (self.copy_value(index_value), self.copy_value(new_value));
+                store_element(&mut self.heap, &array, index_value, new_value, index.span)
+            }
+            _ => Err(RuntimeError::new(target.span, "Invalid assignment target.")),
+        }
+    }
+
+    /* The value an assignment stores: `value` itself, or `current op value`. */
+    fn assigned_value(
+        &mut self,
+        current: &Value,
+        ty: &Type,
+        op: Option<BinaryOp>,
+        value: &Expr,
+    ) -> EvalResult<Value> {
+        let Some(op) = op else {
+            return self.eval(value, Some(ty));
+        };
+        /* `x ??= y` only evaluates `y` when `x` is `nil`. */
+        if op == BinaryOp::NilCoalesce {
+            return match current {
+                Value::Nil => self.eval(value, Some(ty)),
+                _ => Ok(current.clone()),
+            };
+        }
+        let hint = current.numeric_type();
+        let rhs = self.eval(value, hint.as_ref())?;
+        binary_values(&mut self.heap, op, current, &rhs, value.span)
+    }
+
+    fn exec_given(&mut self, given: &Given) -> EvalResult<Flow> {
+        let subject = self.eval(&given.subject, None)?;
+        for arm in &given.arms {
+            for pattern in &arm.patterns {
+                let mut bindings = vec![];
+                if self.pattern_matches(pattern, &subject, &mut bindings)? {
+                    return self.scoped(self.env, |ev| {
+                        for (name, value, ty) in bindings {
+                            let value = ev.copy_value(value);
+                            ev.define(name, value, ty);
+                        }
+                        ev.exec_stmts(&arm.body)
+                    });
+                }
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /* Matches `subject` against `pattern`, collecting the payload bindings of a variant. */
+    fn pattern_matches<'p>(
+        &self,
+        pattern: &'p Pattern,
+        subject: &Value,
+        bindings: &mut Vec<(&'p str, Value, Type)>,
+    ) -> EvalResult<bool> {
+        Ok(match (&pattern.kind, subject) {
+            (PatternKind::Wildcard, _) => true,
+            (PatternKind::Int(int), _) => {
+                subject.int_parts() == Some((*int < 0, int.unsigned_abs()))
+            }
+            (
+                PatternKind::Range {
+                    start,
+                    end,
+                    inclusive,
+                },
+                _,
+            ) => subject.int_in(*start, *end, *inclusive),
+            (PatternKind::Float(float), Value::Flt64(value)) => value == float,
+            (PatternKind::Float(float), Value::Flt32(value)) => f64::from(*value) == *float,
+            (PatternKind::Bool(expected), Value::Bool(value)) => value == expected,
+            (PatternKind::Char(expected), Value::Char(value)) => value == expected,
+            (PatternKind::Str(expected), Value::Str(value)) => **value == **expected,
+            (PatternKind::Nil, _) => subject.is_nil(),
+            (
+                PatternKind::Name {
+                    qualifier: None,
+                    name,
+                    bindings: None,
+                },
+                _,
+            ) if self.lookup(name).is_some_and(|binding| binding.constant) => {
+                let value = self.lookup_value(name, pattern.span)?;
+                self.heap.values_equal(subject, &value)
+            }
+            (
+                PatternKind::Name {
+                    qualifier,
+                    name,
+                    bindings: names,
+                },
+                _,
+            ) => {
+                let Some(value) = self.heap.enum_value(subject) else {
+                    return Err(RuntimeError::new(
+                        pattern.span,
+                        format!(
+                            "Pattern `{}` cannot match {}.",
+                            pattern.kind,
+                            self.heap.type_name(subject)
+                        ),
+                    ));
+                };
+                let (decl, env) = match self.heap.enum_type(value.ty) {
+                    Some(ty) => (&ty.decl, ty.env),
+                    None => unreachable!("enum values point at their enum type"),
+                };
+                if qualifier
+                    .as_ref()
+                    .is_some_and(|qualifier| *qualifier != decl.name)
+                {
+                    return Err(RuntimeError::new(
+                        pattern.span,
+                        format!(
+                            "Pattern `{}` does not belong to enum `{}`.",
+                            pattern.kind, decl.name
+                        ),
+                    ));
+                }
+                let Some(index) = decl.variant_index(name) else {
+                    return Err(RuntimeError::new(
+                        pattern.span,
+                        format!("Enum `{}` has no variant `{name}`.", decl.name),
+                    ));
+                };
+                let fields = &decl.variants[index].fields;
+                if let Some(names) = names {
+                    if names.len() != fields.len() {
+                        return Err(RuntimeError::new(
+                            pattern.span,
+                            format!(
+                                "Variant `{name}` has {} field(s), found {} binding(s).",
+                                fields.len(),
+                                names.len()
+                            ),
+                        ));
+                    }
+                }
+                if index != value.variant {
+                    return Ok(false);
+                }
+                if let Some(names) = names {
+                    for ((binding, item), ty) in names.iter().zip(&value.payload).zip(fields) {
+                        bindings.push((binding, item.clone(), self.resolve_type(env, ty)));
+                    }
+                }
+                true
+            }
+            _ => false,
+        })
+    }
+
+    fn exec_for(
+        &mut self,
+        var_ty: Option<&Type>,
+        var: &str,
+        value_var: Option<&(Option<Type>, String)>,
+        iter: &Expr,
+        body: &Block,
+        label: &Option<String>,
+    ) -> EvalResult<Flow> {
+        let ty = var_ty.map_or(Type::Unknown, |ty| self.resolve_type(self.env, ty));
+        let value_var = value_var.map(|(ty, name)| {
+            let ty = ty
+                .as_ref()
+                .map_or(Type::Unknown, |ty| self.resolve_type(self.env, ty));
+            (ty, name.as_str())
+        });
+        let mut iteration = self.iteration(var_ty.map(|_| &ty), iter, value_var.is_some())?;
+        match &iteration {
+            Iteration::Items { items, .. } => {
+                for item in items {
+                    self.root(item);
+                }
+            }
+            Iteration::Entries { entries, .. } => {
+                for (key, value) in entries {
+                    self.root(key);
+                    self.root(value);
+                }
+            }
+            _ => (),
+        }
+        let mark = self.temps.len();
+        while let Some((item, value)) = self.next_item(&mut iteration, iter.span)? {
+            self.temps.truncate(mark);
+            let item = self.loop_value(item, &ty, var, iter.span)?;
+            let value = match &value_var {
+                Some((value_ty, name)) => Some(self.loop_value(value, value_ty, name, iter.span)?),
+                None => None,
+            };
+            let flow = self.scoped(self.env, |ev| {
+                ev.define(var, item, ty.clone());
+                if let (Some((value_ty, name)), Some(value)) = (&value_var, value) {
+                    ev.define(name, value, value_ty.clone());
+                }
+                ev.exec_stmts(body)
+            })?;
+            if let Some(flow) = after_iteration(flow, label) {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /* An item a `for` loop gives the variable `var` of type `ty`. */
+    fn loop_value(&mut self, item: Value, ty: &Type, var: &str, span: Span) -> EvalResult<Value> {
+        let item = check_type(&self.heap, item, ty, span, || {
+            format!("loop variable `{var}`")
+        })?;
+        Ok(self.copy_value(item))
+    }
+
+    /* The items a `for` loop over `iter` visits, or the entries if it has a variable
+     * for their values. A range is typed by the loop variable's type `hint`, if it is
+     * declared with one. */
+    fn iteration(
+        &mut self,
+        hint: Option<&Type>,
+        iter: &Expr,
+        entries: bool,
+    ) -> EvalResult<Iteration> {
+        let ExprKind::Range {
+            start,
+            end,
+            step,
+            inclusive,
+        } = &iter.kind
+        else {
+            let value = self.eval(iter, None)?;
+            if entries && self.heap.table(&value).is_none() {
+                return Err(no_entries(&self.heap, &value, iter.span));
+            }
+            return self.iteration_of(value, iter.span, 0);
+        };
+        let range = self.range_value(start, end, step.as_deref(), *inclusive, hint, iter.span)?;
+        if entries {
+            let range = Value::Obj(self.heap.alloc(Object::Range(range)));
+            return Err(no_entries(&self.heap, &range, iter.span));
+        }
+        Ok(Iteration::Range(range.iter()))
+    }
+
+    /* `start to end step n`, typed by `hint` if its bounds are literals. */
+    fn range_value(
+        &mut self,
+        start: &Expr,
+        end: &Expr,
+        step: Option<&Expr>,
+        inclusive: bool,
+        hint: Option<&Type>,
+        span: Span,
+    ) -> EvalResult<RangeValue> {
+        let (start, end) = self.eval_operands(start, end, hint)?;
+        let step = match step {
+            Some(step) => Some(self.eval(step, start.numeric_type().as_ref())?),
+            None => None,
+        };
+        range(&self.heap, start, end, step, inclusive, span)
+    }
+
+    /* The items of `value`. A struct gives them by its `next` method, or else by the
+     * value its `iter` method returns. */
+    fn iteration_of(&mut self, value: Value, span: Span, depth: usize) -> EvalResult<Iteration> {
+        let items = match &value {
+            Value::Str(string) => string.chars().map(Value::Char).collect(),
+            Value::Obj(obj) => match self.heap.get(*obj) {
+                Object::Array(items) | Object::Set(items) => items.clone(),
+                Object::Table(table) => {
+                    return Ok(Iteration::Entries {
+                        entries: table.entries.clone(),
+                        next: 0,
+                    })
+                }
+                Object::Coroutine(_) => return Ok(Iteration::Coroutine(*obj)),
+                Object::Range(range) => return Ok(Iteration::Range(range.iter())),
+                Object::Instance(_) if depth < MAX_ITER_DEPTH => {
+                    if self.find_method(&value, "next").is_some() {
+                        return Ok(Iteration::Next(self.copy_value(value)));
+                    }
+                    let Some(iter) = self.find_method(&value, "iter") else {
+                        return Err(not_iterable(&self.heap, &value, span));
+                    };
+                    let items = self.call_method(iter, value, span)?;
+                    return self.iteration_of(items, span, depth + 1);
+                }
+                Object::Instance(_) => return Err(not_iterable(&self.heap, &value, span)),
+                _ => vec![value.clone()],
+            },
+            _ => return Err(not_iterable(&self.heap, &value, span)),
+        };
+        Ok(Iteration::Items { items, next: 0 })
+    }
+
+    /* Calls a method that takes no arguments. */
+    fn call_method(&mut self, method: ObjRef, receiver: Value, span: Span) -> EvalResult<Value> {
+        let value = self.call_closure(method, Some(receiver), &[], span)?;
+        self.root(&value);
+        Ok(value)
+    }
+
+    /* The next item of a `for` loop, with the value of a table entry, or else `nil`.
+     * A coroutine is resumed for it, and has no more once it has returned. */
+    fn next_item(
+        &mut self,
+        iteration: &mut Iteration,
+        span: Span,
+    ) -> EvalResult<Option<(Value, Value)>> {
+        let item = match iteration {
+            Iteration::Items { items, next } => {
+                let item = items.get(*next).cloned();
+                *next += 1;
+                item
+            }
+            Iteration::Entries { entries, next } => {
+                let entry = entries.get(*next).cloned();
+                *next += 1;
+                return Ok(entry);
+            }
+            Iteration::Range(range) => range.next(),
+            Iteration::Coroutine(obj) => {
+                let obj = *obj;
+                if self.coroutine_status(obj) == CoroutineStatus::Dead {
+                    return Ok(None);
+                }
+                let item = self.resume(obj, Value::Nil, span)?;
+                (self.coroutine_status(obj) != CoroutineStatus::Dead).then_some(item)
+            }
+            Iteration::Next(iterator) => {
+                let iterator = iterator.clone();
+                let Some(method) = self.find_method(&iterator, "next") else {
+                    return Ok(None);
+                };
+                let item = self.call_method(method, iterator, span)?;
+                (!item.is_nil()).then_some(item)
+            }
+        };
+        Ok(item.map(|item| (item, Value::Nil)))
+    }
+
+    /* Expressions */
+
+    /* `hint` is the type the context asks for; of an optional type, only the values
+     * other than `nil` matter for it. */
+    pub fn eval(&mut self, expr: &Expr, hint: Option<&Type>) -> EvalResult<Value> {
+        let value = self.eval_expr(expr, hint)?;
+        self.root(&value);
+        Ok(value)
+    }
+
+    fn eval_expr(&mut self, expr: &Expr, hint: Option<&Type>) -> EvalResult<Value> {
+        let hint = hint.map(Type::non_optional);
+        let span = expr.span;
+        match &expr.kind {
+            ExprKind::Int(magnitude) => int_literal(false, *magnitude, hint, span),
+            ExprKind::Float(float) => Ok(float_literal(*float, hint)),
+            ExprKind::Bool(value) => Ok(Value::Bool(*value)),
+            ExprKind::Char(ch) => Ok(Value::Char(*ch)),
+            ExprKind::Str(string) => Ok(Value::Str(string.as_str().into())),
+            ExprKind::Nil => Ok(Value::Nil),
+            ExprKind::Ident(name, id) => {
+                let binding = self
+                    .find_var(name, id.get())
+                    .map(|env| &self.env_ref(env).vars[name])
+                    .ok_or_else(|| RuntimeError::new(span, format!("Undefined name `{name}`.")))?;
+                check_assigned(name, &binding.value, &binding.ty, span)?;
+                Ok(binding.value.clone())
+            }
+            ExprKind::SelF => self.lookup_value("self", span),
+            ExprKind::This => self.lookup_value("this", span),
+            ExprKind::Array(items) => {
+                let elem_hint = match hint {
+                    Some(Type::Array(elem) | Type::List(elem) | Type::Set(elem)) => {
+                        Some(elem.as_ref())
+                    }
+                    _ => None,
+                };
+                let mut values = Vec::with_capacity(items.len());
+                for item in items {
+                    let value = self.eval(item, elem_hint)?;
+                    values.push(self.copy_value(value));
+                }
+                Ok(collection(&mut self.heap, values, hint))
+            }
+            ExprKind::Table(entries) => {
+                let (mut key_ty, mut value_ty) = match hint {
+                    Some(Type::Table(key, value)) => ((**key).clone(), (**value).clone()),
+                    _ => (Type::Unknown, Type::Unknown),
+                };
+                let mut pairs = Vec::with_capacity(entries.len());
+                for (key, value) in entries {
+                    let key = self.eval(key, known(&key_ty))?;
+                    let value = self.eval(value, known(&value_ty))?;
+                    if key_ty == Type::Unknown {
+                        (key_ty, value_ty) = (inferred_type(&key), inferred_type(&value));
+                    }
+                    pairs.push((self.copy_value(key), self.copy_value(value)));
+                }
+                Ok(table(&mut self.heap, key_ty, value_ty, pairs))
+            }
+            ExprKind::Repeat(value, count) => {
+                let elem_hint = match hint {
+                    Some(Type::Array(elem) | Type::List(elem) | Type::Set(elem)) => {
+                        Some(elem.as_ref())
+                    }
+                    _ => None,
+                };
+                let value = self.eval(value, elem_hint)?;
+                let count = match self.eval(count, None)?.int_parts() {
+                    Some((false, count)) => usize::try_from(count).ok(),
+                    _ => None,
+                };
+                let Some(count) = count else {
+                    return Err(RuntimeError::new(
+                        span,
+                        "The size of an array must be a non-negative integer.",
+                    ));
+                };
+                let values = (0..count).map(|_| self.copy_value(value.clone())).collect();
+                Ok(collection(&mut self.heap, values, hint))
+            }
+            ExprKind::Unary(op, operand) => self.eval_unary(*op, operand, hint, span),
+            ExprKind::Binary(op, lhs, rhs) => self.eval_binary(*op, lhs, rhs, hint, span),
+            ExprKind::Range {
+                start,
+                end,
+                step,
+                inclusive,
+            } => {
+                let hint = match hint {
+                    Some(Type::Range(elem)) => Some(&**elem),
+                    _ => None,
+                };
+                let range = self.range_value(start, end, step.as_deref(), *inclusive, hint, span)?;
+                Ok(Value::Obj(self.heap.alloc(Object::Range(range))))
+            }
+            ExprKind::Call(callee, args) => self.eval_call(callee, args, hint, span),
+            ExprKind::Index(target, index) => {
+                let target = self.eval(target, None)?;
+                let int = Type::Int(IntKind::I32);
+                let hint = match (self.heap.table(&target), &index.kind) {
+                    (Some(table), _) => table.key.clone(),
+                    (None, ExprKind::Range { .. }) => Type::Range(Box::new(int)),
+                    (None, _) => int,
+                };
+                let index_value = self.eval(index, known(&hint))?;
+                if let Some(range) = self.heap.range(&index_value).cloned() {
+                    return slice(&mut self.heap, &target, &range, index.span);
+                }
+                element(&self.heap, &target, &index_value, index.span)
+            }
+            ExprKind::Field(target, name) => {
+                let target = self.eval(target, None)?;
+                self.get_field(&target, name, span)
+            }
+            ExprKind::SafeField(target, name) => match self.eval(target, None)? {
+                Value::Nil => Ok(Value::Nil),
+                target => self.get_field(&target, name, span),
+            },
+            ExprKind::Func(decl) => {
+                let closure = self.heap.alloc(Object::Closure(Closure {
+                    decl: Rc::clone(decl),
+                    env: self.env,
+                    owner: None,
+                }));
+                Ok(Value::Obj(closure))
+            }
+            ExprKind::Cast(ty, value) => {
+                let value = self.eval(value, None)?;
+                ops::cast(&value, ty).map_err(|message| RuntimeError::new(span, message))
+            }
+            ExprKind::Try(value) => {
+                let value = self.eval(value, hint)?;
+                if self.heap.error(&value).is_some() {
+                    return Err(raise(&self.heap, &value, span));
+                }
+                Ok(value)
+            }
+            /* Only reached again when the coroutine is resumed. */
+            ExprKind::Yield(_) => match self.resumed.take() {
+                Some(value) => Ok(value),
+                None if self.frames.last().is_some_and(|frame| frame.coroutine) => {
+                    Err(RuntimeError::new(
+                        span,
+                        "`yield` can only be a statement, or the value of an assignment or declaration.",
+                    ))
+                }
+                None => Err(RuntimeError::new(span, "`yield` outside of a coroutine.")),
+            },
+        }
+    }
+
+    fn lookup_value(&self, name: &str, span: Span) -> EvalResult<Value> {
+        self.lookup(name)
+            .map(|binding| binding.value.clone())
+            .ok_or_else(|| RuntimeError::new(span, format!("Undefined name `{name}`.")))
+    }
+
+    fn eval_unary(
+        &mut self,
+        op: UnaryOp,
+        operand: &Expr,
+        hint: Option<&Type>,
+        span: Span,
+    ) -> EvalResult<Value> {
+        match (op, &operand.kind) {
+            (UnaryOp::Neg, ExprKind::Int(magnitude)) => {
+                return int_literal(true, *magnitude, hint, span)
+            }
+            (UnaryOp::Neg, ExprKind::Float(float)) => return Ok(float_literal(-float, hint)),
+            _ => (),
+        }
+        let value = self.eval(operand, hint)?;
+        if op == UnaryOp::Len {
+            return length(&self.heap, &value, span);
+        }
+        ops::unary(op, &value).map_err(|message| RuntimeError::new(span, message))
+    }
+
+    /* Evaluates both operands, giving an untyped literal the type of the other side. */
+    fn eval_operands(
+        &mut self,
+        lhs: &Expr,
+        rhs: &Expr,
+        hint: Option<&Type>,
+    ) -> EvalResult<(Value, Value)> {
+        if lhs.is_untyped_literal() && !rhs.is_untyped_literal() {
+            let rhs = self.eval(rhs, hint)?;
+            let lhs = self.eval(lhs, rhs.numeric_type().as_ref().or(hint))?;
+            return Ok((lhs, rhs));
+        }
+        let lhs = self.eval(lhs, hint)?;
+        let rhs = self.eval(rhs, lhs.numeric_type().as_ref().or(hint))?;
+        Ok((lhs, rhs))
+    }
+
+    fn eval_binary(
+        &mut self,
+        op: BinaryOp,
+        lhs: &Expr,
+        rhs: &Expr,
+        hint: Option<&Type>,
+        span: Span,
+    ) -> EvalResult<Value> {
+        let hint = op.operand_hint(hint);
+        match op {
+            BinaryOp::And | BinaryOp::Or => {
+                let lhs_value = self.eval_condition(lhs)?;
+                if lhs_value == (op == BinaryOp::Or) {
+                    return Ok(Value::Bool(lhs_value));
+                }
+                Ok(Value::Bool(self.eval_condition(rhs)?))
+            }
+            BinaryOp::Xor => {
+                let lhs_value = self.eval_condition(lhs)?;
+                Ok(Value::Bool(lhs_value ^ self.eval_condition(rhs)?))
+            }
+            BinaryOp::NilCoalesce => {
+                let value = self.eval(lhs, hint)?;
+                if value.is_nil() {
+                    return self.eval(rhs, hint);
+                }
+                Ok(value)
+            }
+            /* A literal takes the type of the integers of the range, and a range
+             * literal the type of the value. */
+            BinaryOp::In if lhs.is_untyped_literal() => {
+                let range = self.eval(rhs, None)?;
+                let item = self
+                    .heap
+                    .range(&range)
+                    .and_then(|range| range.start.numeric_type());
+                let value = self.eval(lhs, item.as_ref())?;
+                binary_values(&mut self.heap, op, &value, &range, span)
+            }
+            BinaryOp::In => {
+                let value = self.eval(lhs, None)?;
+                let hint = value.numeric_type().map(|ty| Type::Range(Box::new(ty)));
+                let range = self.eval(rhs, hint.as_ref())?;
+                binary_values(&mut self.heap, op, &value, &range, span)
+            }
+            _ => {
+                let (lhs, rhs) = self.eval_operands(lhs, rhs, hint)?;
+                binary_values(&mut self.heap, op, &lhs, &rhs, span)
+            }
+        }
+    }
+
+    fn field_slot(
+        &self,
+        target: &Value,
+        name: &str,
+        span: Span,
+    ) -> EvalResult<(ObjRef, usize, Type)> {
+        if let (Value::Obj(obj), Some(instance)) = (target, self.heap.instance(target)) {
+            if let Some(ty) = self.heap.struct_type(instance.ty) {
+                if let Some(index) = ty.decl.field_index(name) {
+                    return Ok((*obj, index, self.field_types(ty, &[]).swap_remove(index)));
+                }
+            }
+        }
+        Err(RuntimeError::new(
+            span,
+            format!("{} has no field `{name}`.", self.heap.type_name(target)),
+        ))
+    }
+
+    /* The closure for `name` if `target` is an instance with such a method. */
+    fn find_method(&mut self, target: &Value, name: &str) -> Option<ObjRef> {
+        let instance = self.heap.instance(target)?;
+        let owner = instance.ty;
+        let ty = self.heap.struct_type(owner)?;
+        let decl = Rc::clone(ty.decl.method(name)?);
+        let env = ty.env;
+        Some(self.heap.alloc(Object::Closure(Closure {
+            decl,
+            env,
+            owner: Some(owner),
+        })))
+    }
+
+    fn get_field(&mut self, target: &Value, name: &str, span: Span) -> EvalResult<Value> {
+        if let Value::Obj(obj) = target {
+            match self.heap.get(*obj) {
+                Object::Instance(instance) => {
+                    let fields = &instance.fields;
+                    if let Some(ty) = self.heap.struct_type(instance.ty) {
+                        if let Some(index) = ty.decl.field_index(name) {
+                            return Ok(fields[index].clone());
+                        }
+                    }
+                    if let Some(method) = self.find_method(target, name) {
+                        let bound = self.heap.alloc(Object::BoundMethod {
+                            receiver: target.clone(),
+                            method,
+                        });
+                        return Ok(Value::Obj(bound));
+                    }
+                }
+                Object::Call { args } if name == "args" => return Ok(Value::Obj(*args)),
+                Object::Error(error) => {
+                    if let Some(value) = error.field(name) {
+                        return Ok(value);
+                    }
+                }
+                Object::EnumType(ty) => {
+                    if name == Builtin::FromDiscriminant.name() {
+                        let bound = self.heap.alloc(Object::BoundBuiltin {
+                            receiver: target.clone(),
+                            builtin: Builtin::FromDiscriminant,
+                        });
+                        return Ok(Value::Obj(bound));
+                    }
+                    let Some(variant) = ty.decl.variant_index(name) else {
+                        return Err(RuntimeError::new(
+                            span,
+                            format!("Enum `{}` has no variant `{name}`.", ty.decl.name),
+                        ));
+                    };
+                    let object = if ty.decl.variants[variant].fields.is_empty() {
+                        Object::EnumValue(EnumValue {
+                            ty: *obj,
+                            variant,
+                            payload: vec![],
+                        })
+                    } else {
+                        Object::VariantConstructor { ty: *obj, variant }
+                    };
+                    return Ok(Value::Obj(self.heap.alloc(object)));
+                }
+                _ => (),
+            }
+        }
+        Err(RuntimeError::new(
+            span,
+            format!("{} has no field `{name}`.", self.heap.type_name(target)),
+        ))
+    }
+
+    fn eval_call(
+        &mut self,
+        callee: &Expr,
+        args: &[Expr],
+        hint: Option<&Type>,
+        span: Span,
+    ) -> EvalResult<Value> {
+        match self.callee(callee)? {
+            Callee::Method(method, receiver) => {
+                self.call_closure(method, Some(receiver), args, span)
+            }
+            Callee::Value(function) => self.call_value(function, args, hint, span),
+            Callee::Nil => Ok(Value::Nil),
+        }
+    }
+
+    fn callee(&mut self, callee: &Expr) -> EvalResult<Callee> {
+        /* `a?.f(x)` evaluates to `nil` without evaluating `x` when `a` is `nil`. */
+        if let ExprKind::Field(target, name) | ExprKind::SafeField(target, name) = &callee.kind {
+            let receiver = self.eval(target, None)?;
+            if receiver.is_nil() && matches!(callee.kind, ExprKind::SafeField(..)) {
+                return Ok(Callee::Nil);
+            }
+            if let Some(method) = self.find_method(&receiver, name) {
+                return Ok(Callee::Method(method, receiver));
+            }
+            return Ok(Callee::Value(self.get_field(
+                &receiver,
+                name,
+                callee.span,
+            )?));
+        }
+        Ok(Callee::Value(self.eval(callee, None)?))
+    }
+
+    /* `return f(x)` leaves a closure for the caller to call once the function has
+     * returned, so that tail-recursive loops run in constant space. */
+    fn tail_call(
+        &mut self,
+        callee: &Expr,
+        args: &[Expr],
+        hint: Option<&Type>,
+        span: Span,
+    ) -> EvalResult<Flow> {
+        let (closure, receiver) = match self.callee(callee)? {
+            Callee::Method(method, receiver) => (method, Some(receiver)),
+            Callee::Value(function) => match self.closure_of(&function) {
+                Some(closure) => closure,
+                None => return Ok(Flow::Return(self.call_value(function, args, hint, span)?)),
+            },
+            Callee::Nil => return Ok(Flow::Return(Value::Nil)),
+        };
+        Ok(Flow::TailCall(
+            self.prepare_call(closure, receiver, args, span)?,
+        ))
+    }
+
+    /* The closure a function value runs, with the receiver it is bound to. */
+    fn closure_of(&self, function: &Value) -> Option<(ObjRef, Option<Value>)> {
+        let Value::Obj(obj) = function else {
+            return None;
+        };
+        match self.heap.get(*obj) {
+            Object::Closure(_) => Some((*obj, None)),
+            Object::BoundMethod { receiver, method } => Some((*method, Some(receiver.clone()))),
+            _ => None,
+        }
+    }
+
+    fn call_value(
+        &mut self,
+        function: Value,
+        args: &[Expr],
+        hint: Option<&Type>,
+        span: Span,
+    ) -> EvalResult<Value> {
+        match function {
+            Value::Builtin(builtin) => self.call_builtin(builtin, vec![], args, hint, span),
+            Value::Obj(obj) => match self.heap.get(obj) {
+                Object::BoundBuiltin { receiver, builtin } => {
+                    let (receiver, builtin) = (receiver.clone(), *builtin);
+                    self.call_builtin(builtin, vec![receiver], args, hint, span)
+                }
+                Object::VariantConstructor { ty, variant } => {
+                    let (ty, variant) = (*ty, *variant);
+                    self.construct_variant(ty, variant, args, span)
+                }
+                Object::Closure(_) => self.call_closure(obj, None, args, span),
+                Object::BoundMethod { receiver, method } => {
+                    let (receiver, method) = (receiver.clone(), *method);
+                    self.call_closure(method, Some(receiver), args, span)
+                }
+                Object::StructType(ty) => {
+                    /* `Box<int8> b = Box(1)` constructs a `Box<int8>`. */
+                    let type_args = match hint {
+                        Some(Type::Named(name, args)) if *name == ty.decl.name => args.clone(),
+                        _ => vec![],
+                    };
+                    self.construct(obj, args, &type_args, span)
+                }
+                _ => Err(self.not_callable(&function, span)),
+            },
+            _ => Err(self.not_callable(&function, span)),
+        }
+    }
+
+    /* Calls a builtin with `values` followed by the evaluated `args`. The operands of
+     * `wrappingAdd` and friends are typed like those of the operator. */
+    fn call_builtin(
+        &mut self,
+        builtin: Builtin,
+        mut values: Vec<Value>,
+        args: &[Expr],
+        hint: Option<&Type>,
+        span: Span,
+    ) -> EvalResult<Value> {
+        if let (Builtin::Arithmetic(..), [lhs, rhs]) = (builtin, args) {
+            let (lhs, rhs) = self.eval_operands(lhs, rhs, hint)?;
+            values.extend([lhs, rhs]);
+        } else {
+            for arg in args {
+                values.push(self.eval(arg, None)?);
+            }
+        }
+        let mut named = None;
+        let format = builtin == Builtin::Format
+            || (builtin.formats() && self.is_format_string(args.first()));
+        if let (true, Some(Value::Str(text))) = (format, values.first()) {
+            let pieces = format::parse(text).map_err(|message| RuntimeError::new(span, message))?;
+            let mut values = HashMap::new();
+            for name in format::names(&pieces) {
+                if let Some(binding) = self.lookup(name) {
+                    values.insert(name.to_owned(), binding.value.clone());
+                }
+            }
+            named = Some(values);
+        }
+        if builtin == Builtin::CollectGarbage {
+            self.collect_garbage();
+        }
+        if let (Builtin::Resume, [co @ Value::Obj(obj), rest @ ..]) = (builtin, values.as_slice()) {
+            if self.heap.coroutine(co).is_some() && rest.len() <= 1 {
+                let value = rest.first().cloned().unwrap_or(Value::Nil);
+                return self.resume(*obj, value, span);
+            }
+        }
+        builtins::call(
+            builtin,
+            &mut self.heap,
+            &mut self.output,
+            &values,
+            named.as_ref(),
+        )
+        .map_err(|message| RuntimeError::new(span, message))
+    }
+
+    /* Whether `arg` is read as a format string when it comes first: a string literal,
+     * or a constant, whose placeholders the checker has counted. */
+    fn is_format_string(&self, arg: Option<&Expr>) -> bool {
+        match arg.map(|arg| &arg.kind) {
+            Some(ExprKind::Str(_)) => true,
+            Some(ExprKind::Ident(name, _)) => {
+                self.lookup(name).is_some_and(|binding| binding.constant)
+            }
+            _ => false,
+        }
+    }
+
+    fn construct_variant(
+        &mut self,
+        ty: ObjRef,
+        variant: usize,
+        args: &[Expr],
+        span: Span,
+    ) -> EvalResult<Value> {
+        let Some(EnumType { decl, env }) = self.heap.enum_type(ty).cloned() else {
+            unreachable!("variant constructors point at their enum type");
+        };
+        let Variant { name, fields, .. } = &decl.variants[variant];
+        let fields = fields
+            .iter()
+            .map(|field| self.resolve_type(env, field))
+            .collect::<Vec<_>>();
+        if args.len() != fields.len() {
+            return Err(RuntimeError::new(
+                span,
+                format!(
+                    "Variant `{}.{name}` has {} field(s), found {} argument(s).",
+                    decl.name,
+                    fields.len(),
+                    args.len()
+                ),
+            ));
+        }
+        let mut payload = Vec::with_capacity(args.len());
+        for (i, (field, arg)) in fields.iter().zip(args).enumerate() {
+            let value = self.eval(arg, Some(field))?;
+            let value = check_type(&self.heap, value, field, arg.span, || {
+                format!("field {i} of `{}.{name}`", decl.name)
+            })?;
+            payload.push(self.copy_value(value));
+        }
+        let value = Object::EnumValue(EnumValue {
+            ty,
+            variant,
+            payload,
+        });
+        Ok(Value::Obj(self.heap.alloc(value)))
+    }
+
+    fn not_callable(&self, value: &Value, span: Span) -> RuntimeError {
+        RuntimeError::new(
+            span,
+            format!("{} is not callable.", self.heap.type_name(value)),
+        )
+    }
+
+    fn eval_args(
+        &mut self,
+        decl: &FuncDecl,
+        params: &[Type],
+        args: &[Expr],
+        span: Span,
+    ) -> EvalResult<Vec<Value>> {
+        let name = decl.name.as_deref().unwrap_or("<anonymous>");
+        if args.len() != decl.params.len() {
+            return Err(RuntimeError::new(
+                span,
+                format!(
+                    "Function `{name}` expects {} argument(s), found {}.",
+                    decl.params.len(),
+                    args.len()
+                ),
+            ));
+        }
+        let mut values = Vec::with_capacity(args.len());
+        for ((param, ty), arg) in decl.params.iter().zip(params).zip(args) {
+            let value = self.eval(arg, Some(ty))?;
+            let value = check_type(&self.heap, value, ty, arg.span, || {
+                format!("parameter `{}` of `{name}`", param.name)
+            })?;
+            values.push(self.copy_value(value));
+        }
+        Ok(values)
+    }
+
+    /* The environment of the `static` variables of `decl` closed over `env`, which
+     * sits between `env` and the environment of each call. `None` if it declares
+     * no `static` variable. */
+    fn statics_env(&mut self, decl: &Rc<FuncDecl>, env: ObjRef) -> Option<ObjRef> {
+        let has_statics = decl.body.iter().any(|stmt| {
+            matches!(&stmt.kind, StmtKind::VarDecl(decl) if decl.storage == Some(Storage::Static))
+        });
+        if !has_statics {
+            return None;
+        }
+        let key = (Rc::as_ptr(decl), env);
+        if let Some((_, statics)) = self.statics.get(&key) {
+            return Some(*statics);
+        }
+        let statics = self.new_env(env);
+        self.statics.insert(key, (Rc::clone(decl), statics));
+        Some(statics)
+    }
+
+    fn call_closure(
+        &mut self,
+        closure: ObjRef,
+        receiver: Option<Value>,
+        args: &[Expr],
+        span: Span,
+    ) -> EvalResult<Value> {
+        let mut call = self.prepare_call(closure, receiver, args, span)?;
+        if self.frames.len() >= self.max_depth || self.stack_exhausted() {
+            return Err(RuntimeError::new(span, "Stack overflow."));
+        }
+        /* The function called here, whose return value is also checked when tail
+         * calls replaced it. */
+        let first = (Rc::clone(&call.closure.decl), call.ret.clone());
+        loop {
+            if call.closure.decl.coroutine {
+                let decl = Rc::clone(&call.closure.decl);
+                let value = self.make_coroutine(*call);
+                if Rc::ptr_eq(&decl, &first.0) {
+                    return Ok(value);
+                }
+                return self.return_value(&first.0, &first.1, value, span);
+            }
+            let Call {
+                closure,
+                params,
+                ret,
+                values,
+                self_value,
+                ..
+            } = *call;
+            let decl = closure.decl;
+            let statics = self.statics_env(&decl, closure.env);
+            self.frames.push(Frame {
+                ret: ret.clone(),
+                statics,
+                tries: 0,
+                coroutine: false,
+            });
+            let flow = self.scoped(statics.unwrap_or(closure.env), |ev| {
+                for ((param, ty), value) in decl.params.iter().zip(params).zip(values) {
+                    ev.define(&param.name, value, ty);
+                }
+                if let Some(value) = self_value {
+                    ev.define("self", value, Type::Unknown);
+                }
+                if let Some(owner) = closure.owner {
+                    ev.define("this", Value::Obj(owner), Type::Unknown);
+                }
+                ev.exec_stmts(&decl.body)
+            });
+            self.frames.pop();
+
+            let name = decl.name.as_deref().unwrap_or("<anonymous>");
+            let value = match flow.map_err(|error| error.called(name, span))? {
+                Flow::Return(value) => value,
+                Flow::Next => Value::Nil,
+                Flow::Break(_) => {
+                    return Err(RuntimeError::new(span, "`break` outside of a loop."))
+                }
+                Flow::Continue(_) => {
+                    return Err(RuntimeError::new(span, "`continue` outside of a loop."))
+                }
+                Flow::TailCall(next) => {
+                    call = next;
+                    continue;
+                }
+            };
+            let value = self.return_value(&decl, &ret, value, span)?;
+            if Rc::ptr_eq(&decl, &first.0) {
+                return Ok(value);
+            }
+            return self.return_value(&first.0, &first.1, value, span);
+        }
+    }
+
+    /* Whether the host stack has no room left for a call. */
+    fn stack_exhausted(&self) -> bool {
+        let used = self.stack_start.abs_diff(stack_address());
+        used + 2 * STACK_PER_CALL > stack_size(self.max_depth)
+    }
+
+    /* Resolves the types of a call and evaluates its arguments. */
+    fn prepare_call(
+        &mut self,
+        closure: ObjRef,
+        receiver: Option<Value>,
+        args: &[Expr],
+        span: Span,
+    ) -> EvalResult<Box<Call>> {
+        let Object::Closure(closure) = self.heap.get(closure).clone() else {
+            unreachable!("calls are only made of closures");
+        };
+        let decl = &closure.decl;
+        let params = decl
+            .params
+            .iter()
+            .map(|param| self.resolve_type(closure.env, &param.ty))
+            .collect::<Vec<_>>();
+        let ret = self.resolve_type(closure.env, &decl.ret);
+        let values = self.eval_args(decl, &params, args, span)?;
+        let self_value = match receiver {
+            Some(receiver) => Some(receiver),
+            None if decl.uses_self => {
+                let args = self.heap.alloc(Object::Array(values.clone()));
+                Some(Value::Obj(self.heap.alloc(Object::Call { args })))
+            }
+            None => None,
+        };
+        Ok(Box::new(Call {
+            closure,
+            params,
+            ret,
+            values,
+            self_value,
+        }))
+    }
+
+    fn return_value(
+        &self,
+        decl: &FuncDecl,
+        ret: &Type,
+        value: Value,
+        span: Span,
+    ) -> EvalResult<Value> {
+        let name = decl.name.as_deref().unwrap_or("<anonymous>");
+        if value.is_nil() && !matches!(decl.ret, Type::Void | Type::Unknown | Type::Optional(_)) {
+            return Err(RuntimeError::new(
+                span,
+                format!("Function `{name}` did not return a {}.", decl.ret),
+            ));
+        }
+        check_type(&self.heap, value, ret, span, || {
+            format!("the return value of `{name}`")
+        })
+    }
+
+    /* Coroutines */
+
+    /* A call of a `coroutine` function binds its arguments and makes a coroutine,
+     * which runs nothing until it is resumed. */
+    fn make_coroutine(&mut self, call: Call) -> Value {
+        let Call {
+            closure,
+            params,
+            ret,
+            values,
+            self_value,
+        } = call;
+        let decl = closure.decl;
+        let statics = self.statics_env(&decl, closure.env);
+        let env = self.new_env(statics.unwrap_or(closure.env));
+        let bind = |ev: &mut Self, name: &str, value, ty| {
+            let binding = Binding {
+                value,
+                ty,
+                constant: false,
+                id: None,
+            };
+            ev.define_in(env, name, binding);
+        };
+        for ((param, ty), value) in decl.params.iter().zip(params).zip(values) {
+            bind(self, &param.name, value, ty);
+        }
+        if let Some(value) = self_value {
+            bind(self, "self", value, Type::Unknown);
+        }
+        if let Some(owner) = closure.owner {
+            bind(self, "this", Value::Obj(owner), Type::Unknown);
+        }
+        let coroutine = Coroutine {
+            decl,
+            ret,
+            env,
+            statics,
+            status: CoroutineStatus::Suspended,
+            cursors: vec![],
+        };
+        Value::Obj(self.heap.alloc(Object::Coroutine(coroutine)))
+    }
+
+    fn coroutine_status(&self, obj: ObjRef) -> CoroutineStatus {
+        match self.heap.get(obj) {
+            Object::Coroutine(coroutine) => coroutine.status,
+            _ => unreachable!("only coroutines are resumed"),
+        }
+    }
+
+    fn coroutine_mut(&mut self, obj: ObjRef) -> &mut Coroutine {
+        match self.heap.get_mut(obj) {
+            Object::Coroutine(coroutine) => coroutine,
+            _ => unreachable!("only coroutines are resumed"),
+        }
+    }
+
+    /* Runs a coroutine from where it is suspended until it yields a value, which
+     * `resume` returns, or returns one and is dead. `value` is what the `yield` it
+     * was suspended at evaluates to. */
+    fn resume(&mut self, obj: ObjRef, value: Value, span: Span) -> EvalResult<Value> {
+        match self.coroutine_status(obj) {
+            CoroutineStatus::Suspended => (),
+            CoroutineStatus::Running => {
+                return Err(RuntimeError::new(
+                    span,
+                    "Cannot resume a running coroutine.",
+                ))
+            }
+            CoroutineStatus::Dead => {
+                return Err(RuntimeError::new(span, "Cannot resume a dead coroutine."))
+            }
+        }
+        if self.frames.len() >= self.max_depth || self.stack_exhausted() {
+            return Err(RuntimeError::new(span, "Stack overflow."));
+        }
+        let coroutine = self.coroutine_mut(obj);
+        coroutine.status = CoroutineStatus::Running;
+        let mut cursors = std::mem::take(&mut coroutine.cursors);
+        let (decl, ret, env, statics) = (
+            Rc::clone(&coroutine.decl),
+            coroutine.ret.clone(),
+            coroutine.env,
+            coroutine.statics,
+        );
+        let previous = self.env;
+        let mark = self.temps.len();
+        self.temps.extend([Value::Obj(previous), Value::Obj(obj)]);
+        let mut resume = Some(value);
+        if cursors.is_empty() {
+            cursors.push(Cursor {
+                index: 0,
+                env,
+                branch: 0,
+                iteration: None,
+            });
+            self.env = env;
+            self.hoist(&decl.body);
+            resume = None;
+        }
+        self.running.push(cursors);
+        self.frames.push(Frame {
+            ret: ret.clone(),
+            statics,
+            tries: 0,
+            coroutine: true,
+        });
+        let step = self.co_block(&decl.body, 0, &mut resume);
+        self.frames.pop();
+        let cursors = self.running.pop().unwrap_or_default();
+        self.env = previous;
+        self.temps.truncate(mark);
+
+        let name = decl.name.as_deref().unwrap_or("<anonymous>");
+        let coroutine = self.coroutine_mut(obj);
+        coroutine.status = CoroutineStatus::Dead;
+        let value = match step.map_err(|error| error.called(name, span))? {
+            Step::Yield(value) => {
+                coroutine.status = CoroutineStatus::Suspended;
+                coroutine.cursors = cursors;
+                value
+            }
+            Step::Flow(Flow::Return(value)) => value,
+            Step::Flow(Flow::Next) => Value::Nil,
+            Step::Flow(Flow::Break(_)) => {
+                return Err(RuntimeError::new(span, "`break` outside of a loop."))
+            }
+            Step::Flow(Flow::Continue(_)) => {
+                return Err(RuntimeError::new(span, "`continue` outside of a loop."))
+            }
+            Step::Flow(Flow::TailCall(_)) => unreachable!("coroutines make no tail calls"),
+        };
+        if value.is_nil() {
+            return Ok(value);
+        }
+        check_type(&self.heap, value, &ret, span, || {
+            format!("the value yielded by `{name}`")
+        })
+    }
+
+    fn cursors(&mut self) -> &mut Vec<Cursor> {
+        self.running
+            .last_mut()
+            .expect("cursors are only used while a coroutine runs")
+    }
+
+    /* Runs the block of the running coroutine at `depth` from the statement its
+     * cursor is at. While `resume` holds a value, the coroutine is going back to
+     * where it was suspended, which is the statement at the deepest cursor. */
+    fn co_block(
+        &mut self,
+        block: &Block,
+        depth: usize,
+        resume: &mut Option<Value>,
+    ) -> EvalResult<Step> {
+        let Cursor { index, env, .. } = self.cursors()[depth];
+        self.env = env;
+        for (index, stmt) in block.iter().enumerate().skip(index) {
+            self.cursors()[depth].index = index;
+            self.safe_point();
+            let mark = self.temps.len();
+            let step = if resume.is_some() && self.cursors().len() == depth + 1 {
+                self.resumed = resume.take();
+                self.exec_stmt(stmt).map(Step::Flow)
+            } else if resume.is_some() || yields(stmt) {
+                self.co_stmt(stmt, depth, resume)
+            } else {
+                self.exec_stmt(stmt).map(Step::Flow)
+            };
+            self.temps.truncate(mark);
+            match step? {
+                Step::Flow(Flow::Next) => (),
+                step => return Ok(step),
+            }
+        }
+        Ok(Step::Flow(Flow::Next))
+    }
+
+    /* Runs a statement of the block at `depth` that may yield. */
+    fn co_stmt(
+        &mut self,
+        stmt: &Stmt,
+        depth: usize,
+        resume: &mut Option<Value>,
+    ) -> EvalResult<Step> {
+        let resuming = resume.is_some();
+        match &stmt.kind {
+            StmtKind::Expr(Expr {
+                kind: ExprKind::Yield(value),
+                ..
+            })
+            | StmtKind::Assign {
+                value:
+                    Expr {
+                        kind: ExprKind::Yield(value),
+                        ..
+                    },
+                ..
+            }
+            | StmtKind::VarDecl(VarDecl {
+                value:
+                    Some(Expr {
+                        kind: ExprKind::Yield(value),
+                        ..
+                    }),
+                ..
+            }) => {
+                let ret = self
+                    .frames
+                    .last()
+                    .map_or(Type::Unknown, |frame| frame.ret.clone());
+                let value = match value {
+                    Some(value) => self.eval(value, Some(&ret))?,
+                    None => Value::Nil,
+                };
+                Ok(Step::Yield(value))
+            }
+            StmtKind::If {
+                branches,
+                else_block,
+            } => {
+                let branch = match resuming {
+                    true => self.cursors()[depth + 1].branch,
+                    false => {
+                        let mut taken = branches.len();
+                        for (i, (cond, _)) in branches.iter().enumerate() {
+                            if self.eval_condition(cond)? {
+                                taken = i;
+                                break;
+                            }
+                        }
+                        taken
+                    }
+                };
+                let body = match (branches.get(branch), else_block) {
+                    (Some((_, body)), _) | (None, Some(body)) => body,
+                    (None, None) => return Ok(Step::Flow(Flow::Next)),
+                };
+                self.co_child(body, depth, branch, resume, |_| ())
+            }
+            StmtKind::Given(given) => {
+                let mut arm = self.cursors().get(depth + 1).map(|cursor| cursor.branch);
+                let mut bindings = vec![];
+                if !resuming {
+                    arm = None;
+                    let subject = self.eval(&given.subject, None)?;
+                    'arms: for (i, candidate) in given.arms.iter().enumerate() {
+                        for pattern in &candidate.patterns {
+                            if self.pattern_matches(pattern, &subject, &mut bindings)? {
+                                arm = Some(i);
+                                break 'arms;
+                            }
+                            bindings.clear();
+                        }
+                    }
+                }
+                let Some(arm) = arm else {
+                    return Ok(Step::Flow(Flow::Next));
+                };
+                self.co_child(&given.arms[arm].body, depth, arm, resume, |ev| {
+                    for (name, value, ty) in bindings {
+                        let value = ev.copy_value(value);
+                        ev.define(name, value, ty);
+                    }
+                })
+            }
+            StmtKind::For { .. } => self.co_for(stmt, depth, resume),
+            StmtKind::While { .. } | StmtKind::Loop { .. } => {
+                let (cond, body, label) = match &stmt.kind {
+                    StmtKind::While { cond, body, label } => (Some(cond), body, label),
+                    StmtKind::Loop { body, label } => (None, body, label),
+                    _ => unreachable!("matched above"),
+                };
+                loop {
+                    if let (None, Some(cond)) = (&resume, cond) {
+                        if !self.eval_condition(cond)? {
+                            break;
+                        }
+                    }
+                    let mark = self.temps.len();
+                    let step = self.co_child(body, depth, 0, resume, |_| ())?;
+                    self.temps.truncate(mark);
+                    match step {
+                        Step::Flow(flow) => {
+                            if let Some(flow) = after_iteration(flow, label) {
+                                return Ok(Step::Flow(flow));
+                            }
+                        }
+                        step => return Ok(step),
+                    }
+                }
+                Ok(Step::Flow(Flow::Next))
+            }
+            StmtKind::Try { body, var, handler } => {
+                let branch = match resuming {
+                    true => self.cursors()[depth + 1].branch,
+                    false => 0,
+                };
+                if branch == 1 {
+                    return self.co_child(handler, depth, 1, resume, |_| ());
+                }
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.tries += 1;
+                }
+                let step = self.co_child(body, depth, 0, resume, |_| ());
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.tries -= 1;
+                }
+                let error = match step {
+                    Err(error) => error,
+                    step => return step,
+                };
+                *resume = None;
+                let error = ErrorValue {
+                    kind: error.kind,
+                    message: error.message.into(),
+                };
+                let error = self.heap.alloc(Object::Error(error));
+                self.co_child(handler, depth, 1, resume, |ev| {
+                    if let Some(var) = var {
+                        ev.define(var, Value::Obj(error), Type::Unknown);
+                    }
+                })
+            }
+            _ => self.exec_stmt(stmt).map(Step::Flow),
+        }
+    }
+
+    /* Runs `body` as the block at `depth + 1`: the one the coroutine was suspended in
+     * when resuming, or else a new one in a fresh scope, set up by `enter`. */
+    fn co_child(
+        &mut self,
+        body: &Block,
+        depth: usize,
+        branch: usize,
+        resume: &mut Option<Value>,
+        enter: impl FnOnce(&mut Self),
+    ) -> EvalResult<Step> {
+        let env = self.env;
+        if resume.is_none() {
+            let child = self.new_env(env);
+            self.cursors().push(Cursor {
+                index: 0,
+                env: child,
+                branch,
+                iteration: None,
+            });
+            self.env = child;
+            enter(self);
+            self.hoist(body);
+        }
+        let step = self.co_block(body, depth + 1, resume);
+        self.env = env;
+        if !matches!(step, Ok(Step::Yield(_))) {
+            self.cursors().truncate(depth + 1);
+        }
+        step
+    }
+
+    /* A `for` loop keeps the items it has left in the cursor of its body. */
+    fn co_for(
+        &mut self,
+        stmt: &Stmt,
+        depth: usize,
+        resume: &mut Option<Value>,
+    ) -> EvalResult<Step> {
+        let StmtKind::For {
+            var_ty,
+            var,
+            value_var,
+            iter,
+            body,
+            label,
+        } = &stmt.kind
+        else {
+            unreachable!("only `for` loops are iterated");
+        };
+        let var_ty = var_ty.as_ref();
+        let ty = var_ty.map_or(Type::Unknown, |ty| self.resolve_type(self.env, ty));
+        let value_var = value_var.as_ref().map(|(ty, name)| {
+            let ty = ty
+                .as_ref()
+                .map_or(Type::Unknown, |ty| self.resolve_type(self.env, ty));
+            (ty, name.as_str())
+        });
+        let env = self.env;
+        if resume.is_none() {
+            let iteration = self.iteration(var_ty.map(|_| &ty), iter, value_var.is_some())?;
+            self.cursors().push(Cursor {
+                index: 0,
+                env,
+                branch: 0,
+                iteration: Some(iteration),
+            });
+        }
+        loop {
+            let mark = self.temps.len();
+            if resume.is_none() {
+                let mut iteration = self.cursors()[depth + 1].iteration.take();
+                let item = match iteration.as_mut() {
+                    Some(iteration) => self.next_item(iteration, iter.span),
+                    None => Ok(None),
+                };
+                self.cursors()[depth + 1].iteration = iteration;
+                let Some((item, value)) = item? else {
+                    break;
+                };
+                let item = self.loop_value(item, &ty, var, iter.span)?;
+                let value = match &value_var {
+                    Some((value_ty, name)) => {
+                        Some(self.loop_value(value, value_ty, name, iter.span)?)
+                    }
+                    None => None,
+                };
+                let child = self.new_env(env);
+                let cursor = &mut self.cursors()[depth + 1];
+                cursor.index = 0;
+                cursor.env = child;
+                self.env = child;
+                self.define(var, item, ty.clone());
+                if let (Some((value_ty, name)), Some(value)) = (&value_var, value) {
+                    self.define(name, value, value_ty.clone());
+                }
+                self.hoist(body);
+            }
+            let step = self.co_block(body, depth + 1, resume);
+            self.env = env;
+            self.temps.truncate(mark);
+            match step? {
+                Step::Flow(flow) => {
+                    if let Some(flow) = after_iteration(flow, label) {
+                        self.cursors().truncate(depth + 1);
+                        return Ok(Step::Flow(flow));
+                    }
+                }
+                step => return Ok(step),
+            }
+        }
+        self.cursors().truncate(depth + 1);
+        Ok(Step::Flow(Flow::Next))
+    }
+
+    /* The field types of a struct, with its type parameters replaced by `type_args`. */
+    fn field_types(&self, ty: &StructType, type_args: &[Type]) -> Vec<Type> {
+        ty.decl
+            .fields
+            .iter()
+            .map(|field| {
+                self.resolve_type_except(ty.env, &field.ty, &ty.decl.params, 0)
+                    .substitute(&ty.decl.params, type_args)
+            })
+            .collect()
+    }
+
+    /* `Point(1.0, 2.0)` fills fields in order, or calls `new` if the struct has one. */
+    fn construct(
+        &mut self,
+        ty: ObjRef,
+        args: &[Expr],
+        type_args: &[Type],
+        span: Span,
+    ) -> EvalResult<Value> {
+        let Some(struct_type) = self.heap.struct_type(ty).cloned() else {
+            unreachable!("construct is only given struct types");
+        };
+        let field_types = self.field_types(&struct_type, type_args);
+        let StructType { decl, env } = struct_type;
+        let mut fields = Vec::with_capacity(decl.fields.len());
+        self.scoped(env, |ev| {
+            ev.define("this", Value::Obj(ty), Type::Unknown);
+            for (field, field_ty) in decl.fields.iter().zip(&field_types) {
+                let value = match &field.default {
+                    Some(default) => {
+                        let value = ev.eval(default, Some(field_ty))?;
+                        let value = check_type(&ev.heap, value, field_ty, default.span, || {
+                            format!("field `{}`", field.name)
+                        })?;
+                        ev.copy_value(value)
+                    }
+                    None => Value::Nil,
+                };
+                fields.push(value);
+            }
+            Ok(())
+        })?;
+
+        let has_constructor = decl.method("new").is_some();
+        if !has_constructor {
+            if args.len() > decl.fields.len() {
+                return Err(RuntimeError::new(
+                    span,
+                    format!(
+                        "Struct `{}` has {} field(s), found {} argument(s).",
+                        decl.name,
+                        decl.fields.len(),
+                        args.len()
+                    ),
+                ));
+            }
+            for (((field, field_ty), arg), slot) in decl
+                .fields
+                .iter()
+                .zip(&field_types)
+                .zip(args)
+                .zip(fields.iter_mut())
+            {
+                let value = self.eval(arg, Some(field_ty))?;
+                let value = check_type(&self.heap, value, field_ty, arg.span, || {
+                    format!("field `{}`", field.name)
+                })?;
+                *slot = self.copy_value(value);
+            }
+        }
+
+        let instance = Value::Obj(self.heap.alloc(Object::Instance(Instance { ty, fields })));
+        if has_constructor {
+            if let Some(constructor) = self.find_method(&instance, "new") {
+                self.call_closure(constructor, Some(instance.clone()), args, span)?;
+            }
+        }
+        if let Some(instance_ref) = self.heap.instance(&instance) {
+            if let Some(((field, _), _)) = decl
+                .fields
+                .iter()
+                .zip(&field_types)
+                .zip(&instance_ref.fields)
+                .find(|((_, ty), value)| value.is_nil() && !matches!(ty, Type::Optional(_)))
+            {
+                return Err(RuntimeError::new(
+                    span,
+                    format!(
+                        "Field `{}` of `{}` was not given a value.",
+                        field.name, decl.name
+                    ),
+                ));
+            }
+        }
+        Ok(instance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn run(source: &str) -> Result<String, String> {
+        let program = parse(source).map_err(|errors| errors[0].to_string())?;
+        let mut evaluator = Evaluator::new();
+        evaluator.capture_output();
+        evaluator.run(&program).map_err(|error| error.to_string())?;
+        Ok(evaluator.take_output())
+    }
+
+    const POINT: &str = "struct Point
+    flt64 x = 0.0
+    flt64 y = 0.0
+
+    func flt64 dot(this other)
+        return self.x * other.x + self.y * other.y
+    end
+
+    func shift(flt64 dx, flt64 dy)
+        self.x += dx
+        self.y += dy
+    end
+
+    func this scaled(flt64 k)
+        return this(self.x * k, self.y * k)
+    end
+end
+";
+
+    #[test]
+    fn test_struct_construction_and_methods() {
+        let source = format!(
+            "{POINT}Point p = Point(1.0, 2.0)\np.shift(1, 1)\nprintln(p, p.dot(Point(1.0)), p.scaled(2.0))"
+        );
+        assert_eq!(
+            run(&source).unwrap(),
+            "Point(x = 2.0, y = 3.0) 2.0 Point(x = 4.0, y = 6.0)\n"
+        );
+    }
+
+    #[test]
+    fn test_struct_value_semantics() {
+        let source = format!(
+            "{POINT}Point a = Point()\nPoint b = a\nb.x = 5.0\nfunc bump(Point p)\n\tp.x = 9.0\nend\nbump(a)\nprintln(a.x, b.x, a == Point(), b == a)"
+        );
+        assert_eq!(run(&source).unwrap(), "0.0 5.0 true false\n");
+    }
+
+    #[test]
+    fn test_struct_constructor_method() {
+        let source = "struct Counter\n\tint32 count\n\tstring label\n\tfunc new(string label)\n\t\tself.label = label\n\t\tself.count = 0\n\tend\nend\nCounter c = Counter(\"hits\")\nprintln(c)";
+        assert_eq!(
+            run(source).unwrap(),
+            "Counter(count = 0, label = \"hits\")\n"
+        );
+    }
+
+    #[test]
+    fn test_struct_errors() {
+        let missing = "struct Pair\n\tint32 a\n\tint32 b\nend\nPair p = Pair(1)";
+        assert_eq!(
+            run(missing).unwrap_err(),
+            "Toolip:5:14: Runtime error: Field `b` of `Pair` was not given a value."
+        );
+        let wrong = "struct Pair\n\tint32 a = 0\nend\nPair p = Pair()\np.a = \"one\"";
+        assert_eq!(
+            run(wrong).unwrap_err(),
+            "Toolip:5:7: Runtime error: Expected int32 for field `a`, found string."
+        );
+        let no_field = "struct Pair\n\tint32 a = 0\nend\nprintln(Pair().b)";
+        assert_eq!(
+            run(no_field).unwrap_err(),
+            "Toolip:4:15: Runtime error: Pair has no field `b`."
+        );
+    }
+
+    #[test]
+    fn test_functions_and_control_flow() {
+        let source = std::fs::read_to_string("examples/boolIfTest.tool").unwrap();
+        assert_eq!(
+            run(&source).unwrap(),
+            "This bool is true\nThis bool is false\n"
+        );
+        let sum = "func int32 total(int32 n)\n\tint32 acc = 0\n\tfor int32 i in 0 to n do\n\t\tacc += i\n\tend\n\treturn acc\nend\nprintln(total(5))";
+        assert_eq!(run(sum).unwrap(), "10\n");
+    }
+
+    #[test]
+    fn test_enum_payloads() {
+        let source = "enum Shape Circle(flt64) Rect(flt64, flt64) end
+func flt64 area(Shape shape)
+    given shape do
+        when Circle(r) => return 3.0 * r * r
+        when Shape.Rect(w, h) => return w * h
+    end
+end
+Shape s = Shape.Rect(2, 3)
+println(s, area(s), area(Shape.Circle(1.0)), s == Shape.Rect(2.0, 3.0))";
+        assert_eq!(run(source).unwrap(), "Shape.Rect(2.0, 3.0) 6.0 3.0 true\n");
+        let arity = "enum Shape Circle(flt64) end\nShape s = Shape.Circle(1.0, 2.0)";
+        assert_eq!(
+            run(arity).unwrap_err(),
+            "Toolip:2:23: Runtime error: Variant `Shape.Circle` has 1 field(s), found 2 argument(s)."
+        );
+    }
+
+    #[test]
+    fn test_enum_discriminants() {
+        let source = "enum Color Red Green Blue end
+Color c = Color.fromDiscriminant(2)
+println(c, discriminant(Color.Green), discriminant(c), Color.fromDiscriminant(3))";
+        assert_eq!(run(source).unwrap(), "Color.Blue 1 2 nil\n");
+        let payload = "enum Shape Circle(flt64) Dot end\nprintln(discriminant(Shape.Dot))";
+        assert_eq!(
+            run(payload).unwrap_err(),
+            "Toolip:2:21: Runtime error: Enum `Shape` has variants with payloads and no discriminants."
+        );
+    }
+
+    #[test]
+    fn test_type_aliases_and_generics() {
+        let source = "type Meters = flt64
+type Grid<T> = array<array<T>>
+struct Box<T>
+    T value
+    func T get()
+        return self.value
+    end
+end
+func<T> T first(array<T> xs)
+    return xs[0]
+end
+Meters m = 3
+Grid<int8> grid = [[1, 2], [3]]
+Box<uint8> b = Box(200)
+println(m, first(first(grid)), b.get() + 55, first([\"a\", \"b\"]))";
+        assert_eq!(run(source).unwrap(), "3.0 1 255 a\n");
+        let checked = "type Meters = flt64\nMeters m = \"far\"";
+        assert_eq!(
+            run(checked).unwrap_err(),
+            "Toolip:2:12: Runtime error: Expected flt64 for variable `m`, found string."
+        );
+        let substituted = "struct Box<T>\n\tT value\nend\nBox<int8> b = Box(200)";
+        assert_eq!(
+            run(substituted).unwrap_err(),
+            "Toolip:4:19: Runtime error: Integer literal 200 does not fit in int8."
+        );
+    }
+
+    #[test]
+    fn test_inferred_declarations() {
+        let source = "const x = 5\nglobal name = \"Al\" .. \"ice\"\nconst add = func (a, b) return a + b end\nprintln(add(x, 4), name)";
+        assert_eq!(run(source).unwrap(), "9 Alice\n");
+        assert_eq!(
+            run("global x = 5\nx = \"s\"").unwrap_err(),
+            "Toolip:2:5: Runtime error: Expected int32 for variable `x`, found string."
+        );
+    }
+
+    #[test]
+    fn test_overflow_builtins() {
+        let source = "uint8 x = 250\nint128 m = -170141183460469231731687303715884105728\nprintln(wrappingAdd(x, 10), saturatingAdd(x, 10), wrappingFloorDiv(m, -1), saturatingFloorDiv(m, -1))\nuint8 y = saturatingMul(20, 20)\nprintln(y)";
+        assert_eq!(
+            run(source).unwrap(),
+            "4 255 -170141183460469231731687303715884105728 170141183460469231731687303715884105727\n255\n"
+        );
+        assert_eq!(
+            run("int128 m = -170141183460469231731687303715884105728\nprintln(m // -1)")
+                .unwrap_err(),
+            "Toolip:2:11: Runtime error: Integer overflow: `//` on int128 operands."
+        );
+        assert_eq!(
+            run("wrappingAdd(1.5, 2.5)").unwrap_err(),
+            "Toolip:1:12: Runtime error: `wrappingAdd` expects two integers of the same type, found flt64 and flt64."
+        );
+    }
+
+    #[test]
+    fn test_numeric_conversions() {
+        let source = "int16 a = 300\nint64 b = a\nuint8 u = 200\nflt32 f = u\nprintln(b, f, a + b, u + a)\nprintln(int32(-3.99), char(65), int32('A'), uint8(true), flt32(16777217))";
+        assert_eq!(
+            run(source).unwrap(),
+            "300 200.0 600 500\n-3 A 65 1 16777216.0\n"
+        );
+        assert_eq!(
+            run("int16 a = 300\nprintln(int8(a))").unwrap_err(),
+            "Toolip:2:9: Runtime error: 300 does not fit in int8."
+        );
+        assert_eq!(
+            run("println(int32(0.0 / 0.0))").unwrap_err(),
+            "Toolip:1:9: Runtime error: Cannot convert NaN to int32."
+        );
+    }
+
+    #[test]
+    fn test_concatenation() {
+        let example = std::fs::read_to_string("examples/intConcatTest.tool").unwrap();
+        let source = format!("{example}\nprintln(Int3, multiIntConcat(1, 23))");
+        assert_eq!(run(&source).unwrap(), "12 123\n");
+        let source = "int8 x = 1 .. 2\nuint8 y = 25\ny ..= 5\nprintln(x, y, -1 .. 2, [1, 2] .. [3], \"a\" .. 1 .. 2, 'c' .. 2)";
+        assert_eq!(run(source).unwrap(), "12 255 -12 [1, 2, 3] a12 c2\n");
+        assert_eq!(
+            run("uint8 y = 25\ny ..= 6").unwrap_err(),
+            "Toolip:2:7: Runtime error: Integer overflow: `..` on uint8 operands."
+        );
+    }
+
+    #[test]
+    fn test_nil_operators() {
+        let source = "struct Node\n\tint32 value\n\tNode? next\nend\nfunc int32? find(list<int32> xs, int32 wanted)\n\tfor int32 x in xs do\n\t\tif x == wanted then\n\t\t\treturn x\n\t\tend\n\tend\n\treturn nil\nend\nint32? a = find([1, 2], 2)\nint32? b = find([1, 2], 3)\nb ??= a\na ??= 9\nint64? c = 5\nNode n = Node(1, Node(2))\nprintln(a, b, c, find([], 1) ?? -1, n.next?.value, n.next?.next?.value)";
+        assert_eq!(run(source).unwrap(), "2 2 5 -1 2 nil\n");
+    }
+
+    #[test]
+    fn test_storage_lifetimes() {
+        let source = "const LIMIT = 3\nfunc int32 counter()\n\tstatic int32 count = 0\n\tcount += 1\n\treturn count\nend\nfunc setup()\n\tglobal int32 total = 10\n\tglobal hits = 0\nend\nfunc record()\n\tglobal hits = hits + 1\n\ttotal += LIMIT\nend\nsetup()\nrecord()\nrecord()\ncounter()\nprintln(counter(), hits, total)\nstruct Box\n\tfunc int32 next()\n\t\tstatic int32 n = 100\n\t\tn += 1\n\t\treturn n\n\tend\nend\nBox().next()\nprintln(Box().next())";
+        assert_eq!(run(source).unwrap(), "2 2 16\n102\n");
+        assert_eq!(
+            run("const x = 1\nx = 2").unwrap_err(),
+            "Toolip:2:1: Runtime error: Cannot assign to the constant `x`."
+        );
+    }
+
+    #[test]
+    fn test_constant_sizes_and_patterns() {
+        let source = "const WIDTH = 2\nconst CELLS = WIDTH * 3\nfunc string name(int32 n)\n\tgiven n do\n\t\twhen WIDTH => return \"width\"\n\t\twhen CELLS => return \"cells\"\n\t\telse => return \"other\"\n\tend\nend\narray<int8> row = [7; WIDTH]\nprintln(row, #[0; CELLS], name(2), name(6), name(3))";
+        assert_eq!(run(source).unwrap(), "[7, 7] 6 width cells other\n");
+    }
+
+    #[test]
+    fn test_formatted_output() {
+        let source = "string name = \"Ada\"\nint32 n = 255\nprintln(\"{name}: {} in hex is {0:#>6x}, {:+.1}\", n, 2.25)\neprint(\"{{{}}}\", format(\"{:^5}\", 'c'))\nprintln()\nprintln(n, \"n\")";
+        assert_eq!(
+            run(source).unwrap(),
+            "Ada: 255 in hex is ####ff, +2.2\n{  c  }\n255 n\n"
+        );
+        assert_eq!(
+            run("string f = \"{} {}\"\nprintln(format(f, 1))").unwrap_err(),
+            "Toolip:2:15: Runtime error: The format string takes 2 argument(s), found 1."
+        );
+        /* Only a literal or a constant with a brace in it is a format string. */
+        let source = "string s = \"a {b} c\"\nint32 x = 1\nconst greeting = \"hi {x}\"\nprintln(s)\nprintln(\"label\", x)\nprintln(greeting)\nprintln(s, x .. \"\", \"{{}}\")";
+        assert_eq!(
+            run(source).unwrap(),
+            "a {b} c\nlabel 1\nhi 1\na {b} c 1 {{}}\n"
+        );
+    }
+
+    #[test]
+    fn test_garbage_collection_keeps_live_objects() {
+        let source = "struct Node\n\tint32 value = 0\nend\nfunc int32 remember()\n\tstatic array<int32> seen = [0; 4]\n\tseen[0] += 1\n\treturn seen[0]\nend\nfunc int32 churn(int32 n)\n\tint32 total = 0\n\tfor int32 i in 0 to n do\n\t\tNode node = Node(i)\n\t\tfunc int32 get()\n\t\t\treturn node.value\n\t\tend\n\t\ttotal += get() - i\n\tend\n\treturn total + remember()\nend\nfor int32 round in 0 to 3 do\n\tprint(churn(5000) + churn(1), heapSize() < 4096, \"\")\nend\nprintln(collectGarbage() == heapSize(), [Node(1), Node(2)][1].value)";
+        assert_eq!(run(source).unwrap(), "3 true 7 true 11 true true 2\n");
+        assert_eq!(
+            run("collectGarbage(1)").unwrap_err(),
+            "Toolip:1:15: Runtime error: `collectGarbage` expects 0 argument(s), found 1."
+        );
+    }
+
+    #[test]
+    fn test_stack_traces() {
+        let example = std::fs::read_to_string("examples/intConcatTest.tool").unwrap();
+        let source = format!("{example}\nfunc int32 join(int32 a)\n\treturn multiIntConcat(a, 483648) + 0\nend\njoin(2147)");
+        let program = parse(&source).unwrap();
+        let error = Evaluator::new().run(&program).unwrap_err();
+        assert_eq!(
+            error.report("intConcatTest.tool"),
+            "Toolip:17:21: Runtime error: Integer overflow: `..` on int32 operands.
+Stack trace (most recent call first):
+  in `multiIntConcat` at intConcatTest.tool:17:21
+  in `join` at intConcatTest.tool:23:23
+  in the program at intConcatTest.tool:25:5"
+        );
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let source = "func int64 count(int64 n, int64 acc)\n\tif n == 0 then\n\t\treturn acc\n\tend\n\treturn count(n - 1, acc + n)\nend\nfunc bool isEven(int32 n)\n\tif n == 0 then\n\t\treturn true\n\tend\n\treturn isOdd(n - 1)\nend\nfunc bool isOdd(int32 n)\n\tif n == 0 then\n\t\treturn false\n\tend\n\treturn isEven(n - 1)\nend\nfunc int32 check(int32 n)\n\tif n > 9 then\n\t\traise \"too big\"\n\tend\n\treturn n\nend\nfunc int32 clamp(int32 n)\n\ttry\n\t\treturn check(n)\n\tcatch\n\t\treturn 9\n\tend\nend\nprintln(count(100000, 0), isEven(1001), clamp(4), clamp(12))";
+        assert_eq!(run(source).unwrap(), "5000050000 false 4 9\n");
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let source = "func int64 fact(int64 n)\n\tif n <= 1 then\n\t\treturn 1\n\tend\n\treturn n * fact(n - 1)\nend\nprintln(fact(20))\nfact(21)";
+        let program = parse(source).unwrap();
+        let mut evaluator = Evaluator::new();
+        evaluator.set_max_depth(20);
+        evaluator.capture_output();
+        let error = evaluator.run(&program).unwrap_err();
+        assert_eq!(evaluator.take_output(), "2432902008176640000\n");
+        assert_eq!(
+            (&*error.kind, error.to_string().as_str()),
+            (
+                "stackOverflow",
+                "Toolip:5:17: Runtime error: Stack overflow."
+            )
+        );
+        assert_eq!(error.trace.len(), 20);
+    }
+
+    #[test]
+    fn test_try_catch_and_raise() {
+        let source = "func int64 half(int64 n)\n\tif n % 2 == 1 then\n\t\traise error(\"odd\", \"odd input\")\n\tend\n\treturn n / 2\nend\nfunc int64 quarter(int64 n)\n\tint64 h = try half(n)\n\treturn try half(h)\nend\ntry\n\tprintln(quarter(8))\n\tprintln(quarter(6))\n\tprintln(\"unreachable\")\ncatch e\n\tprintln(e.kind .. \": \" .. e.message)\nend\ntry\n\tint8 x = 127\n\tx += 1\ncatch e\n\tprintln(e.kind)\nend\ntry\n\tarray<int32> xs = [1, 2]\n\tprintln(xs[2])\ncatch\n\traise \"no index \" .. 2\nend";
+        let program = parse(source).unwrap();
+        let mut evaluator = Evaluator::new();
+        evaluator.capture_output();
+        let error = evaluator.run(&program).unwrap_err();
+        assert_eq!(evaluator.take_output(), "2\nodd: odd input\noverflow\n");
+        assert_eq!((&*error.kind, &*error.message), ("error", "no index 2"));
+    }
+
+    #[test]
+    fn test_error_values() {
+        let source = "global e = error(\"io\", \"disk full\")\nprintln(isError(e), isError(\"disk full\"), e == error(\"io\", \"disk full\"))\nprintln(e)\nprintln(error(\"oops\").kind)\nprintln(try 3)";
+        assert_eq!(
+            run(source).unwrap(),
+            "true false true\nerror(\"io\", \"disk full\")\nerror\n3\n"
+        );
+        assert_eq!(
+            run("raise 3").unwrap_err(),
+            "Toolip:1:1: Runtime error: Only errors and strings can be raised, found int32."
+        );
+    }
+
+    #[test]
+    fn test_coroutines() {
+        let source = "coroutine func int32 count(int32 n)\n\tfor int32 i in 0 to n do\n\t\tyield i\n\tend\nend\nfor int32 x in count(3) do\n\tprint(x)\nend\ncoroutine func int32 sum()\n\tint32 total = 0\n\twhile true do\n\t\tint32 x = yield total\n\t\tif x == 0 then\n\t\t\treturn total\n\t\tend\n\t\ttotal += x\n\tend\nend\ncoroutine<int32> c = sum()\nprintln()\nprintln(resume(c), status(c), resume(c, 5), resume(c, 7), resume(c, 0), status(c))";
+        assert_eq!(run(source).unwrap(), "012\n0 suspended 5 12 12 dead\n");
+        let source = "coroutine func string steps()\n\ttry\n\t\tyield \"a\"\n\t\traise \"b\"\n\tcatch e\n\t\tyield e.message\n\tend\n\tgiven 2 do\n\t\twhen 2 => yield \"c\"\n\tend\n\tloop\n\t\tyield \"d\"\n\t\tbreak\n\tend\n\treturn \"e\"\nend\nfor string s in steps() do\n\tprint(s)\nend\nprintln()\nprintln(steps())";
+        assert_eq!(run(source).unwrap(), "abcd\n<coroutine steps>\n");
+        assert_eq!(
+            run("coroutine func int32 f()\n\tresume(co)\nend\nglobal co = f()\nresume(co)")
+                .unwrap_err(),
+            "Toolip:2:8: Runtime error: Cannot resume a running coroutine."
+        );
+        assert_eq!(
+            run("coroutine func int32 f()\nend\nglobal co = f()\nresume(co)\nresume(co)")
+                .unwrap_err(),
+            "Toolip:5:7: Runtime error: Cannot resume a dead coroutine."
+        );
+    }
+
+    #[test]
+    fn test_range_values() {
+        let source = "range<int32> r = 0 to 10 step 3\nprintln(r, 3 in r, 4 in r, 10 in r, r == 0 to 10 step 3)\nrange<int64> down = 4 through 0 step -2\nfor int64 i in down do\n\tprint(i, \"\")\nend\nprintln(down)\narray<int32> xs = [1, 2, 3, 4]\nprintln(xs[1 to 3], xs[3 through 0 step -1], xs[0 to 0], \"hello\"[1 through 3])\nint8 k = 9\ngiven k do\n\twhen -5 to 5 => println(\"small\")\n\twhen 5 through 9 => println(k in 0 to 10)\nend";
+        assert_eq!(
+            run(source).unwrap(),
+            "0 to 10 step 3 true false false true\n4 2 0 4 through 0 step -2\n[2, 3] [4, 3, 2, 1] [] ell\ntrue\n"
+        );
+        assert_eq!(
+            run("array<int32> xs = [1, 2]\nprintln(xs[0 through 2])").unwrap_err(),
+            "Toolip:2:14: Runtime error: Index 2 is out of bounds for length 2."
+        );
+    }
+
+    #[test]
+    fn test_range_steps_and_iterators() {
+        let source = "struct Countdown\n\tint32 n = 3\n\tfunc int32? next()\n\t\tif self.n == 0 then\n\t\t\treturn nil\n\t\tend\n\t\tself.n -= 1\n\t\treturn self.n\n\tend\nend\nstruct Odds\n\tint32 limit\n\tfunc coroutine<int32> iter()\n\t\treturn odds(self.limit)\n\tend\nend\ncoroutine func int32 odds(int32 limit)\n\tfor int32 i in 0 to limit do\n\t\tif i % 2 == 0 then\n\t\t\tcontinue\n\t\tend\n\t\tyield i\n\tend\nend\nfor int32 i in Countdown() do\n\tprint(i)\nend\nfor int32 i in Odds(8) do\n\tprint(i)\nend\nfor int64 i in 10 to 0 step -3 do\n\tprint(i)\nend\nfor int64 i in 0 to 10 step 4 do\n\tprint(i)\nend\nprintln()";
+        assert_eq!(run(source).unwrap(), "210135710741048\n");
+        assert_eq!(
+            run("for int32 i in 0 to 5 step 0 do\nend").unwrap_err(),
+            "Toolip:1:18: Runtime error: The step of a range cannot be 0."
+        );
+    }
+}
@@ -0,0 +1,319 @@
+#![allow(dead_code)]
+/* Coverage analysis for the arms of a `given` statement.
+ *
+ * The checker lowers each `when` pattern to a `Cover` describing which values of
+ * the subject it matches, then `analyze` walks the arms in order to find patterns
+ * that can never be reached and values that no arm handles. */
+
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Domain {
+    Bool,
+    /* Integers in `lo..=hi`. Only `total` domains must be covered completely; the
+     * residues of `x % n` are total, the full range of an `int32` is not. */
+    Ints { lo: i128, hi: i128, total: bool },
+    Enum { name: String, variants: Vec<String> },
+    /* Strings, floats, chars, ...: only repeated literals are detected. */
+    Open,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cover {
+    All,
+    Bool(bool),
+    Ints(i128, i128),
+    Variant(usize),
+    Literal(String),
+    /* A pattern whose coverage cannot be known statically. */
+    Opaque,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /* Every value the pattern matches is handled by an earlier arm. */
+    Covered,
+    /* The pattern matches no value the subject can take. */
+    OutOfDomain,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Report {
+    /* (arm index, pattern index, reason) */
+    pub unreachable: Vec<(usize, usize, Reason)>,
+    /* Human-readable descriptions of the values no arm matches. */
+    pub missing: Vec<String>,
+    /* Whether every value of the subject is matched by some arm. */
+    pub exhaustive: bool,
+}
+
+/* A sorted list of disjoint, inclusive integer intervals. */
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct IntervalSet {
+    ranges: Vec<(i128, i128)>,
+}
+
+impl IntervalSet {
+    pub fn insert(&mut self, lo: i128, hi: i128) {
+        let mut lo = lo;
+        let mut hi = hi;
+        let mut merged = Vec::with_capacity(self.ranges.len() + 1);
+        for &(a, b) in &self.ranges {
+            if b.saturating_add(1) < lo || hi.saturating_add(1) < a {
+                merged.push((a, b));
+            } else {
+                lo = lo.min(a);
+                hi = hi.max(b);
+            }
+        }
+        merged.push((lo, hi));
+        merged.sort_unstable();
+        self.ranges = merged;
+    }
+
+    pub fn contains_range(&self, lo: i128, hi: i128) -> bool {
+        self.ranges.iter().any(|&(a, b)| a <= lo && hi <= b)
+    }
+
+    /* The parts of `lo..=hi` not in the set. */
+    pub fn gaps(&self, lo: i128, hi: i128) -> Vec<(i128, i128)> {
+        let mut gaps = vec![];
+        let mut next = Some(lo);
+        for &(a, b) in &self.ranges {
+            let Some(start) = next else {
+                break;
+            };
+            if b < start {
+                continue;
+            }
+            if a > hi {
+                break;
+            }
+            if a > start {
+                gaps.push((start, a - 1));
+            }
+            next = b.checked_add(1);
+        }
+        if let Some(start) = next {
+            if start <= hi {
+                gaps.push((start, hi));
+            }
+        }
+        gaps
+    }
+}
+
+const MAX_LISTED: usize = 8;
+
+fn describe_gaps(gaps: &[(i128, i128)]) -> Vec<String> {
+    let mut listed = vec![];
+    for &(lo, hi) in gaps {
+        if listed.len() == MAX_LISTED {
+            listed.push("...".to_owned());
+            break;
+        }
+        match hi - lo {
+            0 => listed.push(lo.to_string()),
+            1 => {
+                listed.push(lo.to_string());
+                listed.push(hi.to_string());
+            }
+            _ => listed.push(format!("{lo} through {hi}")),
+        }
+    }
+    listed
+}
+
+pub fn analyze(domain: &Domain, arms: &[Vec<Cover>]) -> Report {
+    let mut report = Report::default();
+    let mut all = false;
+    let mut bools = [false; 2];
+    let mut ints = IntervalSet::default();
+    let variant_count = match domain {
+        Domain::Enum { variants, .. } => variants.len(),
+        _ => 0,
+    };
+    let mut variants = vec![false; variant_count];
+    let mut literals = HashSet::new();
+
+    let is_complete = |bools: &[bool; 2], ints: &IntervalSet, variants: &[bool]| match domain {
+        Domain::Bool => bools[0] && bools[1],
+        Domain::Ints { lo, hi, .. } => ints.contains_range(*lo, *hi),
+        Domain::Enum { .. } => variants.iter().all(|&covered| covered),
+        Domain::Open => false,
+    };
+
+    for (arm, covers) in arms.iter().enumerate() {
+        for (index, cover) in covers.iter().enumerate() {
+            if all {
+                report.unreachable.push((arm, index, Reason::Covered));
+                continue;
+            }
+            let reason = match (cover, domain) {
+                (Cover::All, _) => {
+                    let complete = is_complete(&bools, &ints, &variants);
+                    all = true;
+                    complete.then_some(Reason::Covered)
+                }
+                (Cover::Opaque, _) => None,
+                (Cover::Bool(value), Domain::Bool) => {
+                    let slot = &mut bools[usize::from(*value)];
+                    let seen = *slot;
+                    *slot = true;
+                    seen.then_some(Reason::Covered)
+                }
+                (
+                    Cover::Ints(lo, hi),
+                    Domain::Ints {
+                        lo: min, hi: max, ..
+                    },
+                ) => {
+                    let lo = (*lo).max(*min);
+                    let hi = (*hi).min(*max);
+                    if lo > hi {
+                        Some(Reason::OutOfDomain)
+                    } else if ints.contains_range(lo, hi) {
+                        Some(Reason::Covered)
+                    } else {
+                        ints.insert(lo, hi);
+                        None
+                    }
+                }
+                (Cover::Variant(variant), Domain::Enum { .. }) => {
+                    let seen = variants[*variant];
+                    variants[*variant] = true;
+                    seen.then_some(Reason::Covered)
+                }
+                (Cover::Ints(lo, hi), Domain::Open) if lo == hi => {
+                    (!literals.insert(lo.to_string())).then_some(Reason::Covered)
+                }
+                (Cover::Bool(value), Domain::Open) => {
+                    (!literals.insert(value.to_string())).then_some(Reason::Covered)
+                }
+                (Cover::Literal(key), Domain::Open) => {
+                    (!literals.insert(key.clone())).then_some(Reason::Covered)
+                }
+                (_, Domain::Open) => None,
+                _ => Some(Reason::OutOfDomain),
+            };
+            if let Some(reason) = reason {
+                report.unreachable.push((arm, index, reason));
+            }
+        }
+    }
+
+    if all || is_complete(&bools, &ints, &variants) {
+        report.exhaustive = true;
+        return report;
+    }
+    report.missing = match domain {
+        Domain::Bool => [false, true]
+            .into_iter()
+            .filter(|&value| !bools[usize::from(value)])
+            .map(|value| value.to_string())
+            .collect(),
+        Domain::Ints {
+            lo,
+            hi,
+            total: true,
+        } => describe_gaps(&ints.gaps(*lo, *hi)),
+        Domain::Enum {
+            variants: names, ..
+        } => names
+            .iter()
+            .zip(&variants)
+            .filter(|(_, &covered)| !covered)
+            .map(|(name, _)| name.clone())
+            .collect(),
+        _ => vec![],
+    };
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interval_set_gaps() {
+        let mut set = IntervalSet::default();
+        set.insert(1, 2);
+        set.insert(5, 5);
+        set.insert(3, 3);
+        assert_eq!(set.gaps(0, 7), vec![(0, 0), (4, 4), (6, 7)]);
+        assert!(set.contains_range(1, 3));
+        assert!(!set.contains_range(1, 5));
+    }
+
+    #[test]
+    fn test_residues_covered() {
+        let domain = Domain::Ints {
+            lo: 0,
+            hi: 3,
+            total: true,
+        };
+        let arms = (0..4).map(|n| vec![Cover::Ints(n, n)]).collect::<Vec<_>>();
+        assert_eq!(
+            analyze(&domain, &arms),
+            Report {
+                exhaustive: true,
+                ..Report::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_residues_missing_and_unreachable() {
+        let domain = Domain::Ints {
+            lo: 0,
+            hi: 3,
+            total: true,
+        };
+        let arms = vec![
+            vec![Cover::Ints(0, 0)],
+            vec![Cover::Ints(0, 0), Cover::Ints(4, 4)],
+        ];
+        let report = analyze(&domain, &arms);
+        assert_eq!(
+            report.unreachable,
+            vec![(1, 0, Reason::Covered), (1, 1, Reason::OutOfDomain)]
+        );
+        assert_eq!(report.missing, vec!["1 through 3"]);
+        assert!(!report.exhaustive);
+    }
+
+    #[test]
+    fn test_else_after_complete_bool() {
+        let arms = vec![
+            vec![Cover::Bool(true)],
+            vec![Cover::Bool(false)],
+            vec![Cover::All],
+        ];
+        let report = analyze(&Domain::Bool, &arms);
+        assert_eq!(report.unreachable, vec![(2, 0, Reason::Covered)]);
+        assert!(report.missing.is_empty());
+        assert!(report.exhaustive);
+    }
+
+    #[test]
+    fn test_enum_missing_variants() {
+        let domain = Domain::Enum {
+            name: "Color".to_owned(),
+            variants: vec!["Red".to_owned(), "Green".to_owned(), "Blue".to_owned()],
+        };
+        let report = analyze(&domain, &[vec![Cover::Variant(1)]]);
+        assert_eq!(report.missing, vec!["Red", "Blue"]);
+    }
+
+    #[test]
+    fn test_open_domain_duplicates() {
+        let arms = vec![
+            vec![Cover::Literal("\"a\"".to_owned())],
+            vec![Cover::Literal("\"a\"".to_owned())],
+        ];
+        let report = analyze(&Domain::Open, &arms);
+        assert_eq!(report.unreachable, vec![(1, 0, Reason::Covered)]);
+        assert!(report.missing.is_empty());
+        assert!(!report.exhaustive);
+    }
+}
@@ -0,0 +1,183 @@
+#![allow(dead_code)]
+use crate::ast::{BinaryOp, PatternKind, UnaryOp};
+use crate::ops;
+use crate::types::{FloatKind, Type};
+use crate::value::{Heap, Value};
+
+/* Constant folding: the values of the expressions known before the program runs,
+ * computed as the evaluator would compute them. The checker folds each expression
+ * once its operands have been folded and it has been given a type.
+ *
+ * Only primitive values are folded; of an array of constants only the length is
+ * kept, which is all `#` and `..` need. An operation that would trap at run time,
+ * such as an integer overflow or a division by zero, is an error instead, unless it
+ * is on the right side of an `and`, `or` or `??` that may not evaluate it. */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Value(Value),
+    /* An array literal of constants, by its length. */
+    Array(usize),
+}
+
+/* `Ok(None)` when the result is not a constant after all, as for `==` on arrays. */
+pub type FoldResult = Result<Option<Constant>, String>;
+
+fn value(value: Value) -> FoldResult {
+    Ok(Some(Constant::Value(value)))
+}
+
+/* An integer literal used as `ty`, or `None` if it does not fit. */
+pub fn int_literal(negative: bool, magnitude: u128, ty: &Type) -> Option<Constant> {
+    let value = match ty {
+        Type::Int(kind) => Value::from_int_parts(*kind, negative, magnitude)?,
+        Type::Float(_) => {
+            let float = magnitude as f64;
+            return float_literal(if negative { -float } else { float }, ty);
+        }
+        _ => return None,
+    };
+    Some(Constant::Value(value))
+}
+
+pub fn float_literal(float: f64, ty: &Type) -> Option<Constant> {
+    Some(Constant::Value(match ty {
+        Type::Float(FloatKind::F32) => Value::Flt32(float as f32),
+        _ => Value::Flt64(float),
+    }))
+}
+
+pub fn unary(op: UnaryOp, operand: &Constant) -> FoldResult {
+    match (op, operand) {
+        (UnaryOp::Len, Constant::Array(len)) => length(*len),
+        (UnaryOp::Len, Constant::Value(Value::Str(string))) => length(string.chars().count()),
+        (UnaryOp::Len, _) => Ok(None),
+        (_, Constant::Value(operand)) => ops::unary(op, operand).and_then(value),
+        _ => Ok(None),
+    }
+}
+
+fn length(len: usize) -> FoldResult {
+    i32::try_from(len)
+        .map_err(|_| "Length does not fit in int32.".to_owned())
+        .and_then(|len| value(Value::Int32(len)))
+}
+
+pub fn binary(op: BinaryOp, lhs: &Constant, rhs: &Constant) -> FoldResult {
+    let (lhs, rhs) = match (lhs, rhs) {
+        (Constant::Array(a), Constant::Array(b)) if op == BinaryOp::Concat => {
+            return Ok(Some(Constant::Array(a + b)))
+        }
+        (Constant::Value(lhs), Constant::Value(rhs)) => (lhs, rhs),
+        _ => return Ok(None),
+    };
+    let widened = ops::widen_operands(op, lhs, rhs);
+    let (lhs, rhs) = widened.as_ref().map_or((lhs, rhs), |(lhs, rhs)| (lhs, rhs));
+    let is_text = |value: &Value| matches!(value, Value::Str(_) | Value::Char(_));
+    match (op, lhs, rhs) {
+        (BinaryOp::And, Value::Bool(a), Value::Bool(b)) => value(Value::Bool(*a && *b)),
+        (BinaryOp::Or, Value::Bool(a), Value::Bool(b)) => value(Value::Bool(*a || *b)),
+        (BinaryOp::Xor, Value::Bool(a), Value::Bool(b)) => value(Value::Bool(a ^ b)),
+        (BinaryOp::And | BinaryOp::Or | BinaryOp::Xor, ..) => Ok(None),
+        (BinaryOp::Eq | BinaryOp::NotEq, ..) => {
+            let equal = Heap::default().values_equal(lhs, rhs);
+            value(Value::Bool(equal == (op == BinaryOp::Eq)))
+        }
+        (BinaryOp::NilCoalesce, Value::Nil, _) => value(rhs.clone()),
+        (BinaryOp::NilCoalesce, ..) => value(lhs.clone()),
+        (BinaryOp::Concat, ..) if is_text(lhs) || is_text(rhs) => {
+            let heap = Heap::default();
+            let text = heap.display(lhs) + &heap.display(rhs);
+            value(Value::Str(text.into()))
+        }
+        _ => ops::binary(op, lhs, rhs).and_then(value),
+    }
+}
+
+/* A conversion the checker rejects, as of a string, is left for it to report. */
+pub fn cast(operand: &Constant, ty: &Type) -> FoldResult {
+    let Constant::Value(operand) = operand else {
+        return Ok(None);
+    };
+    let valid = match ty {
+        Type::Int(_) => !matches!(operand, Value::Nil | Value::Str(_)),
+        Type::Float(_) => operand.numeric_type().is_some(),
+        Type::Char => operand.int_kind().is_some() || matches!(operand, Value::Char(_)),
+        Type::Bool => matches!(operand, Value::Bool(_)),
+        _ => false,
+    };
+    if !valid {
+        return Ok(None);
+    }
+    ops::cast(operand, ty).and_then(value)
+}
+
+/* The literal pattern matching the same values as a constant, if there is one. */
+pub fn pattern(constant: &Constant) -> Option<PatternKind> {
+    let Constant::Value(value) = constant else {
+        return None;
+    };
+    if let Some((negative, magnitude)) = value.int_parts() {
+        let int = i128::try_from(magnitude).ok()?;
+        return Some(PatternKind::Int(if negative { -int } else { int }));
+    }
+    Some(match value {
+        Value::Nil => PatternKind::Nil,
+        Value::Bool(value) => PatternKind::Bool(*value),
+        Value::Flt32(float) => PatternKind::Float(f64::from(*float)),
+        Value::Flt64(float) => PatternKind::Float(*float),
+        Value::Char(ch) => PatternKind::Char(*ch),
+        Value::Str(string) => PatternKind::Str(string.to_string()),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::IntKind;
+
+    fn int(kind: IntKind, int: i128) -> Constant {
+        int_literal(int < 0, int.unsigned_abs(), &Type::Int(kind)).unwrap()
+    }
+
+    #[test]
+    fn test_fold_operators() {
+        let i8 = |value| int(IntKind::I8, value);
+        assert_eq!(binary(BinaryOp::Add, &i8(100), &i8(27)), Ok(Some(i8(127))));
+        assert_eq!(
+            binary(BinaryOp::Add, &i8(100), &i8(28)),
+            Err("Integer overflow: `+` on int8 operands.".to_owned())
+        );
+        assert_eq!(
+            binary(BinaryOp::FloorDiv, &i8(1), &i8(0)),
+            Err("Division by zero.".to_owned())
+        );
+        assert_eq!(
+            binary(BinaryOp::Mul, &i8(3), &int(IntKind::I64, 5)),
+            Ok(Some(int(IntKind::I64, 15)))
+        );
+        let text = Constant::Value(Value::Str("n = ".into()));
+        assert_eq!(
+            binary(BinaryOp::Concat, &text, &i8(4)),
+            Ok(Some(Constant::Value(Value::Str("n = 4".into()))))
+        );
+        let bool = |value| Constant::Value(Value::Bool(value));
+        assert_eq!(
+            binary(BinaryOp::Xor, &bool(true), &bool(true)),
+            Ok(Some(bool(false)))
+        );
+        assert_eq!(
+            binary(BinaryOp::Concat, &Constant::Array(2), &Constant::Array(3)),
+            Ok(Some(Constant::Array(5)))
+        );
+        assert_eq!(
+            unary(UnaryOp::Len, &Constant::Array(5)),
+            Ok(Some(int(IntKind::I32, 5)))
+        );
+        assert_eq!(
+            unary(UnaryOp::Neg, &i8(-128)),
+            Err("Integer overflow: `-` on int8.".to_owned())
+        );
+        assert_eq!(pattern(&i8(-3)), Some(PatternKind::Int(-3)));
+    }
+}
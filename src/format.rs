@@ -0,0 +1,401 @@
+#![allow(dead_code)]
+/* Format strings, as taken by `print`, `println`, `eprint`, `eprintln` and `format`.
+ *
+ * `{}` takes the next argument, `{1}` the argument at that index and `{name}` the
+ * variable of that name. After a `:` comes a spec: an optional fill character and
+ * alignment (`<`, `>` or `^`), `+` to always show the sign of a number, `0` to pad
+ * a number with zeros, a width, a precision (`.3`) and a radix (`x`, `X`, `o` or
+ * `b`). `{{` and `}}` stand for a literal brace.
+ *
+ * `print` and friends only read their first argument as a format string when it is
+ * a string literal or a constant with a brace in it. Otherwise they print their
+ * arguments separated by spaces, so `println(s)` prints any text as it is.
+ *
+ * `parse` is shared by the checker, which validates literal format strings, and
+ * the builtins, which render them. */
+
+use crate::value::{Heap, Value};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Arg {
+    Next,
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    Hex,
+    UpperHex,
+    Octal,
+    Binary,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Spec {
+    pub fill: Option<char>,
+    pub align: Option<Align>,
+    pub sign: bool,
+    pub zero: bool,
+    pub width: Option<usize>,
+    pub precision: Option<usize>,
+    pub radix: Option<Radix>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Piece {
+    Text(String),
+    Field(Arg, Spec),
+}
+
+/* Whether `print` and friends read a literal `text` as a format string. */
+pub fn is_format(text: &str) -> bool {
+    text.contains(['{', '}'])
+}
+
+/* The pieces of a format string, or what is wrong with it. */
+pub fn parse(format: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = vec![];
+    let mut text = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '}' => return Err("Unmatched `}` in format string; write `}}` for a brace.".to_owned()),
+            '{' => {
+                let mut field = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(ch) => field.push(ch),
+                        None => {
+                            return Err(
+                                "Unclosed `{` in format string; write `{{` for a brace.".to_owned()
+                            )
+                        }
+                    }
+                }
+                if !text.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut text)));
+                }
+                pieces.push(parse_field(&field)?);
+            }
+            _ => text.push(ch),
+        }
+    }
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    Ok(pieces)
+}
+
+fn parse_field(field: &str) -> Result<Piece, String> {
+    let (arg, spec) = field.split_once(':').unwrap_or((field, ""));
+    let arg = match arg.trim() {
+        "" => Arg::Next,
+        arg if arg.chars().all(|ch| ch.is_ascii_digit()) => Arg::Index(
+            arg.parse()
+                .map_err(|_| format!("Argument index `{arg}` is too large."))?,
+        ),
+        arg if is_name(arg) => Arg::Name(arg.to_owned()),
+        arg => return Err(format!("Invalid placeholder `{{{arg}}}` in format string.")),
+    };
+    Ok(Piece::Field(arg, parse_spec(spec)?))
+}
+
+fn is_name(arg: &str) -> bool {
+    let mut chars = arg.chars();
+    chars
+        .next()
+        .is_some_and(|ch| ch.is_alphabetic() || ch == '_')
+        && chars.all(|ch| ch.is_alphanumeric() || ch == '_')
+}
+
+fn parse_spec(spec: &str) -> Result<Spec, String> {
+    let invalid = || format!("Invalid format spec `{{:{spec}}}`.");
+    let align_of = |ch| match ch {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+    };
+    let chars = spec.chars().collect::<Vec<_>>();
+    let mut parsed = Spec::default();
+    let mut i = 0;
+    if let Some(align) = chars.get(1).copied().and_then(align_of) {
+        parsed.fill = Some(chars[0]);
+        parsed.align = Some(align);
+        i = 2;
+    } else if let Some(align) = chars.first().copied().and_then(align_of) {
+        parsed.align = Some(align);
+        i = 1;
+    }
+    if chars.get(i) == Some(&'+') {
+        parsed.sign = true;
+        i += 1;
+    }
+    if chars.get(i) == Some(&'0') {
+        parsed.zero = true;
+        i += 1;
+    }
+    let number = |i: &mut usize| {
+        let start = *i;
+        while chars.get(*i).is_some_and(char::is_ascii_digit) {
+            *i += 1;
+        }
+        let digits = chars[start..*i].iter().collect::<String>();
+        (!digits.is_empty()).then(|| digits.parse().map_err(|_| invalid()))
+    };
+    parsed.width = number(&mut i).transpose()?;
+    if chars.get(i) == Some(&'.') {
+        i += 1;
+        parsed.precision = Some(number(&mut i).ok_or_else(invalid)??);
+    }
+    parsed.radix = match chars.get(i) {
+        Some('x') => Some(Radix::Hex),
+        Some('X') => Some(Radix::UpperHex),
+        Some('o') => Some(Radix::Octal),
+        Some('b') => Some(Radix::Binary),
+        _ => None,
+    };
+    if parsed.radix.is_some() {
+        i += 1;
+    }
+    if i != chars.len() {
+        return Err(invalid());
+    }
+    Ok(parsed)
+}
+
+/* How many arguments after the format string its placeholders use. */
+pub fn arity(pieces: &[Piece]) -> usize {
+    let mut next = 0;
+    let mut needed = 0;
+    for piece in pieces {
+        match piece {
+            Piece::Field(Arg::Next, _) => {
+                next += 1;
+                needed = needed.max(next);
+            }
+            Piece::Field(Arg::Index(index), _) => needed = needed.max(index + 1),
+            _ => (),
+        }
+    }
+    needed
+}
+
+/* The names of the variables used by `{name}` placeholders. */
+pub fn names(pieces: &[Piece]) -> impl Iterator<Item = &str> {
+    pieces.iter().filter_map(|piece| match piece {
+        Piece::Field(Arg::Name(name), _) => Some(name.as_str()),
+        _ => None,
+    })
+}
+
+/* A message for when the arguments do not match the placeholders. */
+pub fn arity_error(pieces: &[Piece], found: usize) -> Option<String> {
+    let expected = arity(pieces);
+    (expected != found)
+        .then(|| format!("The format string takes {expected} argument(s), found {found}."))
+}
+
+/* Fills in the placeholders from `args` and `named`, the values of the variables
+ * named by the placeholders. */
+pub fn render(
+    pieces: &[Piece],
+    args: &[Value],
+    named: &HashMap<String, Value>,
+    heap: &Heap,
+) -> Result<String, String> {
+    if let Some(message) = arity_error(pieces, args.len()) {
+        return Err(message);
+    }
+    let mut out = String::new();
+    let mut next = 0;
+    for piece in pieces {
+        let (arg, spec) = match piece {
+            Piece::Text(text) => {
+                out.push_str(text);
+                continue;
+            }
+            Piece::Field(arg, spec) => (arg, spec),
+        };
+        let value = match arg {
+            Arg::Next => {
+                next += 1;
+                &args[next - 1]
+            }
+            Arg::Index(index) => &args[*index],
+            Arg::Name(name) => named
+                .get(name)
+                .ok_or_else(|| format!("Undefined name `{name}` in format string."))?,
+        };
+        out.push_str(&render_value(value, spec, heap)?);
+    }
+    Ok(out)
+}
+
+fn render_value(value: &Value, spec: &Spec, heap: &Heap) -> Result<String, String> {
+    let numeric = value.numeric_type().is_some();
+    /* NaN has no sign, as with Rust's formatter. */
+    let nan = match value {
+        Value::Flt32(float) => float.is_nan(),
+        Value::Flt64(float) => float.is_nan(),
+        _ => false,
+    };
+    let invalid = |what: &str| format!("{what} cannot be applied to {}.", heap.type_name(value));
+    let (negative, digits) = match (value, spec.radix, spec.precision) {
+        (_, Some(radix), _) => {
+            let (negative, magnitude) = value.int_parts().ok_or_else(|| invalid("A radix"))?;
+            let digits = match radix {
+                Radix::Hex => format!("{magnitude:x}"),
+                Radix::UpperHex => format!("{magnitude:X}"),
+                Radix::Octal => format!("{magnitude:o}"),
+                Radix::Binary => format!("{magnitude:b}"),
+            };
+            (negative, digits)
+        }
+        (Value::Flt32(float), _, Some(precision)) => (
+            float.is_sign_negative(),
+            format!("{:.precision$}", float.abs()),
+        ),
+        (Value::Flt64(float), _, Some(precision)) => (
+            float.is_sign_negative(),
+            format!("{:.precision$}", float.abs()),
+        ),
+        (Value::Str(_) | Value::Char(_), _, Some(precision)) => {
+            (false, heap.display(value).chars().take(precision).collect())
+        }
+        (_, _, Some(_)) => return Err(invalid("A precision")),
+        _ if numeric => {
+            let text = heap.display(value);
+            match text.strip_prefix('-') {
+                Some(digits) => (true, digits.to_owned()),
+                None => (false, text),
+            }
+        }
+        _ => (false, heap.display(value)),
+    };
+    if (spec.sign || spec.zero) && !numeric {
+        return Err(invalid(if spec.sign { "`+`" } else { "`0`" }));
+    }
+    let sign = match negative {
+        _ if nan => "",
+        true => "-",
+        false if spec.sign => "+",
+        false => "",
+    };
+    let len = sign.chars().count() + digits.chars().count();
+    let padding = spec.width.unwrap_or(0).saturating_sub(len);
+    if spec.zero && spec.align.is_none() {
+        return Ok(format!("{sign}{}{digits}", "0".repeat(padding)));
+    }
+    let align = spec
+        .align
+        .unwrap_or(if numeric { Align::Right } else { Align::Left });
+    let (before, after) = match align {
+        Align::Left => (0, padding),
+        Align::Right => (padding, 0),
+        Align::Center => (padding / 2, padding - padding / 2),
+    };
+    let fill = spec.fill.unwrap_or(' ').to_string();
+    Ok(format!(
+        "{}{sign}{digits}{}",
+        fill.repeat(before),
+        fill.repeat(after)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_str(format: &str, args: &[Value]) -> Result<String, String> {
+        let named = HashMap::from([("name".to_owned(), Value::Str("Ada".into()))]);
+        render(&parse(format)?, args, &named, &Heap::default())
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let args = [Value::Int32(255), Value::Flt64(-2.5)];
+        assert_eq!(
+            render_str("{} and {}, {0} again, {{{name}}}", &args).unwrap(),
+            "255 and -2.5, 255 again, {Ada}"
+        );
+        assert_eq!(
+            render_str(
+                "[{:>6}|{:<5}|{:^7}|{:*^9}]",
+                &[
+                    Value::Int32(42),
+                    Value::Bool(true),
+                    Value::Str("mid".into()),
+                    Value::Char('c')
+                ]
+            )
+            .unwrap(),
+            "[    42|true |  mid  |****c****]"
+        );
+        assert_eq!(
+            render_str(
+                "{0:x} {0:X} {0:o} {0:#>10b} {1:.2} {1:+09.3} {2:.3}",
+                &[
+                    Value::Int32(255),
+                    Value::Flt64(2.0 / 3.0),
+                    Value::Str("abcdef".into())
+                ]
+            )
+            .unwrap(),
+            "ff FF 377 ##11111111 0.67 +0000.667 abc"
+        );
+        let nan = [Value::Flt64(-f64::NAN), Value::Flt32(f32::NAN)];
+        assert_eq!(
+            render_str("{0} {0:.2} {0:+} {1:+.1}", &nan).unwrap(),
+            "NaN NaN NaN NaN"
+        );
+        assert_eq!(render_str("{:05}", &[Value::Int8(-7)]).unwrap(), "-0007");
+    }
+
+    #[test]
+    fn test_format_errors() {
+        assert_eq!(
+            render_str("{} {}", &[Value::Int32(1)]).unwrap_err(),
+            "The format string takes 2 argument(s), found 1."
+        );
+        assert_eq!(
+            render_str("{2}", &[Value::Int32(1)]).unwrap_err(),
+            "The format string takes 3 argument(s), found 1."
+        );
+        assert_eq!(
+            render_str("{:x}", &[Value::Flt64(1.5)]).unwrap_err(),
+            "A radix cannot be applied to flt64."
+        );
+        assert_eq!(
+            parse("{").unwrap_err(),
+            "Unclosed `{` in format string; write `{{` for a brace."
+        );
+        assert_eq!(
+            parse("a } b").unwrap_err(),
+            "Unmatched `}` in format string; write `}}` for a brace."
+        );
+        assert_eq!(parse("{:8z}").unwrap_err(), "Invalid format spec `{:8z}`.");
+        assert_eq!(
+            parse("{a b}").unwrap_err(),
+            "Invalid placeholder `{a b}` in format string."
+        );
+    }
+}
@@ -0,0 +1,1055 @@
+#![allow(dead_code)]
+use crate::ast::{BinaryOp, UnaryOp};
+use crate::compiler::{Function, Hint, LiteralValue, Op};
+use crate::evaluator::{int_literal, EvalResult, RuntimeError};
+use crate::token::Span;
+use crate::types::{FloatKind, IntKind, Type};
+use crate::value::Value;
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{
+    types, AbiParam, Block, InstBuilder, MemFlags, Type as IrType, Value as IrValue,
+};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::Module;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/* The native code compiler for functions marked `jit`, which the machine compiles
+ * with Cranelift on their first call.
+ *
+ * Only functions on numbers and `bool`s are compiled: their parameters, variables
+ * and temporaries must each keep one of those types, and their code may only use
+ * arithmetic, comparisons, conditions, loops over ranges and `return`. Anything
+ * else, including calls, leaves the function to the machine, as does a host that
+ * Cranelift does not support.
+ *
+ * Native code takes its arguments and leaves its result as 64-bit words, and
+ * returns 0 with a result, 1 for `nil`, or 2 plus the index of the runtime error it
+ * stopped at. */
+
+/* The arguments and the place for the result. */
+type NativeFn = extern "C" fn(*const i64, *mut i64) -> u32;
+
+/* The type of a value in native code. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Int(IntKind),
+    Float(FloatKind),
+    Bool,
+    Nil,
+}
+
+impl Kind {
+    fn of(ty: &Type) -> Option<Self> {
+        match ty {
+            Type::Int(kind) if kind.bits() <= 64 => Some(Self::Int(*kind)),
+            Type::Float(kind) => Some(Self::Float(*kind)),
+            Type::Bool => Some(Self::Bool),
+            _ => None,
+        }
+    }
+
+    fn ty(self) -> Type {
+        match self {
+            Self::Int(kind) => Type::Int(kind),
+            Self::Float(kind) => Type::Float(kind),
+            Self::Bool => Type::Bool,
+            Self::Nil => Type::Nil,
+        }
+    }
+
+    fn name(self) -> String {
+        self.ty().to_string()
+    }
+
+    fn ir(self) -> IrType {
+        match self {
+            Self::Int(kind) => IrType::int(kind.bits() as u16).unwrap_or(types::I64),
+            Self::Float(FloatKind::F32) => types::F32,
+            Self::Float(FloatKind::F64) => types::F64,
+            Self::Bool | Self::Nil => types::I8,
+        }
+    }
+}
+
+fn to_bits(value: &Value) -> i64 {
+    match *value {
+        Value::Bool(value) => i64::from(value),
+        Value::Flt32(float) => i64::from(float.to_bits()),
+        Value::Flt64(float) => float.to_bits() as i64,
+        _ => match value.int_parts() {
+            Some((true, magnitude)) => (magnitude as i128).wrapping_neg() as i64,
+            Some((false, magnitude)) => magnitude as i64,
+            None => 0,
+        },
+    }
+}
+
+fn from_bits(kind: Kind, bits: i64) -> Value {
+    match kind {
+        Kind::Int(kind) => {
+            let shift = 64 - kind.bits();
+            let (negative, magnitude) = if kind.is_signed() {
+                let value = (bits << shift) >> shift;
+                (value < 0, u128::from(value.unsigned_abs()))
+            } else {
+                (false, u128::from((bits as u64) << shift >> shift))
+            };
+            Value::from_int_parts(kind, negative, magnitude).unwrap_or(Value::Nil)
+        }
+        Kind::Float(FloatKind::F32) => Value::Flt32(f32::from_bits(bits as u32)),
+        Kind::Float(FloatKind::F64) => Value::Flt64(f64::from_bits(bits as u64)),
+        Kind::Bool => Value::Bool(bits & 0xff != 0),
+        Kind::Nil => Value::Nil,
+    }
+}
+
+struct Native {
+    code: NativeFn,
+    /* The type of the values it returns other than `nil`. */
+    ret: Kind,
+    /* Where each error happened, `None` for the call, and its message. */
+    errors: Vec<(Option<Span>, String)>,
+}
+
+pub struct Jit {
+    /* Made on the first call of a `jit` function; `None` on a host Cranelift does
+     * not support. */
+    module: Option<JITModule>,
+    /* What each function compiled to, holding on to the function so that its address
+     * is not reused. */
+    compiled: HashMap<*const Function, (Rc<Function>, Option<Native>)>,
+}
+
+impl Default for Jit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Jit {
+    pub fn new() -> Self {
+        Self {
+            module: None,
+            compiled: HashMap::new(),
+        }
+    }
+
+    /* Runs a `jit` function natively, compiling it on its first call, or returns
+     * `None` for the machine to run it. The arguments have been checked already. */
+    pub fn call(
+        &mut self,
+        function: &Rc<Function>,
+        args: &[Value],
+        span: Span,
+    ) -> Option<EvalResult<Value>> {
+        let key = Rc::as_ptr(function);
+        if !self.compiled.contains_key(&key) {
+            if self.module.is_none() {
+                self.module = host_module();
+            }
+            let native = self
+                .module
+                .as_mut()
+                .and_then(|module| native(module, function));
+            self.compiled.insert(key, (Rc::clone(function), native));
+        }
+        let native = self.compiled.get(&key)?.1.as_ref()?;
+        let args = args.iter().map(to_bits).collect::<Vec<_>>();
+        let mut result = 0;
+        match (native.code)(args.as_ptr(), &mut result) {
+            0 => Some(Ok(from_bits(native.ret, result))),
+            1 => Some(Ok(Value::Nil)),
+            status => {
+                /* Errors without a position are about the call itself. */
+                let error = match native.errors[status as usize - 2].clone() {
+                    (Some(at), message) => {
+                        RuntimeError::new(at, message).called(function.display_name(), span)
+                    }
+                    (None, message) => RuntimeError::new(span, message),
+                };
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+fn host_module() -> Option<JITModule> {
+    let mut flags = settings::builder();
+    flags.set("use_colocated_libcalls", "false").ok()?;
+    flags.set("is_pic", "false").ok()?;
+    flags.set("opt_level", "speed").ok()?;
+    let isa = cranelift_native::builder()
+        .ok()?
+        .finish(settings::Flags::new(flags))
+        .ok()?;
+    let builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+    Some(JITModule::new(builder))
+}
+
+fn native(module: &mut JITModule, function: &Function) -> Option<Native> {
+    if function.uses_self || !function.captures.is_empty() {
+        return None;
+    }
+    let params = function
+        .params
+        .iter()
+        .map(|(_, ty)| Kind::of(ty))
+        .collect::<Option<Vec<_>>>()?;
+    let pointer = module.target_config().pointer_type();
+    let mut context = module.make_context();
+    let signature = &mut context.func.signature;
+    signature.params.push(AbiParam::new(pointer));
+    signature.params.push(AbiParam::new(pointer));
+    signature.returns.push(AbiParam::new(types::I32));
+    let mut builder_context = FunctionBuilderContext::new();
+    let builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+    let mut translator = Translator {
+        function,
+        builder,
+        vars: HashMap::new(),
+        stack: vec![],
+        locals: vec![None; function.slots],
+        blocks: HashMap::new(),
+        states: HashMap::new(),
+        terminated: false,
+        out: None,
+        ip: 0,
+        span: Span::default(),
+        errors: vec![],
+        ret: None,
+    };
+    translator.translate(&params)?;
+    let Translator {
+        mut builder,
+        errors,
+        ret,
+        ..
+    } = translator;
+    builder.seal_all_blocks();
+    builder.finalize();
+    let id = module
+        .declare_anonymous_function(&context.func.signature)
+        .ok()?;
+    module.define_function(id, &mut context).ok()?;
+    module.clear_context(&mut context);
+    module.finalize_definitions().ok()?;
+    let code = module.get_finalized_function(id);
+    /* SAFETY: the code was compiled with the signature of `NativeFn`, and the module
+     * that owns it lives as long as the machine. */
+    let code = unsafe { std::mem::transmute::<*const u8, NativeFn>(code) };
+    Some(Native {
+        code,
+        ret: ret.unwrap_or(Kind::Nil),
+        errors,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Place {
+    Stack(usize),
+    Local(usize),
+}
+
+/* The types of the temporaries and local variables, `None` for a variable without
+ * one value type on every path to an instruction. */
+#[derive(Debug, Clone, PartialEq)]
+struct State {
+    stack: Vec<Kind>,
+    locals: Vec<Option<Kind>>,
+}
+
+/* Translates bytecode to Cranelift IR in order, keeping each temporary and variable
+ * in a Cranelift variable per slot and type. A translation that returns `None`
+ * found something it cannot compile. */
+struct Translator<'a> {
+    function: &'a Function,
+    builder: FunctionBuilder<'a>,
+    vars: HashMap<(Place, Kind), Variable>,
+    stack: Vec<Kind>,
+    locals: Vec<Option<Kind>>,
+    /* The block starting at each jump target, and the state jumps to it leave. */
+    blocks: HashMap<usize, Block>,
+    states: HashMap<usize, State>,
+    /* Whether the instructions reached are dead, after a jump or return. */
+    terminated: bool,
+    out: Option<IrValue>,
+    /* The instruction being translated. */
+    ip: usize,
+    span: Span,
+    errors: Vec<(Option<Span>, String)>,
+    ret: Option<Kind>,
+}
+
+impl Translator<'_> {
+    fn translate(&mut self, params: &[Kind]) -> Option<()> {
+        let entry = self.builder.create_block();
+        self.builder.append_block_params_for_function_params(entry);
+        self.builder.switch_to_block(entry);
+        let (args, out) = match self.builder.block_params(entry) {
+            &[args, out] => (args, out),
+            _ => return None,
+        };
+        self.out = Some(out);
+        for (i, kind) in params.iter().enumerate() {
+            let offset = i32::try_from(i * 8).ok()?;
+            let word = self
+                .builder
+                .ins()
+                .load(types::I64, MemFlags::trusted(), args, offset);
+            let value = match kind {
+                Kind::Float(FloatKind::F64) => {
+                    self.builder
+                        .ins()
+                        .bitcast(types::F64, MemFlags::new(), word)
+                }
+                Kind::Float(FloatKind::F32) => {
+                    let word = self.builder.ins().ireduce(types::I32, word);
+                    self.builder
+                        .ins()
+                        .bitcast(types::F32, MemFlags::new(), word)
+                }
+                kind if kind.ir() == types::I64 => word,
+                kind => self.builder.ins().ireduce(kind.ir(), word),
+            };
+            self.set_local(i, *kind, Some(value))?;
+        }
+        let function = self.function;
+        for op in &function.code {
+            if let Op::Jump(target)
+            | Op::JumpIfFalse(target)
+            | Op::JumpIfTrue(target)
+            | Op::RangeNext(_, target)
+            | Op::ThroughNext(_, target) = *op
+            {
+                let block = self.builder.create_block();
+                self.blocks.entry(target as usize).or_insert(block);
+            }
+        }
+        for (ip, op) in function.code.iter().enumerate() {
+            if let Some(&block) = self.blocks.get(&ip) {
+                if !self.terminated {
+                    self.enter(ip)?;
+                    self.builder.ins().jump(block, &[]);
+                }
+                let state = self.states.get(&ip)?.clone();
+                self.stack = state.stack;
+                self.locals = state.locals;
+                self.builder.switch_to_block(block);
+                self.terminated = false;
+            }
+            if self.terminated {
+                continue;
+            }
+            self.ip = ip;
+            self.span = function.spans[ip];
+            self.op(*op)?;
+        }
+        self.terminated.then_some(())
+    }
+
+    fn state(&self) -> State {
+        State {
+            stack: self.stack.clone(),
+            locals: self.locals.clone(),
+        }
+    }
+
+    /* Records the state a jump forward leaves at its target, where paths that
+     * disagree on the type of a variable leave it unusable. */
+    fn enter(&mut self, target: usize) -> Option<Block> {
+        let block = *self.blocks.get(&target)?;
+        let state = self.state();
+        match self.states.get_mut(&target) {
+            None => {
+                self.states.insert(target, state);
+            }
+            Some(known) if known.stack != state.stack => return None,
+            Some(known) => {
+                for (known, local) in known.locals.iter_mut().zip(&state.locals) {
+                    if *known != *local {
+                        *known = None;
+                    }
+                }
+            }
+        }
+        Some(block)
+    }
+
+    /* The block to jump to `target` with. A loop jumping back must keep the types
+     * its start was translated with. */
+    fn target(&mut self, target: usize) -> Option<Block> {
+        if target > self.ip {
+            return self.enter(target);
+        }
+        let known = self.states.get(&target)?;
+        let keeps_types = known.stack == self.stack
+            && known
+                .locals
+                .iter()
+                .zip(&self.locals)
+                .all(|(known, local)| known.is_none() || known == local);
+        keeps_types.then(|| self.blocks.get(&target).copied())?
+    }
+
+    fn jump(&mut self, target: usize) -> Option<()> {
+        let block = self.target(target)?;
+        self.builder.ins().jump(block, &[]);
+        self.terminated = true;
+        Some(())
+    }
+
+    /* Continues at `target` if `condition` is `when`, or else at the next
+     * instruction. */
+    fn branch(&mut self, condition: IrValue, target: usize, when: bool) -> Option<()> {
+        let block = self.target(target)?;
+        let next = self.builder.create_block();
+        if when {
+            self.builder.ins().brif(condition, block, &[], next, &[]);
+        } else {
+            self.builder.ins().brif(condition, next, &[], block, &[]);
+        }
+        self.builder.switch_to_block(next);
+        Some(())
+    }
+
+    fn var(&mut self, place: Place, kind: Kind) -> Variable {
+        let next = Variable::from_u32(self.vars.len() as u32);
+        *self.vars.entry((place, kind)).or_insert_with(|| {
+            self.builder.declare_var(next, kind.ir());
+            next
+        })
+    }
+
+    fn push(&mut self, kind: Kind, value: Option<IrValue>) -> Option<()> {
+        if let Some(value) = value {
+            let var = self.var(Place::Stack(self.stack.len()), kind);
+            self.builder.def_var(var, value);
+        } else if kind != Kind::Nil {
+            return None;
+        }
+        self.stack.push(kind);
+        Some(())
+    }
+
+    fn pop(&mut self) -> Option<(Kind, Option<IrValue>)> {
+        let kind = self.stack.pop()?;
+        if kind == Kind::Nil {
+            return Some((kind, None));
+        }
+        let var = self.var(Place::Stack(self.stack.len()), kind);
+        Some((kind, Some(self.builder.use_var(var))))
+    }
+
+    /* Pops a value that is not `nil`. */
+    fn pop_value(&mut self) -> Option<(Kind, IrValue)> {
+        match self.pop()? {
+            (kind, Some(value)) => Some((kind, value)),
+            (_, None) => None,
+        }
+    }
+
+    fn get_local(&mut self, slot: usize) -> Option<(Kind, IrValue)> {
+        let kind = (*self.locals.get(slot)?)?;
+        if kind == Kind::Nil {
+            return None;
+        }
+        let var = self.var(Place::Local(slot), kind);
+        Some((kind, self.builder.use_var(var)))
+    }
+
+    fn set_local(&mut self, slot: usize, kind: Kind, value: Option<IrValue>) -> Option<()> {
+        *self.locals.get_mut(slot)? = Some(kind);
+        if let Some(value) = value {
+            let var = self.var(Place::Local(slot), kind);
+            self.builder.def_var(var, value);
+        }
+        Some(())
+    }
+
+    /* Stops with an error when `condition` is nonzero. */
+    fn fail_if(&mut self, condition: IrValue, span: Option<Span>, message: String) {
+        let fail = self.builder.create_block();
+        let next = self.builder.create_block();
+        self.builder.ins().brif(condition, fail, &[], next, &[]);
+        self.builder.switch_to_block(fail);
+        self.fail(span, message);
+        self.builder.switch_to_block(next);
+    }
+
+    fn fail(&mut self, span: Option<Span>, message: String) {
+        self.errors.push((span, message));
+        let status = self
+            .builder
+            .ins()
+            .iconst(types::I32, self.errors.len() as i64 + 1);
+        self.builder.ins().return_(&[status]);
+    }
+
+    fn iconst(&mut self, kind: Kind, bits: i64) -> IrValue {
+        let ty = kind.ir();
+        let mask = if ty.bits() == 64 {
+            -1
+        } else {
+            (1i64 << ty.bits()) - 1
+        };
+        self.builder.ins().iconst(ty, bits & mask)
+    }
+
+    fn constant(&mut self, value: &Value) -> Option<()> {
+        let kind = match value {
+            Value::Bool(_) => Kind::Bool,
+            Value::Flt32(_) => Kind::Float(FloatKind::F32),
+            Value::Flt64(_) => Kind::Float(FloatKind::F64),
+            value => Kind::of(&value.numeric_type()?)?,
+        };
+        let ir = match *value {
+            Value::Flt32(float) => self.builder.ins().f32const(float),
+            Value::Flt64(float) => self.builder.ins().f64const(float),
+            _ => self.iconst(kind, to_bits(value)),
+        };
+        self.push(kind, Some(ir))
+    }
+
+    /* The type an untyped literal takes, if it can be told before running. */
+    fn hint(&self, hint: &Hint) -> Option<Option<Type>> {
+        let ty = match hint {
+            Hint::None => None,
+            Hint::Type(ty) => Some(ty.clone()),
+            Hint::Numeric(slot, otherwise) => match self.stack.get(*slot as usize) {
+                Some(kind @ (Kind::Int(_) | Kind::Float(_))) => Some(kind.ty()),
+                _ => self.hint(otherwise)?,
+            },
+            Hint::Operands(op, inner) => self
+                .hint(inner)?
+                .filter(|ty| op.operand_hint(Some(ty)).is_some()),
+            Hint::Param(..)
+            | Hint::Global(_)
+            | Hint::Elem(_)
+            | Hint::Key(_)
+            | Hint::Index(_)
+            | Hint::Entry(_)
+            | Hint::Item(_)
+            | Hint::Field(..) => return None,
+        };
+        Some(ty.map(|ty| ty.non_optional().clone()))
+    }
+
+    /* A value converted to `ty` as `check_type` would, where that cannot fail. */
+    fn convert(
+        &mut self,
+        kind: Kind,
+        value: Option<IrValue>,
+        ty: &Type,
+    ) -> Option<(Kind, Option<IrValue>)> {
+        match (ty, value) {
+            (Type::Unknown, _) => Some((kind, value)),
+            (Type::Optional(_), None) => Some((kind, value)),
+            (Type::Optional(inner), Some(_)) => self.convert(kind, value, inner),
+            (ty, Some(value)) if Kind::of(ty) == Some(kind) => Some((kind, Some(value))),
+            (ty, Some(value)) if kind.ty().widens_to(ty) => {
+                let target = Kind::of(ty)?;
+                Some((target, Some(self.widen(kind, value, target))))
+            }
+            _ => None,
+        }
+    }
+
+    fn widen(&mut self, from: Kind, value: IrValue, to: Kind) -> IrValue {
+        let ins = self.builder.ins();
+        match (from, to) {
+            (Kind::Int(from), Kind::Int(_)) if from.is_signed() => ins.sextend(to.ir(), value),
+            (Kind::Int(_), Kind::Int(_)) => ins.uextend(to.ir(), value),
+            (Kind::Int(from), Kind::Float(_)) => {
+                if from.is_signed() {
+                    let value = if from.bits() < 64 {
+                        ins.sextend(types::I64, value)
+                    } else {
+                        value
+                    };
+                    self.builder.ins().fcvt_from_sint(to.ir(), value)
+                } else {
+                    let value = if from.bits() < 64 {
+                        ins.uextend(types::I64, value)
+                    } else {
+                        value
+                    };
+                    self.builder.ins().fcvt_from_uint(to.ir(), value)
+                }
+            }
+            _ => ins.fpromote(to.ir(), value),
+        }
+    }
+
+    /* Both operands of a binary operator with the narrower number widened. */
+    fn operands(
+        &mut self,
+        (lhs_kind, lhs): (Kind, IrValue),
+        (rhs_kind, rhs): (Kind, IrValue),
+    ) -> Option<(Kind, IrValue, IrValue)> {
+        if lhs_kind == rhs_kind {
+            return Some((lhs_kind, lhs, rhs));
+        }
+        if lhs_kind.ty().widens_to(&rhs_kind.ty()) {
+            return Some((rhs_kind, self.widen(lhs_kind, lhs, rhs_kind), rhs));
+        }
+        if rhs_kind.ty().widens_to(&lhs_kind.ty()) {
+            return Some((lhs_kind, lhs, self.widen(rhs_kind, rhs, lhs_kind)));
+        }
+        None
+    }
+
+    fn binary(
+        &mut self,
+        op: BinaryOp,
+        lhs: (Kind, IrValue),
+        rhs: (Kind, IrValue),
+    ) -> Option<(Kind, IrValue)> {
+        let (kind, a, b) = self.operands(lhs, rhs)?;
+        let span = Some(self.span);
+        let overflow = || {
+            format!(
+                "Integer overflow: `{}` on {} operands.",
+                op.symbol(),
+                kind.name()
+            )
+        };
+        let value = match (op, kind) {
+            (BinaryOp::Eq | BinaryOp::NotEq, Kind::Float(_)) => {
+                let cc = if op == BinaryOp::Eq {
+                    FloatCC::Equal
+                } else {
+                    FloatCC::NotEqual
+                };
+                return Some((Kind::Bool, self.builder.ins().fcmp(cc, a, b)));
+            }
+            (BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq, Kind::Float(_)) => {
+                let unordered = self.builder.ins().fcmp(FloatCC::Unordered, a, b);
+                let message = format!(
+                    "Operator `{}` cannot be applied to {} and {}.",
+                    op.symbol(),
+                    kind.name(),
+                    kind.name()
+                );
+                self.fail_if(unordered, span, message);
+                let cc = match op {
+                    BinaryOp::Lt => FloatCC::LessThan,
+                    BinaryOp::LtEq => FloatCC::LessThanOrEqual,
+                    BinaryOp::Gt => FloatCC::GreaterThan,
+                    _ => FloatCC::GreaterThanOrEqual,
+                };
+                return Some((Kind::Bool, self.builder.ins().fcmp(cc, a, b)));
+            }
+            (
+                BinaryOp::Eq
+                | BinaryOp::NotEq
+                | BinaryOp::Lt
+                | BinaryOp::LtEq
+                | BinaryOp::Gt
+                | BinaryOp::GtEq,
+                Kind::Int(_) | Kind::Bool,
+            ) => {
+                let signed = matches!(kind, Kind::Int(int) if int.is_signed());
+                let cc = match (op, signed) {
+                    (BinaryOp::Eq, _) => IntCC::Equal,
+                    (BinaryOp::NotEq, _) => IntCC::NotEqual,
+                    (BinaryOp::Lt, true) => IntCC::SignedLessThan,
+                    (BinaryOp::Lt, false) => IntCC::UnsignedLessThan,
+                    (BinaryOp::LtEq, true) => IntCC::SignedLessThanOrEqual,
+                    (BinaryOp::LtEq, false) => IntCC::UnsignedLessThanOrEqual,
+                    (BinaryOp::Gt, true) => IntCC::SignedGreaterThan,
+                    (BinaryOp::Gt, false) => IntCC::UnsignedGreaterThan,
+                    (_, true) => IntCC::SignedGreaterThanOrEqual,
+                    (_, false) => IntCC::UnsignedGreaterThanOrEqual,
+                };
+                return Some((Kind::Bool, self.builder.ins().icmp(cc, a, b)));
+            }
+            (BinaryOp::BitAnd, Kind::Int(_) | Kind::Bool) => self.builder.ins().band(a, b),
+            (BinaryOp::BitOr, Kind::Int(_) | Kind::Bool) => self.builder.ins().bor(a, b),
+            (BinaryOp::BitXor, Kind::Int(_) | Kind::Bool) => self.builder.ins().bxor(a, b),
+            (BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul, Kind::Int(int)) => {
+                let ins = self.builder.ins();
+                let (value, overflowed) = match (op, int.is_signed()) {
+                    (BinaryOp::Add, true) => ins.sadd_overflow(a, b),
+                    (BinaryOp::Add, false) => ins.uadd_overflow(a, b),
+                    (BinaryOp::Sub, true) => ins.ssub_overflow(a, b),
+                    (BinaryOp::Sub, false) => ins.usub_overflow(a, b),
+                    (_, true) => ins.smul_overflow(a, b),
+                    (_, false) => ins.umul_overflow(a, b),
+                };
+                self.fail_if(overflowed, span, overflow());
+                value
+            }
+            (BinaryOp::Div | BinaryOp::FloorDiv | BinaryOp::Mod, Kind::Int(int)) => {
+                let zero = self.builder.ins().icmp_imm(IntCC::Equal, b, 0);
+                self.fail_if(zero, span, "Division by zero.".to_owned());
+                if !int.is_signed() {
+                    let ins = self.builder.ins();
+                    let value = match op {
+                        BinaryOp::Mod => ins.urem(a, b),
+                        _ => ins.udiv(a, b),
+                    };
+                    return Some((kind, value));
+                }
+                let remainder = self.builder.ins().srem(a, b);
+                if op == BinaryOp::Mod {
+                    /* The remainder takes the sign of the divisor. */
+                    let adjust = self.floor_adjust(remainder, b);
+                    let adjusted = self.builder.ins().iadd(remainder, b);
+                    return Some((kind, self.builder.ins().select(adjust, adjusted, remainder)));
+                }
+                let min = self.iconst(kind, int.min() as i64);
+                let is_min = self.builder.ins().icmp(IntCC::Equal, a, min);
+                let is_minus_one = self.builder.ins().icmp_imm(IntCC::Equal, b, -1);
+                let overflowed = self.builder.ins().band(is_min, is_minus_one);
+                self.fail_if(overflowed, span, overflow());
+                let quotient = self.builder.ins().sdiv(a, b);
+                if op == BinaryOp::Div {
+                    return Some((kind, quotient));
+                }
+                let adjust = self.floor_adjust(remainder, b);
+                let lower = self.builder.ins().iadd_imm(quotient, -1);
+                self.builder.ins().select(adjust, lower, quotient)
+            }
+            (_, Kind::Float(_)) => {
+                let ins = self.builder.ins();
+                match op {
+                    BinaryOp::Add => ins.fadd(a, b),
+                    BinaryOp::Sub => ins.fsub(a, b),
+                    BinaryOp::Mul => ins.fmul(a, b),
+                    BinaryOp::Div => ins.fdiv(a, b),
+                    BinaryOp::FloorDiv => {
+                        let quotient = ins.fdiv(a, b);
+                        self.builder.ins().floor(quotient)
+                    }
+                    BinaryOp::Mod => {
+                        let quotient = ins.fdiv(a, b);
+                        let floor = self.builder.ins().floor(quotient);
+                        let product = self.builder.ins().fmul(floor, b);
+                        self.builder.ins().fsub(a, product)
+                    }
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        Some((kind, value))
+    }
+
+    /* Whether a nonzero remainder has the opposite sign to the divisor, so that
+     * floored division rounds the other way. */
+    fn floor_adjust(&mut self, remainder: IrValue, divisor: IrValue) -> IrValue {
+        let nonzero = self.builder.ins().icmp_imm(IntCC::NotEqual, remainder, 0);
+        let signs = self.builder.ins().bxor(remainder, divisor);
+        let opposite = self.builder.ins().icmp_imm(IntCC::SignedLessThan, signs, 0);
+        self.builder.ins().band(nonzero, opposite)
+    }
+
+    fn unary(&mut self, op: UnaryOp, kind: Kind, value: IrValue) -> Option<IrValue> {
+        Some(match (op, kind) {
+            (UnaryOp::Not, Kind::Bool) => self.builder.ins().bxor_imm(value, 1),
+            (UnaryOp::Neg, Kind::Float(_)) => self.builder.ins().fneg(value),
+            (UnaryOp::Neg, Kind::Int(int)) if int.is_signed() => {
+                let min = self.iconst(kind, int.min() as i64);
+                let overflowed = self.builder.ins().icmp(IntCC::Equal, value, min);
+                let message = format!("Integer overflow: `-` on {}.", int.name());
+                self.fail_if(overflowed, Some(self.span), message);
+                self.builder.ins().ineg(value)
+            }
+            (UnaryOp::BitNot, Kind::Int(_)) => self.builder.ins().bnot(value),
+            _ => return None,
+        })
+    }
+
+    fn op(&mut self, op: Op) -> Option<()> {
+        let function = self.function;
+        match op {
+            Op::Constant(index) => self.constant(function.constants.get(index as usize)?)?,
+            Op::Literal(index) => {
+                let literal = function.literals.get(index as usize)?;
+                let hint = self.hint(&literal.hint)?;
+                let value = match literal.value {
+                    LiteralValue::Int {
+                        negative,
+                        magnitude,
+                    } => int_literal(negative, magnitude, hint.as_ref(), self.span).ok()?,
+                    LiteralValue::Float(float) => {
+                        crate::evaluator::float_literal(float, hint.as_ref())
+                    }
+                };
+                self.constant(&value)?;
+            }
+            Op::Nil => self.push(Kind::Nil, None)?,
+            Op::Pop => {
+                self.pop()?;
+            }
+            Op::Dup => {
+                let (kind, value) = self.pop()?;
+                self.push(kind, value)?;
+                self.push(kind, value)?;
+            }
+            Op::Swap => {
+                let (b_kind, b) = self.pop()?;
+                let (a_kind, a) = self.pop()?;
+                self.push(b_kind, b)?;
+                self.push(a_kind, a)?;
+            }
+            Op::GetLocal(slot) => {
+                let (kind, value) = self.get_local(slot as usize)?;
+                self.push(kind, Some(value))?;
+            }
+            Op::SetLocal(slot) => {
+                let (kind, value) = self.pop()?;
+                self.set_local(slot as usize, kind, value)?;
+            }
+            Op::Check(index) => {
+                let (ty, _) = function.checks.get(index as usize)?;
+                let (kind, value) = self.pop()?;
+                let (kind, value) = self.convert(kind, value, ty)?;
+                self.push(kind, value)?;
+            }
+            Op::Condition => {
+                if self.stack.last() != Some(&Kind::Bool) {
+                    return None;
+                }
+            }
+            Op::Unary(op) => {
+                let (kind, value) = self.pop_value()?;
+                let value = self.unary(op, kind, value)?;
+                self.push(kind, Some(value))?;
+            }
+            Op::Binary(op) => {
+                let rhs = self.pop_value()?;
+                let lhs = self.pop_value()?;
+                let (kind, value) = self.binary(op, lhs, rhs)?;
+                self.push(kind, Some(value))?;
+            }
+            Op::Jump(target) => self.jump(target as usize)?,
+            Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => {
+                let (kind, condition) = self.pop_value()?;
+                if kind != Kind::Bool {
+                    return None;
+                }
+                self.branch(condition, target as usize, matches!(op, Op::JumpIfTrue(_)))?;
+            }
+            Op::RangeStart(slot) => {
+                let (end_kind, end) = self.pop_value()?;
+                let (start_kind, start) = self.pop_value()?;
+                self.set_local(slot as usize, start_kind, Some(start))?;
+                self.set_local(slot as usize + 1, end_kind, Some(end))?;
+            }
+            Op::RangeNext(slot, exit) => {
+                let current = self.get_local(slot as usize)?;
+                let end = self.get_local(slot as usize + 1)?;
+                let (_, more) = self.binary(BinaryOp::Lt, current, end)?;
+                self.branch(more, exit as usize, false)?;
+                let Kind::Int(_) = current.0 else {
+                    return None;
+                };
+                let one = self.iconst(current.0, 1);
+                let (kind, next) = self.binary(BinaryOp::Add, current, (current.0, one))?;
+                self.set_local(slot as usize, kind, Some(next))?;
+                self.push(current.0, Some(current.1))?;
+            }
+            Op::ThroughNext(slot, exit) => {
+                let current = self.get_local(slot as usize)?;
+                let end = self.get_local(slot as usize + 1)?;
+                let (_, more) = self.binary(BinaryOp::LtEq, current, end)?;
+                self.branch(more, exit as usize, false)?;
+                let (Kind::Int(_), true) = (current.0, current.0 == end.0) else {
+                    return None;
+                };
+                /* Short of the end, the next integer cannot overflow. */
+                let last = self.builder.ins().icmp(IntCC::Equal, current.1, end.1);
+                let one = self.iconst(current.0, 1);
+                let zero = self.iconst(current.0, 0);
+                let after = self.builder.ins().iadd(current.1, one);
+                let next = self.builder.ins().select(last, one, after);
+                let end = self.builder.ins().select(last, zero, end.1);
+                self.set_local(slot as usize, current.0, Some(next))?;
+                self.set_local(slot as usize + 1, current.0, Some(end))?;
+                self.push(current.0, Some(current.1))?;
+            }
+            Op::Return => self.ret()?,
+            /* The values translated code has on its stack are never `nil`. */
+            Op::Assigned(_) => (),
+            Op::Fail(message) => {
+                let message = function.names.get(message as usize)?.clone();
+                self.fail(Some(self.span), message);
+                self.terminated = true;
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn ret(&mut self) -> Option<()> {
+        let function = self.function;
+        let (kind, value) = self.pop()?;
+        let ret = &function.ret;
+        self.terminated = true;
+        let Some(value) = value else {
+            if matches!(ret, Type::Void | Type::Unknown | Type::Optional(_)) {
+                let status = self.builder.ins().iconst(types::I32, 1);
+                self.builder.ins().return_(&[status]);
+            } else {
+                let message = format!(
+                    "Function `{}` did not return a {ret}.",
+                    function.display_name()
+                );
+                self.fail(None, message);
+            }
+            return Some(());
+        };
+        let (kind, value) = self.convert(kind, Some(value), ret)?;
+        if self.ret.is_some_and(|known| known != kind) {
+            return None;
+        }
+        self.ret = Some(kind);
+        self.builder
+            .ins()
+            .store(MemFlags::trusted(), value?, self.out?, 0);
+        let status = self.builder.ins().iconst(types::I32, 0);
+        self.builder.ins().return_(&[status]);
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::parser::parse;
+    use crate::vm::Vm;
+    use std::time::Instant;
+
+    fn run(source: &str) -> (String, Option<RuntimeError>) {
+        let bytecode = compile(&parse(source).unwrap()).unwrap();
+        let mut vm = Vm::new();
+        vm.capture_output();
+        let result = vm.run(&bytecode);
+        (vm.take_output(), result.err())
+    }
+
+    /* Runs `source` with and without `jit` on its functions, which must agree, and
+     * returns the output of the native run. */
+    fn run_native(source: &str) -> (String, Option<RuntimeError>) {
+        assert!(compiles(source), "{source}");
+        let native = run(source);
+        assert_eq!(native, run(&source.replace("jit func", "func")), "{source}");
+        native
+    }
+
+    /* Whether every function of `source` compiles to native code. */
+    fn compiles(source: &str) -> bool {
+        let bytecode = compile(&parse(source).unwrap()).unwrap();
+        let mut module = host_module().unwrap();
+        bytecode
+            .script
+            .functions
+            .iter()
+            .all(|function| native(&mut module, function).is_some())
+    }
+
+    #[test]
+    fn test_numeric_functions_compile() {
+        for source in [
+            "jit func int64 sum(int64 n)\n\tint64 total = 0\n\tfor int64 i in 0 to n do\n\t\ttotal += i\n\tend\n\treturn total\nend",
+            "jit func flt64 f(flt64 x, int32 n)\n\twhile n > 0 do\n\t\tx = x * 1.5 + n\n\t\tn -= 1\n\tend\n\treturn x\nend",
+            "jit func bool f(uint8 a, uint8 b)\n\treturn a < b and not (a == 3) or a % b == 1\nend",
+            "jit func uint8 f(uint8 a)\n\tuint8 n = 0\n\tfor uint8 i in a through 255 do\n\t\tn += 1\n\tend\n\treturn n\nend",
+        ] {
+            assert!(compiles(source), "{source}");
+        }
+        for source in [
+            "jit func int32 f(int32 n)\n\tprintln(n)\n\treturn n\nend",
+            "jit func string f(int32 n)\n\treturn \"n\"\nend",
+            "jit func int128 f(int128 n)\n\treturn n\nend",
+            "jit func int32 f(int32 n)\n\treturn f(n)\nend",
+        ] {
+            assert!(!compiles(source), "{source}");
+        }
+    }
+
+    #[test]
+    fn test_native_code_matches_the_machine() {
+        let source = "jit func int64 sum(int64 n)\n\tint64 total = 0\n\tfor int64 i in 0 to n do\n\t\tif i % 3 == 0 or i // 7 == 2 then\n\t\t\ttotal += i * 2\n\t\telse\n\t\t\ttotal -= -i\n\t\tend\n\tend\n\treturn total\nend\njit func flt64 mean(flt32 a, int16 b)\n\treturn (a + b) / 2 + 7.0 // 2 + -7.5 % 2\nend\njit func int8 floors(int8 a, int8 b)\n\treturn a // b * 10 + a % b\nend\njit func uint8 bits(uint8 a)\n\treturn ~a & 240 | a ^ 3\nend\njit func int32? half(int32 n)\n\tif n % 2 == 1 then\n\t\treturn nil\n\tend\n\treturn n / 2\nend\njit func bool between(int32 a, int64 b, int64 c)\n\treturn a < b == (b <= c)\nend\nprintln(sum(1000), mean(1.5, 4), floors(-7, 2), floors(7, -2), bits(13), half(7), half(-8), between(1, 2, 3))";
+        assert_eq!(
+            run_native(source),
+            ("666419 6.25 -39 -41 254 nil -4 true\n".to_owned(), None)
+        );
+        let source = "jit func uint8 count(uint8 a, uint8 b)\n\tuint8 n = 0\n\tfor uint8 i in a through b do\n\t\tn += 1\n\tend\n\treturn n\nend\nprintln(count(250, 255), count(0, 0), count(3, 2))";
+        assert_eq!(run_native(source), ("6 1 0\n".to_owned(), None));
+    }
+
+    #[test]
+    fn test_native_errors_match_the_machine() {
+        for (source, error) in [
+            (
+                "jit func int8 f(int8 a)\n\treturn a * 2\nend\nf(100)",
+                "Toolip:2:11: Runtime error: Integer overflow: `*` on int8 operands.",
+            ),
+            (
+                "jit func int32 f(int32 a, int32 b)\n\treturn a // b\nend\nf(1, 0)",
+                "Toolip:2:11: Runtime error: Division by zero.",
+            ),
+            (
+                "jit func int8 f(int8 a)\n\treturn -a\nend\nf(-128)",
+                "Toolip:2:9: Runtime error: Integer overflow: `-` on int8.",
+            ),
+            (
+                "jit func int32 f(int32 a)\n\tif a > 0 then\n\t\treturn a\n\tend\nend\nf(-1)",
+                "Toolip:6:2: Runtime error: Function `f` did not return a int32.",
+            ),
+            (
+                "jit func uint8 f(uint8 a)\n\tfor uint8 i in 250 to a do\n\tend\n\treturn a\nend\nf(255)",
+                "",
+            ),
+            (
+                "jit func bool f(flt64 a)\n\treturn a < a\nend\nf(0.0 / 0.0)",
+                "Toolip:2:11: Runtime error: Operator `<` cannot be applied to flt64 and flt64.",
+            ),
+        ] {
+            let (_, result) = run_native(source);
+            let result = result.map(|error| error.to_string());
+            assert_eq!(result.unwrap_or_default(), error, "{source}");
+        }
+    }
+
+    #[test]
+    fn test_native_errors_are_traced() {
+        let source = "jit func int32 f(int32 a)\n\treturn a // 0\nend\nfunc int32 g()\n\treturn f(1) + 0\nend\ng()";
+        let (_, error) = run(source);
+        assert_eq!(error, run(&source.replace("jit func", "func")).1);
+        assert_eq!(
+            error.unwrap().report("f.tool"),
+            "Toolip:2:11: Runtime error: Division by zero.
+Stack trace (most recent call first):
+  in `f` at f.tool:2:11
+  in `g` at f.tool:5:10
+  in the program at f.tool:7:2"
+        );
+    }
+
+    /* `cargo test --release bench -- --ignored --nocapture` */
+    #[test]
+    #[ignore]
+    fn bench_sum_loop() {
+        let source = "jit func int64 sum(int64 n)\n\tint64 total = 0\n\tfor int64 i in 0 to n do\n\t\ttotal += i\n\tend\n\treturn total\nend\nprintln(sum(10_000_000))";
+        let mut times = vec![];
+        for source in [source.to_owned(), source.replace("jit func", "func")] {
+            let start = Instant::now();
+            assert_eq!(run(&source), ("49999995000000\n".to_owned(), None));
+            times.push(start.elapsed());
+        }
+        println!(
+            "native: {:?}, bytecode: {:?}, {:.0}x faster",
+            times[0],
+            times[1],
+            times[1].as_secs_f64() / times[0].as_secs_f64()
+        );
+        assert!(times[0] < times[1]);
+    }
+}
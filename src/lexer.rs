@@ -1,4 +1,4 @@
-use crate::token::{self, SymbolKind, Token, TokenType, KEYWORDS};
+use crate::token::{self, KeywordKind, Span, SymbolKind, Token, TokenType, KEYWORDS};
 use std::{env, process::exit};
 
 #[derive(Debug, Clone, PartialEq)]
@@ -28,22 +28,22 @@ impl Lexer {
 
         lexer.next_char();
 
-        return lexer;
+        lexer
     }
 
-    fn peek_char(&mut self) -> char {
+    fn peek_char(&self) -> char {
         if self.next_pos >= self.input.len() {
-            return '\x00';
+            '\x00'
         } else {
-            return self.input[self.next_pos];
+            self.input[self.next_pos]
         }
     }
 
-    fn peek_nth_char(&mut self, n: usize) -> char {
+    fn peek_nth_char(&self, n: usize) -> char {
         if self.pos + n >= self.input.len() {
-            return '\x00';
+            '\x00'
         } else {
-            return self.input[self.pos + n];
+            self.input[self.pos + n]
         }
     }
 
@@ -64,14 +64,16 @@ impl Lexer {
         self.skip_whitespace();
         self.skip_comments();
 
+        let span = Span::new(self.line_num, self.line_pos);
         let (tok_type, tok_val) = self.match_token(self.char);
 
-        let token = Token::new(tok_type, tok_val);
+        let mut token = Token::new(tok_type, tok_val);
+        token.span = span;
 
         self.prev_token = token.clone();
         self.next_char();
 
-        return token;
+        token
     }
 
     fn skip_whitespace(&mut self) {
@@ -81,14 +83,43 @@ impl Lexer {
     }
 
     fn skip_comments(&mut self) {
-        if self.char == '#' {
-            loop {
-                match self.peek_char() {
-                    '#' => self.eat_line_comment(),
-                    '[' => self.eat_block_comment(),
-                    _ => break,
-                }
+        while self.char == '#' {
+            match self.peek_char() {
+                '#' => self.eat_line_comment(),
+                '[' if !self.expects_operand() => self.eat_block_comment(),
+                _ => break,
             }
+            self.skip_whitespace();
+        }
+    }
+
+    /* Whether the previous token leaves an operand to come, as after `=`, `(`,
+     * `return` or `to`. There `#[` is the length of an array literal, as in `n = #[1, 2]`,
+     * rather than the start of a block comment. */
+    fn expects_operand(&self) -> bool {
+        match &self.prev_token.Type {
+            TokenType::Symbol(symbol) => !matches!(
+                symbol,
+                SymbolKind::RightParen | SymbolKind::RightBracket | SymbolKind::RightBrace
+            ),
+            TokenType::Keyword(keyword) => matches!(
+                keyword,
+                KeywordKind::Return
+                    | KeywordKind::And
+                    | KeywordKind::Or
+                    | KeywordKind::Not
+                    | KeywordKind::Xor
+                    | KeywordKind::In
+                    | KeywordKind::If
+                    | KeywordKind::ElseIf
+                    | KeywordKind::While
+                    | KeywordKind::Given
+                    | KeywordKind::When
+                    | KeywordKind::Raise
+                    | KeywordKind::Yield
+            ),
+            TokenType::Identifier(word) => matches!(word.as_str(), "to" | "through" | "step"),
+            _ => false,
         }
     }
 
@@ -111,11 +142,16 @@ impl Lexer {
                         "Toolip:{}:{}: End of file reached before end of block comment.",
                         self.line_num, self.line_pos
                     );
+                    return;
                 }
-                ']' => {
-                    if self.peek_char() == '#' {
-                        break;
-                    }
+                '\n' => {
+                    self.line_num += 1;
+                    self.line_pos = 0;
+                }
+                ']' if self.peek_char() == '#' => {
+                    self.next_char();
+                    self.next_char();
+                    return;
                 }
                 _ => (),
             }
@@ -123,7 +159,7 @@ impl Lexer {
     }
 
     fn match_token(&mut self, char: char) -> (TokenType, String) {
-        return match char {
+        match char {
             '=' => match self.peek_char() {
                 '=' => {
                     self.next_char();
@@ -259,14 +295,20 @@ impl Lexer {
                         '.' => {
                             self.next_char();
                             match self.peek_char() {
-                                '=' => (TokenType::Symbol(SymbolKind::EtcEQ), "...=".to_owned()),
+                                '=' => {
+                                    self.next_char();
+                                    (TokenType::Symbol(SymbolKind::EtcEQ), "...=".to_owned())
+                                }
                                 _ => (TokenType::Symbol(SymbolKind::Etc), "...".to_owned()),
                             }
                         }
-                        '=' => (
-                            TokenType::Symbol(SymbolKind::ConcatAssign),
-                            "..=".to_owned(),
-                        ),
+                        '=' => {
+                            self.next_char();
+                            (
+                                TokenType::Symbol(SymbolKind::ConcatAssign),
+                                "..=".to_owned(),
+                            )
+                        }
                         _ => (TokenType::Symbol(SymbolKind::Concat), "..".to_owned()),
                     }
                 }
@@ -339,7 +381,7 @@ impl Lexer {
             '\n' => (TokenType::NewLine, "\n".to_owned()),
             '\x00' => (TokenType::Eof, "\x00".to_owned()),
             x => self.read_complex_token(x),
-        };
+        }
     }
 
     fn read_char_string(&mut self) -> (TokenType, String) {
@@ -458,7 +500,7 @@ impl Lexer {
         (TokenType::StringVal(parser_value), token_output_value)
     }
 
-    fn read_string_with_escape_sequence(input: &Vec<&char>) -> String {
+    fn read_string_with_escape_sequence(input: &[&char]) -> String {
         let mut result = String::new();
         let mut index = 0;
         while index < input.len() {
@@ -543,6 +585,9 @@ impl Lexer {
                 '0'..='9' => {
                     self.next_char();
                 }
+                '_' if self.peek_nth_char(2).is_ascii_digit() => {
+                    self.next_char();
+                }
                 '.' => {
                     if dot_count == 1
                         || self.peek_nth_char(2) == '.'
@@ -557,18 +602,32 @@ impl Lexer {
             }
         }
 
-        let f64_token_value: f64 = String::from_iter(&self.input[pos..=self.pos])
-            .parse::<f64>()
-            .map_or_else(
-                |_err| {
-                    println!(
-                        "Toolip:{}:{}: Could not tokenize this number!",
-                        self.line_num, self.line_pos
-                    );
-                    exit(1);
-                },
-                |float: f64| float,
+        let digits = self.input[pos..=self.pos]
+            .iter()
+            .filter(|&&ch| ch != '_')
+            .collect::<String>();
+
+        if dot_count == 0 {
+            let Ok(int_token_value) = digits.parse::<u128>() else {
+                println!(
+                    "Toolip:{}:{}: Integer literal {} is too large!",
+                    self.line_num, self.line_pos, digits
+                );
+                exit(1);
+            };
+            return match i64::try_from(int_token_value) {
+                Ok(int) => (TokenType::Int64Val(int), digits),
+                Err(_) => (TokenType::UInt128Val(int_token_value), digits),
+            };
+        }
+
+        let Ok(f64_token_value) = digits.parse::<f64>() else {
+            println!(
+                "Toolip:{}:{}: Could not tokenize this number!",
+                self.line_num, self.line_pos
             );
+            exit(1);
+        };
 
         (
             TokenType::Float64Val(f64_token_value),
@@ -588,7 +647,7 @@ impl Lexer {
             let index = KEYWORDS
                 .iter()
                 .position(|&x| x == ident)
-                .map_or_else(|| KEYWORDS.len(), |index| index);
+                .unwrap_or(KEYWORDS.len());
             return (
                 TokenType::Keyword(token::match_keyword_to_index(index)),
                 ident,
@@ -653,6 +712,62 @@ mod tests {
         assert_eq!(lexer.prev_char, expected_lexer.prev_char);
         assert_eq!(lexer.prev_token, expected_lexer.prev_token);
     }
+
+    #[test]
+    fn test_numbers_and_compound_symbols() {
+        let input = "sum ..= 1_000 ## trailing\n#[ block\n]# x...=2.5"
+            .chars()
+            .collect::<Vec<char>>();
+        let tokens = Lexer::new(input).tokenize();
+        let types = tokens
+            .iter()
+            .map(|tok| tok.Type.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                TokenType::Identifier("sum".to_owned()),
+                TokenType::Symbol(SymbolKind::ConcatAssign),
+                TokenType::Int64Val(1000),
+                TokenType::NewLine,
+                TokenType::Identifier("x".to_owned()),
+                TokenType::Symbol(SymbolKind::EtcEQ),
+                TokenType::Float64Val(2.5),
+                TokenType::Eof,
+            ]
+        );
+        assert_eq!(tokens[4].span, Span::new(3, 4));
+    }
+
+    #[test]
+    fn test_length_of_literal_and_block_comment() {
+        let input = "n = #[1] #[ c ]#\nf(#[2])".chars().collect::<Vec<char>>();
+        let types = Lexer::new(input)
+            .tokenize()
+            .into_iter()
+            .map(|tok| tok.Type)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                TokenType::Identifier("n".to_owned()),
+                TokenType::Symbol(SymbolKind::Assign),
+                TokenType::Symbol(SymbolKind::Hash),
+                TokenType::Symbol(SymbolKind::LeftBracket),
+                TokenType::Int64Val(1),
+                TokenType::Symbol(SymbolKind::RightBracket),
+                TokenType::NewLine,
+                TokenType::Identifier("f".to_owned()),
+                TokenType::Symbol(SymbolKind::LeftParen),
+                TokenType::Symbol(SymbolKind::Hash),
+                TokenType::Symbol(SymbolKind::LeftBracket),
+                TokenType::Int64Val(2),
+                TokenType::Symbol(SymbolKind::RightBracket),
+                TokenType::Symbol(SymbolKind::RightParen),
+                TokenType::Eof,
+            ]
+        );
+    }
 }
 
 #[test]
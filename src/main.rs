@@ -1,43 +1,207 @@
-use std::{env, fs, process::exit};
+use std::{env, fs, process::exit, thread};
 
+mod assignment;
 mod ast;
+mod builtins;
+mod cache;
+mod checker;
+mod compiler;
+mod diagnostic;
 mod evaluator;
+mod exhaustive;
+mod fold;
+mod format;
+mod jit;
 mod lexer;
+mod ops;
 mod parser;
 mod repl;
+mod resolver;
 mod token;
+mod types;
+mod value;
+mod vm;
 
 use lexer::Lexer;
 
-fn main() {
-    let mut args = env::args();
-    match args.len() {
-        1 => {
-            repl::start();
-        }
-        2 => {
-            let filename = args.nth(1).map_or_else(
-                || {
-                    println!("Could not read file.");
-                    exit(1);
-                },
-                |filename| filename,
-            );
-            if &filename[filename.len() - 5..] != ".tool" {
-                println!("Not a Toolip file.");
+fn read_source(filename: &str) -> String {
+    if !filename.ends_with(".tool") {
+        println!("Not a Toolip file.");
+        exit(1);
+    }
+    fs::read_to_string(filename).unwrap_or_else(|_| {
+        println!("Error reading file: {filename}");
+        exit(1);
+    })
+}
+
+fn check_file(filename: &str) -> ast::Program {
+    check_source(&read_source(filename))
+}
+
+fn check_source(source: &str) -> ast::Program {
+    let program = parser::parse(source).unwrap_or_else(|errors| {
+        for error in errors {
+            println!("{error}");
+        }
+        exit(1);
+    });
+    let diagnostics = checker::check(&program);
+    for diagnostic in &diagnostics {
+        println!("{diagnostic}");
+    }
+    if diagnostic::has_errors(&diagnostics) {
+        exit(1);
+    }
+    program
+}
+
+fn run_file(filename: &str, options: &Options) {
+    let program = check_file(filename);
+    let max_depth = options.max_depth;
+    /* Programs using what the bytecode compiler does not support yet are evaluated,
+     * with a warning saying so, unless `--vm` asks for the machine only. */
+    let result = match compiler::compile(&program) {
+        Ok(bytecode) => {
+            let mut vm = vm::Vm::new();
+            vm.set_max_depth(max_depth.unwrap_or(vm::MAX_CALL_DEPTH));
+            vm.run(&bytecode)
+        }
+        Err(error) if options.vm => {
+            println!("{error}");
+            exit(1);
+        }
+        Err(error) => {
+            let message = format!("{} The evaluator runs the program instead.", error.message);
+            println!("{}", diagnostic::Diagnostic::warning(error.span, message));
+            let mut evaluator = evaluator::Evaluator::new();
+            evaluator.set_max_depth(max_depth.unwrap_or(evaluator::MAX_CALL_DEPTH));
+            evaluator.run(&program).map(|_| ())
+        }
+    };
+    report(result, filename);
+}
+
+fn report(result: evaluator::EvalResult<()>, filename: &str) {
+    if let Err(error) = result {
+        println!("{}", error.report(filename));
+        exit(1);
+    }
+}
+
+/* Compiles the source into a bytecode file, writing it and returning the bytecode. */
+fn build_file(filename: &str, source: &str) -> compiler::Bytecode {
+    let program = check_source(source);
+    let bytecode = compiler::compile(&program).unwrap_or_else(|error| {
+        println!("{error}");
+        exit(1);
+    });
+    let target = format!("{filename}c");
+    if fs::write(&target, cache::write(&bytecode, source)).is_err() {
+        println!("Error writing file: {target}");
+        exit(1);
+    }
+    bytecode
+}
+
+/* Runs a bytecode file, rebuilding it first when it is stale and its source is at
+ * hand. */
+fn run_bytecode_file(filename: &str, max_depth: Option<usize>) {
+    let source_name = &filename[..filename.len() - 1];
+    let source = fs::read_to_string(source_name).ok();
+    let loaded = fs::read(filename)
+        .map_err(|_| format!("Error reading file: {filename}"))
+        .and_then(|bytes| cache::read(&bytes, source.as_deref()));
+    let bytecode = match (loaded, source) {
+        (Ok(bytecode), _) => bytecode,
+        (Err(_), Some(source)) => build_file(source_name, &source),
+        (Err(error), None) => {
+            println!("{error}");
+            exit(1);
+        }
+    };
+    let mut vm = vm::Vm::new();
+    vm.set_max_depth(max_depth.unwrap_or(vm::MAX_CALL_DEPTH));
+    report(vm.run(&bytecode), source_name);
+}
+
+fn print_tokens(filename: &str) {
+    let contents = read_source(filename).chars().collect::<Vec<char>>();
+    let mut lexer = Lexer::new(contents);
+    let tokens = lexer.tokenize();
+    Lexer::print_tokens(tokens);
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    /* `--max-depth=N` limits the calls being executed at once; each backend has its
+     * own default. */
+    max_depth: Option<usize>,
+    /* `--vm` fails on a program the bytecode compiler does not support, rather than
+     * evaluating it. */
+    vm: bool,
+}
+
+/* Takes the options out of the arguments. */
+fn options(args: Vec<String>) -> (Vec<String>, Options) {
+    let mut options = Options::default();
+    let mut rest = vec![];
+    for arg in args {
+        if !arg.starts_with("--") {
+            rest.push(arg);
+            continue;
+        }
+        if arg == "--vm" {
+            options.vm = true;
+            continue;
+        }
+        match arg.strip_prefix("--max-depth=").map(str::parse) {
+            Some(Ok(depth)) if depth > 0 => options.max_depth = Some(depth),
+            Some(_) => {
+                println!("Invalid maximum call depth: {arg}");
+                exit(1);
+            }
+            None => {
+                println!("Unknown option: {arg}");
                 exit(1);
             }
-            let contents = fs::read_to_string(&filename).map_or_else(
-                |_| {
-                    println!("Error reading file: {}", &filename);
-                    exit(1);
-                },
-                |contents| contents.chars().collect::<Vec<char>>(),
-            );
-            let mut lexer = lexer::Lexer::new(contents);
-            let tokens = lexer.tokenize();
-            Lexer::print_tokens(tokens);
         }
+    }
+    (rest, options)
+}
+
+fn main() {
+    let (args, options) = options(env::args().collect());
+    /* The evaluator recurses on the host stack, so it runs on a thread with room for
+     * the deepest calls. */
+    let depth = options.max_depth.unwrap_or(evaluator::MAX_CALL_DEPTH);
+    let runner = thread::Builder::new()
+        .stack_size(evaluator::stack_size(depth))
+        .spawn(move || run(&args, &options))
+        .unwrap_or_else(|_| {
+            println!("Cannot make a stack for {depth} calls.");
+            exit(1);
+        });
+    if runner.join().is_err() {
+        exit(101);
+    }
+}
+
+fn run(args: &[String], options: &Options) {
+    let max_depth = options.max_depth;
+    match args {
+        [_] => {
+            repl::start(max_depth.unwrap_or(evaluator::MAX_CALL_DEPTH));
+        }
+        [_, command, filename] if command == "check" => {
+            check_file(filename);
+        }
+        [_, command, filename] if command == "build" => {
+            build_file(filename, &read_source(filename));
+        }
+        [_, command, filename] if command == "tokens" => print_tokens(filename),
+        [_, filename] if filename.ends_with(".toolc") => run_bytecode_file(filename, max_depth),
+        [_, filename] => run_file(filename, options),
         _ => {
             println!("Too many arguments. Shutting down.");
             exit(1);
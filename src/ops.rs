@@ -0,0 +1,756 @@
+#![allow(dead_code)]
+/* Arithmetic, bitwise and comparison operators on primitive values.
+ *
+ * Both operands must have the same type; the evaluator makes literals take the
+ * type of the other operand before calling into here. Integer arithmetic traps
+ * on overflow unless asked to wrap or saturate, division by zero always traps, and
+ * `//` and `%` round towards negative infinity. `..` on integers appends the
+ * decimal digits of the right operand to the left one. */
+
+use crate::ast::{BinaryOp, UnaryOp};
+use crate::types::{FloatKind, IntKind, Type};
+use crate::value::Value;
+use std::cmp::Ordering;
+
+pub type OpResult = Result<Value, String>;
+
+/* What integer arithmetic does with a result that does not fit its type. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Overflow {
+    Trap,
+    Wrap,
+    Saturate,
+}
+
+trait Int: Copy + PartialOrd {
+    fn floor_div(self, rhs: Self) -> Option<Self>;
+    fn floor_mod(self, rhs: Self) -> Option<Self>;
+    fn is_negative(self) -> bool;
+
+    /* `+`, `-`, `*`, `/`, `//` or `**`, `None` when trapping on overflow. The
+     * divisor must not be zero and the exponent must not be negative. */
+    fn arithmetic(self, op: BinaryOp, rhs: Self, overflow: Overflow) -> Option<Self>;
+    fn power(self, exp: u128, overflow: Overflow) -> Option<Self>;
+}
+
+/* The `Int` methods shared by the signed and unsigned widths. */
+macro_rules! int_arithmetic {
+    () => {
+        #[allow(clippy::unnecessary_cast)]
+        fn arithmetic(self, op: BinaryOp, rhs: Self, overflow: Overflow) -> Option<Self> {
+            match (op, overflow) {
+                (BinaryOp::Add, Overflow::Trap) => self.checked_add(rhs),
+                (BinaryOp::Add, Overflow::Wrap) => Some(self.wrapping_add(rhs)),
+                (BinaryOp::Add, Overflow::Saturate) => Some(self.saturating_add(rhs)),
+                (BinaryOp::Sub, Overflow::Trap) => self.checked_sub(rhs),
+                (BinaryOp::Sub, Overflow::Wrap) => Some(self.wrapping_sub(rhs)),
+                (BinaryOp::Sub, Overflow::Saturate) => Some(self.saturating_sub(rhs)),
+                (BinaryOp::Mul, Overflow::Trap) => self.checked_mul(rhs),
+                (BinaryOp::Mul, Overflow::Wrap) => Some(self.wrapping_mul(rhs)),
+                (BinaryOp::Mul, Overflow::Saturate) => Some(self.saturating_mul(rhs)),
+                (BinaryOp::Div, Overflow::Trap) => self.checked_div(rhs),
+                (BinaryOp::Div, Overflow::Wrap) => Some(self.wrapping_div(rhs)),
+                (BinaryOp::Div, Overflow::Saturate) => Some(self.saturating_div(rhs)),
+                /* Only `MIN // -1` overflows, and it divides exactly. */
+                (BinaryOp::FloorDiv, _) => self.floor_div(rhs).or_else(|| match overflow {
+                    Overflow::Trap => None,
+                    Overflow::Wrap => Some(self.wrapping_div(rhs)),
+                    Overflow::Saturate => Some(self.saturating_div(rhs)),
+                }),
+                (BinaryOp::Pow, _) => self.power(rhs as u128, overflow),
+                _ => None,
+            }
+        }
+
+        /* Exponentiation by squaring. A square overflowing is only an overflow of
+         * the result if it is multiplied in later. */
+        fn power(self, exp: u128, overflow: Overflow) -> Option<Self> {
+            let (mut result, mut base, mut rest): (Self, Self, u128) = (1, self, exp);
+            let (mut overflowed, mut base_overflowed) = (false, false);
+            while rest > 0 {
+                if rest & 1 == 1 {
+                    let (product, carry) = result.overflowing_mul(base);
+                    result = product;
+                    overflowed |= carry || base_overflowed;
+                }
+                rest >>= 1;
+                if rest > 0 {
+                    let (square, carry) = base.overflowing_mul(base);
+                    base = square;
+                    base_overflowed |= carry;
+                }
+            }
+            match overflow {
+                _ if !overflowed => Some(result),
+                Overflow::Trap => None,
+                Overflow::Wrap => Some(result),
+                Overflow::Saturate if self.is_negative() && exp & 1 == 1 => Some(Self::MIN),
+                Overflow::Saturate => Some(Self::MAX),
+            }
+        }
+    };
+}
+
+macro_rules! impl_signed_int {
+    ($($int:ty),*) => {$(
+        impl Int for $int {
+            fn floor_div(self, rhs: Self) -> Option<Self> {
+                let quotient = self.checked_div(rhs)?;
+                if self.wrapping_rem(rhs) != 0 && ((self < 0) != (rhs < 0)) {
+                    return Some(quotient - 1);
+                }
+                Some(quotient)
+            }
+
+            fn floor_mod(self, rhs: Self) -> Option<Self> {
+                if rhs == 0 {
+                    return None;
+                }
+                let rem = self.wrapping_rem(rhs);
+                if rem != 0 && ((rem < 0) != (rhs < 0)) {
+                    return Some(rem + rhs);
+                }
+                Some(rem)
+            }
+
+            fn is_negative(self) -> bool {
+                self < 0
+            }
+
+            int_arithmetic!();
+        }
+    )*};
+}
+
+macro_rules! impl_unsigned_int {
+    ($($int:ty),*) => {$(
+        impl Int for $int {
+            fn floor_div(self, rhs: Self) -> Option<Self> {
+                self.checked_div(rhs)
+            }
+
+            fn floor_mod(self, rhs: Self) -> Option<Self> {
+                self.checked_rem(rhs)
+            }
+
+            fn is_negative(self) -> bool {
+                false
+            }
+
+            int_arithmetic!();
+        }
+    )*};
+}
+
+impl_signed_int!(i8, i16, i32, i64, i128);
+impl_unsigned_int!(u8, u16, u32, u64, u128);
+
+/* Expands `$body` once per integer width with `$a` and `$b` bound to the operands
+ * and `$make` to the matching `Value` constructor. */
+macro_rules! int_pair {
+    ($lhs:expr, $rhs:expr, |$a:ident, $b:ident, $make:ident| $body:expr, $fallback:expr) => {
+        match ($lhs, $rhs) {
+            (&Value::UInt8($a), &Value::UInt8($b)) => {
+                let $make = Value::UInt8;
+                $body
+            }
+            (&Value::UInt16($a), &Value::UInt16($b)) => {
+                let $make = Value::UInt16;
+                $body
+            }
+            (&Value::UInt32($a), &Value::UInt32($b)) => {
+                let $make = Value::UInt32;
+                $body
+            }
+            (&Value::UInt64($a), &Value::UInt64($b)) => {
+                let $make = Value::UInt64;
+                $body
+            }
+            (&Value::UInt128($a), &Value::UInt128($b)) => {
+                let $make = Value::UInt128;
+                $body
+            }
+            (&Value::Int8($a), &Value::Int8($b)) => {
+                let $make = Value::Int8;
+                $body
+            }
+            (&Value::Int16($a), &Value::Int16($b)) => {
+                let $make = Value::Int16;
+                $body
+            }
+            (&Value::Int32($a), &Value::Int32($b)) => {
+                let $make = Value::Int32;
+                $body
+            }
+            (&Value::Int64($a), &Value::Int64($b)) => {
+                let $make = Value::Int64;
+                $body
+            }
+            (&Value::Int128($a), &Value::Int128($b)) => {
+                let $make = Value::Int128;
+                $body
+            }
+            _ => $fallback,
+        }
+    };
+}
+
+macro_rules! float_pair {
+    ($lhs:expr, $rhs:expr, |$a:ident, $b:ident, $make:ident| $body:expr, $fallback:expr) => {
+        match ($lhs, $rhs) {
+            (&Value::Flt32($a), &Value::Flt32($b)) => {
+                let $make = Value::Flt32;
+                $body
+            }
+            (&Value::Flt64($a), &Value::Flt64($b)) => {
+                let $make = Value::Flt64;
+                $body
+            }
+            _ => $fallback,
+        }
+    };
+}
+
+fn type_name(value: &Value) -> String {
+    if let Some(kind) = value.int_kind() {
+        return kind.name().to_owned();
+    }
+    if let Some(kind) = value.float_kind() {
+        return kind.name().to_owned();
+    }
+    match value {
+        Value::Nil => "nil",
+        Value::Bool(_) => "bool",
+        Value::Char(_) => "char",
+        Value::Str(_) => "string",
+        _ => "object",
+    }
+    .to_owned()
+}
+
+fn mismatch(op: BinaryOp, lhs: &Value, rhs: &Value) -> String {
+    format!(
+        "Operator `{}` cannot be applied to {} and {}.",
+        op.symbol(),
+        type_name(lhs),
+        type_name(rhs)
+    )
+}
+
+fn overflow_error(op: BinaryOp, lhs: &Value) -> String {
+    format!(
+        "Integer overflow: `{}` on {} operands.",
+        op.symbol(),
+        type_name(lhs)
+    )
+}
+
+pub fn binary(op: BinaryOp, lhs: &Value, rhs: &Value) -> OpResult {
+    match op {
+        BinaryOp::Add
+        | BinaryOp::Sub
+        | BinaryOp::Mul
+        | BinaryOp::Div
+        | BinaryOp::FloorDiv
+        | BinaryOp::Pow => arithmetic(op, lhs, rhs, Overflow::Trap),
+        BinaryOp::Mod => int_pair!(
+            lhs,
+            rhs,
+            |a, b, make| a
+                .floor_mod(b)
+                .map(make)
+                .ok_or_else(|| "Division by zero.".to_owned()),
+            float_pair!(
+                lhs,
+                rhs,
+                |a, b, make| Ok(make(a - (a / b).floor() * b)),
+                Err(mismatch(op, lhs, rhs))
+            )
+        ),
+        BinaryOp::Concat => concat_digits(lhs, rhs),
+        BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor => bitwise(op, lhs, rhs),
+        BinaryOp::Shl | BinaryOp::Shr => shift(op, lhs, rhs),
+        BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq => {
+            let ordering = compare(lhs, rhs).ok_or_else(|| mismatch(op, lhs, rhs))?;
+            Ok(Value::Bool(match op {
+                BinaryOp::Lt => ordering == Ordering::Less,
+                BinaryOp::LtEq => ordering != Ordering::Greater,
+                BinaryOp::Gt => ordering == Ordering::Greater,
+                _ => ordering != Ordering::Less,
+            }))
+        }
+        _ => Err(mismatch(op, lhs, rhs)),
+    }
+}
+
+/* `+`, `-`, `*`, `/`, `//` or `**`, with integer overflow handled as `overflow` says. */
+pub fn arithmetic(op: BinaryOp, lhs: &Value, rhs: &Value, overflow: Overflow) -> OpResult {
+    int_pair!(
+        lhs,
+        rhs,
+        |a, b, make| {
+            if b == 0 && matches!(op, BinaryOp::Div | BinaryOp::FloorDiv) {
+                Err("Division by zero.".to_owned())
+            } else if b.is_negative() && op == BinaryOp::Pow {
+                Err("Negative exponent on an integer `**`.".to_owned())
+            } else {
+                a.arithmetic(op, b, overflow)
+                    .map(make)
+                    .ok_or_else(|| overflow_error(op, lhs))
+            }
+        },
+        float_pair!(
+            lhs,
+            rhs,
+            |a, b, make| Ok(make(match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Sub => a - b,
+                BinaryOp::Mul => a * b,
+                BinaryOp::Div => a / b,
+                BinaryOp::FloorDiv => (a / b).floor(),
+                _ => a.powf(b),
+            })),
+            Err(mismatch(op, lhs, rhs))
+        )
+    )
+}
+
+/* `12 .. 34` is `1234`, and `-1 .. 2` is `-12`: the result takes the sign of the
+ * left operand, and the right one must not be negative. */
+fn concat_digits(lhs: &Value, rhs: &Value) -> OpResult {
+    let op = BinaryOp::Concat;
+    let (Some(kind), Some((negative, magnitude)), Some((rhs_negative, digits))) =
+        (lhs.int_kind(), lhs.int_parts(), rhs.int_parts())
+    else {
+        return Err(mismatch(op, lhs, rhs));
+    };
+    if rhs.int_kind() != Some(kind) {
+        return Err(mismatch(op, lhs, rhs));
+    }
+    if rhs_negative {
+        return Err(format!(
+            "Cannot append the digits of the negative -{digits} with `..`."
+        ));
+    }
+    let len = digits.checked_ilog10().map_or(1, |log| log + 1);
+    10u128
+        .checked_pow(len)
+        .and_then(|scale| magnitude.checked_mul(scale))
+        .and_then(|shifted| shifted.checked_add(digits))
+        .and_then(|magnitude| Value::from_int_parts(kind, negative, magnitude))
+        .ok_or_else(|| overflow_error(op, lhs))
+}
+
+fn bitwise(op: BinaryOp, lhs: &Value, rhs: &Value) -> OpResult {
+    if let (Value::Bool(a), Value::Bool(b)) = (lhs, rhs) {
+        return Ok(Value::Bool(match op {
+            BinaryOp::BitAnd => a & b,
+            BinaryOp::BitOr => a | b,
+            _ => a ^ b,
+        }));
+    }
+    int_pair!(
+        lhs,
+        rhs,
+        |a, b, make| Ok(make(match op {
+            BinaryOp::BitAnd => a & b,
+            BinaryOp::BitOr => a | b,
+            _ => a ^ b,
+        })),
+        Err(mismatch(op, lhs, rhs))
+    )
+}
+
+/* The shift amount may be any integer type but must be less than the bit width. */
+fn shift(op: BinaryOp, lhs: &Value, rhs: &Value) -> OpResult {
+    let (Some(kind), Some((negative, amount))) = (lhs.int_kind(), rhs.int_parts()) else {
+        return Err(mismatch(op, lhs, rhs));
+    };
+    if negative || amount >= u128::from(kind.bits()) {
+        return Err(format!(
+            "Shift amount {}{amount} is out of range for {}.",
+            if negative { "-" } else { "" },
+            kind.name()
+        ));
+    }
+    let amount = amount as u32;
+    int_pair!(
+        lhs,
+        lhs,
+        |a, _b, make| Ok(make(if op == BinaryOp::Shl {
+            a << amount
+        } else {
+            a >> amount
+        })),
+        Err(mismatch(op, lhs, rhs))
+    )
+}
+
+/* Ordering between two values of the same primitive type. */
+pub fn compare(lhs: &Value, rhs: &Value) -> Option<Ordering> {
+    if lhs.int_kind().is_some() && lhs.int_kind() == rhs.int_kind() {
+        let (a, b) = (lhs.int_parts()?, rhs.int_parts()?);
+        return Some(match (a.0, b.0) {
+            (false, false) => a.1.cmp(&b.1),
+            (true, true) => b.1.cmp(&a.1),
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+        });
+    }
+    match (lhs, rhs) {
+        (Value::Flt32(a), Value::Flt32(b)) => a.partial_cmp(b),
+        (Value::Flt64(a), Value::Flt64(b)) => a.partial_cmp(b),
+        (Value::Char(a), Value::Char(b)) => Some(a.cmp(b)),
+        (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+pub fn unary(op: UnaryOp, value: &Value) -> OpResult {
+    let invalid = || {
+        format!(
+            "Operator `{}` cannot be applied to {}.",
+            op.symbol(),
+            type_name(value)
+        )
+    };
+    match (op, value) {
+        (UnaryOp::Not, Value::Bool(value)) => Ok(Value::Bool(!value)),
+        (UnaryOp::Neg, Value::Flt32(float)) => Ok(Value::Flt32(-float)),
+        (UnaryOp::Neg, Value::Flt64(float)) => Ok(Value::Flt64(-float)),
+        (UnaryOp::Neg, _) => match value.int_kind() {
+            Some(kind) if kind.is_signed() => int_pair!(
+                value,
+                value,
+                |a, _b, make| a
+                    .checked_neg()
+                    .map(make)
+                    .ok_or_else(|| format!("Integer overflow: `-` on {}.", kind.name())),
+                Err(invalid())
+            ),
+            _ => Err(invalid()),
+        },
+        (UnaryOp::BitNot, _) => int_pair!(value, value, |a, _b, make| Ok(make(!a)), Err(invalid())),
+        _ => Err(invalid()),
+    }
+}
+
+/* `value` converted to the wider numeric type `ty`, if it implicitly converts. */
+pub fn widen(value: &Value, ty: &Type) -> Option<Value> {
+    if !value.numeric_type()?.widens_to(ty) {
+        return None;
+    }
+    cast(value, ty).ok()
+}
+
+/* The operands of `op` with the narrower number widened to the type of the other,
+ * or `None` if they need no widening. The amount of a shift is left alone. */
+pub fn widen_operands(op: BinaryOp, lhs: &Value, rhs: &Value) -> Option<(Value, Value)> {
+    if matches!(op, BinaryOp::Shl | BinaryOp::Shr) {
+        return None;
+    }
+    if let Some(lhs) = widen(lhs, &rhs.numeric_type()?) {
+        return Some((lhs, rhs.clone()));
+    }
+    widen(rhs, &lhs.numeric_type()?).map(|rhs| (lhs.clone(), rhs))
+}
+
+/* `int32(x)` and friends. Integers, `bool`s and `char`s convert to integers exactly
+ * or not at all, and floats are truncated towards zero first; NaN and values out of
+ * range are errors. Conversions to floats round to the nearest value, and
+ * `char(n)` needs a Unicode scalar value. */
+pub fn cast(value: &Value, ty: &Type) -> OpResult {
+    let invalid = || format!("Cannot convert {} to {ty}.", type_name(value));
+    match ty {
+        Type::Int(kind) => {
+            let (negative, magnitude) = match value {
+                Value::Bool(value) => (false, u128::from(*value)),
+                Value::Char(ch) => (false, u128::from(u32::from(*ch))),
+                Value::Flt32(float) => float_parts(f64::from(*float), *kind)?,
+                Value::Flt64(float) => float_parts(*float, *kind)?,
+                _ => value.int_parts().ok_or_else(invalid)?,
+            };
+            Value::from_int_parts(*kind, negative, magnitude).ok_or_else(|| {
+                let sign = if negative { "-" } else { "" };
+                format!("{sign}{magnitude} does not fit in {}.", kind.name())
+            })
+        }
+        Type::Float(kind) => {
+            let float = match (value, kind) {
+                (Value::Flt32(float), FloatKind::F32) => return Ok(Value::Flt32(*float)),
+                (Value::Flt32(float), FloatKind::F64) => f64::from(*float),
+                (Value::Flt64(float), _) => *float,
+                /* Straight to the target width, so that nothing is rounded twice. */
+                (_, FloatKind::F32) => {
+                    let (negative, magnitude) = value.int_parts().ok_or_else(invalid)?;
+                    let float = magnitude as f32;
+                    return Ok(Value::Flt32(if negative { -float } else { float }));
+                }
+                _ => {
+                    let (negative, magnitude) = value.int_parts().ok_or_else(invalid)?;
+                    let float = magnitude as f64;
+                    if negative {
+                        -float
+                    } else {
+                        float
+                    }
+                }
+            };
+            Ok(match kind {
+                FloatKind::F32 => Value::Flt32(float as f32),
+                FloatKind::F64 => Value::Flt64(float),
+            })
+        }
+        Type::Char => match value {
+            Value::Char(ch) => Ok(Value::Char(*ch)),
+            _ => {
+                let (negative, magnitude) = value
+                    .int_parts()
+                    .filter(|_| value.int_kind().is_some())
+                    .ok_or_else(invalid)?;
+                u32::try_from(magnitude)
+                    .ok()
+                    .filter(|_| !negative)
+                    .and_then(char::from_u32)
+                    .map(Value::Char)
+                    .ok_or_else(|| {
+                        let sign = if negative { "-" } else { "" };
+                        format!("{sign}{magnitude} is not a valid char.")
+                    })
+            }
+        },
+        Type::Bool if *value == Value::Bool(true) || *value == Value::Bool(false) => {
+            Ok(value.clone())
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/* The sign and magnitude of `float` truncated towards zero. */
+fn float_parts(float: f64, kind: IntKind) -> Result<(bool, u128), String> {
+    if float.is_nan() {
+        return Err(format!("Cannot convert NaN to {}.", kind.name()));
+    }
+    let truncated = float.trunc();
+    /* 2^128: every larger magnitude is out of range of every integer type. */
+    if truncated.abs() >= 340_282_366_920_938_463_463_374_607_431_768_211_456.0 {
+        return Err(format!("{float} does not fit in {}.", kind.name()));
+    }
+    Ok((truncated < 0.0, truncated.abs() as u128))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checked_arithmetic() {
+        assert_eq!(
+            binary(BinaryOp::Add, &Value::Int32(40), &Value::Int32(5)),
+            Ok(Value::Int32(45))
+        );
+        assert!(binary(BinaryOp::Add, &Value::UInt8(255), &Value::UInt8(1)).is_err());
+        assert!(binary(BinaryOp::Add, &Value::Int32(1), &Value::Int64(1)).is_err());
+    }
+
+    #[test]
+    fn test_floored_division_and_modulo() {
+        assert_eq!(
+            binary(BinaryOp::FloorDiv, &Value::Int32(-7), &Value::Int32(2)),
+            Ok(Value::Int32(-4))
+        );
+        assert_eq!(
+            binary(BinaryOp::Mod, &Value::Int32(-7), &Value::Int32(4)),
+            Ok(Value::Int32(1))
+        );
+        assert_eq!(
+            binary(BinaryOp::Div, &Value::Int32(-7), &Value::Int32(2)),
+            Ok(Value::Int32(-3))
+        );
+        assert_eq!(
+            binary(BinaryOp::Mod, &Value::Flt64(-1.5), &Value::Flt64(1.0)),
+            Ok(Value::Flt64(0.5))
+        );
+        assert!(binary(BinaryOp::Mod, &Value::Int8(1), &Value::Int8(0)).is_err());
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert_eq!(
+            binary(BinaryOp::Lt, &Value::Int8(-3), &Value::Int8(2)),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            binary(
+                BinaryOp::GtEq,
+                &Value::Str("b".into()),
+                &Value::Str("a".into())
+            ),
+            Ok(Value::Bool(true))
+        );
+    }
+
+    /* Overflow at the edges of each width, in each of the three modes. */
+    macro_rules! check_boundaries {
+        ($($make:path => $int:ty),*) => {$({
+            let (min, max) = (<$int>::MIN, <$int>::MAX);
+            let op = |op, a: $int, b: $int, overflow| arithmetic(op, &$make(a), &$make(b), overflow);
+            assert!(op(BinaryOp::Add, max, 1, Overflow::Trap).is_err());
+            assert_eq!(op(BinaryOp::Add, max, 1, Overflow::Wrap), Ok($make(min)));
+            assert_eq!(op(BinaryOp::Add, max, 1, Overflow::Saturate), Ok($make(max)));
+            assert!(op(BinaryOp::Sub, min, 1, Overflow::Trap).is_err());
+            assert_eq!(op(BinaryOp::Sub, min, 1, Overflow::Wrap), Ok($make(max)));
+            assert_eq!(op(BinaryOp::Sub, min, 1, Overflow::Saturate), Ok($make(min)));
+            assert!(op(BinaryOp::Mul, max, 2, Overflow::Trap).is_err());
+            assert_eq!(op(BinaryOp::Mul, max, 2, Overflow::Wrap), Ok($make(max.wrapping_mul(2))));
+            assert_eq!(op(BinaryOp::Mul, max, 2, Overflow::Saturate), Ok($make(max)));
+            let bits = <$int>::BITS as $int;
+            assert!(op(BinaryOp::Pow, 2, bits, Overflow::Trap).is_err());
+            assert_eq!(op(BinaryOp::Pow, 2, bits, Overflow::Wrap), Ok($make(0)));
+            assert_eq!(op(BinaryOp::Pow, 2, bits, Overflow::Saturate), Ok($make(max)));
+            assert_eq!(op(BinaryOp::Pow, 2, bits - 2, Overflow::Trap), Ok($make(1 << (bits - 2))));
+            for overflow in [Overflow::Trap, Overflow::Wrap, Overflow::Saturate] {
+                assert_eq!(op(BinaryOp::Add, max - 1, 1, overflow), Ok($make(max)));
+                assert_eq!(
+                    op(BinaryOp::FloorDiv, max, 0, overflow),
+                    Err("Division by zero.".to_owned())
+                );
+            }
+        })*};
+    }
+
+    #[test]
+    fn test_overflow_at_boundaries() {
+        check_boundaries!(
+            Value::UInt8 => u8, Value::UInt16 => u16, Value::UInt32 => u32,
+            Value::UInt64 => u64, Value::UInt128 => u128, Value::Int8 => i8,
+            Value::Int16 => i16, Value::Int32 => i32, Value::Int64 => i64,
+            Value::Int128 => i128
+        );
+    }
+
+    #[test]
+    fn test_signed_division_overflow() {
+        let (min, neg_one) = (Value::Int128(i128::MIN), Value::Int128(-1));
+        for op in [BinaryOp::Div, BinaryOp::FloorDiv] {
+            assert_eq!(
+                binary(op, &min, &neg_one),
+                Err(format!(
+                    "Integer overflow: `{}` on int128 operands.",
+                    op.symbol()
+                ))
+            );
+            assert_eq!(
+                arithmetic(op, &min, &neg_one, Overflow::Wrap),
+                Ok(min.clone())
+            );
+            assert_eq!(
+                arithmetic(op, &min, &neg_one, Overflow::Saturate),
+                Ok(Value::Int128(i128::MAX))
+            );
+        }
+        assert_eq!(binary(BinaryOp::Mod, &min, &neg_one), Ok(Value::Int128(0)));
+        assert!(binary(BinaryOp::FloorDiv, &Value::Int8(i8::MIN), &Value::Int8(-1)).is_err());
+        assert_eq!(
+            arithmetic(
+                BinaryOp::Pow,
+                &Value::Int8(-2),
+                &Value::Int8(9),
+                Overflow::Saturate
+            ),
+            Ok(Value::Int8(i8::MIN))
+        );
+        assert_eq!(
+            binary(BinaryOp::Pow, &Value::Int8(-2), &Value::Int8(7)),
+            Ok(Value::Int8(i8::MIN))
+        );
+    }
+
+    #[test]
+    fn test_casts() {
+        let int8 = Type::Int(IntKind::I8);
+        assert_eq!(cast(&Value::Flt64(-3.99), &int8), Ok(Value::Int8(-3)));
+        assert_eq!(cast(&Value::Int32(-128), &int8), Ok(Value::Int8(-128)));
+        assert_eq!(
+            cast(&Value::Int32(200), &int8),
+            Err("200 does not fit in int8.".to_owned())
+        );
+        assert_eq!(
+            cast(&Value::Flt32(f32::NAN), &int8),
+            Err("Cannot convert NaN to int8.".to_owned())
+        );
+        assert!(cast(&Value::Flt64(f64::INFINITY), &Type::Int(IntKind::U128)).is_err());
+        assert_eq!(
+            cast(&Value::UInt128(u128::MAX), &Type::Float(FloatKind::F32)),
+            Ok(Value::Flt32(u128::MAX as f32))
+        );
+        assert_eq!(cast(&Value::Int32(97), &Type::Char), Ok(Value::Char('a')));
+        assert_eq!(
+            cast(&Value::UInt32(0xD800), &Type::Char),
+            Err("55296 is not a valid char.".to_owned())
+        );
+        assert_eq!(
+            cast(&Value::Char('a'), &Type::Int(IntKind::U8)),
+            Ok(Value::UInt8(97))
+        );
+        assert!(cast(&Value::Char('a'), &Type::Float(FloatKind::F64)).is_err());
+    }
+
+    #[test]
+    fn test_widening() {
+        let int64 = Type::Int(IntKind::I64);
+        assert_eq!(widen(&Value::Int16(-5), &int64), Some(Value::Int64(-5)));
+        assert_eq!(widen(&Value::UInt32(5), &int64), Some(Value::Int64(5)));
+        assert_eq!(widen(&Value::UInt64(5), &int64), None);
+        assert_eq!(widen(&Value::Int8(-1), &Type::Int(IntKind::U64)), None);
+        assert_eq!(
+            widen(&Value::Int16(3), &Type::Float(FloatKind::F32)),
+            Some(Value::Flt32(3.0))
+        );
+        assert_eq!(widen(&Value::Int32(3), &Type::Float(FloatKind::F32)), None);
+        assert_eq!(
+            widen_operands(BinaryOp::Add, &Value::Int64(1), &Value::Int8(2)),
+            Some((Value::Int64(1), Value::Int64(2)))
+        );
+        assert_eq!(
+            widen_operands(BinaryOp::Shl, &Value::Int64(1), &Value::Int8(2)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_concat_digits() {
+        let concat = |lhs, rhs| binary(BinaryOp::Concat, &lhs, &rhs);
+        assert_eq!(
+            concat(Value::Int32(1), Value::Int32(2)),
+            Ok(Value::Int32(12))
+        );
+        assert_eq!(
+            concat(Value::Int32(-1), Value::Int32(20)),
+            Ok(Value::Int32(-120))
+        );
+        assert_eq!(
+            concat(Value::Int32(5), Value::Int32(0)),
+            Ok(Value::Int32(50))
+        );
+        assert_eq!(
+            concat(Value::UInt8(25), Value::UInt8(5)),
+            Ok(Value::UInt8(255))
+        );
+        assert_eq!(
+            concat(Value::UInt8(25), Value::UInt8(6)),
+            Err("Integer overflow: `..` on uint8 operands.".to_owned())
+        );
+        assert_eq!(
+            concat(Value::Int8(-12), Value::Int8(8)),
+            Ok(Value::Int8(-128))
+        );
+        assert!(concat(Value::UInt128(u128::MAX / 10), Value::UInt128(9)).is_err());
+        assert_eq!(
+            concat(Value::Int32(1), Value::Int32(-2)),
+            Err("Cannot append the digits of the negative -2 with `..`.".to_owned())
+        );
+        assert!(concat(Value::Int32(1), Value::Int64(2)).is_err());
+    }
+}
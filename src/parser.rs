@@ -1,14 +1,1568 @@
-#![allow(unused_imports, dead_code)]
-use crate::ast;
-use crate::lexer::{self, Lexer};
-use crate::token::{self, Token};
+#![allow(dead_code)]
+use crate::ast::{
+    BinaryOp, Block, EnumDecl, Expr, ExprKind, FieldDecl, FuncDecl, Given, Param, Pattern,
+    PatternKind, Program, Stmt, StmtKind, Storage, StructDecl, TypeAlias, UnaryOp, VarDecl,
+    Variant, WhenArm,
+};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Lexer;
+use crate::token::{KeywordKind, Span, SymbolKind, Token, TokenType, KEYWORDS, SYMBOLS};
+use crate::types::Type;
+use std::cell::Cell;
+use std::rc::Rc;
+
+type ParseResult<T> = Result<T, Diagnostic>;
 
 pub struct Parser {
     input: Vec<Token>,
+    pos: usize,
+    errors: Vec<Diagnostic>,
+    /* Positions where a `>>` was split into two `>` while closing type arguments. */
+    splits: Vec<usize>,
+    /* Per enclosing function: whether its body has used `self` so far. */
+    functions: Vec<bool>,
+    /* The types `this` stands for in the structs being declared, innermost last. */
+    structs: Vec<Type>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Checkpoint {
+    pos: usize,
+    errors: usize,
+    splits: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Infix {
+    Binary(BinaryOp),
+    /* `to`, or `through` when inclusive. */
+    Range(bool),
+}
+
+pub fn parse(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    let mut lexer = Lexer::new(source.chars().collect::<Vec<char>>());
+    let tokens = lexer.tokenize();
+    Parser::new(tokens).parse_program()
+}
+
+fn keyword_name(keyword: KeywordKind) -> &'static str {
+    KEYWORDS[keyword as usize]
+}
+
+fn describe(token: &Token) -> String {
+    match token.Type {
+        TokenType::NewLine => "newline".to_owned(),
+        TokenType::Eof => "end of file".to_owned(),
+        _ => format!("`{}`", token.value),
+    }
+}
+
+/* An anonymous function stored in a variable is named after it, in error messages
+ * and stack traces. */
+fn name_after(value: &mut Expr, name: &str) {
+    if let ExprKind::Func(decl) = &mut value.kind {
+        if decl.name.is_none() {
+            Rc::make_mut(decl).name = Some(name.to_owned());
+        }
+    }
+}
+
+const fn assign_op(symbol: SymbolKind) -> Option<Option<BinaryOp>> {
+    match symbol {
+        SymbolKind::Assign => Some(None),
+        SymbolKind::PlusAssign => Some(Some(BinaryOp::Add)),
+        SymbolKind::MinusAssign => Some(Some(BinaryOp::Sub)),
+        SymbolKind::MultiplyAssign => Some(Some(BinaryOp::Mul)),
+        SymbolKind::DivideAssign => Some(Some(BinaryOp::Div)),
+        SymbolKind::FloorDivideAssign => Some(Some(BinaryOp::FloorDiv)),
+        SymbolKind::ModuloAssign => Some(Some(BinaryOp::Mod)),
+        SymbolKind::ExponentAssign => Some(Some(BinaryOp::Pow)),
+        SymbolKind::ConcatAssign => Some(Some(BinaryOp::Concat)),
+        SymbolKind::BitAndAssign => Some(Some(BinaryOp::BitAnd)),
+        SymbolKind::BitOrAssign => Some(Some(BinaryOp::BitOr)),
+        SymbolKind::BitXorAssign => Some(Some(BinaryOp::BitXor)),
+        SymbolKind::BitshiftLeftAssign => Some(Some(BinaryOp::Shl)),
+        SymbolKind::BitshiftRightAssign => Some(Some(BinaryOp::Shr)),
+        SymbolKind::NilCoalesceAssign => Some(Some(BinaryOp::NilCoalesce)),
+        _ => None,
+    }
+}
+
+/* Binding powers: left-associative operators bind their right side one level tighter. */
+fn infix_op(token: &Token) -> Option<(Infix, u8, u8)> {
+    let (op, left, right) = match &token.Type {
+        TokenType::Symbol(symbol) => match symbol {
+            SymbolKind::NilCoalesce => (BinaryOp::NilCoalesce, 1, 1),
+            SymbolKind::Equals => (BinaryOp::Eq, 5, 6),
+            SymbolKind::BoolNotEQ => (BinaryOp::NotEq, 5, 6),
+            SymbolKind::LessThan => (BinaryOp::Lt, 5, 6),
+            SymbolKind::LessThanEquals => (BinaryOp::LtEq, 5, 6),
+            SymbolKind::GreaterThan => (BinaryOp::Gt, 5, 6),
+            SymbolKind::GreaterThanEquals => (BinaryOp::GtEq, 5, 6),
+            SymbolKind::BitOr => (BinaryOp::BitOr, 7, 8),
+            SymbolKind::BitXor => (BinaryOp::BitXor, 8, 9),
+            SymbolKind::BitAnd => (BinaryOp::BitAnd, 9, 10),
+            SymbolKind::BitshiftLeft => (BinaryOp::Shl, 10, 11),
+            SymbolKind::BitshiftRight => (BinaryOp::Shr, 10, 11),
+            SymbolKind::Concat => (BinaryOp::Concat, 11, 11),
+            SymbolKind::Plus => (BinaryOp::Add, 12, 13),
+            SymbolKind::Minus => (BinaryOp::Sub, 12, 13),
+            SymbolKind::Multiply => (BinaryOp::Mul, 13, 14),
+            SymbolKind::Divide => (BinaryOp::Div, 13, 14),
+            SymbolKind::FloorDivide => (BinaryOp::FloorDiv, 13, 14),
+            SymbolKind::Modulo => (BinaryOp::Mod, 13, 14),
+            _ => return None,
+        },
+        TokenType::Keyword(keyword) => match keyword {
+            KeywordKind::Or => (BinaryOp::Or, 2, 3),
+            KeywordKind::Xor => (BinaryOp::Xor, 3, 4),
+            KeywordKind::And => (BinaryOp::And, 4, 5),
+            KeywordKind::In => (BinaryOp::In, 5, 6),
+            _ => return None,
+        },
+        TokenType::Identifier(name) if name == "to" => return Some((Infix::Range(false), 6, 7)),
+        TokenType::Identifier(name) if name == "through" => {
+            return Some((Infix::Range(true), 6, 7))
+        }
+        _ => return None,
+    };
+    Some((Infix::Binary(op), left, right))
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { input: tokens }
+        let mut input = tokens;
+        if input.last().is_none_or(|tok| tok.Type != TokenType::Eof) {
+            let mut eof = Token::new(TokenType::Eof, "\x00".to_owned());
+            eof.span = input.last().map(|tok| tok.span).unwrap_or_default();
+            input.push(eof);
+        }
+        Self {
+            input,
+            pos: 0,
+            errors: vec![],
+            splits: vec![],
+            functions: vec![],
+            structs: vec![],
+        }
+    }
+
+    pub fn parse_program(&mut self) -> Result<Program, Vec<Diagnostic>> {
+        let mut stmts = vec![];
+        loop {
+            self.skip_separators();
+            if self.at_eof() {
+                break;
+            }
+            match self.parse_statement().and_then(|stmt| {
+                self.end_statement()?;
+                Ok(stmt)
+            }) {
+                Ok(stmt) => stmts.push(stmt),
+                Err(err) => {
+                    self.errors.push(err);
+                    self.synchronize();
+                }
+            }
+        }
+        if self.errors.is_empty() {
+            Ok(Program { stmts })
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    /* Token helpers */
+
+    fn peek(&self) -> &Token {
+        &self.input[self.pos.min(self.input.len() - 1)]
+    }
+
+    fn peek_nth(&self, n: usize) -> &Token {
+        &self.input[(self.pos + n).min(self.input.len() - 1)]
+    }
+
+    fn span(&self) -> Span {
+        self.peek().span
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.peek().clone();
+        if self.pos < self.input.len() - 1 {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn at_eof(&self) -> bool {
+        self.peek().Type == TokenType::Eof
+    }
+
+    fn check_symbol(&self, symbol: SymbolKind) -> bool {
+        self.peek().Type == TokenType::Symbol(symbol)
+    }
+
+    fn check_keyword(&self, keyword: KeywordKind) -> bool {
+        self.peek().Type == TokenType::Keyword(keyword)
+    }
+
+    fn check_ident(&self) -> bool {
+        matches!(self.peek().Type, TokenType::Identifier(_))
+    }
+
+    fn check_contextual(&self, word: &str) -> bool {
+        matches!(&self.peek().Type, TokenType::Identifier(name) if name == word)
+    }
+
+    fn eat_symbol(&mut self, symbol: SymbolKind) -> bool {
+        if self.check_symbol(symbol) {
+            self.advance();
+            return true;
+        }
+        false
+    }
+
+    fn eat_keyword(&mut self, keyword: KeywordKind) -> bool {
+        if self.check_keyword(keyword) {
+            self.advance();
+            return true;
+        }
+        false
+    }
+
+    fn error_here(&self, expected: &str) -> Diagnostic {
+        Diagnostic::error(
+            self.span(),
+            format!("Expected {expected}, found {}.", describe(self.peek())),
+        )
+    }
+
+    fn expect_symbol(&mut self, symbol: SymbolKind) -> ParseResult<Span> {
+        if self.check_symbol(symbol) {
+            return Ok(self.advance().span);
+        }
+        Err(self.error_here(&format!("`{}`", SYMBOLS[symbol as usize])))
+    }
+
+    fn expect_keyword(&mut self, keyword: KeywordKind) -> ParseResult<Span> {
+        if self.check_keyword(keyword) {
+            return Ok(self.advance().span);
+        }
+        Err(self.error_here(&format!("`{}`", keyword_name(keyword))))
+    }
+
+    fn expect_ident(&mut self) -> ParseResult<(String, Span)> {
+        if let TokenType::Identifier(name) = &self.peek().Type {
+            let name = name.clone();
+            return Ok((name, self.advance().span));
+        }
+        Err(self.error_here("an identifier"))
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek().Type == TokenType::NewLine {
+            self.advance();
+        }
+    }
+
+    fn skip_separators(&mut self) {
+        while matches!(
+            self.peek().Type,
+            TokenType::NewLine | TokenType::Symbol(SymbolKind::Semicolon)
+        ) {
+            self.advance();
+        }
+    }
+
+    fn synchronize(&mut self) {
+        loop {
+            match self.peek().Type {
+                TokenType::Eof => return,
+                TokenType::NewLine | TokenType::Symbol(SymbolKind::Semicolon) => {
+                    self.advance();
+                    return;
+                }
+                _ => {
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            pos: self.pos,
+            errors: self.errors.len(),
+            splits: self.splits.len(),
+        }
+    }
+
+    fn restore(&mut self, checkpoint: Checkpoint) {
+        while self.splits.len() > checkpoint.splits {
+            let at = self.splits.pop().unwrap_or_default();
+            self.input.remove(at + 1);
+            self.input[at].new_type(TokenType::Symbol(SymbolKind::BitshiftRight));
+            self.input[at].value = ">>".to_owned();
+        }
+        self.pos = checkpoint.pos;
+        self.errors.truncate(checkpoint.errors);
+    }
+
+    fn at_statement_end(&self) -> bool {
+        matches!(
+            self.peek().Type,
+            TokenType::NewLine
+                | TokenType::Eof
+                | TokenType::Symbol(SymbolKind::Semicolon)
+                | TokenType::Keyword(
+                    KeywordKind::End | KeywordKind::Else | KeywordKind::ElseIf | KeywordKind::When
+                )
+        )
+    }
+
+    fn end_statement(&mut self) -> ParseResult<()> {
+        match self.peek().Type {
+            TokenType::NewLine | TokenType::Symbol(SymbolKind::Semicolon) => {
+                self.advance();
+                Ok(())
+            }
+            _ if self.at_statement_end() => Ok(()),
+            _ => Err(self.error_here("a newline after the statement")),
+        }
+    }
+
+    /* Types */
+
+    fn is_type_keyword(keyword: KeywordKind) -> bool {
+        Type::from_keyword(keyword).is_some()
+            || matches!(
+                keyword,
+                KeywordKind::Array
+                    | KeywordKind::List
+                    | KeywordKind::Set
+                    | KeywordKind::Table
+                    | KeywordKind::Coroutine
+            )
+    }
+
+    /* A type, made optional by a trailing `?` as in `int32?`. */
+    fn parse_type(&mut self) -> ParseResult<Type> {
+        let ty = self.parse_plain_type()?;
+        if self.eat_symbol(SymbolKind::Ternary) {
+            return Ok(ty.optional());
+        }
+        Ok(ty)
+    }
+
+    fn parse_plain_type(&mut self) -> ParseResult<Type> {
+        let token = self.peek().clone();
+        match token.Type {
+            TokenType::Keyword(KeywordKind::This) if !self.structs.is_empty() => {
+                self.advance();
+                Ok(self.structs.last().cloned().unwrap_or(Type::Unknown))
+            }
+            TokenType::Keyword(keyword) => {
+                if let Some(ty) = Type::from_keyword(keyword) {
+                    self.advance();
+                    return Ok(ty);
+                }
+                let arity = match keyword {
+                    KeywordKind::Array
+                    | KeywordKind::List
+                    | KeywordKind::Set
+                    | KeywordKind::Coroutine => 1,
+                    KeywordKind::Table => 2,
+                    _ => return Err(self.error_here("a type")),
+                };
+                self.advance();
+                let mut args = self.parse_type_args()?;
+                if args.is_empty() {
+                    args = vec![Type::Unknown; arity];
+                } else if args.len() != arity {
+                    return Err(Diagnostic::error(
+                        token.span,
+                        format!(
+                            "Type `{}` takes {arity} type argument(s), found {}.",
+                            token.value,
+                            args.len()
+                        ),
+                    ));
+                }
+                let mut args = args.into_iter().map(Box::new);
+                let mut next_arg = || args.next().unwrap_or(Box::new(Type::Unknown));
+                Ok(match keyword {
+                    KeywordKind::Array => Type::Array(next_arg()),
+                    KeywordKind::List => Type::List(next_arg()),
+                    KeywordKind::Set => Type::Set(next_arg()),
+                    KeywordKind::Coroutine => Type::Coroutine(next_arg()),
+                    _ => Type::Table(next_arg(), next_arg()),
+                })
+            }
+            TokenType::Identifier(name) => {
+                self.advance();
+                let args = self.parse_type_args()?;
+                /* `range` is only a type name when given its item type. */
+                if name == "range" && args.len() == 1 {
+                    return Ok(Type::Range(Box::new(args[0].clone())));
+                }
+                Ok(Type::Named(name, args))
+            }
+            _ => Err(self.error_here("a type")),
+        }
+    }
+
+    fn parse_type_args(&mut self) -> ParseResult<Vec<Type>> {
+        if !self.eat_symbol(SymbolKind::LessThan) {
+            return Ok(vec![]);
+        }
+        let mut args = vec![self.parse_type()?];
+        while self.eat_symbol(SymbolKind::Comma) {
+            args.push(self.parse_type()?);
+        }
+        self.expect_closing_angle()?;
+        Ok(args)
+    }
+
+    fn expect_closing_angle(&mut self) -> ParseResult<()> {
+        if self.eat_symbol(SymbolKind::GreaterThan) {
+            return Ok(());
+        }
+        if self.check_symbol(SymbolKind::BitshiftRight) {
+            let at = self.pos;
+            let mut second = self.input[at].clone();
+            second.new_type(TokenType::Symbol(SymbolKind::GreaterThan));
+            second.value = ">".to_owned();
+            second.span.col += 1;
+            self.input[at].new_type(TokenType::Symbol(SymbolKind::GreaterThan));
+            self.input[at].value = ">".to_owned();
+            self.input.insert(at + 1, second);
+            self.splits.push(at);
+            self.advance();
+            return Ok(());
+        }
+        Err(self.error_here("`>`"))
+    }
+
+    /* A declaration that starts with an identifier looks like `Shape s = ...`. */
+    fn try_parse_decl_type(&mut self) -> Option<Type> {
+        let checkpoint = self.checkpoint();
+        if let Ok(ty) = self.parse_type() {
+            if self.check_ident() {
+                return Some(ty);
+            }
+        }
+        self.restore(checkpoint);
+        None
+    }
+
+    /* Statements */
+
+    fn parse_statement(&mut self) -> ParseResult<Stmt> {
+        let span = self.span();
+        let kind = match self.peek().Type.clone() {
+            TokenType::Keyword(keyword) => match keyword {
+                KeywordKind::Func => StmtKind::Func(Rc::new(self.parse_func(true)?)),
+                KeywordKind::Jit => {
+                    self.expect_keyword(KeywordKind::Jit)?;
+                    let decl = self.parse_func(true)?;
+                    StmtKind::Func(Rc::new(FuncDecl { jit: true, ..decl }))
+                }
+                /* Otherwise `coroutine` is a type, as in `coroutine<int32> c = f()`. */
+                KeywordKind::Coroutine
+                    if self.peek_nth(1).Type == TokenType::Keyword(KeywordKind::Func) =>
+                {
+                    self.advance();
+                    let decl = self.parse_func(true)?;
+                    StmtKind::Func(Rc::new(FuncDecl {
+                        coroutine: true,
+                        ..decl
+                    }))
+                }
+                KeywordKind::Enum => self.parse_enum()?,
+                KeywordKind::Struct => StmtKind::Struct(Rc::new(self.parse_struct()?)),
+                KeywordKind::Type => StmtKind::TypeAlias(Rc::new(self.parse_type_alias()?)),
+                KeywordKind::Global | KeywordKind::Const | KeywordKind::Static => {
+                    self.parse_storage_decl()?
+                }
+                KeywordKind::If => self.parse_if()?,
+                KeywordKind::Given => self.parse_given()?,
+                KeywordKind::For | KeywordKind::While | KeywordKind::Loop => {
+                    self.parse_loop(None)?
+                }
+                KeywordKind::Break => {
+                    self.advance();
+                    StmtKind::Break(self.parse_label())
+                }
+                KeywordKind::Continue => {
+                    self.advance();
+                    StmtKind::Continue(self.parse_label())
+                }
+                /* Otherwise `try` starts an expression, as in `try f()`. */
+                KeywordKind::Try if self.peek_nth(1).Type == TokenType::NewLine => {
+                    self.parse_try()?
+                }
+                KeywordKind::Raise => {
+                    self.advance();
+                    StmtKind::Raise(self.parse_expr()?)
+                }
+                KeywordKind::Return => {
+                    self.advance();
+                    if self.at_statement_end() {
+                        StmtKind::Return(None)
+                    } else {
+                        StmtKind::Return(Some(self.parse_expr()?))
+                    }
+                }
+                keyword
+                    if Self::is_type_keyword(keyword)
+                        && self.peek_nth(1).Type != TokenType::Symbol(SymbolKind::LeftParen) =>
+                {
+                    let ty = self.parse_type()?;
+                    self.parse_var_decl(ty, None)?
+                }
+                _ => self.parse_expr_statement()?,
+            },
+            TokenType::Identifier(label)
+                if self.peek_nth(1).Type == TokenType::Symbol(SymbolKind::Colon) =>
+            {
+                self.advance();
+                self.advance();
+                self.parse_loop(Some(label))?
+            }
+            TokenType::Identifier(_) => match self.try_parse_decl_type() {
+                Some(ty) => self.parse_var_decl(ty, None)?,
+                None => self.parse_expr_statement()?,
+            },
+            _ => self.parse_expr_statement()?,
+        };
+        Ok(Stmt { kind, span })
+    }
+
+    fn parse_block(&mut self, terminators: &[KeywordKind]) -> ParseResult<Block> {
+        let mut stmts = vec![];
+        loop {
+            self.skip_separators();
+            if terminators
+                .iter()
+                .any(|&keyword| self.check_keyword(keyword))
+            {
+                return Ok(stmts);
+            }
+            if self.at_eof() {
+                return Err(self.error_here(&format!("`{}`", keyword_name(terminators[0]))));
+            }
+            match self.parse_statement().and_then(|stmt| {
+                self.end_statement()?;
+                Ok(stmt)
+            }) {
+                Ok(stmt) => stmts.push(stmt),
+                Err(err) => {
+                    self.errors.push(err);
+                    self.synchronize();
+                }
+            }
+        }
+    }
+
+    fn parse_var_decl(&mut self, ty: Type, storage: Option<Storage>) -> ParseResult<StmtKind> {
+        let (name, span) = self.expect_ident()?;
+        let value = if self.eat_symbol(SymbolKind::Assign) {
+            let mut value = self.parse_expr()?;
+            name_after(&mut value, &name);
+            Some(value)
+        } else {
+            None
+        };
+        if ty == Type::Unknown && value.is_none() {
+            return Err(Diagnostic::error(
+                span,
+                format!("`{name}` needs a type or a value to infer it from."),
+            ));
+        }
+        Ok(StmtKind::VarDecl(VarDecl {
+            ty,
+            name,
+            value,
+            storage,
+            binding: Cell::new(None),
+        }))
+    }
+
+    /* `const x = 5` infers the type of `x`; `const int32 x = 5` declares it. */
+    fn parse_storage_decl(&mut self) -> ParseResult<StmtKind> {
+        let storage = match self.advance().Type {
+            TokenType::Keyword(KeywordKind::Global) => Storage::Global,
+            TokenType::Keyword(KeywordKind::Const) => Storage::Const,
+            _ => Storage::Static,
+        };
+        let inferred = self.check_ident()
+            && !matches!(
+                self.peek_nth(1).Type,
+                TokenType::Identifier(_)
+                    | TokenType::Symbol(SymbolKind::LessThan | SymbolKind::Ternary)
+            );
+        let ty = if inferred {
+            Type::Unknown
+        } else {
+            self.parse_type()?
+        };
+        self.parse_var_decl(ty, Some(storage))
+    }
+
+    fn parse_expr_statement(&mut self) -> ParseResult<StmtKind> {
+        let target = self.parse_expr()?;
+        let op = match self.peek().Type {
+            TokenType::Symbol(symbol) => assign_op(symbol),
+            _ => None,
+        };
+        let Some(op) = op else {
+            return Ok(StmtKind::Expr(target));
+        };
+        if !matches!(
+            target.kind,
+            ExprKind::Ident(..) | ExprKind::Field(..) | ExprKind::Index(..)
+        ) {
+            return Err(Diagnostic::error(
+                target.span,
+                "Invalid assignment target.".to_owned(),
+            ));
+        }
+        self.advance();
+        let mut value = self.parse_expr()?;
+        if let (ExprKind::Ident(name, _), None) = (&target.kind, op) {
+            name_after(&mut value, name);
+        }
+        Ok(StmtKind::Assign { target, op, value })
+    }
+
+    fn parse_try(&mut self) -> ParseResult<StmtKind> {
+        self.advance();
+        let body = self.parse_block(&[KeywordKind::Catch])?;
+        self.expect_keyword(KeywordKind::Catch)?;
+        let var = if self.check_ident() {
+            Some(self.expect_ident()?.0)
+        } else {
+            None
+        };
+        let handler = self.parse_block(&[KeywordKind::End])?;
+        self.expect_keyword(KeywordKind::End)?;
+        Ok(StmtKind::Try { body, var, handler })
+    }
+
+    fn parse_if(&mut self) -> ParseResult<StmtKind> {
+        self.advance();
+        let mut branches = vec![];
+        let mut else_block = None;
+        loop {
+            let cond = self.parse_expr()?;
+            self.expect_keyword(KeywordKind::Then)?;
+            let body =
+                self.parse_block(&[KeywordKind::ElseIf, KeywordKind::Else, KeywordKind::End])?;
+            branches.push((cond, body));
+            if self.eat_keyword(KeywordKind::ElseIf) {
+                continue;
+            }
+            if self.eat_keyword(KeywordKind::Else) {
+                else_block = Some(self.parse_block(&[KeywordKind::End])?);
+            }
+            self.expect_keyword(KeywordKind::End)?;
+            break;
+        }
+        Ok(StmtKind::If {
+            branches,
+            else_block,
+        })
+    }
+
+    fn parse_given(&mut self) -> ParseResult<StmtKind> {
+        self.advance();
+        let subject = self.parse_expr()?;
+        self.expect_keyword(KeywordKind::Do)?;
+        let mut arms = vec![];
+        loop {
+            self.skip_separators();
+            let span = self.span();
+            if self.eat_keyword(KeywordKind::End) {
+                break;
+            }
+            let patterns = if self.eat_keyword(KeywordKind::When) {
+                let mut patterns = vec![self.parse_pattern()?];
+                while self.eat_symbol(SymbolKind::Comma) {
+                    patterns.push(self.parse_pattern()?);
+                }
+                self.expect_symbol(SymbolKind::FatArrow)?;
+                patterns
+            } else if self.eat_keyword(KeywordKind::Else) {
+                self.eat_symbol(SymbolKind::FatArrow);
+                vec![Pattern {
+                    kind: PatternKind::Wildcard,
+                    span,
+                    constant: Cell::new(None),
+                }]
+            } else {
+                return Err(self.error_here("`when`, `else` or `end`"));
+            };
+            let body = if self.peek().Type == TokenType::NewLine {
+                self.parse_block(&[KeywordKind::When, KeywordKind::Else, KeywordKind::End])?
+            } else {
+                vec![self.parse_statement()?]
+            };
+            arms.push(WhenArm {
+                patterns,
+                body,
+                span,
+            });
+        }
+        Ok(StmtKind::Given(Given {
+            subject,
+            arms,
+            exhaustive: Cell::new(false),
+        }))
+    }
+
+    fn parse_pattern(&mut self) -> ParseResult<Pattern> {
+        let span = self.span();
+        let negative = self.eat_symbol(SymbolKind::Minus);
+        let token = self.advance();
+        let kind = match token.Type {
+            TokenType::Int64Val(int) => PatternKind::Int(if negative {
+                -i128::from(int)
+            } else {
+                i128::from(int)
+            }),
+            TokenType::UInt128Val(int) => {
+                let Ok(int) = i128::try_from(int) else {
+                    return Err(Diagnostic::error(
+                        token.span,
+                        format!("Integer pattern {int} is too large."),
+                    ));
+                };
+                PatternKind::Int(if negative { -int } else { int })
+            }
+            TokenType::Float64Val(float) => {
+                PatternKind::Float(if negative { -float } else { float })
+            }
+            _ if negative => {
+                return Err(Diagnostic::error(
+                    token.span,
+                    format!("Expected a number after `-`, found {}.", describe(&token)),
+                ))
+            }
+            TokenType::Keyword(KeywordKind::True) => PatternKind::Bool(true),
+            TokenType::Keyword(KeywordKind::False) => PatternKind::Bool(false),
+            TokenType::Keyword(KeywordKind::Nil) => PatternKind::Nil,
+            TokenType::CharVal(ch) => PatternKind::Char(ch),
+            TokenType::StringVal(string) => PatternKind::Str(string),
+            TokenType::Identifier(first) => {
+                let (qualifier, name) = if self.eat_symbol(SymbolKind::Dot) {
+                    (Some(first), self.expect_ident()?.0)
+                } else {
+                    (None, first)
+                };
+                let bindings = if self.eat_symbol(SymbolKind::LeftParen) {
+                    let mut bindings = vec![];
+                    if !self.check_symbol(SymbolKind::RightParen) {
+                        bindings.push(self.expect_ident()?.0);
+                        while self.eat_symbol(SymbolKind::Comma) {
+                            bindings.push(self.expect_ident()?.0);
+                        }
+                    }
+                    self.expect_symbol(SymbolKind::RightParen)?;
+                    Some(bindings)
+                } else {
+                    None
+                };
+                PatternKind::Name {
+                    qualifier,
+                    name,
+                    bindings,
+                }
+            }
+            _ => {
+                return Err(Diagnostic::error(
+                    token.span,
+                    format!("Expected a pattern, found {}.", describe(&token)),
+                ))
+            }
+        };
+        let kind = match kind {
+            PatternKind::Int(start)
+                if self.check_contextual("to") || self.check_contextual("through") =>
+            {
+                let inclusive = self.advance().value == "through";
+                let end = self.parse_pattern()?;
+                let PatternKind::Int(end) = end.kind else {
+                    return Err(Diagnostic::error(
+                        end.span,
+                        format!(
+                            "Expected an integer to end the range, found `{}`.",
+                            end.kind
+                        ),
+                    ));
+                };
+                let kind = PatternKind::Range {
+                    start,
+                    end,
+                    inclusive,
+                };
+                if end < start || (end == start && !inclusive) {
+                    return Err(Diagnostic::error(
+                        span,
+                        format!("Pattern `{kind}` matches no integers."),
+                    ));
+                }
+                kind
+            }
+            kind => kind,
+        };
+        Ok(Pattern {
+            kind,
+            span,
+            constant: Cell::new(None),
+        })
+    }
+
+    fn parse_loop(&mut self, label: Option<String>) -> ParseResult<StmtKind> {
+        if self.check_keyword(KeywordKind::For) {
+            return self.parse_for(label);
+        }
+        if self.eat_keyword(KeywordKind::While) {
+            let cond = self.parse_expr()?;
+            self.expect_keyword(KeywordKind::Do)?;
+            let body = self.parse_block(&[KeywordKind::End])?;
+            self.expect_keyword(KeywordKind::End)?;
+            return Ok(StmtKind::While { cond, body, label });
+        }
+        if self.eat_keyword(KeywordKind::Loop) {
+            self.eat_keyword(KeywordKind::Do);
+            let body = self.parse_block(&[KeywordKind::End])?;
+            self.expect_keyword(KeywordKind::End)?;
+            return Ok(StmtKind::Loop { body, label });
+        }
+        Err(self.error_here("a loop"))
+    }
+
+    /* The label a `break` or `continue` names, if any. */
+    fn parse_label(&mut self) -> Option<String> {
+        match self.check_ident() {
+            true => self.expect_ident().ok().map(|(label, _)| label),
+            false => None,
+        }
+    }
+
+    fn parse_for(&mut self, label: Option<String>) -> ParseResult<StmtKind> {
+        self.advance();
+        let (var_ty, var) = self.parse_loop_var()?;
+        let value_var = match self.eat_symbol(SymbolKind::Comma) {
+            true => Some(self.parse_loop_var()?),
+            false => None,
+        };
+        self.expect_keyword(KeywordKind::In)?;
+        let iter = self.parse_expr()?;
+        self.expect_keyword(KeywordKind::Do)?;
+        let body = self.parse_block(&[KeywordKind::End])?;
+        self.expect_keyword(KeywordKind::End)?;
+        Ok(StmtKind::For {
+            var_ty,
+            var,
+            value_var,
+            iter,
+            body,
+            label,
+        })
+    }
+
+    /* A loop variable, with its type unless the name comes right before `in` or the
+     * comma of a key and value. */
+    fn parse_loop_var(&mut self) -> ParseResult<(Option<Type>, String)> {
+        let untyped = self.check_ident()
+            && matches!(
+                self.peek_nth(1).Type,
+                TokenType::Keyword(KeywordKind::In) | TokenType::Symbol(SymbolKind::Comma)
+            );
+        let ty = match untyped {
+            true => None,
+            false => Some(self.parse_type()?),
+        };
+        let (name, _) = self.expect_ident()?;
+        Ok((ty, name))
+    }
+
+    fn parse_func(&mut self, named: bool) -> ParseResult<FuncDecl> {
+        let span = self.expect_keyword(KeywordKind::Func)?;
+        let type_params = self.parse_type_params()?;
+        let (ret, name) = if self.check_ident()
+            && self.peek_nth(1).Type == TokenType::Symbol(SymbolKind::LeftParen)
+        {
+            (Type::Void, Some(self.expect_ident()?.0))
+        } else if self.check_symbol(SymbolKind::LeftParen) {
+            /* An anonymous function without a return type has it inferred. */
+            (Type::Unknown, None)
+        } else {
+            let ret = self.parse_type()?;
+            let name = if self.check_ident() {
+                Some(self.expect_ident()?.0)
+            } else {
+                None
+            };
+            (ret, name)
+        };
+        if named && name.is_none() {
+            return Err(self.error_here("a function name"));
+        }
+        self.expect_symbol(SymbolKind::LeftParen)?;
+        let mut params = vec![];
+        self.skip_newlines();
+        if !self.check_symbol(SymbolKind::RightParen) {
+            loop {
+                let param_span = self.span();
+                let untyped = !named
+                    && self.check_ident()
+                    && matches!(
+                        self.peek_nth(1).Type,
+                        TokenType::Symbol(SymbolKind::Comma | SymbolKind::RightParen)
+                    );
+                let ty = if untyped {
+                    Type::Unknown
+                } else {
+                    self.parse_type()?
+                };
+                let (param_name, _) = self.expect_ident()?;
+                params.push(Param {
+                    ty,
+                    name: param_name,
+                    span: param_span,
+                });
+                self.skip_newlines();
+                if !self.eat_symbol(SymbolKind::Comma) {
+                    break;
+                }
+                self.skip_newlines();
+            }
+        }
+        self.expect_symbol(SymbolKind::RightParen)?;
+        self.functions.push(false);
+        let body = self.parse_block(&[KeywordKind::End]);
+        let uses_self = self.functions.pop().unwrap_or_default();
+        let body = body?;
+        self.expect_keyword(KeywordKind::End)?;
+        Ok(FuncDecl {
+            name,
+            type_params,
+            params,
+            ret,
+            body,
+            span,
+            uses_self,
+            jit: false,
+            coroutine: false,
+        })
+    }
+
+    /* `<T, U>` after the name of a generic struct or type alias, or after `func`. */
+    fn parse_type_params(&mut self) -> ParseResult<Vec<String>> {
+        let mut params: Vec<String> = vec![];
+        if !self.eat_symbol(SymbolKind::LessThan) {
+            return Ok(params);
+        }
+        loop {
+            let (param, span) = self.expect_ident()?;
+            if params.contains(&param) {
+                return Err(Diagnostic::error(
+                    span,
+                    format!("Type parameter `{param}` is declared twice."),
+                ));
+            }
+            params.push(param);
+            if !self.eat_symbol(SymbolKind::Comma) {
+                break;
+            }
+        }
+        self.expect_closing_angle()?;
+        Ok(params)
+    }
+
+    fn parse_type_alias(&mut self) -> ParseResult<TypeAlias> {
+        let span = self.advance().span;
+        let (name, _) = self.expect_ident()?;
+        let params = self.parse_type_params()?;
+        self.expect_symbol(SymbolKind::Assign)?;
+        let ty = self.parse_type()?;
+        Ok(TypeAlias {
+            name,
+            params,
+            ty,
+            span,
+        })
+    }
+
+    fn parse_struct(&mut self) -> ParseResult<StructDecl> {
+        let span = self.advance().span;
+        let (name, _) = self.expect_ident()?;
+        let params = self.parse_type_params()?;
+        let this = params
+            .iter()
+            .map(|param| Type::Named(param.clone(), vec![]))
+            .collect();
+        self.structs.push(Type::Named(name.clone(), this));
+        let members = self.parse_struct_members();
+        self.structs.pop();
+        let (fields, methods) = members?;
+        Ok(StructDecl {
+            name,
+            params,
+            fields,
+            methods,
+            span,
+        })
+    }
+
+    fn parse_struct_members(&mut self) -> ParseResult<(Vec<FieldDecl>, Vec<Rc<FuncDecl>>)> {
+        let mut fields: Vec<FieldDecl> = vec![];
+        let mut methods: Vec<Rc<FuncDecl>> = vec![];
+        loop {
+            self.skip_separators();
+            if self.eat_keyword(KeywordKind::End) {
+                return Ok((fields, methods));
+            }
+            let span = self.span();
+            if self.check_keyword(KeywordKind::Func) {
+                let method = self.parse_func(true)?;
+                let name = method.name.clone().unwrap_or_default();
+                if methods
+                    .iter()
+                    .any(|other| other.name.as_ref() == Some(&name))
+                {
+                    return Err(Diagnostic::error(
+                        span,
+                        format!("Method `{name}` is declared twice."),
+                    ));
+                }
+                methods.push(Rc::new(method));
+            } else {
+                let ty = self.parse_type()?;
+                let (name, _) = self.expect_ident()?;
+                if fields.iter().any(|field| field.name == name) {
+                    return Err(Diagnostic::error(
+                        span,
+                        format!("Field `{name}` is declared twice."),
+                    ));
+                }
+                let default = if self.eat_symbol(SymbolKind::Assign) {
+                    Some(self.parse_expr()?)
+                } else {
+                    None
+                };
+                fields.push(FieldDecl {
+                    ty,
+                    name,
+                    default,
+                    span,
+                });
+            }
+            self.end_statement()?;
+        }
+    }
+
+    fn parse_enum(&mut self) -> ParseResult<StmtKind> {
+        self.advance();
+        let (name, _) = self.expect_ident()?;
+        let mut variants = vec![];
+        loop {
+            self.skip_separators();
+            if self.eat_symbol(SymbolKind::Comma) {
+                continue;
+            }
+            if self.eat_keyword(KeywordKind::End) {
+                break;
+            }
+            let (variant, span) = self.expect_ident()?;
+            let mut fields = vec![];
+            if self.eat_symbol(SymbolKind::LeftParen) {
+                if !self.check_symbol(SymbolKind::RightParen) {
+                    fields.push(self.parse_type()?);
+                    while self.eat_symbol(SymbolKind::Comma) {
+                        fields.push(self.parse_type()?);
+                    }
+                }
+                self.expect_symbol(SymbolKind::RightParen)?;
+            }
+            variants.push(Variant {
+                name: variant,
+                fields,
+                span,
+            });
+        }
+        Ok(StmtKind::Enum(Rc::new(EnumDecl { name, variants })))
+    }
+
+    /* Expressions */
+
+    pub fn parse_expr(&mut self) -> ParseResult<Expr> {
+        self.parse_binary(0)
+    }
+
+    fn parse_binary(&mut self, min_power: u8) -> ParseResult<Expr> {
+        let mut lhs = self.parse_unary()?;
+        while let Some((op, left, right)) = infix_op(self.peek()) {
+            if left < min_power {
+                break;
+            }
+            let span = self.advance().span;
+            let rhs = self.parse_binary(right)?;
+            let kind = match op {
+                Infix::Binary(op) => ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+                Infix::Range(inclusive) => {
+                    let step = match self.check_contextual("step") {
+                        true => {
+                            self.advance();
+                            Some(Box::new(self.parse_binary(right)?))
+                        }
+                        false => None,
+                    };
+                    ExprKind::Range {
+                        start: Box::new(lhs),
+                        end: Box::new(rhs),
+                        step,
+                        inclusive,
+                    }
+                }
+            };
+            lhs = Expr { kind, span };
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> ParseResult<Expr> {
+        let op = match self.peek().Type {
+            TokenType::Symbol(SymbolKind::Minus) => UnaryOp::Neg,
+            TokenType::Symbol(SymbolKind::BoolNot) | TokenType::Keyword(KeywordKind::Not) => {
+                UnaryOp::Not
+            }
+            TokenType::Symbol(SymbolKind::BitNot) => UnaryOp::BitNot,
+            TokenType::Symbol(SymbolKind::Hash) => UnaryOp::Len,
+            TokenType::Keyword(KeywordKind::Try) => {
+                let span = self.advance().span;
+                let operand = self.parse_unary()?;
+                return Ok(Expr {
+                    kind: ExprKind::Try(Box::new(operand)),
+                    span,
+                });
+            }
+            TokenType::Keyword(KeywordKind::Yield) => {
+                let span = self.advance().span;
+                let value = if self.at_statement_end() || self.check_symbol(SymbolKind::RightParen)
+                {
+                    None
+                } else {
+                    Some(Box::new(self.parse_expr()?))
+                };
+                return Ok(Expr {
+                    kind: ExprKind::Yield(value),
+                    span,
+                });
+            }
+            _ => return self.parse_power(),
+        };
+        let span = self.advance().span;
+        let operand = self.parse_unary()?;
+        Ok(Expr {
+            kind: ExprKind::Unary(op, Box::new(operand)),
+            span,
+        })
+    }
+
+    fn parse_power(&mut self) -> ParseResult<Expr> {
+        let base = self.parse_postfix()?;
+        if self.check_symbol(SymbolKind::Exponent) {
+            let span = self.advance().span;
+            let exponent = self.parse_unary()?;
+            return Ok(Expr {
+                kind: ExprKind::Binary(BinaryOp::Pow, Box::new(base), Box::new(exponent)),
+                span,
+            });
+        }
+        Ok(base)
+    }
+
+    fn parse_postfix(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.parse_primary()?;
+        loop {
+            let span = self.span();
+            if self.eat_symbol(SymbolKind::LeftParen) {
+                let args = self.parse_list(SymbolKind::RightParen)?;
+                expr = Expr {
+                    kind: ExprKind::Call(Box::new(expr), args),
+                    span,
+                };
+            } else if self.eat_symbol(SymbolKind::LeftBracket) {
+                self.skip_newlines();
+                let index = self.parse_expr()?;
+                self.skip_newlines();
+                self.expect_symbol(SymbolKind::RightBracket)?;
+                expr = Expr {
+                    kind: ExprKind::Index(Box::new(expr), Box::new(index)),
+                    span,
+                };
+            } else if self.eat_symbol(SymbolKind::Dot) {
+                let (field, _) = self.expect_ident()?;
+                expr = Expr {
+                    kind: ExprKind::Field(Box::new(expr), field),
+                    span,
+                };
+            } else if self.check_symbol(SymbolKind::Ternary)
+                && self.peek_nth(1).Type == TokenType::Symbol(SymbolKind::Dot)
+            {
+                self.advance();
+                self.advance();
+                let (field, _) = self.expect_ident()?;
+                expr = Expr {
+                    kind: ExprKind::SafeField(Box::new(expr), field),
+                    span,
+                };
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    /* Comma-separated expressions up to `close`; newlines are insignificant inside. */
+    fn parse_list(&mut self, close: SymbolKind) -> ParseResult<Vec<Expr>> {
+        let mut items = vec![];
+        self.skip_newlines();
+        if !self.check_symbol(close) {
+            loop {
+                items.push(self.parse_expr()?);
+                self.skip_newlines();
+                if !self.eat_symbol(SymbolKind::Comma) {
+                    break;
+                }
+                self.skip_newlines();
+            }
+        }
+        self.expect_symbol(close)?;
+        Ok(items)
+    }
+
+    /* The rest of `[key: value, ...]`, after the first key and its colon. */
+    fn parse_table(&mut self, key: Expr, span: Span) -> ParseResult<Expr> {
+        self.skip_newlines();
+        let mut entries = vec![(key, self.parse_expr()?)];
+        self.skip_newlines();
+        while self.eat_symbol(SymbolKind::Comma) {
+            self.skip_newlines();
+            let key = self.parse_expr()?;
+            self.expect_symbol(SymbolKind::Colon)?;
+            self.skip_newlines();
+            entries.push((key, self.parse_expr()?));
+            self.skip_newlines();
+        }
+        self.expect_symbol(SymbolKind::RightBracket)?;
+        Ok(Expr {
+            kind: ExprKind::Table(entries),
+            span,
+        })
+    }
+
+    fn parse_primary(&mut self) -> ParseResult<Expr> {
+        let span = self.span();
+        let kind = match self.peek().Type.clone() {
+            TokenType::Int64Val(int) => ExprKind::Int(int.unsigned_abs().into()),
+            TokenType::UInt128Val(int) => ExprKind::Int(int),
+            TokenType::Float64Val(float) => ExprKind::Float(float),
+            TokenType::StringVal(string) => ExprKind::Str(string),
+            TokenType::CharVal(ch) => ExprKind::Char(ch),
+            TokenType::Identifier(name) => ExprKind::Ident(name, Cell::new(None)),
+            TokenType::Keyword(KeywordKind::True) => ExprKind::Bool(true),
+            TokenType::Keyword(KeywordKind::False) => ExprKind::Bool(false),
+            TokenType::Keyword(KeywordKind::Nil) => ExprKind::Nil,
+            TokenType::Keyword(KeywordKind::SelF) => {
+                let Some(uses_self) = self.functions.last_mut() else {
+                    return Err(Diagnostic::error(
+                        span,
+                        "`self` can only be used inside a function.".to_owned(),
+                    ));
+                };
+                *uses_self = true;
+                ExprKind::SelF
+            }
+            TokenType::Keyword(KeywordKind::This) => {
+                if self.structs.is_empty() {
+                    return Err(Diagnostic::error(
+                        span,
+                        "`this` can only be used inside a struct.".to_owned(),
+                    ));
+                }
+                ExprKind::This
+            }
+            TokenType::Keyword(KeywordKind::Func) => {
+                return Ok(Expr {
+                    kind: ExprKind::Func(Rc::new(self.parse_func(false)?)),
+                    span,
+                })
+            }
+            TokenType::Keyword(KeywordKind::Coroutine)
+                if self.peek_nth(1).Type == TokenType::Keyword(KeywordKind::Func) =>
+            {
+                self.advance();
+                let decl = self.parse_func(false)?;
+                return Ok(Expr {
+                    kind: ExprKind::Func(Rc::new(FuncDecl {
+                        coroutine: true,
+                        ..decl
+                    })),
+                    span,
+                });
+            }
+            TokenType::Keyword(keyword)
+                if self.peek_nth(1).Type == TokenType::Symbol(SymbolKind::LeftParen) =>
+            {
+                let Some(ty) = Type::from_keyword(keyword) else {
+                    return Err(self.error_here("an expression"));
+                };
+                self.advance();
+                self.advance();
+                self.skip_newlines();
+                let value = self.parse_expr()?;
+                self.skip_newlines();
+                self.expect_symbol(SymbolKind::RightParen)?;
+                return Ok(Expr {
+                    kind: ExprKind::Cast(ty, Box::new(value)),
+                    span,
+                });
+            }
+            TokenType::Symbol(SymbolKind::LeftParen) => {
+                self.advance();
+                self.skip_newlines();
+                let expr = self.parse_expr()?;
+                self.skip_newlines();
+                self.expect_symbol(SymbolKind::RightParen)?;
+                return Ok(expr);
+            }
+            TokenType::Symbol(SymbolKind::LeftBracket) => {
+                self.advance();
+                self.skip_newlines();
+                if !self.check_symbol(SymbolKind::RightBracket) {
+                    let value = self.parse_expr()?;
+                    if self.eat_symbol(SymbolKind::Semicolon) {
+                        let count = self.parse_expr()?;
+                        self.expect_symbol(SymbolKind::RightBracket)?;
+                        return Ok(Expr {
+                            kind: ExprKind::Repeat(Box::new(value), Box::new(count)),
+                            span,
+                        });
+                    }
+                    if self.eat_symbol(SymbolKind::Colon) {
+                        return self.parse_table(value, span);
+                    }
+                    self.skip_newlines();
+                    let mut items = vec![value];
+                    if self.eat_symbol(SymbolKind::Comma) {
+                        items.extend(self.parse_list(SymbolKind::RightBracket)?);
+                    } else {
+                        self.expect_symbol(SymbolKind::RightBracket)?;
+                    }
+                    return Ok(Expr {
+                        kind: ExprKind::Array(items),
+                        span,
+                    });
+                }
+                let items = self.parse_list(SymbolKind::RightBracket)?;
+                return Ok(Expr {
+                    kind: ExprKind::Array(items),
+                    span,
+                });
+            }
+            _ => return Err(self.error_here("an expression")),
+        };
+        self.advance();
+        Ok(Expr { kind, span })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn parse_ok(source: &str) -> Program {
+        parse(source).unwrap_or_else(|errors| {
+            panic!(
+                "{}",
+                errors
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            )
+        })
+    }
+
+    #[test]
+    fn test_parse_examples() {
+        for entry in fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+            let source = fs::read_to_string(&path).unwrap();
+            assert!(parse(&source).is_ok(), "{} failed to parse", path.display());
+        }
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let program = parse_ok("int32 x = 1 + 2 * 3 .. 4");
+        let StmtKind::VarDecl(decl) = &program.stmts[0].kind else {
+            panic!("expected a declaration");
+        };
+        let Some(Expr {
+            kind: ExprKind::Binary(BinaryOp::Concat, lhs, _),
+            ..
+        }) = &decl.value
+        else {
+            panic!("expected `..` at the root");
+        };
+        assert!(matches!(lhs.kind, ExprKind::Binary(BinaryOp::Add, ..)));
+    }
+
+    #[test]
+    fn test_parse_given() {
+        let program =
+            parse_ok("given x % 4 do\n\twhen 0, 1 => print(x)\n\telse =>\n\t\tprint(0)\nend");
+        let StmtKind::Given(given) = &program.stmts[0].kind else {
+            panic!("expected a given statement");
+        };
+        assert_eq!(given.arms.len(), 2);
+        assert_eq!(given.arms[0].patterns.len(), 2);
+        assert_eq!(given.arms[1].patterns[0].kind, PatternKind::Wildcard);
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        let program = parse_ok(
+            "range<int8> r = 9 through 0 step -2\nbool b = 3 in 0 to 5 and true\ngiven b do\n\twhen -2 to 3, 5 through 8 => print(b)\nend",
+        );
+        let StmtKind::VarDecl(decl) = &program.stmts[0].kind else {
+            panic!("expected a declaration");
+        };
+        assert_eq!(decl.ty.to_string(), "range<int8>");
+        assert!(matches!(
+            decl.value.as_ref().map(|value| &value.kind),
+            Some(ExprKind::Range {
+                step: Some(_),
+                inclusive: true,
+                ..
+            })
+        ));
+        let StmtKind::VarDecl(decl) = &program.stmts[1].kind else {
+            panic!("expected a declaration");
+        };
+        let Some(ExprKind::Binary(BinaryOp::And, lhs, _)) = decl.value.as_ref().map(|v| &v.kind)
+        else {
+            panic!("expected `and` at the root");
+        };
+        assert!(matches!(
+            &lhs.kind,
+            ExprKind::Binary(BinaryOp::In, _, rhs) if matches!(rhs.kind, ExprKind::Range { .. })
+        ));
+        let StmtKind::Given(given) = &program.stmts[2].kind else {
+            panic!("expected a given statement");
+        };
+        let patterns = given.arms[0]
+            .patterns
+            .iter()
+            .map(|pattern| pattern.kind.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(patterns, ["-2 to 3", "5 through 8"]);
+        assert_eq!(
+            parse("given 1 do\n\twhen 3 to 3 => print(1)\nend").unwrap_err()[0].to_string(),
+            "Toolip:2:7: Pattern `3 to 3` matches no integers."
+        );
+    }
+
+    #[test]
+    fn test_parse_nested_type_args() {
+        let program = parse_ok("list<list<int32>> xs\nbool b = xs >> 1");
+        let StmtKind::VarDecl(decl) = &program.stmts[0].kind else {
+            panic!("expected a declaration");
+        };
+        assert_eq!(decl.ty.to_string(), "list<list<int32>>");
+        assert_eq!(program.stmts.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_generics() {
+        let program = parse_ok(
+            "type Row<T> = list<T>\nstruct Pair<K, V>\n\tK key\n\tfunc this swap()\n\t\treturn self\n\tend\nend\nfunc<A, B> B second(A a, B b)\n\treturn b\nend",
+        );
+        let StmtKind::TypeAlias(alias) = &program.stmts[0].kind else {
+            panic!("expected a type alias");
+        };
+        assert_eq!(
+            (alias.params.len(), alias.ty.to_string()),
+            (1, "list<T>".to_owned())
+        );
+        let StmtKind::Struct(decl) = &program.stmts[1].kind else {
+            panic!("expected a struct");
+        };
+        assert_eq!(decl.params, ["K", "V"]);
+        assert_eq!(decl.methods[0].ret.to_string(), "Pair<K, V>");
+        let StmtKind::Func(func) = &program.stmts[2].kind else {
+            panic!("expected a function");
+        };
+        assert_eq!(func.type_params, ["A", "B"]);
+    }
+
+    #[test]
+    fn test_parse_error_recovery() {
+        let errors = parse("int32 = 4\nbool b = true\nx = )").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].span.line, 1);
+        assert_eq!(errors[1].span.line, 3);
+    }
+
+    #[test]
+    fn test_parse_storage_decls() {
+        let program = parse_ok("const x = 5\nglobal list<int32> xs\nstatic Pair<int8, int8> p\nconst f = func (a, b) return a end");
+        let decls = program
+            .stmts
+            .iter()
+            .map(|stmt| match &stmt.kind {
+                StmtKind::VarDecl(decl) => (decl.storage, decl.ty.to_string()),
+                _ => panic!("expected a declaration"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(decls[0], (Some(Storage::Const), "<unknown>".to_owned()));
+        assert_eq!(decls[1], (Some(Storage::Global), "list<int32>".to_owned()));
+        assert_eq!(
+            decls[2],
+            (Some(Storage::Static), "Pair<int8, int8>".to_owned())
+        );
+        let errors = parse("const x").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "`x` needs a type or a value to infer it from."
+        );
+    }
+
+    #[test]
+    fn test_parse_tables() {
+        let program = parse_ok("var t = [\"a\": 1,\n\t\"b\": 2]\nfor string k, v in t do\nend");
+        let StmtKind::VarDecl(decl) = &program.stmts[0].kind else {
+            panic!("expected a declaration");
+        };
+        assert!(matches!(
+            decl.value.as_ref().map(|value| &value.kind),
+            Some(ExprKind::Table(entries)) if entries.len() == 2
+        ));
+        let StmtKind::For {
+            var_ty, value_var, ..
+        } = &program.stmts[1].kind
+        else {
+            panic!("expected a for loop");
+        };
+        assert_eq!(
+            var_ty.as_ref().map(ToString::to_string).as_deref(),
+            Some("string")
+        );
+        assert_eq!(value_var, &Some((None, "v".to_owned())));
     }
 }
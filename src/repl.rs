@@ -1,24 +1,62 @@
 #![allow(dead_code, unused_imports)]
+use crate::evaluator::Evaluator;
 use crate::lexer::{self, Lexer};
-use crate::token;
+use crate::value::Value;
+use crate::{checker, diagnostic, parser, token};
 use reedline::{DefaultPrompt, DefaultPromptSegment, Reedline, Signal};
 
-pub fn start() {
+/* A block is still open when every error is about reaching the end of the input. */
+fn is_incomplete(errors: &[diagnostic::Diagnostic]) -> bool {
+    errors
+        .iter()
+        .all(|error| error.message.ends_with("found end of file."))
+}
+
+pub fn start(max_depth: usize) {
     println!("Welcome to the Toolip Programming Language! Enter some code below and hit Enter to execute.");
     let mut line_editor = Reedline::create();
     let prompt = DefaultPrompt::new(
         DefaultPromptSegment::Basic("toolip".to_owned()),
         DefaultPromptSegment::Empty,
     );
+    let continuation = DefaultPrompt::new(
+        DefaultPromptSegment::Basic("......".to_owned()),
+        DefaultPromptSegment::Empty,
+    );
+    let mut evaluator = Evaluator::new();
+    evaluator.set_max_depth(max_depth);
+    let mut source = String::new();
 
     loop {
-        let sig = line_editor.read_line(&prompt);
+        let sig = if source.is_empty() {
+            line_editor.read_line(&prompt)
+        } else {
+            line_editor.read_line(&continuation)
+        };
         match sig {
             Ok(Signal::Success(buffer)) => {
-                let line = buffer.chars().collect::<Vec<char>>();
-                let mut lex = Lexer::new(line);
-                let tokens = lex.tokenize();
-                Lexer::print_tokens(tokens);
+                source.push_str(&buffer);
+                source.push('\n');
+                let program = match parser::parse(&source) {
+                    Ok(program) => program,
+                    Err(errors) if is_incomplete(&errors) => continue,
+                    Err(errors) => {
+                        errors.iter().for_each(|error| println!("{error}"));
+                        source.clear();
+                        continue;
+                    }
+                };
+                source.clear();
+                let diagnostics = checker::check_with_globals(&program, &evaluator.global_names());
+                diagnostics.iter().for_each(|error| println!("{error}"));
+                if diagnostic::has_errors(&diagnostics) {
+                    continue;
+                }
+                match evaluator.run(&program) {
+                    Ok(Value::Nil) => (),
+                    Ok(value) => println!("{}", evaluator.heap.display(&value)),
+                    Err(error) => println!("{}", error.report("<repl>")),
+                }
             }
             Ok(Signal::CtrlD | Signal::CtrlC) => {
                 println!("\nAborted!");
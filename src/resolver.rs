@@ -0,0 +1,720 @@
+#![allow(dead_code)]
+use crate::ast::{
+    BindingId, Block, Expr, ExprKind, FuncDecl, Given, PatternKind, Program, Stmt, StmtKind,
+    Storage, StructDecl,
+};
+use crate::diagnostic::Diagnostic;
+use crate::format;
+use crate::token::Span;
+use crate::value::Builtin;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/* Name resolution: ties every name in a program to the declaration it refers to.
+ *
+ * There is a scope for the program, each block, each loop body with its loop
+ * variable, each `given` arm with its bindings and each function with its
+ * parameters. Functions, structs, enums and type aliases can be used anywhere in
+ * their block; variables only after their declaration. Bodies (and field defaults)
+ * are resolved once the enclosing function or program has been, but see only the
+ * variables of enclosing functions declared by the end of the statement that
+ * declares them. Variables at the top level of the program are globals, visible to
+ * every function.
+ *
+ * `globals` are the names already defined, such as those of earlier REPL inputs;
+ * they live next to the builtins and may be declared again, as may the variables
+ * functions declare with `global`.
+ *
+ * Also checks how `const` and `static` declarations are used. */
+pub fn resolve(program: &Program, globals: &[String]) -> Vec<Diagnostic> {
+    let mut resolver = Resolver::new();
+    let builtins = Builtin::ALL.iter().map(|builtin| builtin.name().to_owned());
+    let mut names = builtins.chain(globals.iter().cloned()).collect::<Vec<_>>();
+    nested_globals(&program.stmts, false, &mut names);
+    for name in names {
+        let id = resolver.fresh_id();
+        resolver.scopes[0].names.entry(name).or_insert(id);
+    }
+    resolver.resolve_block(&program.stmts);
+    let mut next = 0;
+    while let Some(body) = resolver.deferred.get(next).cloned() {
+        resolver.resolve_deferred(body);
+        next += 1;
+    }
+    resolver
+        .diagnostics
+        .sort_by_key(|diagnostic| diagnostic.span);
+    resolver.diagnostics
+}
+
+/* The scope of the top level of the program, after that of the builtins. */
+const PROGRAM_SCOPE: usize = 1;
+
+#[derive(Debug, Default)]
+struct Scope {
+    parent: Option<usize>,
+    names: HashMap<String, BindingId>,
+}
+
+/* Code resolved after the scope it appears in is complete. */
+#[derive(Debug, Clone)]
+enum Deferred {
+    Func(Rc<FuncDecl>, Site),
+    Fields(Rc<StructDecl>, Site),
+}
+
+/* Where deferred code appears: its scope, and the horizons it sees enclosing scopes
+ * through. Each horizon is the scope a function around the code (or the code itself)
+ * is declared in, with the first id declared after that declaration; variables of
+ * the scope and its ancestors with later ids are out of sight. Innermost last. */
+#[derive(Debug, Clone)]
+struct Site {
+    scope: usize,
+    horizons: Vec<(usize, u32)>,
+}
+
+/* The horizon of a declaration whose statement has not been resolved yet. */
+const PENDING: u32 = u32::MAX;
+
+struct Resolver {
+    /* Every scope met so far; the first holds the builtins and `globals`. */
+    scopes: Vec<Scope>,
+    current: usize,
+    /* The scope of the body of the function being resolved. */
+    function: Option<usize>,
+    /* The labels of the loops around the statement being resolved, innermost last. */
+    loops: Vec<Option<String>>,
+    /* The `const` variables, and the functions, structs, enums and type aliases. */
+    consts: HashSet<BindingId>,
+    decls: HashSet<BindingId>,
+    next_id: u32,
+    deferred: Vec<Deferred>,
+    /* Those of the deferred code being resolved. */
+    horizons: Vec<(usize, u32)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolver {
+    fn new() -> Self {
+        Self {
+            scopes: vec![Scope::default()],
+            current: 0,
+            function: None,
+            loops: vec![],
+            consts: HashSet::new(),
+            decls: HashSet::new(),
+            next_id: 0,
+            deferred: vec![],
+            horizons: vec![],
+            diagnostics: vec![],
+        }
+    }
+
+    fn fresh_id(&mut self) -> BindingId {
+        self.next_id += 1;
+        BindingId(self.next_id - 1)
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(Scope {
+            parent: Some(self.current),
+            names: HashMap::new(),
+        });
+        self.current = self.scopes.len() - 1;
+    }
+
+    fn scoped(&mut self, f: impl FnOnce(&mut Self)) {
+        let previous = self.current;
+        self.push_scope();
+        f(self);
+        self.current = previous;
+    }
+
+    fn declare(&mut self, name: &str, span: Span) -> Option<BindingId> {
+        if self.scopes[self.current].names.contains_key(name) {
+            self.diagnostics.push(Diagnostic::error(
+                span,
+                format!("`{name}` is already declared in this scope."),
+            ));
+            return None;
+        }
+        /* Builtins and earlier globals may be shadowed freely. */
+        let mut scope = self.scopes[self.current].parent;
+        while let Some(index) = scope.filter(|&index| index != 0) {
+            if self.scopes[index].names.contains_key(name) {
+                self.diagnostics.push(Diagnostic::warning(
+                    span,
+                    format!("`{name}` shadows a declaration in an enclosing scope."),
+                ));
+                break;
+            }
+            scope = self.scopes[index].parent;
+        }
+        let id = self.fresh_id();
+        self.scopes[self.current].names.insert(name.to_owned(), id);
+        Some(id)
+    }
+
+    fn lookup(&self, name: &str) -> Option<BindingId> {
+        self.lookup_from(self.current, name)
+    }
+
+    fn lookup_from(&self, start: usize, name: &str) -> Option<BindingId> {
+        let mut horizons = self.horizons.iter().rev().peekable();
+        let mut horizon = PENDING;
+        let mut scope = Some(start);
+        while let Some(index) = scope {
+            while let Some(&(_, limit)) = horizons.next_if(|&&(scope, _)| scope == index) {
+                horizon = limit;
+            }
+            if let Some(&id) = self.scopes[index].names.get(name) {
+                if id.0 < horizon || index <= PROGRAM_SCOPE {
+                    return Some(id);
+                }
+            }
+            scope = self.scopes[index].parent;
+        }
+        None
+    }
+
+    /* The site of code deferred from the current scope. */
+    fn site(&self) -> Site {
+        let mut horizons = self.horizons.clone();
+        horizons.push((self.current, PENDING));
+        Site {
+            scope: self.current,
+            horizons,
+        }
+    }
+
+    fn defer(&mut self, body: Deferred) {
+        self.deferred.push(body);
+    }
+
+    fn resolve_deferred(&mut self, body: Deferred) {
+        let previous = self.current;
+        let (Deferred::Func(_, site) | Deferred::Fields(_, site)) = &body;
+        let horizons = std::mem::replace(&mut self.horizons, site.horizons.clone());
+        self.current = site.scope;
+        match body {
+            Deferred::Func(func, _) => {
+                self.scoped(|resolver| {
+                    for param in &func.params {
+                        resolver.declare(&param.name, param.span);
+                    }
+                    resolver.function = Some(resolver.current);
+                    let loops = std::mem::take(&mut resolver.loops);
+                    resolver.resolve_stmts(&func.body);
+                    resolver.loops = loops;
+                    resolver.function = None;
+                });
+            }
+            Deferred::Fields(decl, _) => {
+                for field in &decl.fields {
+                    if let Some(default) = &field.default {
+                        self.resolve_expr(default);
+                    }
+                }
+            }
+        }
+        self.horizons = horizons;
+        self.current = previous;
+    }
+
+    /* Statements */
+
+    fn resolve_block(&mut self, block: &Block) {
+        self.scoped(|resolver| resolver.resolve_stmts(block));
+    }
+
+    fn resolve_stmts(&mut self, block: &Block) {
+        for stmt in block {
+            let hoisted = match &stmt.kind {
+                StmtKind::Func(func) => func.name.as_ref().map(|name| (name, func.span)),
+                StmtKind::Struct(decl) => Some((&decl.name, decl.span)),
+                StmtKind::Enum(decl) => Some((&decl.name, stmt.span)),
+                StmtKind::TypeAlias(alias) => Some((&alias.name, alias.span)),
+                _ => None,
+            };
+            if let Some(id) = hoisted.and_then(|(name, span)| self.declare(name, span)) {
+                self.decls.insert(id);
+            }
+        }
+        for stmt in block {
+            let start = self.deferred.len();
+            self.resolve_stmt(stmt);
+            for body in &mut self.deferred[start..] {
+                let (Deferred::Func(_, site) | Deferred::Fields(_, site)) = body;
+                if let Some((_, limit @ PENDING)) = site.horizons.last_mut() {
+                    *limit = self.next_id;
+                }
+            }
+        }
+    }
+
+    fn resolve_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::VarDecl(decl) => {
+                if let Some(value) = &decl.value {
+                    self.resolve_expr(value);
+                }
+                match decl.storage {
+                    Some(Storage::Const) => match &decl.value {
+                        Some(value) if !self.is_constant(value) => self.error(
+                            value.span,
+                            format!(
+                                "The value of the constant `{}` is not known at compile time.",
+                                decl.name
+                            ),
+                        ),
+                        Some(_) => (),
+                        None => self.error(
+                            stmt.span,
+                            format!("The constant `{}` needs a value.", decl.name),
+                        ),
+                    },
+                    Some(Storage::Static) if self.function != Some(self.current) => self.error(
+                        stmt.span,
+                        "A `static` variable must be declared at the top level of a function body.",
+                    ),
+                    /* Declared at the top level of the program before it is resolved. */
+                    Some(Storage::Global) if self.current != PROGRAM_SCOPE => {
+                        decl.binding
+                            .set(self.lookup_from(PROGRAM_SCOPE, &decl.name));
+                        return;
+                    }
+                    _ => (),
+                }
+                let id = self.declare(&decl.name, stmt.span);
+                if let (Some(id), Some(Storage::Const)) = (id, decl.storage) {
+                    self.consts.insert(id);
+                }
+                decl.binding.set(id);
+            }
+            StmtKind::Func(func) => self.defer(Deferred::Func(Rc::clone(func), self.site())),
+            StmtKind::Struct(decl) => {
+                self.defer(Deferred::Fields(Rc::clone(decl), self.site()));
+                for method in &decl.methods {
+                    self.defer(Deferred::Func(Rc::clone(method), self.site()));
+                }
+            }
+            StmtKind::Enum(_) | StmtKind::TypeAlias(_) => (),
+            StmtKind::Break(Some(label)) | StmtKind::Continue(Some(label))
+                if !self.loops.iter().any(|name| name.as_ref() == Some(label)) =>
+            {
+                self.error(
+                    stmt.span,
+                    format!("No enclosing loop is labelled `{label}`."),
+                );
+            }
+            StmtKind::Break(_) | StmtKind::Continue(_) => (),
+            StmtKind::Expr(expr) => self.resolve_expr(expr),
+            StmtKind::Assign { target, value, .. } => {
+                self.resolve_expr(value);
+                self.resolve_expr(target);
+                /* Nor can the fields or elements of a constant be assigned. */
+                let mut root = target;
+                while let ExprKind::Field(inner, _) | ExprKind::Index(inner, _) = &root.kind {
+                    root = inner;
+                }
+                if let ExprKind::Ident(name, binding) = &root.kind {
+                    if binding.get().is_some_and(|id| self.consts.contains(&id)) {
+                        self.error(
+                            root.span,
+                            format!("Cannot assign to the constant `{name}`."),
+                        );
+                    }
+                }
+            }
+            StmtKind::If {
+                branches,
+                else_block,
+            } => {
+                for (cond, block) in branches {
+                    self.resolve_expr(cond);
+                    self.resolve_block(block);
+                }
+                if let Some(block) = else_block {
+                    self.resolve_block(block);
+                }
+            }
+            StmtKind::Given(given) => self.resolve_given(given),
+            StmtKind::For {
+                var,
+                value_var,
+                iter,
+                body,
+                label,
+                ..
+            } => {
+                self.resolve_expr(iter);
+                self.loops.push(label.clone());
+                self.scoped(|resolver| {
+                    resolver.declare(var, stmt.span);
+                    if let Some((_, value_var)) = value_var {
+                        resolver.declare(value_var, stmt.span);
+                    }
+                    resolver.resolve_stmts(body);
+                });
+                self.loops.pop();
+            }
+            StmtKind::While { cond, body, label } => {
+                self.resolve_expr(cond);
+                self.loops.push(label.clone());
+                self.resolve_block(body);
+                self.loops.pop();
+            }
+            StmtKind::Loop { body, label } => {
+                self.loops.push(label.clone());
+                self.resolve_block(body);
+                self.loops.pop();
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    self.resolve_expr(value);
+                }
+            }
+            StmtKind::Try { body, var, handler } => {
+                self.resolve_block(body);
+                self.scoped(|resolver| {
+                    if let Some(var) = var {
+                        resolver.declare(var, stmt.span);
+                    }
+                    resolver.resolve_stmts(handler);
+                });
+            }
+            StmtKind::Raise(value) => self.resolve_expr(value),
+        }
+    }
+
+    fn error(&mut self, span: Span, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic::error(span, message));
+    }
+
+    /* Whether the value of `expr` is known before the program runs: literals,
+     * functions, types, other constants and the operators applied to them. */
+    fn is_constant(&self, expr: &Expr) -> bool {
+        match &expr.kind {
+            ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::Bool(_)
+            | ExprKind::Char(_)
+            | ExprKind::Str(_)
+            | ExprKind::Nil
+            | ExprKind::Func(_) => true,
+            ExprKind::Ident(_, binding) => binding
+                .get()
+                .is_some_and(|id| self.consts.contains(&id) || self.decls.contains(&id)),
+            ExprKind::Array(items) => items.iter().all(|item| self.is_constant(item)),
+            ExprKind::Repeat(value, count) => self.is_constant(value) && self.is_constant(count),
+            ExprKind::Table(entries) => entries
+                .iter()
+                .all(|(key, value)| self.is_constant(key) && self.is_constant(value)),
+            ExprKind::Unary(_, operand)
+            | ExprKind::Cast(_, operand)
+            | ExprKind::Field(operand, _) => self.is_constant(operand),
+            ExprKind::Binary(_, lhs, rhs) | ExprKind::Index(lhs, rhs) => {
+                self.is_constant(lhs) && self.is_constant(rhs)
+            }
+            ExprKind::SelF
+            | ExprKind::This
+            | ExprKind::Range { .. }
+            | ExprKind::Call(..)
+            | ExprKind::SafeField(..)
+            | ExprKind::Try(_)
+            | ExprKind::Yield(_) => false,
+        }
+    }
+
+    fn resolve_given(&mut self, given: &Given) {
+        self.resolve_expr(&given.subject);
+        for arm in &given.arms {
+            self.scoped(|resolver| {
+                for pattern in &arm.patterns {
+                    match &pattern.kind {
+                        PatternKind::Name {
+                            bindings: Some(bindings),
+                            ..
+                        } => {
+                            for binding in bindings {
+                                resolver.declare(binding, pattern.span);
+                            }
+                        }
+                        /* A bare name is a `const` in scope, or else a variant of the subject's enum. */
+                        PatternKind::Name {
+                            qualifier: None,
+                            name,
+                            bindings: None,
+                        } => {
+                            let id = resolver.lookup(name);
+                            if let Some(id) = id.filter(|id| resolver.consts.contains(id)) {
+                                pattern.constant.set(Some(id));
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                resolver.resolve_stmts(&arm.body);
+            });
+        }
+    }
+
+    /* The names in the `{name}` placeholders of a literal format string passed to
+     * `println` and friends. */
+    fn resolve_format(&mut self, callee: &Expr, args: &[Expr]) {
+        let (ExprKind::Ident(name, binding), Some(format)) = (&callee.kind, args.first()) else {
+            return;
+        };
+        let ExprKind::Str(text) = &format.kind else {
+            return;
+        };
+        let is_builtin = Builtin::ALL
+            .iter()
+            .any(|builtin| builtin.formats() && builtin.name() == name);
+        if !is_builtin || binding.get() != self.scopes[0].names.get(name).copied() {
+            return;
+        }
+        let Ok(pieces) = format::parse(text) else {
+            return;
+        };
+        for name in format::names(&pieces) {
+            if self.lookup(name).is_none() {
+                self.error(
+                    format.span,
+                    format!("Undefined name `{name}` in format string."),
+                );
+            }
+        }
+    }
+
+    /* Expressions */
+
+    fn resolve_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Ident(name, binding) => match self.lookup(name) {
+                Some(id) => binding.set(Some(id)),
+                None => self.diagnostics.push(Diagnostic::error(
+                    expr.span,
+                    format!("Undefined name `{name}`."),
+                )),
+            },
+            ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::Bool(_)
+            | ExprKind::Char(_)
+            | ExprKind::Str(_)
+            | ExprKind::Nil
+            | ExprKind::SelF
+            | ExprKind::This => (),
+            ExprKind::Array(items) => {
+                for item in items {
+                    self.resolve_expr(item);
+                }
+            }
+            ExprKind::Table(entries) => {
+                for (key, value) in entries {
+                    self.resolve_expr(key);
+                    self.resolve_expr(value);
+                }
+            }
+            ExprKind::Repeat(value, count) => {
+                self.resolve_expr(value);
+                self.resolve_expr(count);
+                if !self.is_constant(count) {
+                    self.error(
+                        count.span,
+                        "The size of an array must be known at compile time.",
+                    );
+                }
+            }
+            ExprKind::Unary(_, operand) | ExprKind::Cast(_, operand) | ExprKind::Try(operand) => {
+                self.resolve_expr(operand);
+            }
+            ExprKind::Yield(value) => {
+                if let Some(value) = value {
+                    self.resolve_expr(value);
+                }
+            }
+            ExprKind::Field(target, _) | ExprKind::SafeField(target, _) => {
+                self.resolve_expr(target);
+            }
+            ExprKind::Binary(_, lhs, rhs) | ExprKind::Index(lhs, rhs) => {
+                self.resolve_expr(lhs);
+                self.resolve_expr(rhs);
+            }
+            ExprKind::Range {
+                start, end, step, ..
+            } => {
+                self.resolve_expr(start);
+                self.resolve_expr(end);
+                if let Some(step) = step {
+                    self.resolve_expr(step);
+                }
+            }
+            ExprKind::Call(callee, args) => {
+                self.resolve_expr(callee);
+                for arg in args {
+                    self.resolve_expr(arg);
+                }
+                self.resolve_format(callee, args);
+            }
+            ExprKind::Func(func) => self.defer(Deferred::Func(Rc::clone(func), self.site())),
+        }
+    }
+}
+
+/* The variables declared with `global` inside the functions in `block`. */
+fn nested_globals(block: &Block, in_function: bool, names: &mut Vec<String>) {
+    for stmt in block {
+        match &stmt.kind {
+            StmtKind::VarDecl(decl) if in_function && decl.storage == Some(Storage::Global) => {
+                names.push(decl.name.clone());
+            }
+            StmtKind::Func(func) => nested_globals(&func.body, true, names),
+            StmtKind::Struct(decl) => {
+                for method in &decl.methods {
+                    nested_globals(&method.body, true, names);
+                }
+            }
+            StmtKind::If {
+                branches,
+                else_block,
+            } => {
+                for (_, body) in branches {
+                    nested_globals(body, in_function, names);
+                }
+                if let Some(body) = else_block {
+                    nested_globals(body, in_function, names);
+                }
+            }
+            StmtKind::Given(given) => {
+                for arm in &given.arms {
+                    nested_globals(&arm.body, in_function, names);
+                }
+            }
+            StmtKind::For { body, .. }
+            | StmtKind::While { body, .. }
+            | StmtKind::Loop { body, .. } => nested_globals(body, in_function, names),
+            StmtKind::Try { body, handler, .. } => {
+                nested_globals(body, in_function, names);
+                nested_globals(handler, in_function, names);
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn messages(source: &str) -> Vec<String> {
+        let program = parse(source).unwrap();
+        resolve(&program, &[])
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    /* The binding of each identifier in the statements `println(...)`, in order. */
+    fn printed_bindings(source: &str) -> Vec<Option<BindingId>> {
+        fn collect(block: &Block, out: &mut Vec<Option<BindingId>>) {
+            for stmt in block {
+                match &stmt.kind {
+                    StmtKind::Expr(Expr {
+                        kind: ExprKind::Call(_, args),
+                        ..
+                    }) => {
+                        for arg in args {
+                            if let ExprKind::Ident(_, binding) = &arg.kind {
+                                out.push(binding.get());
+                            }
+                        }
+                    }
+                    StmtKind::If { branches, .. } => {
+                        for (_, block) in branches {
+                            collect(block, out);
+                        }
+                    }
+                    StmtKind::Func(func) => collect(&func.body, out),
+                    _ => (),
+                }
+            }
+        }
+        let program = parse(source).unwrap();
+        assert!(resolve(&program, &[]).iter().all(|d| !d.is_error()));
+        let mut bindings = vec![];
+        collect(&program.stmts, &mut bindings);
+        bindings
+    }
+
+    #[test]
+    fn test_undefined_and_duplicate_names() {
+        let source = "println(a)\nint32 a = 1\nint32 a = 2\nfunc f(int32 b)\n\tint32 b = 3\n\tif true then\n\t\tint32 a = 4\n\tend\nend\nfor int32 i in 0 to 3 do\nend\nprintln(i)\nc = 5";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:1:9: Undefined name `a`.",
+                "Toolip:3:1: `a` is already declared in this scope.",
+                "Toolip:5:2: `b` is already declared in this scope.",
+                "Toolip:7:3: warning: `a` shadows a declaration in an enclosing scope.",
+                "Toolip:12:9: Undefined name `i`.",
+                "Toolip:13:1: Undefined name `c`.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_loop_labels() {
+        let source = "outer: for int32 i in 0 to 3 do\n\twhile true do\n\t\tbreak outer\n\tend\n\tcontinue inner\nend\nloop\n\tfunc f()\n\t\tbreak outer\n\tend\n\tbreak\nend";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:5:2: No enclosing loop is labelled `inner`.",
+                "Toolip:9:3: No enclosing loop is labelled `outer`.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hoisting_and_late_names() {
+        let source = "show()\nfunc show()\n\tprintln(later, Point(1))\nend\nint32 later = 5\nstruct Point\n\tint32 x = later\nend\nconst f = func (n) return f(n) end\nint32 println = 1";
+        assert!(messages(source).is_empty());
+    }
+
+    #[test]
+    fn test_binding_ids() {
+        let source = "int32 a = 1\nprintln(a)\nif true then\n\tint32 a = 2\n\tprintln(a)\nend\nfunc f()\n\tprintln(a)\nend";
+        let bindings = printed_bindings(source);
+        assert_eq!(bindings.len(), 3);
+        assert!(bindings.iter().all(Option::is_some));
+        assert_eq!(bindings[0], bindings[2]);
+        assert_ne!(bindings[0], bindings[1]);
+    }
+
+    #[test]
+    fn test_later_variables_are_out_of_sight() {
+        let source = "int32 a = 1\nprintln(a)\nfunc f()\n\tfunc g()\n\t\tprintln(a)\n\tend\n\tconst h = func () return h() end\n\tstring a = \"five\"\n\tfunc k()\n\t\tprintln(a)\n\tend\nend";
+        let bindings = printed_bindings(source);
+        assert_eq!(bindings.len(), 3);
+        assert_eq!(bindings[1], bindings[0]);
+        assert_ne!(bindings[2], bindings[0]);
+    }
+
+    #[test]
+    fn test_storage_rules() {
+        let source = "const LIMIT = 3\nconst int64 BIG = -LIMIT * 2\nLIMIT += 1\nconst xs = [LIMIT, BIG]\nxs[0] = 5\nconst n = count()\nconst int32 m\nstatic int32 s = 0\nfunc int32 count()\n\tstatic int32 calls = 0\n\tif true then\n\t\tstatic int32 bad = 0\n\t\tglobal int32 total = calls\n\tend\n\treturn calls\nend\nprintln(total)";
+        assert_eq!(
+            messages(source),
+            vec![
+                "Toolip:3:1: Cannot assign to the constant `LIMIT`.",
+                "Toolip:5:1: Cannot assign to the constant `xs`.",
+                "Toolip:6:16: The value of the constant `n` is not known at compile time.",
+                "Toolip:7:1: The constant `m` needs a value.",
+                "Toolip:8:1: A `static` variable must be declared at the top level of a function body.",
+                "Toolip:12:3: A `static` variable must be declared at the top level of a function body.",
+            ]
+        );
+    }
+}
@@ -61,6 +61,11 @@ pub const KEYWORDS: &[&str] = &[
     "true",
     "false",
     "nil",
+    "try",
+    "catch",
+    "raise",
+    "yield",
+    "continue",
 ];
 
 pub const SYMBOLS: &[&str] = &[
@@ -132,6 +137,11 @@ pub enum KeywordKind {
     True = 56,
     False = 57,
     Nil = 58,
+    Try = 59,
+    Catch = 60,
+    Raise = 61,
+    Yield = 62,
+    Continue = 63,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -200,11 +210,39 @@ pub enum SymbolKind {
     DollarSign = 60,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    pub const fn new(line: usize, col: usize) -> Self {
+        Self { line, col }
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Token {
     pub Type: TokenType,
     pub type_literal: String,
     pub value: String,
+    pub span: Span,
+}
+
+/* Two tokens are equal if they lex the same text, wherever they appear. */
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.Type == other.Type
+            && self.type_literal == other.type_literal
+            && self.value == other.value
+    }
 }
 
 impl Token {
@@ -214,6 +252,7 @@ impl Token {
             Type: tok_type,
             type_literal: tok_lit,
             value,
+            span: Span::default(),
         }
     }
 
@@ -351,6 +390,11 @@ const fn get_keyword_literal(a: &KeywordKind) -> &str {
         KeywordKind::True => "KW_True",
         KeywordKind::False => "KW_False",
         KeywordKind::Nil => "KW_Nil",
+        KeywordKind::Try => "KW_Try",
+        KeywordKind::Catch => "KW_Catch",
+        KeywordKind::Raise => "KW_Raise",
+        KeywordKind::Yield => "KW_Yield",
+        KeywordKind::Continue => "KW_Continue",
     }
 }
 
@@ -415,6 +459,11 @@ pub fn match_keyword_to_index(index: usize) -> KeywordKind {
         56 => KeywordKind::True,
         57 => KeywordKind::False,
         58 => KeywordKind::Nil,
+        59 => KeywordKind::Try,
+        60 => KeywordKind::Catch,
+        61 => KeywordKind::Raise,
+        62 => KeywordKind::Yield,
+        63 => KeywordKind::Continue,
         _ => KeywordKind::Illegal,
     }
 }
@@ -424,7 +473,7 @@ mod tests {
 
     #[test]
     fn test_len_keywords() {
-        assert_eq!(KEYWORDS.len(), 59);
+        assert_eq!(KEYWORDS.len(), 64);
     }
 
     #[test]
@@ -0,0 +1,388 @@
+#![allow(dead_code)]
+use crate::token::KeywordKind;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IntKind {
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+}
+
+impl IntKind {
+    pub const ALL: [Self; 10] = [
+        Self::U8,
+        Self::U16,
+        Self::U32,
+        Self::U64,
+        Self::U128,
+        Self::I8,
+        Self::I16,
+        Self::I32,
+        Self::I64,
+        Self::I128,
+    ];
+
+    pub const fn bits(self) -> u32 {
+        match self {
+            Self::U8 | Self::I8 => 8,
+            Self::U16 | Self::I16 => 16,
+            Self::U32 | Self::I32 => 32,
+            Self::U64 | Self::I64 => 64,
+            Self::U128 | Self::I128 => 128,
+        }
+    }
+
+    pub const fn is_signed(self) -> bool {
+        matches!(
+            self,
+            Self::I8 | Self::I16 | Self::I32 | Self::I64 | Self::I128
+        )
+    }
+
+    pub const fn min(self) -> i128 {
+        match self {
+            Self::U8 | Self::U16 | Self::U32 | Self::U64 | Self::U128 => 0,
+            Self::I8 => i8::MIN as i128,
+            Self::I16 => i16::MIN as i128,
+            Self::I32 => i32::MIN as i128,
+            Self::I64 => i64::MIN as i128,
+            Self::I128 => i128::MIN,
+        }
+    }
+
+    pub const fn max(self) -> u128 {
+        match self {
+            Self::U8 => u8::MAX as u128,
+            Self::U16 => u16::MAX as u128,
+            Self::U32 => u32::MAX as u128,
+            Self::U64 => u64::MAX as u128,
+            Self::U128 => u128::MAX,
+            Self::I8 => i8::MAX as u128,
+            Self::I16 => i16::MAX as u128,
+            Self::I32 => i32::MAX as u128,
+            Self::I64 => i64::MAX as u128,
+            Self::I128 => i128::MAX as u128,
+        }
+    }
+
+    /* Whether a literal with the given sign and magnitude fits in this type. */
+    pub const fn fits_literal(self, negative: bool, magnitude: u128) -> bool {
+        if negative {
+            magnitude == 0 || magnitude <= self.min().unsigned_abs()
+        } else {
+            magnitude <= self.max()
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::U8 => "uint8",
+            Self::U16 => "uint16",
+            Self::U32 => "uint32",
+            Self::U64 => "uint64",
+            Self::U128 => "uint128",
+            Self::I8 => "int8",
+            Self::I16 => "int16",
+            Self::I32 => "int32",
+            Self::I64 => "int64",
+            Self::I128 => "int128",
+        }
+    }
+
+    pub const fn from_keyword(keyword: KeywordKind) -> Option<Self> {
+        match keyword {
+            KeywordKind::Uint8 => Some(Self::U8),
+            KeywordKind::Uint16 => Some(Self::U16),
+            KeywordKind::Uint32 => Some(Self::U32),
+            KeywordKind::Uint64 => Some(Self::U64),
+            KeywordKind::Uint128 => Some(Self::U128),
+            KeywordKind::Int8 => Some(Self::I8),
+            KeywordKind::Int16 => Some(Self::I16),
+            KeywordKind::Int32 => Some(Self::I32),
+            KeywordKind::Int64 => Some(Self::I64),
+            KeywordKind::Int128 => Some(Self::I128),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FloatKind {
+    F32,
+    F64,
+}
+
+impl FloatKind {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::F32 => "flt32",
+            Self::F64 => "flt64",
+        }
+    }
+
+    /* Bits of precision, counting the implicit leading one. */
+    pub const fn mantissa_digits(self) -> u32 {
+        match self {
+            Self::F32 => f32::MANTISSA_DIGITS,
+            Self::F64 => f64::MANTISSA_DIGITS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Type {
+    /* The type of a function that returns nothing. */
+    Void,
+    Nil,
+    Bool,
+    Int(IntKind),
+    Float(FloatKind),
+    Char,
+    String,
+    Array(Box<Type>),
+    List(Box<Type>),
+    Set(Box<Type>),
+    Table(Box<Type>, Box<Type>),
+    Func(Vec<Type>, Box<Type>),
+    /* `coroutine<int32>`: a coroutine that yields `int32`s. */
+    Coroutine(Box<Type>),
+    /* `range<int32>`: a range of `int32`s, such as `0 to 10`. */
+    Range(Box<Type>),
+    /* `int32?`: an `int32` or `nil`. */
+    Optional(Box<Type>),
+    /* A user-defined type (enum, struct, ...) not yet resolved by the checker. */
+    Named(String, Vec<Type>),
+    /* A type the checker is still inferring; it never reaches the evaluator. */
+    Var(usize),
+    #[default]
+    Unknown,
+}
+
+impl Type {
+    pub const fn is_int(&self) -> bool {
+        matches!(self, Self::Int(_))
+    }
+
+    pub const fn is_float(&self) -> bool {
+        matches!(self, Self::Float(_))
+    }
+
+    pub const fn is_numeric(&self) -> bool {
+        matches!(self, Self::Int(_) | Self::Float(_))
+    }
+
+    /* `T?`; `nil`, unknown and already optional types are left as they are. */
+    pub fn optional(self) -> Self {
+        match self {
+            Self::Nil | Self::Unknown | Self::Optional(_) => self,
+            ty => Self::Optional(Box::new(ty)),
+        }
+    }
+
+    /* Whether `nil` is a value of this type, as it is of `T?` and of unknown types. */
+    pub const fn admits_nil(&self) -> bool {
+        matches!(
+            self,
+            Self::Nil | Self::Void | Self::Unknown | Self::Var(_) | Self::Optional(_)
+        )
+    }
+
+    /* `T` for `T?`, the type of the values other than `nil`. */
+    pub fn non_optional(&self) -> &Self {
+        match self {
+            Self::Optional(inner) => inner,
+            ty => ty,
+        }
+    }
+
+    /* Whether every value of this numeric type is exactly representable in `target`,
+     * so that it converts implicitly, as `int16` to `int64` or `uint8` to `flt32`. */
+    pub const fn widens_to(&self, target: &Self) -> bool {
+        match (self, target) {
+            (Self::Int(from), Self::Int(to)) => {
+                if from.is_signed() == to.is_signed() {
+                    from.bits() < to.bits()
+                } else {
+                    !from.is_signed() && from.bits() < to.bits()
+                }
+            }
+            (Self::Int(from), Self::Float(to)) => from.bits() < to.mantissa_digits(),
+            (Self::Float(FloatKind::F32), Self::Float(FloatKind::F64)) => true,
+            _ => false,
+        }
+    }
+
+    pub fn from_keyword(keyword: KeywordKind) -> Option<Self> {
+        if let Some(kind) = IntKind::from_keyword(keyword) {
+            return Some(Self::Int(kind));
+        }
+        match keyword {
+            KeywordKind::Bool => Some(Self::Bool),
+            KeywordKind::Flt32 => Some(Self::Float(FloatKind::F32)),
+            KeywordKind::Flt64 => Some(Self::Float(FloatKind::F64)),
+            KeywordKind::Char => Some(Self::Char),
+            KeywordKind::String => Some(Self::String),
+            _ => None,
+        }
+    }
+
+    /* Rebuilds the type bottom-up, replacing each named type `f` returns `Some` for.
+     * The arguments given to `f` have already been mapped. */
+    pub fn map_named(&self, f: &mut impl FnMut(&str, &[Type]) -> Option<Type>) -> Self {
+        let mut map = |ty: &Self| Box::new(ty.map_named(f));
+        match self {
+            Self::Array(elem) => Self::Array(map(elem)),
+            Self::List(elem) => Self::List(map(elem)),
+            Self::Set(elem) => Self::Set(map(elem)),
+            Self::Coroutine(elem) => Self::Coroutine(map(elem)),
+            Self::Range(elem) => Self::Range(map(elem)),
+            Self::Table(key, value) => Self::Table(map(key), map(value)),
+            Self::Func(params, ret) => Self::Func(
+                params.iter().map(|param| param.map_named(f)).collect(),
+                Box::new(ret.map_named(f)),
+            ),
+            Self::Optional(inner) => Self::Optional(map(inner)),
+            Self::Named(name, args) => {
+                let args = args.iter().map(|arg| arg.map_named(f)).collect::<Vec<_>>();
+                f(name, &args).unwrap_or(Self::Named(name.clone(), args))
+            }
+            _ => self.clone(),
+        }
+    }
+
+    /* Rebuilds the type bottom-up, replacing each type variable `f` returns `Some` for. */
+    pub fn map_vars(&self, f: &mut impl FnMut(usize) -> Option<Type>) -> Self {
+        let mut map = |ty: &Self| Box::new(ty.map_vars(f));
+        match self {
+            Self::Array(elem) => Self::Array(map(elem)),
+            Self::List(elem) => Self::List(map(elem)),
+            Self::Set(elem) => Self::Set(map(elem)),
+            Self::Coroutine(elem) => Self::Coroutine(map(elem)),
+            Self::Range(elem) => Self::Range(map(elem)),
+            Self::Table(key, value) => Self::Table(map(key), map(value)),
+            Self::Func(params, ret) => Self::Func(
+                params.iter().map(|param| param.map_vars(f)).collect(),
+                Box::new(ret.map_vars(f)),
+            ),
+            Self::Optional(inner) => Self::Optional(map(inner)),
+            Self::Named(name, args) => Self::Named(
+                name.clone(),
+                args.iter().map(|arg| arg.map_vars(f)).collect(),
+            ),
+            Self::Var(var) => f(*var).unwrap_or(Self::Var(*var)),
+            _ => self.clone(),
+        }
+    }
+
+    pub fn contains_var(&self, var: usize) -> bool {
+        let mut found = false;
+        self.map_vars(&mut |other| {
+            found |= other == var;
+            None
+        });
+        found
+    }
+
+    /* Replaces the type parameters `params` with `args`; missing arguments are unknown. */
+    pub fn substitute(&self, params: &[String], args: &[Type]) -> Self {
+        self.map_named(&mut |name, _| {
+            let index = params.iter().position(|param| param == name)?;
+            Some(args.get(index).cloned().unwrap_or(Self::Unknown))
+        })
+    }
+}
+
+fn write_list(f: &mut fmt::Formatter<'_>, types: &[Type]) -> fmt::Result {
+    for (i, ty) in types.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{ty}")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Void => write!(f, "void"),
+            Self::Nil => write!(f, "nil"),
+            Self::Bool => write!(f, "bool"),
+            Self::Int(kind) => write!(f, "{}", kind.name()),
+            Self::Float(kind) => write!(f, "{}", kind.name()),
+            Self::Char => write!(f, "char"),
+            Self::String => write!(f, "string"),
+            Self::Array(elem) => write!(f, "array<{elem}>"),
+            Self::List(elem) => write!(f, "list<{elem}>"),
+            Self::Set(elem) => write!(f, "set<{elem}>"),
+            Self::Coroutine(elem) => write!(f, "coroutine<{elem}>"),
+            Self::Range(elem) => write!(f, "range<{elem}>"),
+            Self::Table(key, value) => write!(f, "table<{key}, {value}>"),
+            Self::Func(params, ret) => {
+                write!(f, "func(")?;
+                write_list(f, params)?;
+                write!(f, ") -> {ret}")
+            }
+            Self::Optional(inner) => write!(f, "{inner}?"),
+            Self::Named(name, args) => {
+                write!(f, "{name}")?;
+                if !args.is_empty() {
+                    write!(f, "<")?;
+                    write_list(f, args)?;
+                    write!(f, ">")?;
+                }
+                Ok(())
+            }
+            Self::Var(var) => write!(f, "?{var}"),
+            Self::Unknown => write!(f, "<unknown>"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_int_kind_bounds() {
+        assert_eq!(IntKind::I8.min(), -128);
+        assert_eq!(IntKind::I8.max(), 127);
+        assert_eq!(IntKind::U128.max(), u128::MAX);
+        assert!(IntKind::I8.fits_literal(true, 128));
+        assert!(!IntKind::I8.fits_literal(false, 128));
+        assert!(!IntKind::U8.fits_literal(true, 1));
+    }
+
+    #[test]
+    fn test_type_display() {
+        let ty = Type::Table(
+            Box::new(Type::String),
+            Box::new(Type::List(Box::new(Type::Int(IntKind::I32)))),
+        );
+        assert_eq!(ty.to_string(), "table<string, list<int32>>");
+        let optional = Type::List(Box::new(Type::Bool.optional().optional()));
+        assert_eq!(optional.to_string(), "list<bool?>");
+    }
+
+    #[test]
+    fn test_substitute() {
+        let param = |name: &str| Type::Named(name.to_owned(), vec![]);
+        let ty = Type::Func(
+            vec![Type::List(Box::new(param("T")))],
+            Box::new(Type::Named("Pair".to_owned(), vec![param("T"), param("U")])),
+        );
+        let params = ["T".to_owned(), "U".to_owned()];
+        assert_eq!(
+            ty.substitute(&params, &[Type::Bool]).to_string(),
+            "func(list<bool>) -> Pair<bool, <unknown>>"
+        );
+    }
+}
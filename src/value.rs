@@ -0,0 +1,1204 @@
+#![allow(dead_code)]
+use crate::ast::{BinaryOp, BindingId, EnumDecl, FuncDecl, StructDecl, TypeAlias};
+use crate::compiler::Function;
+use crate::ops::{self, Overflow};
+use crate::types::{FloatKind, IntKind, Type};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+/* A handle to an object on the `Heap`. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(pub u32);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    UInt8(u8),
+    UInt16(u16),
+    UInt32(u32),
+    UInt64(u64),
+    UInt128(u128),
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    Int128(i128),
+    Flt32(f32),
+    Flt64(f64),
+    Char(char),
+    Str(Rc<str>),
+    Obj(ObjRef),
+    Builtin(Builtin),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Builtin {
+    Print,
+    Println,
+    Eprint,
+    Eprintln,
+    Format,
+    Discriminant,
+    /* Only reachable as `EnumName.fromDiscriminant`, bound to the enum type. */
+    FromDiscriminant,
+    /* `wrappingAdd(a, b)`, `saturatingPow(a, b)` and so on: an arithmetic operator
+     * that wraps or saturates on integer overflow instead of trapping. */
+    Arithmetic(BinaryOp, Overflow),
+    /* `collectGarbage()`: frees unreachable objects and returns the live count. */
+    CollectGarbage,
+    /* `heapSize()`: the number of live objects on the heap. */
+    HeapSize,
+    /* `error(kind, message)`, or `error(message)` of kind `"error"`. */
+    Error,
+    IsError,
+    /* `resume(c)` or `resume(c, value)`: runs a coroutine until it yields. */
+    Resume,
+    /* `status(c)`: "suspended", "running" or "dead". */
+    Status,
+}
+
+impl Builtin {
+    /* The builtins defined as globals. */
+    pub const ALL: &'static [Self] = &[
+        Self::Print,
+        Self::Println,
+        Self::Eprint,
+        Self::Eprintln,
+        Self::Format,
+        Self::Discriminant,
+        Self::Arithmetic(BinaryOp::Add, Overflow::Wrap),
+        Self::Arithmetic(BinaryOp::Sub, Overflow::Wrap),
+        Self::Arithmetic(BinaryOp::Mul, Overflow::Wrap),
+        Self::Arithmetic(BinaryOp::Div, Overflow::Wrap),
+        Self::Arithmetic(BinaryOp::FloorDiv, Overflow::Wrap),
+        Self::Arithmetic(BinaryOp::Pow, Overflow::Wrap),
+        Self::Arithmetic(BinaryOp::Add, Overflow::Saturate),
+        Self::Arithmetic(BinaryOp::Sub, Overflow::Saturate),
+        Self::Arithmetic(BinaryOp::Mul, Overflow::Saturate),
+        Self::Arithmetic(BinaryOp::Div, Overflow::Saturate),
+        Self::Arithmetic(BinaryOp::FloorDiv, Overflow::Saturate),
+        Self::Arithmetic(BinaryOp::Pow, Overflow::Saturate),
+        Self::CollectGarbage,
+        Self::HeapSize,
+        Self::Error,
+        Self::IsError,
+        Self::Resume,
+        Self::Status,
+    ];
+
+    /* Whether the first argument is a format string when it is a string. */
+    pub const fn formats(self) -> bool {
+        matches!(
+            self,
+            Self::Print | Self::Println | Self::Eprint | Self::Eprintln | Self::Format
+        )
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Print => "print",
+            Self::Println => "println",
+            Self::Eprint => "eprint",
+            Self::Eprintln => "eprintln",
+            Self::Format => "format",
+            Self::Discriminant => "discriminant",
+            Self::FromDiscriminant => "fromDiscriminant",
+            Self::CollectGarbage => "collectGarbage",
+            Self::HeapSize => "heapSize",
+            Self::Error => "error",
+            Self::IsError => "isError",
+            Self::Resume => "resume",
+            Self::Status => "status",
+            Self::Arithmetic(op, Overflow::Wrap) => match op {
+                BinaryOp::Add => "wrappingAdd",
+                BinaryOp::Sub => "wrappingSub",
+                BinaryOp::Mul => "wrappingMul",
+                BinaryOp::Div => "wrappingDiv",
+                BinaryOp::FloorDiv => "wrappingFloorDiv",
+                _ => "wrappingPow",
+            },
+            Self::Arithmetic(op, _) => match op {
+                BinaryOp::Add => "saturatingAdd",
+                BinaryOp::Sub => "saturatingSub",
+                BinaryOp::Mul => "saturatingMul",
+                BinaryOp::Div => "saturatingDiv",
+                BinaryOp::FloorDiv => "saturatingFloorDiv",
+                _ => "saturatingPow",
+            },
+        }
+    }
+}
+
+impl Value {
+    pub const fn int_kind(&self) -> Option<IntKind> {
+        match self {
+            Self::UInt8(_) => Some(IntKind::U8),
+            Self::UInt16(_) => Some(IntKind::U16),
+            Self::UInt32(_) => Some(IntKind::U32),
+            Self::UInt64(_) => Some(IntKind::U64),
+            Self::UInt128(_) => Some(IntKind::U128),
+            Self::Int8(_) => Some(IntKind::I8),
+            Self::Int16(_) => Some(IntKind::I16),
+            Self::Int32(_) => Some(IntKind::I32),
+            Self::Int64(_) => Some(IntKind::I64),
+            Self::Int128(_) => Some(IntKind::I128),
+            _ => None,
+        }
+    }
+
+    pub const fn float_kind(&self) -> Option<FloatKind> {
+        match self {
+            Self::Flt32(_) => Some(FloatKind::F32),
+            Self::Flt64(_) => Some(FloatKind::F64),
+            _ => None,
+        }
+    }
+
+    pub fn numeric_type(&self) -> Option<Type> {
+        self.int_kind()
+            .map(Type::Int)
+            .or_else(|| self.float_kind().map(Type::Float))
+    }
+
+    /* Whether this is an integer from `start` up to `end`, matching a range pattern. */
+    pub fn int_in(&self, start: i128, end: i128, inclusive: bool) -> bool {
+        let Some((negative, magnitude)) = self.int_parts() else {
+            return false;
+        };
+        let int = match negative {
+            true => 0i128.checked_sub_unsigned(magnitude),
+            false => i128::try_from(magnitude).ok(),
+        };
+        let Some(int) = int else {
+            return false;
+        };
+        start <= int && (int < end || (inclusive && int == end))
+    }
+
+    /* An integer as its sign and magnitude, which every width converts to losslessly. */
+    pub fn int_parts(&self) -> Option<(bool, u128)> {
+        let signed = |int: i128| (int < 0, int.unsigned_abs());
+        match *self {
+            Self::UInt8(int) => Some((false, int.into())),
+            Self::UInt16(int) => Some((false, int.into())),
+            Self::UInt32(int) => Some((false, int.into())),
+            Self::UInt64(int) => Some((false, int.into())),
+            Self::UInt128(int) => Some((false, int)),
+            Self::Int8(int) => Some(signed(int.into())),
+            Self::Int16(int) => Some(signed(int.into())),
+            Self::Int32(int) => Some(signed(int.into())),
+            Self::Int64(int) => Some(signed(int.into())),
+            Self::Int128(int) => Some(signed(int)),
+            _ => None,
+        }
+    }
+
+    /* Builds an integer of the given width, or `None` if it does not fit. */
+    pub fn from_int_parts(kind: IntKind, negative: bool, magnitude: u128) -> Option<Self> {
+        if !kind.fits_literal(negative, magnitude) {
+            return None;
+        }
+        if !kind.is_signed() {
+            return Some(match kind {
+                IntKind::U8 => Self::UInt8(magnitude as u8),
+                IntKind::U16 => Self::UInt16(magnitude as u16),
+                IntKind::U32 => Self::UInt32(magnitude as u32),
+                IntKind::U64 => Self::UInt64(magnitude as u64),
+                _ => Self::UInt128(magnitude),
+            });
+        }
+        /* Fits in the signed width, so wrapping negation of the magnitude is exact. */
+        let int = if negative {
+            (magnitude as i128).wrapping_neg()
+        } else {
+            magnitude as i128
+        };
+        Some(match kind {
+            IntKind::I8 => Self::Int8(int as i8),
+            IntKind::I16 => Self::Int16(int as i16),
+            IntKind::I32 => Self::Int32(int as i32),
+            IntKind::I64 => Self::Int64(int as i64),
+            _ => Self::Int128(int),
+        })
+    }
+
+    pub const fn is_nil(&self) -> bool {
+        matches!(self, Self::Nil)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub value: Value,
+    pub ty: Type,
+    /* Declared with `const`, so that it cannot be assigned. */
+    pub constant: bool,
+    /* The declaration of a variable the evaluator declares in a function or block. */
+    pub id: Option<BindingId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Env {
+    pub vars: HashMap<String, Binding>,
+    pub parent: Option<ObjRef>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Closure {
+    pub decl: Rc<FuncDecl>,
+    pub env: ObjRef,
+    /* The struct type a method belongs to. */
+    pub owner: Option<ObjRef>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledClosure {
+    pub function: Rc<Function>,
+    /* The cells of the variables it captures, in the order the function numbers them. */
+    pub captures: Vec<ObjRef>,
+    /* The cells of its `static` variables, with whether each was initialised. */
+    pub statics: Vec<(ObjRef, bool)>,
+}
+
+/* A struct type of the virtual machine. Its declaration only has the name and fields,
+ * typed as the machine checks them; the field defaults are the array returned by
+ * `init`, and `methods` are closures whose `this` is the struct type. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledStruct {
+    pub decl: Rc<StructDecl>,
+    pub init: ObjRef,
+    pub methods: Vec<ObjRef>,
+}
+
+impl CompiledStruct {
+    pub fn method(&self, heap: &Heap, name: &str) -> Option<ObjRef> {
+        self.methods.iter().copied().find(|method| {
+            matches!(heap.get(*method), Object::CompiledClosure(closure)
+                if closure.function.name.as_deref() == Some(name))
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructType {
+    pub decl: Rc<StructDecl>,
+    pub env: ObjRef,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instance {
+    pub ty: ObjRef,
+    pub fields: Vec<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumType {
+    pub decl: Rc<EnumDecl>,
+    pub env: ObjRef,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumValue {
+    pub ty: ObjRef,
+    pub variant: usize,
+    pub payload: Vec<Value>,
+}
+
+/* A failure as a value: made by `error(kind, message)`, and caught by `catch` when
+ * raised. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorValue {
+    pub kind: Rc<str>,
+    pub message: Rc<str>,
+}
+
+impl ErrorValue {
+    /* `.kind` and `.message`. */
+    pub fn field(&self, name: &str) -> Option<Value> {
+        match name {
+            "kind" => Some(Value::Str(Rc::clone(&self.kind))),
+            "message" => Some(Value::Str(Rc::clone(&self.message))),
+            _ => None,
+        }
+    }
+}
+
+/* The entries of a table in the order their keys were first added, each key once.
+ * The key and value types it was made as type the keys and values given to it. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub key: Type,
+    pub value: Type,
+    pub entries: Vec<(Value, Value)>,
+}
+
+/* `start to end step n`: integers of one type from `start`, counting down for a
+ * negative `n`, up to `end`, which only an inclusive range (`through`) visits. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeValue {
+    pub start: Value,
+    pub end: Value,
+    pub step: Value,
+    pub inclusive: bool,
+}
+
+impl RangeValue {
+    pub fn iter(&self) -> RangeIter {
+        RangeIter {
+            next: Some(self.start.clone()),
+            range: self.clone(),
+        }
+    }
+
+    /* Whether `value`, reached by stepping from the start, is not past the end. */
+    pub fn reaches(&self, value: &Value) -> bool {
+        let Some((down, _)) = self.step.int_parts() else {
+            return false;
+        };
+        match ops::compare(value, &self.end) {
+            Some(Ordering::Equal) => self.inclusive,
+            Some(Ordering::Less) => !down,
+            Some(Ordering::Greater) => down,
+            None => false,
+        }
+    }
+
+    /* The integer a step after `value`; stepping past the largest value of the type
+     * ends the range. */
+    pub fn after(&self, value: &Value) -> Option<Value> {
+        ops::binary(BinaryOp::Add, value, &self.step).ok()
+    }
+
+    /* Whether the range visits `value`, an integer of its type. */
+    pub fn contains(&self, value: &Value) -> bool {
+        let (Some(start), Some(end), Some((down, step))) = (
+            ops::compare(value, &self.start),
+            ops::compare(value, &self.end),
+            self.step.int_parts(),
+        ) else {
+            return false;
+        };
+        let (before, after) = match down {
+            true => (Ordering::Greater, Ordering::Less),
+            false => (Ordering::Less, Ordering::Greater),
+        };
+        if start == before || end == after || (end == Ordering::Equal && !self.inclusive) {
+            return false;
+        }
+        let (Some(a), Some(b)) = (value.int_parts(), self.start.int_parts()) else {
+            return false;
+        };
+        /* The distance between two integers of one type always fits in a `u128`. */
+        let distance = match a.0 == b.0 {
+            true => a.1.abs_diff(b.1),
+            false => a.1 + b.1,
+        };
+        distance % step == 0
+    }
+}
+
+/* The integers of a range not visited yet. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeIter {
+    next: Option<Value>,
+    range: RangeValue,
+}
+
+impl Iterator for RangeIter {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        let next = self.next.take()?;
+        if !self.range.reaches(&next) {
+            return None;
+        }
+        self.next = self.range.after(&next);
+        Some(next)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoroutineStatus {
+    Suspended,
+    Running,
+    Dead,
+}
+
+impl CoroutineStatus {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Suspended => "suspended",
+            Self::Running => "running",
+            Self::Dead => "dead",
+        }
+    }
+}
+
+/* A call of a `coroutine` function, which runs when resumed until it yields. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coroutine {
+    pub decl: Rc<FuncDecl>,
+    /* The type of the values it yields. */
+    pub ret: Type,
+    /* The scope of the body, holding the arguments. */
+    pub env: ObjRef,
+    pub statics: Option<ObjRef>,
+    pub status: CoroutineStatus,
+    /* Where it is suspended, outermost block first; empty until it starts. */
+    pub cursors: Vec<Cursor>,
+}
+
+/* A coroutine of the virtual machine, which keeps the frame of its closure while it
+ * is suspended. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledCoroutine {
+    pub closure: ObjRef,
+    pub status: CoroutineStatus,
+    /* The instruction it goes on from, 0 until it starts. */
+    pub ip: usize,
+    /* Its local slots and the values it was computing, only the arguments until it
+     * starts. */
+    pub stack: Vec<Value>,
+    /* The `try` blocks it is suspended in, as the height of its stack at their start
+     * and their handler, innermost last. */
+    pub tries: Vec<(usize, u32)>,
+}
+
+/* Where a suspended coroutine is in one of the blocks it is executing. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    /* The statement being executed. */
+    pub index: usize,
+    /* The scope of the block. */
+    pub env: ObjRef,
+    /* Which block of the enclosing statement this is: the branch of an `if` or the
+     * arm of a `given` taken, or for a `try`, 0 for its body and 1 for its handler. */
+    pub branch: usize,
+    /* The items left to the `for` loop this is the body of. */
+    pub iteration: Option<Iteration>,
+}
+
+impl Cursor {
+    /* Pushes the objects a suspended or running coroutine keeps here onto `out`. */
+    pub fn trace(&self, out: &mut Vec<ObjRef>) {
+        out.push(self.env);
+        match &self.iteration {
+            Some(Iteration::Items { items, .. }) => {
+                out.extend(items.iter().filter_map(|item| match item {
+                    Value::Obj(obj) => Some(*obj),
+                    _ => None,
+                }));
+            }
+            Some(Iteration::Entries { entries, .. }) => {
+                out.extend(
+                    entries
+                        .iter()
+                        .flat_map(|(key, value)| [key, value])
+                        .filter_map(|item| match item {
+                            Value::Obj(obj) => Some(*obj),
+                            _ => None,
+                        }),
+                );
+            }
+            Some(Iteration::Coroutine(obj) | Iteration::Next(Value::Obj(obj))) => out.push(*obj),
+            Some(Iteration::Range(_) | Iteration::Next(_)) | None => (),
+        }
+    }
+}
+
+/* The items a `for` loop has left. */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Iteration {
+    Items {
+        items: Vec<Value>,
+        next: usize,
+    },
+    /* The keys and values of a table. */
+    Entries {
+        entries: Vec<(Value, Value)>,
+        next: usize,
+    },
+    Range(RangeIter),
+    /* The values a coroutine yields until it returns. */
+    Coroutine(ObjRef),
+    /* The values the `next` method of a struct returns until it returns `nil`. */
+    Next(Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    Array(Vec<Value>),
+    /* The items of a set in the order they were added, each once. */
+    Set(Vec<Value>),
+    Table(Table),
+    Instance(Instance),
+    StructType(StructType),
+    Closure(Closure),
+    BoundMethod { receiver: Value, method: ObjRef },
+    /* A builtin called with `receiver` as its first argument. */
+    BoundBuiltin { receiver: Value, builtin: Builtin },
+    EnumType(EnumType),
+    EnumValue(EnumValue),
+    /* `Shape.Circle` for a variant with a payload. */
+    VariantConstructor { ty: ObjRef, variant: usize },
+    TypeAlias { alias: Rc<TypeAlias>, env: ObjRef },
+    /* `self` inside a plain function: the call being executed. */
+    Call { args: ObjRef },
+    Env(Env),
+    /* A function of the bytecode virtual machine. */
+    CompiledClosure(CompiledClosure),
+    CompiledStruct(CompiledStruct),
+    /* An enum type of the virtual machine, its payloads typed as the machine checks
+     * them. */
+    CompiledEnum(Rc<EnumDecl>),
+    /* A variable of the virtual machine captured by a closure, shared with it. */
+    Cell(Value),
+    Error(ErrorValue),
+    Coroutine(Coroutine),
+    CompiledCoroutine(CompiledCoroutine),
+    Range(RangeValue),
+}
+
+impl Object {
+    /* Pushes every object this one refers to onto `out`. */
+    fn trace(&self, out: &mut Vec<ObjRef>) {
+        let values = |out: &mut Vec<ObjRef>, values: &mut dyn Iterator<Item = &Value>| {
+            out.extend(values.filter_map(|value| match value {
+                Value::Obj(obj) => Some(*obj),
+                _ => None,
+            }));
+        };
+        match self {
+            Self::Array(items) | Self::Set(items) => values(out, &mut items.iter()),
+            Self::Table(table) => values(
+                out,
+                &mut table.entries.iter().flat_map(|(key, value)| [key, value]),
+            ),
+            Self::Instance(Instance { ty, fields }) => {
+                out.push(*ty);
+                values(out, &mut fields.iter());
+            }
+            Self::StructType(StructType { env, .. })
+            | Self::EnumType(EnumType { env, .. })
+            | Self::TypeAlias { env, .. } => out.push(*env),
+            Self::Closure(Closure { env, owner, .. }) => {
+                out.push(*env);
+                out.extend(owner);
+            }
+            Self::BoundMethod { receiver, method } => {
+                out.push(*method);
+                values(out, &mut std::iter::once(receiver));
+            }
+            Self::BoundBuiltin { receiver, .. } => values(out, &mut std::iter::once(receiver)),
+            Self::EnumValue(EnumValue { ty, payload, .. }) => {
+                out.push(*ty);
+                values(out, &mut payload.iter());
+            }
+            Self::VariantConstructor { ty, .. } => out.push(*ty),
+            Self::Call { args } => out.push(*args),
+            Self::Env(Env { vars, parent }) => {
+                out.extend(parent);
+                values(out, &mut vars.values().map(|binding| &binding.value));
+            }
+            Self::CompiledClosure(CompiledClosure {
+                captures, statics, ..
+            }) => {
+                out.extend(captures);
+                out.extend(statics.iter().map(|(cell, _)| *cell));
+            }
+            Self::CompiledStruct(CompiledStruct { init, methods, .. }) => {
+                out.push(*init);
+                out.extend(methods);
+            }
+            Self::CompiledEnum(_) => (),
+            Self::Cell(contents) => values(out, &mut std::iter::once(contents)),
+            Self::Error(_) | Self::Range(_) => (),
+            Self::Coroutine(coroutine) => {
+                out.push(coroutine.env);
+                out.extend(coroutine.statics);
+                for cursor in &coroutine.cursors {
+                    cursor.trace(out);
+                }
+            }
+            Self::CompiledCoroutine(coroutine) => {
+                out.push(coroutine.closure);
+                values(out, &mut coroutine.stack.iter());
+            }
+        }
+    }
+}
+
+/* The number of live objects below which the heap is never collected. */
+const MIN_COLLECTION: usize = 1024;
+
+#[derive(Debug)]
+pub struct Heap {
+    objects: Vec<Option<Object>>,
+    free: Vec<u32>,
+    /* The live count at which `should_collect` asks for a collection. */
+    next_collection: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self {
+            objects: Vec::new(),
+            free: Vec::new(),
+            next_collection: MIN_COLLECTION,
+        }
+    }
+}
+
+impl Heap {
+    pub fn alloc(&mut self, object: Object) -> ObjRef {
+        if let Some(index) = self.free.pop() {
+            self.objects[index as usize] = Some(object);
+            return ObjRef(index);
+        }
+        self.objects.push(Some(object));
+        ObjRef(u32::try_from(self.objects.len() - 1).expect("heap exhausted"))
+    }
+
+    pub fn get(&self, obj: ObjRef) -> &Object {
+        self.objects[obj.0 as usize]
+            .as_ref()
+            .expect("use of a freed object")
+    }
+
+    pub fn get_mut(&mut self, obj: ObjRef) -> &mut Object {
+        self.objects[obj.0 as usize]
+            .as_mut()
+            .expect("use of a freed object")
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    /* Whether enough objects were allocated since the last collection to run
+     * another. Only checked where every live value is reachable from the roots. */
+    pub fn should_collect(&self) -> bool {
+        self.len() >= self.next_collection
+    }
+
+    /* Frees every object not reachable from `roots` (mark and sweep). Returns the
+     * number of objects freed. */
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = ObjRef>) -> usize {
+        let mut marked = vec![false; self.objects.len()];
+        let mut pending: Vec<ObjRef> = roots.into_iter().collect();
+        while let Some(obj) = pending.pop() {
+            let index = obj.0 as usize;
+            if marked[index] {
+                continue;
+            }
+            marked[index] = true;
+            self.get(obj).trace(&mut pending);
+        }
+        let mut freed = 0;
+        for (index, object) in self.objects.iter_mut().enumerate() {
+            if object.is_some() && !marked[index] {
+                *object = None;
+                self.free.push(index as u32);
+                freed += 1;
+            }
+        }
+        self.next_collection = (self.len() * 2).max(MIN_COLLECTION);
+        freed
+    }
+
+    pub fn struct_type(&self, obj: ObjRef) -> Option<&StructType> {
+        match self.get(obj) {
+            Object::StructType(ty) => Some(ty),
+            _ => None,
+        }
+    }
+
+    pub fn enum_type(&self, obj: ObjRef) -> Option<&EnumType> {
+        match self.get(obj) {
+            Object::EnumType(ty) => Some(ty),
+            _ => None,
+        }
+    }
+
+    /* The declaration of a struct type of either backend. */
+    pub fn struct_decl(&self, obj: ObjRef) -> Option<&StructDecl> {
+        match self.get(obj) {
+            Object::StructType(ty) => Some(&ty.decl),
+            Object::CompiledStruct(ty) => Some(&ty.decl),
+            _ => None,
+        }
+    }
+
+    /* The declaration of an enum type of either backend. */
+    pub fn enum_decl(&self, obj: ObjRef) -> Option<&EnumDecl> {
+        match self.get(obj) {
+            Object::EnumType(ty) => Some(&ty.decl),
+            Object::CompiledEnum(decl) => Some(decl),
+            _ => None,
+        }
+    }
+
+    pub fn enum_value(&self, value: &Value) -> Option<&EnumValue> {
+        match value {
+            Value::Obj(obj) => match self.get(*obj) {
+                Object::EnumValue(value) => Some(value),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn error(&self, value: &Value) -> Option<&ErrorValue> {
+        match value {
+            Value::Obj(obj) => match self.get(*obj) {
+                Object::Error(error) => Some(error),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn range(&self, value: &Value) -> Option<&RangeValue> {
+        match value {
+            Value::Obj(obj) => match self.get(*obj) {
+                Object::Range(range) => Some(range),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /* The status of a coroutine of either backend. */
+    pub fn coroutine_status(&self, value: &Value) -> Option<CoroutineStatus> {
+        match value {
+            Value::Obj(obj) => match self.get(*obj) {
+                Object::Coroutine(coroutine) => Some(coroutine.status),
+                Object::CompiledCoroutine(coroutine) => Some(coroutine.status),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn coroutine(&self, value: &Value) -> Option<&Coroutine> {
+        match value {
+            Value::Obj(obj) => match self.get(*obj) {
+                Object::Coroutine(coroutine) => Some(coroutine),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn table(&self, value: &Value) -> Option<&Table> {
+        match value {
+            Value::Obj(obj) => match self.get(*obj) {
+                Object::Table(table) => Some(table),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /* A set of `items`, each kept the first time it comes. */
+    pub fn set(&self, items: Vec<Value>) -> Object {
+        let mut set: Vec<Value> = Vec::with_capacity(items.len());
+        for item in items {
+            if !set.iter().any(|kept| self.values_equal(kept, &item)) {
+                set.push(item);
+            }
+        }
+        Object::Set(set)
+    }
+
+    pub fn instance(&self, value: &Value) -> Option<&Instance> {
+        match value {
+            Value::Obj(obj) => match self.get(*obj) {
+                Object::Instance(instance) => Some(instance),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /* Structs are values: storing one anywhere stores a copy of it. */
+    pub fn copy_value(&mut self, value: Value) -> Value {
+        let Some(instance) = self.instance(&value) else {
+            return value;
+        };
+        let mut copy = instance.clone();
+        copy.fields = copy
+            .fields
+            .into_iter()
+            .map(|field| self.copy_value(field))
+            .collect();
+        Value::Obj(self.alloc(Object::Instance(copy)))
+    }
+
+    /* Whether `value` has type `ty`, as far as the run time can tell. */
+    pub fn conforms(&self, value: &Value, ty: &Type) -> bool {
+        /* An error stands in for a value of any type, so that any function can
+         * return one. */
+        if self.error(value).is_some() {
+            return true;
+        }
+        if let Value::Obj(obj) = value {
+            /* `int32 f` may also name a function that returns an `int32`, or one
+             * whose return type is inferred. */
+            let ret = match self.get(*obj) {
+                Object::Closure(closure) => Some(&closure.decl.ret),
+                Object::CompiledClosure(closure) => Some(&closure.function.ret),
+                _ => None,
+            };
+            if let Some(ret) = ret {
+                if *ty != Type::Void && (ret == ty || *ret == Type::Unknown) {
+                    return true;
+                }
+            }
+        }
+        match ty {
+            Type::Unknown | Type::Var(_) => true,
+            Type::Void | Type::Nil => value.is_nil(),
+            Type::Optional(inner) => value.is_nil() || self.conforms(value, inner),
+            Type::Bool => matches!(value, Value::Bool(_)),
+            Type::Int(kind) => value.int_kind() == Some(*kind),
+            Type::Float(kind) => value.float_kind() == Some(*kind),
+            Type::Char => matches!(value, Value::Char(_)),
+            Type::String => matches!(value, Value::Str(_)),
+            Type::Array(_) | Type::List(_) => {
+                matches!(value, Value::Obj(obj) if matches!(self.get(*obj), Object::Array(_)))
+            }
+            Type::Set(_) => {
+                matches!(value, Value::Obj(obj) if matches!(self.get(*obj), Object::Set(_)))
+            }
+            Type::Table(..) => self.table(value).is_some(),
+            Type::Coroutine(_) => self.coroutine_status(value).is_some(),
+            Type::Range(elem) => self
+                .range(value)
+                .is_some_and(|range| self.conforms(&range.start, elem)),
+            Type::Func(..) => match value {
+                Value::Builtin(_) => true,
+                Value::Obj(obj) => matches!(
+                    self.get(*obj),
+                    Object::Closure(_)
+                        | Object::CompiledClosure(_)
+                        | Object::BoundMethod { .. }
+                        | Object::BoundBuiltin { .. }
+                        | Object::StructType(_)
+                        | Object::CompiledStruct(_)
+                        | Object::VariantConstructor { .. }
+                ),
+                _ => false,
+            },
+            Type::Named(name, _) => {
+                if let Some(value) = self.enum_value(value) {
+                    return self
+                        .enum_decl(value.ty)
+                        .is_some_and(|decl| decl.name == *name);
+                }
+                self.instance(value).is_some_and(|instance| {
+                    self.struct_decl(instance.ty)
+                        .is_some_and(|decl| decl.name == *name)
+                })
+            }
+        }
+    }
+
+    pub fn type_name(&self, value: &Value) -> String {
+        if let Some(kind) = value.int_kind() {
+            return kind.name().to_owned();
+        }
+        if let Some(kind) = value.float_kind() {
+            return kind.name().to_owned();
+        }
+        match value {
+            Value::Nil => "nil".to_owned(),
+            Value::Bool(_) => "bool".to_owned(),
+            Value::Char(_) => "char".to_owned(),
+            Value::Str(_) => "string".to_owned(),
+            Value::Builtin(_) => "func".to_owned(),
+            Value::Obj(obj) => match self.get(*obj) {
+                Object::Array(_) => "array".to_owned(),
+                Object::Set(_) => "set".to_owned(),
+                Object::Table(_) => "table".to_owned(),
+                Object::Instance(instance) => self
+                    .struct_decl(instance.ty)
+                    .map_or_else(|| "struct".to_owned(), |decl| decl.name.clone()),
+                Object::StructType(_) | Object::CompiledStruct(_) => "struct type".to_owned(),
+                Object::EnumValue(value) => self
+                    .enum_decl(value.ty)
+                    .map_or_else(|| "enum".to_owned(), |decl| decl.name.clone()),
+                Object::EnumType(_) | Object::CompiledEnum(_) => "enum type".to_owned(),
+                Object::TypeAlias { .. } => "type alias".to_owned(),
+                Object::Closure(_)
+                | Object::CompiledClosure(_)
+                | Object::BoundMethod { .. }
+                | Object::BoundBuiltin { .. }
+                | Object::VariantConstructor { .. } => "func".to_owned(),
+                Object::Call { .. } => "call".to_owned(),
+                Object::Env(_) => "environment".to_owned(),
+                Object::Cell(_) => "cell".to_owned(),
+                Object::Error(_) => "error".to_owned(),
+                Object::Coroutine(_) | Object::CompiledCoroutine(_) => "coroutine".to_owned(),
+                Object::Range(_) => "range".to_owned(),
+            },
+            _ => unreachable!("numbers are named above"),
+        }
+    }
+
+    /* Structural equality for structs, enums and ranges, identity for every other
+     * object. */
+    pub fn values_equal(&self, lhs: &Value, rhs: &Value) -> bool {
+        if let (Some(a), Some(b)) = (lhs.int_parts(), rhs.int_parts()) {
+            return a == b || (a.1 == 0 && b.1 == 0);
+        }
+        if let (Some(a), Some(b)) = (self.enum_value(lhs), self.enum_value(rhs)) {
+            return a.ty == b.ty
+                && a.variant == b.variant
+                && a.payload
+                    .iter()
+                    .zip(&b.payload)
+                    .all(|(x, y)| self.values_equal(x, y));
+        }
+        if let (Some(a), Some(b)) = (self.error(lhs), self.error(rhs)) {
+            return a == b;
+        }
+        if let (Some(a), Some(b)) = (self.range(lhs), self.range(rhs)) {
+            return a == b;
+        }
+        match (self.instance(lhs), self.instance(rhs)) {
+            (Some(a), Some(b)) => {
+                a.ty == b.ty
+                    && a.fields
+                        .iter()
+                        .zip(&b.fields)
+                        .all(|(x, y)| self.values_equal(x, y))
+            }
+            _ => lhs == rhs,
+        }
+    }
+
+    pub fn display(&self, value: &Value) -> String {
+        let mut out = String::new();
+        self.write_value(&mut out, value, false);
+        out
+    }
+
+    fn write_value(&self, out: &mut String, value: &Value, quoted: bool) {
+        let _ = match value {
+            Value::Nil => write!(out, "nil"),
+            Value::Bool(value) => write!(out, "{value}"),
+            Value::UInt8(int) => write!(out, "{int}"),
+            Value::UInt16(int) => write!(out, "{int}"),
+            Value::UInt32(int) => write!(out, "{int}"),
+            Value::UInt64(int) => write!(out, "{int}"),
+            Value::UInt128(int) => write!(out, "{int}"),
+            Value::Int8(int) => write!(out, "{int}"),
+            Value::Int16(int) => write!(out, "{int}"),
+            Value::Int32(int) => write!(out, "{int}"),
+            Value::Int64(int) => write!(out, "{int}"),
+            Value::Int128(int) => write!(out, "{int}"),
+            Value::Flt32(float) => write!(out, "{float:?}"),
+            Value::Flt64(float) => write!(out, "{float:?}"),
+            Value::Char(ch) if quoted => write!(out, "{ch:?}"),
+            Value::Char(ch) => write!(out, "{ch}"),
+            Value::Str(string) if quoted => write!(out, "{string:?}"),
+            Value::Str(string) => write!(out, "{string}"),
+            Value::Builtin(builtin) => write!(out, "<func {}>", builtin.name()),
+            Value::Obj(obj) => {
+                self.write_object(out, *obj);
+                Ok(())
+            }
+        };
+    }
+
+    fn write_object(&self, out: &mut String, obj: ObjRef) {
+        let _ = match self.get(obj) {
+            Object::Array(items) | Object::Set(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    self.write_value(out, item, true);
+                }
+                out.push(']');
+                Ok(())
+            }
+            Object::Table(table) => {
+                out.push('[');
+                for (i, (key, value)) in table.entries.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    self.write_value(out, key, true);
+                    out.push_str(": ");
+                    self.write_value(out, value, true);
+                }
+                out.push(']');
+                Ok(())
+            }
+            Object::Instance(instance) => {
+                let Some(decl) = self.struct_decl(instance.ty) else {
+                    return;
+                };
+                let _ = write!(out, "{}(", decl.name);
+                for (i, (field, value)) in decl.fields.iter().zip(&instance.fields).enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    let _ = write!(out, "{} = ", field.name);
+                    self.write_value(out, value, true);
+                }
+                out.push(')');
+                Ok(())
+            }
+            Object::StructType(ty) => write!(out, "<struct {}>", ty.decl.name),
+            Object::CompiledStruct(ty) => write!(out, "<struct {}>", ty.decl.name),
+            Object::EnumValue(value) => {
+                let Some(decl) = self.enum_decl(value.ty) else {
+                    return;
+                };
+                let _ = write!(out, "{}.{}", decl.name, decl.variants[value.variant].name);
+                if !value.payload.is_empty() {
+                    out.push('(');
+                    for (i, item) in value.payload.iter().enumerate() {
+                        if i > 0 {
+                            out.push_str(", ");
+                        }
+                        self.write_value(out, item, true);
+                    }
+                    out.push(')');
+                }
+                Ok(())
+            }
+            Object::EnumType(ty) => write!(out, "<enum {}>", ty.decl.name),
+            Object::CompiledEnum(decl) => write!(out, "<enum {}>", decl.name),
+            Object::TypeAlias { alias, .. } => write!(out, "<type {}>", alias.name),
+            Object::VariantConstructor { ty, variant } => match self.enum_decl(*ty) {
+                Some(decl) => write!(out, "<func {}.{}>", decl.name, decl.variants[*variant].name),
+                None => write!(out, "<func>"),
+            },
+            Object::BoundBuiltin { builtin, .. } => write!(out, "<func {}>", builtin.name()),
+            Object::Closure(closure) => match &closure.decl.name {
+                Some(name) => write!(out, "<func {name}>"),
+                None => write!(out, "<func>"),
+            },
+            Object::BoundMethod { method, .. } => {
+                self.write_object(out, *method);
+                Ok(())
+            }
+            Object::CompiledClosure(closure) => match &closure.function.name {
+                Some(name) => write!(out, "<func {name}>"),
+                None => write!(out, "<func>"),
+            },
+            Object::Call { .. } => write!(out, "<call>"),
+            Object::Env(_) => write!(out, "<environment>"),
+            Object::Cell(_) => write!(out, "<cell>"),
+            Object::Error(error) => write!(out, "error({:?}, {:?})", error.kind, error.message),
+            Object::Coroutine(coroutine) => match &coroutine.decl.name {
+                Some(name) => write!(out, "<coroutine {name}>"),
+                None => write!(out, "<coroutine>"),
+            },
+            Object::CompiledCoroutine(coroutine) => {
+                let name = match self.get(coroutine.closure) {
+                    Object::CompiledClosure(closure) => closure.function.name.as_deref(),
+                    _ => None,
+                };
+                match name {
+                    Some(name) => write!(out, "<coroutine {name}>"),
+                    None => write!(out, "<coroutine>"),
+                }
+            }
+            Object::Range(range) => {
+                let word = if range.inclusive { "through" } else { "to" };
+                self.write_value(out, &range.start, false);
+                let _ = write!(out, " {word} ");
+                self.write_value(out, &range.end, false);
+                if range.step.int_parts() != Some((false, 1)) {
+                    out.push_str(" step ");
+                    self.write_value(out, &range.step, false);
+                }
+                Ok(())
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_int_parts_round_trip() {
+        for kind in IntKind::ALL {
+            let min =
+                Value::from_int_parts(kind, kind.is_signed(), kind.min().unsigned_abs()).unwrap();
+            let max = Value::from_int_parts(kind, false, kind.max()).unwrap();
+            assert_eq!(min.int_kind(), Some(kind));
+            assert_eq!(max.int_parts(), Some((false, kind.max())));
+            assert!(
+                Value::from_int_parts(kind, false, kind.max().wrapping_add(1)).is_none()
+                    || kind == IntKind::U128
+            );
+        }
+        assert_eq!(
+            Value::from_int_parts(IntKind::I128, true, 1 << 127),
+            Some(Value::Int128(i128::MIN))
+        );
+    }
+
+    #[test]
+    fn test_ranges_end_at_the_limits_of_their_type() {
+        let range = RangeValue {
+            start: Value::UInt8(250),
+            end: Value::UInt8(255),
+            step: Value::UInt8(1),
+            inclusive: true,
+        };
+        assert_eq!(range.iter().count(), 6);
+        let down = RangeValue {
+            start: Value::Int8(-100),
+            end: Value::Int8(-128),
+            step: Value::Int8(-7),
+            inclusive: true,
+        };
+        assert_eq!(down.iter().last(), Some(Value::Int8(-128)));
+        assert!(down.contains(&Value::Int8(-128)));
+        assert!(!down.contains(&Value::Int8(-127)));
+        assert!(!down.contains(&Value::Int8(-93)));
+        assert!(Value::Int128(i128::MIN).int_in(i128::MIN, 0, false));
+    }
+
+    #[test]
+    fn test_heap_display() {
+        let mut heap = Heap::default();
+        let array = heap.alloc(Object::Array(vec![
+            Value::Int32(1),
+            Value::Str("two".into()),
+            Value::Flt64(3.0),
+        ]));
+        assert_eq!(heap.display(&Value::Obj(array)), "[1, \"two\", 3.0]");
+    }
+
+    #[test]
+    fn test_collect_frees_unreachable_cycles() {
+        let mut heap = Heap::default();
+        let kept = heap.alloc(Object::Array(vec![]));
+        let inner = heap.alloc(Object::Cell(Value::Obj(kept)));
+        *heap.get_mut(kept) = Object::Array(vec![Value::Obj(inner)]);
+        let a = heap.alloc(Object::Array(vec![]));
+        let b = heap.alloc(Object::Array(vec![Value::Obj(a), Value::Obj(kept)]));
+        *heap.get_mut(a) = Object::Array(vec![Value::Obj(b)]);
+        assert_eq!(heap.collect([kept]), 2);
+        assert_eq!(heap.len(), 2);
+        assert_eq!(heap.get(inner), &Object::Cell(Value::Obj(kept)));
+        /* Freed slots are reused. */
+        assert!([a, b].contains(&heap.alloc(Object::Array(vec![]))));
+        assert_eq!(heap.collect([]), 3);
+        assert_eq!(heap.len(), 0);
+    }
+}
@@ -0,0 +1,2012 @@
+#![allow(dead_code)]
+use crate::ast::{BinaryOp, PatternKind, Storage, UnaryOp, Variant};
+use crate::builtins::{self, Output};
+use crate::compiler::{Bytecode, CallSite, Capture, Function, Hint, LiteralValue, Op};
+use crate::evaluator::{
+    assigned_element, binary_values, check_assigned, check_type, collection, element,
+    float_literal, inferred_type, int_literal, length, no_entries, not_iterable, raise, range,
+    slice, store_element, table, EvalResult, RuntimeError, MAX_ITER_DEPTH,
+};
+use crate::format;
+use crate::jit::Jit;
+use crate::ops;
+use crate::token::Span;
+use crate::types::{IntKind, Type};
+use crate::value::{
+    Binding, Builtin, CompiledClosure, CompiledCoroutine, CompiledStruct, CoroutineStatus,
+    EnumValue, ErrorValue, Heap, Instance, ObjRef, Object, Value,
+};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/* The stack-based virtual machine that runs the bytecode of `compiler.rs`. It shares
+ * its values, heap and runtime errors with the evaluator. */
+
+/* The default limit on the calls being executed at once. Frames live on the heap
+ * rather than the host stack, so it is far above the evaluator's. */
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+/* A call being executed. Its local variable slots start at `base` on the stack, with
+ * the function being called just below them. */
+struct CallFrame {
+    function: Rc<Function>,
+    /* `None` for the program itself. */
+    closure: Option<ObjRef>,
+    ip: usize,
+    base: usize,
+    /* Where it was called from, for the errors about its return value. */
+    span: Span,
+    /* The function first called in the frame, when tail calls replaced it. */
+    replaced: Option<Rc<Function>>,
+}
+
+/* A `try` being run: an error before it ends goes back to `frames` calls and
+ * `height` values on the stack, and runs its handler at `target`. */
+struct Handler {
+    frames: usize,
+    height: usize,
+    target: u32,
+}
+
+pub struct Vm {
+    pub heap: Heap,
+    globals: Vec<Option<Binding>>,
+    global_names: Vec<String>,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    handlers: Vec<Handler>,
+    max_depth: usize,
+    output: Output,
+    jit: Jit,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self {
+            heap: Heap::default(),
+            globals: vec![],
+            global_names: vec![],
+            stack: vec![],
+            frames: vec![],
+            handlers: vec![],
+            max_depth: MAX_CALL_DEPTH,
+            output: Output::Stdout,
+            jit: Jit::new(),
+        }
+    }
+
+    /* Collects everything the program prints instead of writing it to stdout. */
+    pub fn capture_output(&mut self) {
+        self.output = Output::Captured(String::new());
+    }
+
+    pub fn take_output(&mut self) -> String {
+        self.output.take()
+    }
+
+    /* Limits the calls being executed at once, past which a call is a stack overflow. */
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn run(&mut self, bytecode: &Bytecode) -> EvalResult<()> {
+        self.global_names.clone_from(&bytecode.globals);
+        self.globals = Builtin::ALL
+            .iter()
+            .map(|&builtin| {
+                Some(Binding {
+                    value: Value::Builtin(builtin),
+                    ty: Type::Unknown,
+                    constant: false,
+                    id: None,
+                })
+            })
+            .collect();
+        self.globals.resize(bytecode.globals.len(), None);
+        self.stack.clear();
+        self.stack.resize(bytecode.script.slots, Value::Nil);
+        self.frames = vec![CallFrame {
+            function: Rc::clone(&bytecode.script),
+            closure: None,
+            ip: 0,
+            base: 0,
+            span: Span::default(),
+            replaced: None,
+        }];
+        let result = self.execute(0).map_err(|error| {
+            /* The frames are left as they were when the error happened. */
+            self.frames[1..].iter().rev().fold(error, |error, frame| {
+                error.called(frame.function.display_name(), frame.span)
+            })
+        });
+        self.stack.clear();
+        self.frames.clear();
+        self.handlers.clear();
+        result
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("the program has a frame")
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the stack holds the operands")
+    }
+
+    fn peek(&mut self) -> &mut Value {
+        self.stack.last_mut().expect("the stack holds the operands")
+    }
+
+    fn local(&mut self, slot: u32) -> &mut Value {
+        let base = self.frame().base;
+        &mut self.stack[base + slot as usize]
+    }
+
+    fn cell(&self, value: &Value) -> ObjRef {
+        match value {
+            Value::Obj(obj) if matches!(self.heap.get(*obj), Object::Cell(_)) => *obj,
+            _ => unreachable!("captured variables live in cells"),
+        }
+    }
+
+    fn cell_value(&self, cell: ObjRef) -> Value {
+        match self.heap.get(cell) {
+            Object::Cell(value) => value.clone(),
+            _ => unreachable!("captured variables live in cells"),
+        }
+    }
+
+    fn set_cell(&mut self, cell: ObjRef, value: Value) {
+        if let Object::Cell(slot) = self.heap.get_mut(cell) {
+            *slot = value;
+        }
+    }
+
+    fn capture(&self, index: u32) -> ObjRef {
+        let closure = self.frame().closure.expect("only closures capture");
+        match self.heap.get(closure) {
+            Object::CompiledClosure(closure) => closure.captures[index as usize],
+            _ => unreachable!("frames run closures"),
+        }
+    }
+
+    /* The type a hint stands for at the current instruction, as the evaluator would
+     * compute it. */
+    fn hint_type(&self, hint: &Hint) -> Option<Type> {
+        let frame = self.frame();
+        let temps = frame.base + frame.function.slots;
+        let ty = match hint {
+            Hint::None => None,
+            Hint::Type(ty) => Some(ty.clone()),
+            Hint::Numeric(slot, otherwise) => self.stack[temps + *slot as usize]
+                .numeric_type()
+                .or_else(|| self.hint_type(otherwise)),
+            Hint::Param(slot, index) => {
+                self.param_type(&self.stack[temps + *slot as usize], *index as usize)
+            }
+            Hint::Field(slot, name) => {
+                let target = &self.stack[temps + *slot as usize];
+                let name = &frame.function.names[*name as usize];
+                self.field_slot(target, name, Span::default())
+                    .ok()
+                    .map(|(_, _, ty)| ty)
+            }
+            Hint::Global(global) => self.globals[*global as usize]
+                .as_ref()
+                .map(|binding| binding.ty.clone()),
+            Hint::Elem(inner) => match self.hint_type(inner) {
+                Some(
+                    Type::Array(elem) | Type::List(elem) | Type::Set(elem) | Type::Table(_, elem),
+                ) => Some(*elem),
+                _ => None,
+            },
+            Hint::Key(inner) => match self.hint_type(inner) {
+                Some(Type::Table(key, _)) => Some(*key),
+                _ => None,
+            },
+            Hint::Index(slot) => match self.heap.table(&self.stack[temps + *slot as usize]) {
+                Some(table) => Some(table.key.clone()).filter(|key| *key != Type::Unknown),
+                None => Some(Type::Int(IntKind::I32)),
+            },
+            Hint::Entry(slot) => self
+                .heap
+                .table(&self.stack[temps + *slot as usize])
+                .map(|table| table.value.clone())
+                .filter(|value| *value != Type::Unknown),
+            Hint::Item(slot) => self
+                .heap
+                .range(&self.stack[temps + *slot as usize])
+                .and_then(|range| range.start.numeric_type()),
+            Hint::Operands(op, inner) => self
+                .hint_type(inner)
+                .filter(|ty| op.operand_hint(Some(ty)).is_some()),
+        };
+        ty.map(|ty| ty.non_optional().clone())
+    }
+
+    /* The type of parameter `index` of `callee`, which the argument is used as. */
+    fn param_type(&self, callee: &Value, index: usize) -> Option<Type> {
+        let Value::Obj(obj) = callee else {
+            return None;
+        };
+        let closure_param = |closure: ObjRef| match self.heap.get(closure) {
+            Object::CompiledClosure(closure) => {
+                closure.function.params.get(index).map(|(_, ty)| ty.clone())
+            }
+            _ => None,
+        };
+        match self.heap.get(*obj) {
+            Object::CompiledClosure(_) => closure_param(*obj),
+            Object::BoundMethod { method, .. } => closure_param(*method),
+            Object::CompiledStruct(ty) => match ty.method(&self.heap, "new") {
+                Some(constructor) => closure_param(constructor),
+                None => ty
+                    .decl
+                    .fields
+                    .get(index)
+                    .map(|field| field.ty.substitute(&ty.decl.params, &[])),
+            },
+            Object::VariantConstructor { ty, variant } => self
+                .heap
+                .enum_decl(*ty)
+                .and_then(|decl| decl.variants[*variant].fields.get(index).cloned()),
+            _ => None,
+        }
+    }
+
+    /* Frees the objects unreachable from the stack, the globals and the closures
+     * being called. Everything live is on one of them between instructions. */
+    fn collect_garbage(&mut self) -> usize {
+        let stack = self.stack.iter();
+        let globals = self.globals.iter().flatten().map(|binding| &binding.value);
+        let values = stack.chain(globals).filter_map(|value| match value {
+            Value::Obj(obj) => Some(*obj),
+            _ => None,
+        });
+        let closures = self.frames.iter().filter_map(|frame| frame.closure);
+        let roots: Vec<ObjRef> = values.chain(closures).collect();
+        self.heap.collect(roots)
+    }
+
+    /* Runs the innermost frame until the number of frames drops to `stop`, leaving
+     * the value it returns on the stack, or until the program ends. An error inside
+     * a `try` of one of these frames runs its handler with the error as a value. */
+    fn execute(&mut self, stop: usize) -> EvalResult<()> {
+        loop {
+            let error = match self.run_frames(stop) {
+                Err(error) if self.handlers.last().is_some_and(|h| h.frames > stop) => error,
+                result => return result,
+            };
+            let handler = self.handlers.pop().expect("a handler was found");
+            self.frames.truncate(handler.frames);
+            self.stack.truncate(handler.height);
+            let error = ErrorValue {
+                kind: error.kind,
+                message: error.message.into(),
+            };
+            let error = self.heap.alloc(Object::Error(error));
+            self.stack.push(Value::Obj(error));
+            if let Some(frame) = self.frames.last_mut() {
+                frame.ip = handler.target as usize;
+            }
+        }
+    }
+
+    fn run_frames(&mut self, stop: usize) -> EvalResult<()> {
+        let mut function = Rc::clone(&self.frame().function);
+        let mut ip = self.frame().ip;
+        loop {
+            if self.heap.should_collect() {
+                self.collect_garbage();
+            }
+            let op = function.code[ip];
+            let span = function.spans[ip];
+            ip += 1;
+            match op {
+                Op::Constant(index) => {
+                    let value = function.constants[index as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::Literal(index) => {
+                    let literal = &function.literals[index as usize];
+                    let hint = self.hint_type(&literal.hint);
+                    let value = match literal.value {
+                        LiteralValue::Int {
+                            negative,
+                            magnitude,
+                        } => int_literal(negative, magnitude, hint.as_ref(), span)?,
+                        LiteralValue::Float(float) => float_literal(float, hint.as_ref()),
+                    };
+                    self.stack.push(value);
+                }
+                Op::Nil => self.stack.push(Value::Nil),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Dup => {
+                    let value = self.peek().clone();
+                    self.stack.push(value);
+                }
+                Op::Swap => {
+                    let len = self.stack.len();
+                    self.stack.swap(len - 1, len - 2);
+                }
+                Op::GetLocal(slot) => {
+                    let value = self.local(slot).clone();
+                    self.stack.push(value);
+                }
+                Op::SetLocal(slot) => {
+                    let value = self.pop();
+                    *self.local(slot) = value;
+                }
+                Op::NewCell(slot) => {
+                    let cell = self.heap.alloc(Object::Cell(Value::Nil));
+                    *self.local(slot) = Value::Obj(cell);
+                }
+                Op::GetCell(slot) => {
+                    let cell = self.cell(&self.stack[self.frame().base + slot as usize]);
+                    let value = self.cell_value(cell);
+                    self.stack.push(value);
+                }
+                Op::SetCell(slot) => {
+                    let cell = self.cell(&self.stack[self.frame().base + slot as usize]);
+                    let value = self.pop();
+                    self.set_cell(cell, value);
+                }
+                Op::GetCapture(index) => {
+                    let value = self.cell_value(self.capture(index));
+                    self.stack.push(value);
+                }
+                Op::SetCapture(index) => {
+                    let value = self.pop();
+                    self.set_cell(self.capture(index), value);
+                }
+                Op::GetGlobal(global) => {
+                    let Some(binding) = &self.globals[global as usize] else {
+                        let name = &self.global_names[global as usize];
+                        return Err(RuntimeError::new(span, format!("Undefined name `{name}`.")));
+                    };
+                    let name = &self.global_names[global as usize];
+                    check_assigned(name, &binding.value, &binding.ty, span)?;
+                    let value = binding.value.clone();
+                    self.stack.push(value);
+                }
+                Op::AssignGlobal(global) => {
+                    let name = &self.global_names[global as usize];
+                    let value = match &self.globals[global as usize] {
+                        None => {
+                            return Err(RuntimeError::new(
+                                span,
+                                format!("Undefined variable `{name}`."),
+                            ))
+                        }
+                        Some(binding) if binding.constant => {
+                            return Err(RuntimeError::new(
+                                span,
+                                format!("Cannot assign to the constant `{name}`."),
+                            ))
+                        }
+                        Some(binding) => binding.value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                Op::SetGlobal(global) => {
+                    let value = self.pop();
+                    let index = global as usize;
+                    if let Some(binding) = &self.globals[index] {
+                        let name = &self.global_names[index];
+                        let value = check_type(&self.heap, value, &binding.ty, span, || {
+                            format!("variable `{name}`")
+                        })?;
+                        if let Some(binding) = &mut self.globals[index] {
+                            binding.value = value;
+                        }
+                    }
+                }
+                Op::DefineGlobal(index) => self.define_global(&function, index, span)?,
+                Op::Check(index) => {
+                    let (ty, what) = &function.checks[index as usize];
+                    let value = self.pop();
+                    let value = check_type(&self.heap, value, ty, span, || what.clone())?;
+                    self.stack.push(value);
+                }
+                Op::Assigned(name) => {
+                    if self.peek().is_nil() {
+                        let name = &function.names[name as usize];
+                        return Err(RuntimeError::new(
+                            span,
+                            format!("`{name}` is used before it is assigned."),
+                        ));
+                    }
+                }
+                Op::Copy => {
+                    let value = self.pop();
+                    let value = self.heap.copy_value(value);
+                    self.stack.push(value);
+                }
+                Op::Condition => {
+                    if !matches!(self.peek(), Value::Bool(_)) {
+                        let value = self.pop();
+                        let found = self.heap.type_name(&value);
+                        return Err(RuntimeError::new(
+                            span,
+                            format!("Condition must be a bool, found {found}."),
+                        ));
+                    }
+                }
+                Op::Unary(op) => {
+                    let value = self.pop();
+                    let value = match op {
+                        UnaryOp::Len => length(&self.heap, &value, span)?,
+                        _ => ops::unary(op, &value)
+                            .map_err(|message| RuntimeError::new(span, message))?,
+                    };
+                    self.stack.push(value);
+                }
+                Op::Binary(op) => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    let value = binary_values(&mut self.heap, op, &lhs, &rhs, span)?;
+                    self.stack.push(value);
+                }
+                Op::Cast(index) => {
+                    let value = self.pop();
+                    let value = ops::cast(&value, &function.types[index as usize])
+                        .map_err(|message| RuntimeError::new(span, message))?;
+                    self.stack.push(value);
+                }
+                Op::Array(len) => {
+                    let items = self.stack.split_off(self.stack.len() - len as usize);
+                    let items = items
+                        .into_iter()
+                        .map(|item| self.heap.copy_value(item))
+                        .collect();
+                    let array = self.heap.alloc(Object::Array(items));
+                    self.stack.push(Value::Obj(array));
+                }
+                Op::Repeat => {
+                    let count = match self.pop().int_parts() {
+                        Some((false, count)) => usize::try_from(count).ok(),
+                        _ => None,
+                    };
+                    let value = self.pop();
+                    let Some(count) = count else {
+                        return Err(RuntimeError::new(
+                            span,
+                            "The size of an array must be a non-negative integer.",
+                        ));
+                    };
+                    let items = (0..count)
+                        .map(|_| self.heap.copy_value(value.clone()))
+                        .collect();
+                    let array = self.heap.alloc(Object::Array(items));
+                    self.stack.push(Value::Obj(array));
+                }
+                Op::Collection(index) => {
+                    let hint = self.hint_type(&function.hints[index as usize]);
+                    let array = self.pop();
+                    let items = match &array {
+                        Value::Obj(obj) => match self.heap.get(*obj) {
+                            Object::Array(items) => items.clone(),
+                            _ => vec![],
+                        },
+                        _ => vec![],
+                    };
+                    let value = match hint {
+                        Some(Type::Set(_) | Type::Table(..)) => {
+                            collection(&mut self.heap, items, hint.as_ref())
+                        }
+                        _ => array,
+                    };
+                    self.stack.push(value);
+                }
+                Op::Table(len, index) => {
+                    let items = self.stack.split_off(self.stack.len() - 2 * len as usize);
+                    let mut pairs = items
+                        .chunks(2)
+                        .map(|pair| (pair[0].clone(), pair[1].clone()))
+                        .collect::<Vec<_>>();
+                    for (key, value) in &mut pairs {
+                        *key = self.heap.copy_value(key.clone());
+                        *value = self.heap.copy_value(value.clone());
+                    }
+                    let (key_ty, value_ty) = match self.hint_type(&function.hints[index as usize]) {
+                        Some(Type::Table(key, value)) => (*key, *value),
+                        _ => pairs
+                            .first()
+                            .map_or((Type::Unknown, Type::Unknown), |(key, value)| {
+                                (inferred_type(key), inferred_type(value))
+                            }),
+                    };
+                    let table = table(&mut self.heap, key_ty, value_ty, pairs);
+                    self.stack.push(table);
+                }
+                Op::Index => {
+                    let index = self.pop();
+                    let target = self.pop();
+                    let value = match self.heap.range(&index).cloned() {
+                        Some(range) => slice(&mut self.heap, &target, &range, span)?,
+                        None => element(&self.heap, &target, &index, span)?,
+                    };
+                    self.stack.push(value);
+                }
+                Op::Range(inclusive, stepped) => {
+                    let step = stepped.then(|| self.pop());
+                    let end = self.pop();
+                    let start = self.pop();
+                    let range = range(&self.heap, start, end, step, inclusive, span)?;
+                    let range = self.heap.alloc(Object::Range(range));
+                    self.stack.push(Value::Obj(range));
+                }
+                Op::IndexSlot => {
+                    let len = self.stack.len();
+                    let (target, index) = (&self.stack[len - 2], &self.stack[len - 1]);
+                    let current = assigned_element(&self.heap, target, index, span)?;
+                    self.stack.push(current);
+                }
+                Op::StoreIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let target = self.pop();
+                    let index = self.heap.copy_value(index);
+                    let value = self.heap.copy_value(value);
+                    store_element(&mut self.heap, &target, index, value, span)?;
+                }
+                Op::Field(name) => {
+                    let target = self.pop();
+                    let value = self.field(&target, &function.names[name as usize], span)?;
+                    self.stack.push(value);
+                }
+                Op::SafeField(name, target) => {
+                    if self.peek().is_nil() {
+                        ip = target as usize;
+                        continue;
+                    }
+                    let object = self.pop();
+                    let value = self.field(&object, &function.names[name as usize], span)?;
+                    self.stack.push(value);
+                }
+                Op::FieldSlot(name) => {
+                    let target = self.peek().clone();
+                    let (obj, index, _) =
+                        self.field_slot(&target, &function.names[name as usize], span)?;
+                    let current = match self.heap.get(obj) {
+                        Object::Instance(instance) => instance.fields[index].clone(),
+                        _ => unreachable!("field slots belong to instances"),
+                    };
+                    self.stack.push(current);
+                }
+                Op::StoreField(name) => {
+                    let value = self.pop();
+                    let target = self.pop();
+                    let field = &function.names[name as usize];
+                    let (obj, index, ty) = self.field_slot(&target, field, span)?;
+                    let value =
+                        check_type(&self.heap, value, &ty, span, || format!("field `{field}`"))?;
+                    let value = self.heap.copy_value(value);
+                    if let Object::Instance(instance) = self.heap.get_mut(obj) {
+                        instance.fields[index] = value;
+                    }
+                }
+                Op::Jump(target) => ip = target as usize,
+                Op::JumpIfFalse(target) => {
+                    if self.pop() == Value::Bool(false) {
+                        ip = target as usize;
+                    }
+                }
+                Op::JumpIfTrue(target) => {
+                    if self.pop() == Value::Bool(true) {
+                        ip = target as usize;
+                    }
+                }
+                Op::JumpIfNotNil(target) => {
+                    if !self.peek().is_nil() {
+                        ip = target as usize;
+                    }
+                }
+                Op::Closure(index) => {
+                    let closure = self.closure(&function, index);
+                    self.stack.push(Value::Obj(closure));
+                }
+                Op::Struct(index) => {
+                    let def = &function.structs[index as usize];
+                    let init = self.closure(&function, def.init);
+                    let methods = def
+                        .methods
+                        .iter()
+                        .map(|&method| self.closure(&function, method))
+                        .collect();
+                    let ty = self.heap.alloc(Object::CompiledStruct(CompiledStruct {
+                        decl: Rc::clone(&def.decl),
+                        init,
+                        methods,
+                    }));
+                    self.stack.push(Value::Obj(ty));
+                }
+                Op::Enum(index) => {
+                    let decl = Rc::clone(&function.enums[index as usize]);
+                    let ty = self.heap.alloc(Object::CompiledEnum(decl));
+                    self.stack.push(Value::Obj(ty));
+                }
+                Op::Static(index, slot) => {
+                    let (cell, _) = self.statics()[index as usize];
+                    *self.local(slot) = Value::Obj(cell);
+                }
+                Op::StaticInit(index, target) => {
+                    let closure = self.frame().closure.expect("only closures have statics");
+                    if let Object::CompiledClosure(closure) = self.heap.get_mut(closure) {
+                        let initialised = &mut closure.statics[index as usize].1;
+                        if *initialised {
+                            ip = target as usize;
+                        }
+                        *initialised = true;
+                    }
+                }
+                Op::Call(site) | Op::TailCall(site) => {
+                    if let Some(frame) = self.frames.last_mut() {
+                        frame.ip = ip;
+                    }
+                    let tail = matches!(op, Op::TailCall(_));
+                    if self.call(&function, site, span, tail)? {
+                        function = Rc::clone(&self.frame().function);
+                        ip = 0;
+                    }
+                }
+                Op::Return => {
+                    let value = self.pop();
+                    let frame = self.frames.pop().expect("the program has a frame");
+                    let depth = self.frames.len();
+                    while self.handlers.last().is_some_and(|h| h.frames > depth) {
+                        self.handlers.pop();
+                    }
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    /* `resume` checks what a coroutine returns as a value it yields. */
+                    let value = match frame.function.coroutine {
+                        true => value,
+                        false => self.return_value(&frame, value)?,
+                    };
+                    self.stack.truncate(frame.base - 1);
+                    self.stack.push(value);
+                    if self.frames.len() == stop {
+                        return Ok(());
+                    }
+                    function = Rc::clone(&self.frame().function);
+                    ip = self.frame().ip;
+                }
+                Op::RangeStart(slot) => {
+                    let end = self.pop();
+                    let start = self.pop();
+                    *self.local(slot) = start;
+                    *self.local(slot + 1) = end;
+                }
+                Op::RangeNext(slot, exit) => {
+                    let current = self.local(slot).clone();
+                    let end = self.local(slot + 1).clone();
+                    let more = binary_values(&mut self.heap, BinaryOp::Lt, &current, &end, span)?;
+                    if more != Value::Bool(true) {
+                        ip = exit as usize;
+                        continue;
+                    }
+                    let one = match current.int_kind() {
+                        Some(kind) => Value::from_int_parts(kind, false, 1).unwrap_or(Value::Nil),
+                        None => Value::Nil,
+                    };
+                    let next = binary_values(&mut self.heap, BinaryOp::Add, &current, &one, span)?;
+                    *self.local(slot) = next;
+                    self.stack.push(current);
+                }
+                Op::ThroughNext(slot, exit) => {
+                    let current = self.local(slot).clone();
+                    let end = self.local(slot + 1).clone();
+                    let more = binary_values(&mut self.heap, BinaryOp::LtEq, &current, &end, span)?;
+                    if more != Value::Bool(true) {
+                        ip = exit as usize;
+                        continue;
+                    }
+                    let int = |magnitude| match current.int_kind() {
+                        Some(kind) => {
+                            Value::from_int_parts(kind, false, magnitude).unwrap_or(Value::Nil)
+                        }
+                        None => Value::Nil,
+                    };
+                    if current == end {
+                        let (one, zero) = (int(1), int(0));
+                        *self.local(slot) = one;
+                        *self.local(slot + 1) = zero;
+                    } else {
+                        let next =
+                            binary_values(&mut self.heap, BinaryOp::Add, &current, &int(1), span)?;
+                        *self.local(slot) = next;
+                    }
+                    self.stack.push(current);
+                }
+                Op::IterStart(slot) => {
+                    let value = self.pop();
+                    let (state, next) = self.iteration(value, span, 0)?;
+                    *self.local(slot) = state;
+                    *self.local(slot + 1) = next;
+                }
+                Op::IterNext(slot, exit) => {
+                    let state = self.local(slot).clone();
+                    let next = self.local(slot + 1).clone();
+                    let item = match (self.heap.range(&state).cloned(), &state, next) {
+                        /* A range keeps the integer it visits next, `nil` once past the
+                         * largest value of its type. */
+                        (Some(range), _, next) => {
+                            let item =
+                                Some(next).filter(|next| !next.is_nil() && range.reaches(next));
+                            let after = item.as_ref().and_then(|item| range.after(item));
+                            *self.local(slot + 1) = after.unwrap_or(Value::Nil);
+                            item
+                        }
+                        (None, Value::Obj(items), Value::UInt64(next)) => {
+                            let item = match self.heap.get(*items) {
+                                Object::Array(items) => items.get(next as usize).cloned(),
+                                _ => None,
+                            };
+                            *self.local(slot + 1) = Value::UInt64(next + 1);
+                            item
+                        }
+                        (None, Value::Obj(obj), _)
+                            if matches!(self.heap.get(*obj), Object::CompiledCoroutine(_)) =>
+                        {
+                            self.next_yielded(*obj, span)?
+                        }
+                        _ => match self.method(&state, "next") {
+                            Some(method) => Some(self.call_method(method, state, span)?)
+                                .filter(|item| !item.is_nil()),
+                            None => None,
+                        },
+                    };
+                    let Some(item) = item else {
+                        ip = exit as usize;
+                        continue;
+                    };
+                    let item = self.heap.copy_value(item);
+                    self.stack.push(item);
+                }
+                Op::EntriesStart(slot) => {
+                    let value = self.pop();
+                    let Some(table) = self.heap.table(&value) else {
+                        return Err(no_entries(&self.heap, &value, span));
+                    };
+                    let entries = Object::Table(table.clone());
+                    *self.local(slot) = Value::Obj(self.heap.alloc(entries));
+                    *self.local(slot + 1) = Value::UInt64(0);
+                }
+                Op::EntriesNext(slot, exit) => {
+                    let (entries, next) = (self.local(slot).clone(), self.local(slot + 1).clone());
+                    let entry = match (self.heap.table(&entries), next) {
+                        (Some(table), Value::UInt64(next)) => table
+                            .entries
+                            .get(next as usize)
+                            .cloned()
+                            .map(|entry| (entry, next)),
+                        _ => None,
+                    };
+                    let Some(((key, value), next)) = entry else {
+                        ip = exit as usize;
+                        continue;
+                    };
+                    *self.local(slot + 1) = Value::UInt64(next + 1);
+                    let (key, value) = (self.heap.copy_value(key), self.heap.copy_value(value));
+                    self.stack.push(value);
+                    self.stack.push(key);
+                }
+                Op::Match(index, slot) => {
+                    let subject = self.local(slot).clone();
+                    let matched = self.matches(&function, index, &subject)?;
+                    self.stack.push(Value::Bool(matched));
+                }
+                Op::MatchValue(slot) => {
+                    let value = self.pop();
+                    let subject = self.local(slot).clone();
+                    let equal = self.heap.values_equal(&subject, &value);
+                    self.stack.push(Value::Bool(equal));
+                }
+                Op::Payload(slot, index) => {
+                    let subject = self.local(slot).clone();
+                    let item = self
+                        .heap
+                        .enum_value(&subject)
+                        .and_then(|value| value.payload.get(index as usize).cloned())
+                        .unwrap_or(Value::Nil);
+                    let item = self.heap.copy_value(item);
+                    self.stack.push(item);
+                }
+                Op::TryStart(target) => self.handlers.push(Handler {
+                    frames: self.frames.len(),
+                    height: self.stack.len(),
+                    target,
+                }),
+                Op::TryEnd => {
+                    self.handlers.pop();
+                }
+                Op::Raise => {
+                    let value = self.pop();
+                    return Err(raise(&self.heap, &value, span));
+                }
+                Op::Unwrap => {
+                    let value = self.peek().clone();
+                    if self.heap.error(&value).is_some() {
+                        return Err(raise(&self.heap, &value, span));
+                    }
+                }
+                /* Only a coroutine yields, in the frame `resume` runs it in, below
+                 * which the coroutine itself stands. */
+                Op::Yield => {
+                    let value = self.pop();
+                    let frame = self.frames.pop().expect("coroutines run in a frame");
+                    let depth = self.frames.len();
+                    let mut tries = vec![];
+                    while self.handlers.last().is_some_and(|h| h.frames > depth) {
+                        let handler = self.handlers.pop().expect("a handler was found");
+                        tries.push((handler.height - frame.base, handler.target));
+                    }
+                    tries.reverse();
+                    let stack = self.stack.split_off(frame.base);
+                    let Value::Obj(obj) = self.pop() else {
+                        unreachable!("coroutines stand below their frame");
+                    };
+                    if let Object::CompiledCoroutine(coroutine) = self.heap.get_mut(obj) {
+                        coroutine.status = CoroutineStatus::Suspended;
+                        coroutine.ip = ip;
+                        coroutine.stack = stack;
+                        coroutine.tries = tries;
+                    }
+                    self.stack.push(value);
+                    return Ok(());
+                }
+                Op::Fail(message) => {
+                    return Err(RuntimeError::new(
+                        span,
+                        function.names[message as usize].clone(),
+                    ))
+                }
+            }
+        }
+    }
+
+    /* A `global` variable declared again is assigned instead, as in the evaluator. */
+    fn define_global(&mut self, function: &Function, index: u32, span: Span) -> EvalResult<()> {
+        let decl = &function.globals[index as usize];
+        let global = decl.global as usize;
+        let existing = self.globals[global]
+            .clone()
+            .filter(|_| decl.storage == Some(Storage::Global));
+        let ty = match (&decl.ty, &existing) {
+            (Type::Unknown, Some(existing)) => existing.ty.clone(),
+            (ty, _) => ty.clone(),
+        };
+        let value = if decl.has_value {
+            let value = self.pop();
+            let name = &self.global_names[global];
+            check_type(&self.heap, value, &ty, span, || {
+                format!("variable `{name}`")
+            })?
+        } else {
+            existing.map_or(Value::Nil, |existing| existing.value)
+        };
+        let ty = match ty {
+            Type::Unknown => inferred_type(&value),
+            ty => ty,
+        };
+        self.globals[global] = Some(Binding {
+            value,
+            ty,
+            constant: decl.storage == Some(Storage::Const),
+            id: None,
+        });
+        Ok(())
+    }
+
+    /* Creates a closure of `functions[index]`, with the cells it captures from the
+     * current frame and new ones for its `static` variables. */
+    fn closure(&mut self, function: &Function, index: u32) -> ObjRef {
+        let inner = Rc::clone(&function.functions[index as usize]);
+        let base = self.frame().base;
+        let captures = inner
+            .captures
+            .iter()
+            .map(|capture| match capture {
+                Capture::Local(slot) => self.cell(&self.stack[base + *slot as usize]),
+                Capture::Capture(index) => self.capture(*index),
+            })
+            .collect();
+        let statics = (0..inner.statics)
+            .map(|_| (self.heap.alloc(Object::Cell(Value::Nil)), false))
+            .collect();
+        self.heap.alloc(Object::CompiledClosure(CompiledClosure {
+            function: inner,
+            captures,
+            statics,
+        }))
+    }
+
+    fn statics(&self) -> &[(ObjRef, bool)] {
+        let closure = self.frame().closure.expect("only closures have statics");
+        match self.heap.get(closure) {
+            Object::CompiledClosure(closure) => &closure.statics,
+            _ => unreachable!("frames run closures"),
+        }
+    }
+
+    fn field(&mut self, target: &Value, name: &str, span: Span) -> EvalResult<Value> {
+        if let Value::Obj(obj) = target {
+            match (self.heap.get(*obj), name) {
+                (Object::Call { args }, "args") => return Ok(Value::Obj(*args)),
+                (Object::Error(error), _) => {
+                    if let Some(value) = error.field(name) {
+                        return Ok(value);
+                    }
+                }
+                (Object::Instance(instance), _) => {
+                    if let Object::CompiledStruct(ty) = self.heap.get(instance.ty) {
+                        if let Some(index) = ty.decl.field_index(name) {
+                            return Ok(instance.fields[index].clone());
+                        }
+                        if let Some(method) = ty.method(&self.heap, name) {
+                            let bound = self.heap.alloc(Object::BoundMethod {
+                                receiver: target.clone(),
+                                method,
+                            });
+                            return Ok(Value::Obj(bound));
+                        }
+                    }
+                }
+                (Object::CompiledEnum(decl), _) => {
+                    if name == Builtin::FromDiscriminant.name() {
+                        let bound = self.heap.alloc(Object::BoundBuiltin {
+                            receiver: target.clone(),
+                            builtin: Builtin::FromDiscriminant,
+                        });
+                        return Ok(Value::Obj(bound));
+                    }
+                    let Some(variant) = decl.variant_index(name) else {
+                        return Err(RuntimeError::new(
+                            span,
+                            format!("Enum `{}` has no variant `{name}`.", decl.name),
+                        ));
+                    };
+                    let object = if decl.variants[variant].fields.is_empty() {
+                        Object::EnumValue(EnumValue {
+                            ty: *obj,
+                            variant,
+                            payload: vec![],
+                        })
+                    } else {
+                        Object::VariantConstructor { ty: *obj, variant }
+                    };
+                    return Ok(Value::Obj(self.heap.alloc(object)));
+                }
+                _ => {}
+            }
+        }
+        Err(self.no_field(target, name, span))
+    }
+
+    /* The state a `for` loop keeps to go through the items of `value`: its items and
+     * the position of the next, or else a struct whose `next` method gives them and
+     * `nil`. A struct without one gives the items of what its `iter` method returns. */
+    fn iteration(&mut self, value: Value, span: Span, depth: usize) -> EvalResult<(Value, Value)> {
+        let items = match &value {
+            Value::Str(string) => string.chars().map(Value::Char).collect(),
+            Value::Obj(obj) => match self.heap.get(*obj) {
+                Object::Array(items) | Object::Set(items) => items.clone(),
+                Object::Table(table) => table.entries.iter().map(|(key, _)| key.clone()).collect(),
+                Object::Range(range) => return Ok((value.clone(), range.start.clone())),
+                Object::CompiledCoroutine(_) => return Ok((value.clone(), Value::Nil)),
+                Object::Instance(_) if depth < MAX_ITER_DEPTH => {
+                    if self.method(&value, "next").is_some() {
+                        return Ok((self.heap.copy_value(value), Value::Nil));
+                    }
+                    let Some(iter) = self.method(&value, "iter") else {
+                        return Err(not_iterable(&self.heap, &value, span));
+                    };
+                    let items = self.call_method(iter, value, span)?;
+                    return self.iteration(items, span, depth + 1);
+                }
+                Object::Instance(_) => return Err(not_iterable(&self.heap, &value, span)),
+                _ => vec![value.clone()],
+            },
+            _ => return Err(not_iterable(&self.heap, &value, span)),
+        };
+        let items = self.heap.alloc(Object::Array(items));
+        Ok((Value::Obj(items), Value::UInt64(0)))
+    }
+
+    /* The method `name` of the struct `value` is an instance of. */
+    fn method(&self, value: &Value, name: &str) -> Option<ObjRef> {
+        let instance = self.heap.instance(value)?;
+        match self.heap.get(instance.ty) {
+            Object::CompiledStruct(ty) => ty.method(&self.heap, name),
+            _ => None,
+        }
+    }
+
+    /* Calls a method that takes no arguments. */
+    fn call_method(&mut self, method: ObjRef, receiver: Value, span: Span) -> EvalResult<Value> {
+        self.stack.push(Value::Obj(method));
+        self.run_call(
+            method,
+            Some(receiver),
+            self.stack.len() - 1,
+            &[],
+            span,
+            true,
+        )
+    }
+
+    fn no_field(&self, target: &Value, name: &str, span: Span) -> RuntimeError {
+        RuntimeError::new(
+            span,
+            format!("{} has no field `{name}`.", self.heap.type_name(target)),
+        )
+    }
+
+    /* The instance, index and type of the field `name` of `target`, to assign it. */
+    fn field_slot(
+        &self,
+        target: &Value,
+        name: &str,
+        span: Span,
+    ) -> EvalResult<(ObjRef, usize, Type)> {
+        if let (Value::Obj(obj), Some(instance)) = (target, self.heap.instance(target)) {
+            if let Object::CompiledStruct(ty) = self.heap.get(instance.ty) {
+                if let Some(index) = ty.decl.field_index(name) {
+                    let ty = ty.decl.fields[index].ty.substitute(&ty.decl.params, &[]);
+                    return Ok((*obj, index, ty));
+                }
+            }
+        }
+        Err(self.no_field(target, name, span))
+    }
+
+    /* Matches a subject against a pattern, as the evaluator does. A bare name matches
+     * a constant, or else a variant of the subject's enum. */
+    fn matches(&self, function: &Function, index: u32, subject: &Value) -> EvalResult<bool> {
+        let test = &function.patterns[index as usize];
+        let pattern = &test.pattern;
+        Ok(match (&pattern.kind, subject) {
+            (PatternKind::Wildcard, _) => true,
+            (PatternKind::Int(int), _) => {
+                subject.int_parts() == Some((*int < 0, int.unsigned_abs()))
+            }
+            (
+                PatternKind::Range {
+                    start,
+                    end,
+                    inclusive,
+                },
+                _,
+            ) => subject.int_in(*start, *end, *inclusive),
+            (PatternKind::Float(float), Value::Flt64(value)) => value == float,
+            (PatternKind::Float(float), Value::Flt32(value)) => f64::from(*value) == *float,
+            (PatternKind::Bool(expected), Value::Bool(value)) => value == expected,
+            (PatternKind::Char(expected), Value::Char(value)) => value == expected,
+            (PatternKind::Str(expected), Value::Str(value)) => **value == **expected,
+            (PatternKind::Nil, _) => subject.is_nil(),
+            (
+                PatternKind::Name {
+                    qualifier,
+                    name,
+                    bindings,
+                },
+                _,
+            ) => {
+                let constant = test
+                    .global
+                    .and_then(|global| self.globals[global as usize].as_ref())
+                    .filter(|binding| binding.constant);
+                if let Some(constant) = constant {
+                    return Ok(self.heap.values_equal(subject, &constant.value));
+                }
+                let Some(value) = self.heap.enum_value(subject) else {
+                    return Err(RuntimeError::new(
+                        pattern.span,
+                        format!(
+                            "Pattern `{}` cannot match {}.",
+                            pattern.kind,
+                            self.heap.type_name(subject)
+                        ),
+                    ));
+                };
+                let Some(decl) = self.heap.enum_decl(value.ty) else {
+                    unreachable!("enum values point at their enum type");
+                };
+                if qualifier
+                    .as_ref()
+                    .is_some_and(|qualifier| *qualifier != decl.name)
+                {
+                    return Err(RuntimeError::new(
+                        pattern.span,
+                        format!(
+                            "Pattern `{}` does not belong to enum `{}`.",
+                            pattern.kind, decl.name
+                        ),
+                    ));
+                }
+                let Some(index) = decl.variant_index(name) else {
+                    return Err(RuntimeError::new(
+                        pattern.span,
+                        format!("Enum `{}` has no variant `{name}`.", decl.name),
+                    ));
+                };
+                let fields = &decl.variants[index].fields;
+                if let Some(names) = bindings {
+                    if names.len() != fields.len() {
+                        return Err(RuntimeError::new(
+                            pattern.span,
+                            format!(
+                                "Variant `{name}` has {} field(s), found {} binding(s).",
+                                fields.len(),
+                                names.len()
+                            ),
+                        ));
+                    }
+                }
+                index == value.variant
+            }
+            _ => false,
+        })
+    }
+
+    /* Calls the value below the arguments of `sites[site]`. Returns whether it pushed
+     * a frame to run, rather than leaving the result. A tail call of a closure runs
+     * it in the frame of the function returning instead. */
+    fn call(&mut self, function: &Function, site: u32, span: Span, tail: bool) -> EvalResult<bool> {
+        let site = &function.sites[site as usize];
+        let argc = site.argc as usize;
+        let named_at = self.stack.len() - site.named.len();
+        let callee_at = named_at - argc - 1;
+        let callee = self.stack[callee_at].clone();
+        let Value::Obj(obj) = callee else {
+            return match callee {
+                Value::Builtin(builtin) => {
+                    self.call_builtin(builtin, None, site, callee_at, span)?;
+                    Ok(false)
+                }
+                _ => Err(self.not_callable(&callee, span)),
+            };
+        };
+        let result = match self.heap.get(obj) {
+            Object::CompiledClosure(_) => {
+                self.stack.truncate(named_at);
+                return self.enter(obj, None, callee_at, &site.arg_spans, span, tail);
+            }
+            Object::BoundMethod { receiver, method } => {
+                let (receiver, method) = (receiver.clone(), *method);
+                self.stack.truncate(named_at);
+                return self.enter(
+                    method,
+                    Some(receiver),
+                    callee_at,
+                    &site.arg_spans,
+                    span,
+                    tail,
+                );
+            }
+            Object::BoundBuiltin { receiver, builtin } => {
+                let (receiver, builtin) = (receiver.clone(), *builtin);
+                self.call_builtin(builtin, Some(receiver), site, callee_at, span)?;
+                return Ok(false);
+            }
+            Object::VariantConstructor { ty, variant } => {
+                let (ty, variant) = (*ty, *variant);
+                self.stack.truncate(named_at);
+                self.construct_variant(ty, variant, callee_at, &site.arg_spans, span)?
+            }
+            Object::CompiledStruct(_) => {
+                self.stack.truncate(named_at);
+                self.construct(obj, callee_at, site, span)?
+            }
+            _ => return Err(self.not_callable(&callee, span)),
+        };
+        self.stack.truncate(callee_at);
+        self.stack.push(result);
+        Ok(false)
+    }
+
+    /* Calls a builtin with the arguments of `site`, after `receiver` if it is bound to
+     * one, and leaves its result in place of the callee. */
+    fn call_builtin(
+        &mut self,
+        builtin: Builtin,
+        receiver: Option<Value>,
+        site: &CallSite,
+        callee_at: usize,
+        span: Span,
+    ) -> EvalResult<()> {
+        if builtin == Builtin::CollectGarbage {
+            self.collect_garbage();
+        }
+        let named = self.stack.split_off(self.stack.len() - site.named.len());
+        let mut args = self.stack.split_off(callee_at + 1);
+        self.pop();
+        let mut values = HashMap::new();
+        let format = builtin == Builtin::Format || (builtin.formats() && site.format);
+        if let (true, Some(Value::Str(text))) = (format, args.first()) {
+            let pieces = format::parse(text).map_err(|message| RuntimeError::new(span, message))?;
+            for name in format::names(&pieces) {
+                let value = match site.named.iter().position(|known| known == name) {
+                    Some(index) => Some(named[index].clone()),
+                    None => self
+                        .global_names
+                        .iter()
+                        .position(|global| global == name)
+                        .and_then(|global| self.globals[global].as_ref())
+                        .map(|binding| binding.value.clone()),
+                };
+                if let Some(value) = value {
+                    values.insert(name.to_owned(), value);
+                }
+            }
+        }
+        let named = format.then_some(&values);
+        if let Some(receiver) = receiver {
+            args.insert(0, receiver);
+        }
+        if let (Builtin::Resume, [Value::Obj(obj), rest @ ..]) = (builtin, args.as_slice()) {
+            if matches!(self.heap.get(*obj), Object::CompiledCoroutine(_)) && rest.len() <= 1 {
+                let value = rest.first().cloned().unwrap_or(Value::Nil);
+                let result = self.resume(*obj, value, span)?;
+                self.stack.push(result);
+                return Ok(());
+            }
+        }
+        let result = builtins::call(builtin, &mut self.heap, &mut self.output, &args, named)
+            .map_err(|message| RuntimeError::new(span, message))?;
+        self.stack.push(result);
+        Ok(())
+    }
+
+    /* Calls `closure` with the arguments above `callee_at`, and `receiver` as `self`
+     * for a method. Returns whether it pushed a frame to run, rather than leaving
+     * the result of native code. */
+    fn enter(
+        &mut self,
+        closure: ObjRef,
+        receiver: Option<Value>,
+        callee_at: usize,
+        arg_spans: &[Span],
+        span: Span,
+        tail: bool,
+    ) -> EvalResult<bool> {
+        let Object::CompiledClosure(compiled) = self.heap.get(closure) else {
+            unreachable!("methods are closures");
+        };
+        let callee_fn = Rc::clone(&compiled.function);
+        let name = callee_fn.display_name();
+        let argc = self.stack.len() - callee_at - 1;
+        if argc != callee_fn.params.len() {
+            return Err(RuntimeError::new(
+                span,
+                format!(
+                    "Function `{name}` expects {} argument(s), found {argc}.",
+                    callee_fn.params.len()
+                ),
+            ));
+        }
+        let base = callee_at + 1;
+        for (i, ((param, ty), arg_span)) in callee_fn.params.iter().zip(arg_spans).enumerate() {
+            let value = std::mem::replace(&mut self.stack[base + i], Value::Nil);
+            let value = check_type(&self.heap, value, ty, *arg_span, || {
+                format!("parameter `{param}` of `{name}`")
+            })?;
+            self.stack[base + i] = self.heap.copy_value(value);
+        }
+        if !tail && self.frames.len() > self.max_depth {
+            return Err(RuntimeError::new(span, "Stack overflow."));
+        }
+        if callee_fn.jit && !callee_fn.coroutine {
+            if let Some(result) = self.jit.call(&callee_fn, &self.stack[base..], span) {
+                let value = result?;
+                self.stack.truncate(callee_at);
+                self.stack.push(value);
+                return Ok(false);
+            }
+        }
+        self.stack.resize(base + callee_fn.slots, Value::Nil);
+        if callee_fn.uses_self {
+            let value = match receiver {
+                Some(receiver) => receiver,
+                None => {
+                    let args = self.stack[base..base + argc].to_vec();
+                    let args = self.heap.alloc(Object::Array(args));
+                    Value::Obj(self.heap.alloc(Object::Call { args }))
+                }
+            };
+            self.stack[base + argc] = value;
+        }
+        if callee_fn.coroutine {
+            /* It runs nothing until it is resumed. */
+            let coroutine = CompiledCoroutine {
+                closure,
+                status: CoroutineStatus::Suspended,
+                ip: 0,
+                stack: self.stack.split_off(base),
+                tries: vec![],
+            };
+            let coroutine = self.heap.alloc(Object::CompiledCoroutine(coroutine));
+            self.stack.truncate(callee_at);
+            self.stack.push(Value::Obj(coroutine));
+            return Ok(false);
+        }
+        let mut frame = CallFrame {
+            function: callee_fn,
+            closure: Some(closure),
+            ip: 0,
+            base,
+            span,
+            replaced: None,
+        };
+        if tail {
+            let caller = self.frames.pop().expect("tail calls are made in functions");
+            self.stack.drain(caller.base - 1..callee_at);
+            frame.base = caller.base;
+            frame.span = caller.span;
+            frame.replaced = caller.replaced.or(Some(caller.function));
+        }
+        self.frames.push(frame);
+        Ok(true)
+    }
+
+    /* Calls `closure` from native code and runs it to completion, returning its value.
+     * The frame of the call only shows in the stack trace of an error if `traced`. */
+    fn run_call(
+        &mut self,
+        closure: ObjRef,
+        receiver: Option<Value>,
+        callee_at: usize,
+        arg_spans: &[Span],
+        span: Span,
+        traced: bool,
+    ) -> EvalResult<Value> {
+        let depth = self.frames.len();
+        let result = self
+            .enter(closure, receiver, callee_at, arg_spans, span, false)
+            .and_then(|pushed| match pushed {
+                true => self.execute(depth),
+                false => Ok(()),
+            });
+        if let Err(error) = result {
+            let first = if traced { depth } else { depth + 1 };
+            let error = self.frames[first.min(self.frames.len())..]
+                .iter()
+                .rev()
+                .fold(error, |error, frame| {
+                    error.called(frame.function.display_name(), frame.span)
+                });
+            self.frames.truncate(depth);
+            return Err(error);
+        }
+        Ok(self.pop())
+    }
+
+    /* `Point(1.0, 2.0)` fills fields in order, or calls `new` if the struct has one,
+     * with the arguments above `callee_at`. */
+    fn construct(
+        &mut self,
+        ty: ObjRef,
+        callee_at: usize,
+        site: &CallSite,
+        span: Span,
+    ) -> EvalResult<Value> {
+        let Object::CompiledStruct(compiled) = self.heap.get(ty) else {
+            unreachable!("construct is only given struct types");
+        };
+        let decl = Rc::clone(&compiled.decl);
+        let init = compiled.init;
+        let constructor = compiled.method(&self.heap, "new");
+        let field_types = decl
+            .fields
+            .iter()
+            .map(|field| field.ty.substitute(&decl.params, &site.type_args))
+            .collect::<Vec<_>>();
+        self.stack.push(Value::Obj(init));
+        let defaults = self.run_call(init, None, self.stack.len() - 1, &[], span, false)?;
+        /* The defaults fill every field, unless a damaged file says otherwise. */
+        let mut fields = match defaults {
+            Value::Obj(defaults) => match self.heap.get(defaults) {
+                Object::Array(items) => items.clone(),
+                _ => vec![],
+            },
+            _ => vec![],
+        };
+        fields.resize(decl.fields.len(), Value::Nil);
+        let argc = self.stack.len() - callee_at - 1;
+        if constructor.is_none() {
+            if argc > decl.fields.len() {
+                return Err(RuntimeError::new(
+                    span,
+                    format!(
+                        "Struct `{}` has {} field(s), found {argc} argument(s).",
+                        decl.name,
+                        decl.fields.len()
+                    ),
+                ));
+            }
+            for (i, (field, arg_span)) in decl.fields.iter().zip(&site.arg_spans).enumerate() {
+                let value = std::mem::replace(&mut self.stack[callee_at + 1 + i], Value::Nil);
+                let value = check_type(&self.heap, value, &field_types[i], *arg_span, || {
+                    format!("field `{}`", field.name)
+                })?;
+                fields[i] = self.heap.copy_value(value);
+            }
+        }
+        let instance = Value::Obj(self.heap.alloc(Object::Instance(Instance { ty, fields })));
+        if let Some(constructor) = constructor {
+            /* The instance takes the place of the callee, which keeps it alive. */
+            self.stack[callee_at] = instance.clone();
+            let receiver = Some(instance.clone());
+            self.run_call(
+                constructor,
+                receiver,
+                callee_at,
+                &site.arg_spans,
+                span,
+                true,
+            )?;
+        }
+        if let Some(instance_ref) = self.heap.instance(&instance) {
+            if let Some((field, _)) = decl
+                .fields
+                .iter()
+                .zip(&field_types)
+                .zip(&instance_ref.fields)
+                .find(|((_, ty), value)| value.is_nil() && !matches!(ty, Type::Optional(_)))
+                .map(|(field, _)| field)
+            {
+                return Err(RuntimeError::new(
+                    span,
+                    format!(
+                        "Field `{}` of `{}` was not given a value.",
+                        field.name, decl.name
+                    ),
+                ));
+            }
+        }
+        Ok(instance)
+    }
+
+    /* `Shape.Circle(1.0)`: the arguments above `callee_at` make the payload. */
+    fn construct_variant(
+        &mut self,
+        ty: ObjRef,
+        variant: usize,
+        callee_at: usize,
+        arg_spans: &[Span],
+        span: Span,
+    ) -> EvalResult<Value> {
+        let Some(decl) = self.heap.enum_decl(ty) else {
+            unreachable!("variant constructors point at their enum type");
+        };
+        let enum_name = decl.name.clone();
+        let Variant { name, fields, .. } = decl.variants[variant].clone();
+        let args = self.stack.split_off(callee_at + 1);
+        if args.len() != fields.len() {
+            return Err(RuntimeError::new(
+                span,
+                format!(
+                    "Variant `{enum_name}.{name}` has {} field(s), found {} argument(s).",
+                    fields.len(),
+                    args.len()
+                ),
+            ));
+        }
+        let mut payload = Vec::with_capacity(args.len());
+        for (i, ((field, arg), arg_span)) in fields.iter().zip(args).zip(arg_spans).enumerate() {
+            let value = check_type(&self.heap, arg, field, *arg_span, || {
+                format!("field {i} of `{enum_name}.{name}`")
+            })?;
+            payload.push(self.heap.copy_value(value));
+        }
+        let value = Object::EnumValue(EnumValue {
+            ty,
+            variant,
+            payload,
+        });
+        Ok(Value::Obj(self.heap.alloc(value)))
+    }
+
+    fn not_callable(&self, value: &Value, span: Span) -> RuntimeError {
+        RuntimeError::new(
+            span,
+            format!("{} is not callable.", self.heap.type_name(value)),
+        )
+    }
+
+    /* Coroutines */
+
+    /* Runs a coroutine from where it is suspended until it yields a value, which
+     * `resume` returns, or returns one and is dead. `value` is what the `yield` it
+     * was suspended at gives. */
+    fn resume(&mut self, obj: ObjRef, value: Value, span: Span) -> EvalResult<Value> {
+        let Object::CompiledCoroutine(coroutine) = self.heap.get_mut(obj) else {
+            unreachable!("only coroutines are resumed");
+        };
+        match coroutine.status {
+            CoroutineStatus::Suspended => (),
+            CoroutineStatus::Running => {
+                return Err(RuntimeError::new(
+                    span,
+                    "Cannot resume a running coroutine.",
+                ))
+            }
+            CoroutineStatus::Dead => {
+                return Err(RuntimeError::new(span, "Cannot resume a dead coroutine."))
+            }
+        }
+        if self.frames.len() > self.max_depth {
+            return Err(RuntimeError::new(span, "Stack overflow."));
+        }
+        coroutine.status = CoroutineStatus::Running;
+        let (closure, ip) = (coroutine.closure, coroutine.ip);
+        let stack = std::mem::take(&mut coroutine.stack);
+        let tries = std::mem::take(&mut coroutine.tries);
+        let function = match self.heap.get(closure) {
+            Object::CompiledClosure(closure) => Rc::clone(&closure.function),
+            _ => unreachable!("coroutines run closures"),
+        };
+        /* The coroutine stands below its frame, where a function called would. */
+        let depth = self.frames.len();
+        self.stack.push(Value::Obj(obj));
+        let base = self.stack.len();
+        self.stack.extend(stack);
+        if ip > 0 {
+            self.stack.push(value);
+        }
+        self.handlers
+            .extend(tries.into_iter().map(|(height, target)| Handler {
+                frames: depth + 1,
+                height: base + height,
+                target,
+            }));
+        self.frames.push(CallFrame {
+            function: Rc::clone(&function),
+            closure: Some(closure),
+            ip,
+            base,
+            span,
+            replaced: None,
+        });
+        let result = self.execute(depth);
+        let Object::CompiledCoroutine(coroutine) = self.heap.get_mut(obj) else {
+            unreachable!("only coroutines are resumed");
+        };
+        if coroutine.status == CoroutineStatus::Running {
+            coroutine.status = CoroutineStatus::Dead;
+        }
+        if let Err(error) = result {
+            let error = self.frames[depth..]
+                .iter()
+                .rev()
+                .fold(error, |error, frame| {
+                    error.called(frame.function.display_name(), frame.span)
+                });
+            self.frames.truncate(depth);
+            return Err(error);
+        }
+        let value = self.pop();
+        if value.is_nil() {
+            return Ok(value);
+        }
+        let name = function.display_name();
+        check_type(&self.heap, value, &function.ret, span, || {
+            format!("the value yielded by `{name}`")
+        })
+    }
+
+    /* The next value a coroutine yields to a `for` loop, which has no more once it
+     * has returned. */
+    fn next_yielded(&mut self, obj: ObjRef, span: Span) -> EvalResult<Option<Value>> {
+        let dead =
+            |vm: &Self| vm.heap.coroutine_status(&Value::Obj(obj)) == Some(CoroutineStatus::Dead);
+        if dead(self) {
+            return Ok(None);
+        }
+        let item = self.resume(obj, Value::Nil, span)?;
+        Ok((!dead(self)).then_some(item))
+    }
+
+    fn return_value(&self, frame: &CallFrame, value: Value) -> EvalResult<Value> {
+        let value = self.check_return(&frame.function, frame.span, value)?;
+        match &frame.replaced {
+            Some(first) if !Rc::ptr_eq(first, &frame.function) => {
+                self.check_return(first, frame.span, value)
+            }
+            _ => Ok(value),
+        }
+    }
+
+    fn check_return(&self, function: &Function, span: Span, value: Value) -> EvalResult<Value> {
+        let name = function.display_name();
+        let ret = &function.ret;
+        if value.is_nil() && !matches!(ret, Type::Void | Type::Unknown | Type::Optional(_)) {
+            return Err(RuntimeError::new(
+                span,
+                format!("Function `{name}` did not return a {ret}."),
+            ));
+        }
+        check_type(&self.heap, value, ret, span, || {
+            format!("the return value of `{name}`")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker;
+    use crate::compiler::compile;
+    use crate::diagnostic;
+    use crate::evaluator::Evaluator;
+    use crate::parser::parse;
+    use std::fs;
+    use std::path::Path;
+
+    /* The checker runs first, as it does before a program is run, to record the
+     * resolver's bindings; what it reports is left to the tests. */
+    fn run(source: &str) -> Result<String, String> {
+        let program = parse(source).map_err(|errors| errors[0].to_string())?;
+        checker::check(&program);
+        let bytecode = compile(&program).map_err(|error| error.to_string())?;
+        let mut vm = Vm::new();
+        vm.capture_output();
+        let result = vm.run(&bytecode);
+        let output = vm.take_output();
+        result.map_err(|error| format!("{output}{error}"))?;
+        Ok(output)
+    }
+
+    fn evaluate(source: &str) -> Result<String, String> {
+        let program = parse(source).map_err(|errors| errors[0].to_string())?;
+        checker::check(&program);
+        let mut evaluator = Evaluator::new();
+        evaluator.capture_output();
+        let result = evaluator.run(&program);
+        let output = evaluator.take_output();
+        result.map_err(|error| format!("{output}{error}"))?;
+        Ok(output)
+    }
+
+    /* Runs `source` through both backends, which must agree. */
+    fn run_both(source: &str) -> Result<String, String> {
+        let result = run(source);
+        assert_eq!(
+            result,
+            evaluate(source),
+            "the backends disagree on:\n{source}"
+        );
+        result
+    }
+
+    #[test]
+    fn test_examples_match_the_evaluator() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+        let mut paths = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "tool"))
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let source = fs::read_to_string(&path).unwrap();
+            let program = parse(&source).unwrap();
+            assert!(!diagnostic::has_errors(&checker::check(&program)));
+            assert!(compile(&program).is_ok(), "{} compiles", path.display());
+            run_both(&source).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_loops_and_closures() {
+        let source = "func counter()\n\tint32 n = 0\n\tglobal c = func ()\n\t\tn += 1\n\t\treturn n\n\tend\nend\nfunc int32 sum(int32 n)\n\tint32 total = 0\n\tfor int32 i in 0 to n do\n\t\tif i % 2 == 0 then\n\t\t\ttotal += i\n\t\tend\n\tend\n\treturn total\nend\ncounter()\nc()\nprintln(c(), sum(10))\nint8 k = 0\nwhile k < 100 do\n\tk += 30\nend\narray<int8> xs = [1, 2, 3]\nxs[0] += 5\nfor x in xs do\n\tprint(x, \"\")\nend\nloop\n\tbreak\nend\nprintln(k, \"{}\".. \"!\", #xs)";
+        assert_eq!(run_both(source).unwrap(), "2 20\n6 2 3 120 {}! 3\n");
+    }
+
+    #[test]
+    fn test_closures_do_not_see_later_variables() {
+        let source = "int32 a = 1\nfunc f()\n\tconst g = func () return a + 1 end\n\tstring a = \"five\"\n\tprintln(g())\nend\nf()\nfunc h()\n\tfunc int32 k()\n\t\treturn a\n\tend\n\tstring a = \"five\"\n\tprintln(k(), a)\nend\nh()";
+        let program = parse(source).unwrap();
+        assert!(!diagnostic::has_errors(&checker::check(&program)));
+        assert_eq!(run_both(source).unwrap(), "2\n1 five\n");
+    }
+
+    #[test]
+    fn test_continue_and_labelled_loops() {
+        let source = "outer: for int32 i in 0 to 4 do\n\tfor int32 j in 0 to 4 do\n\t\tif j == 2 then\n\t\t\tcontinue outer\n\t\tend\n\t\tif i == 3 then\n\t\t\tbreak outer\n\t\tend\n\t\tprint(i * 10 + j, \"\")\n\tend\nend\nint32 n = 0\nspin: loop\n\tn += 1\n\twhile n < 5 do\n\t\tn += 1\n\t\tcontinue spin\n\tend\n\tbreak\nend\nprintln(n)";
+        assert_eq!(run_both(source).unwrap(), "0 1 10 11 20 21 5\n");
+    }
+
+    #[test]
+    fn test_format_strings_match_the_evaluator() {
+        let source = "string s = \"a {b} c\"\nconst top = \"top {}\"\nfunc show(int32 x)\n\tconst inner = \"{x}!\"\n\tprintln(s)\n\tprintln(\"label\", x)\n\tprintln(inner)\n\tprintln(top, x)\nend\nshow(1)";
+        assert_eq!(run_both(source).unwrap(), "a {b} c\nlabel 1\n1!\ntop 1\n");
+    }
+
+    #[test]
+    fn test_range_patterns() {
+        let source = "for int32 i in 0 to 12 do\n\tgiven i do\n\t\twhen 0 to 3 => print(\"a\")\n\t\twhen 3 through 5, 9 => print(\"b\")\n\t\telse => print(\"c\")\n\tend\nend\nprintln()";
+        assert_eq!(run_both(source).unwrap(), "aaabbbcccbcc\n");
+    }
+
+    #[test]
+    fn test_literal_types_follow_context() {
+        let source = "func int64 twice(int64 x)\n\treturn x * 2\nend\nint8 small = 100\nint64 big = twice(4000000000)\nprintln(small + 27, big, 1.5 + flt32(1), wrappingAdd(small, 100))\nconst LIMIT = 3\ngiven 3 do\n\twhen LIMIT => println(\"limit {small}\")\n\telse => println(\"other\")\nend";
+        assert_eq!(
+            run_both(source).unwrap(),
+            "127 8000000000 2.5 -56\nlimit 100\n"
+        );
+    }
+
+    #[test]
+    fn test_runtime_errors_match_the_evaluator() {
+        for source in [
+            "int8 x = 100\nx += 28",
+            "const x = 1\nx = 2",
+            "func int32 f(int32 a)\n\treturn a\nend\nf(\"no\")",
+            "func int32 f()\nend\nf()",
+            "array<int32> xs = [1]\nprintln(xs[3])",
+            "if 1 then\nend",
+        ] {
+            assert!(run_both(source).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn test_unassigned_reads_match_the_evaluator() {
+        for (source, error) in [
+            ("int32 x\nwhile false do\n\tx = 1\nend\nprintln(x + 1)", "5:9"),
+            ("func f()\n\tprintln(x + 1)\nend\nint32 x\nf()", "2:10"),
+            ("int32 x\nx += 1", "2:1"),
+            ("func f()\n\tstring s\n\ts ..= \"a\"\nend\nf()", "3:2"),
+            ("func f()\n\tbool b\n\tfunc bool g()\n\t\treturn b\n\tend\n\tprintln(g())\nend\nf()", "4:10"),
+        ] {
+            let message = run_both(source).unwrap_err();
+            assert!(
+                message.contains(&format!("{error}: Runtime error: `")),
+                "{message}"
+            );
+            assert!(message.contains("` is used before it is assigned."));
+        }
+        let source = "int32? x\nprintln(x)\nint32 y\ny ??= 2\nfunc f()\n\tint32 z\n\tz = 3\n\tprintln(y, z)\nend\nf()";
+        assert_eq!(run_both(source).unwrap(), "nil\n2 3\n");
+    }
+
+    #[test]
+    fn test_garbage_is_collected() {
+        let source = "func int32 churn(int32 n)\n\tint32 total = 0\n\tfor int32 i in 0 to n do\n\t\tarray<int32> items = [i, i + 1, i + 2]\n\t\tfunc int32 sum()\n\t\t\treturn items[0] + items[1] + items[2]\n\t\tend\n\t\ttotal += sum() - 3 * i\n\tend\n\treturn total\nend\narray<int32> kept = [1, 2, 3]\nint64 base = heapSize()\nfor int32 round in 0 to 3 do\n\tprintln(churn(5000), kept[round], heapSize() < 4096)\nend\nprintln(collectGarbage() - base, kept)";
+        assert_eq!(
+            run_both(source).unwrap(),
+            "15000 1 true\n15000 2 true\n15000 3 true\n0 [1, 2, 3]\n"
+        );
+    }
+
+    #[test]
+    fn test_stack_traces_match_the_evaluator() {
+        let source = "const pick = func (array<int32> xs, int32 i)\n\treturn xs[i]\nend\nfunc int32 down(int32 n)\n\tif n == 0 then\n\t\treturn pick([1], 1) + 0\n\tend\n\treturn down(n - 1) + 0\nend\nprintln(down(3))";
+        let program = parse(source).unwrap();
+        let error = Vm::new().run(&compile(&program).unwrap()).unwrap_err();
+        assert_eq!(Evaluator::new().run(&program).unwrap_err(), error);
+        assert_eq!(
+            error.report("down.tool"),
+            "Toolip:2:12: Runtime error: Index 1 is out of bounds for length 1.
+Stack trace (most recent call first):
+  in `pick` at down.tool:2:12
+  in `down` at down.tool:6:14
+  in `down` at down.tool:8:13
+  ... repeated 2 more time(s)
+  in the program at down.tool:10:13"
+        );
+    }
+
+    #[test]
+    fn test_tail_calls_match_the_evaluator() {
+        let source = "func int64 count(int64 n, int64 acc)\n\tif n == 0 then\n\t\treturn acc // 0\n\tend\n\treturn count(n - 1, acc + n)\nend\nfunc int64 start()\n\treturn count(100000, 0)\nend\nprintln(start())";
+        let program = parse(source).unwrap();
+        let error = Vm::new().run(&compile(&program).unwrap()).unwrap_err();
+        assert_eq!(Evaluator::new().run(&program).unwrap_err(), error);
+        assert_eq!(
+            error.report("count.tool"),
+            "Toolip:3:14: Runtime error: Division by zero.
+Stack trace (most recent call first):
+  in `count` at count.tool:3:14
+  in the program at count.tool:10:14"
+        );
+    }
+
+    const POINT: &str = "struct Point\n\tflt64 x = 0.0\n\tflt64 y = 0.0\n\tfunc flt64 dot(this other)\n\t\treturn self.x * other.x + self.y * other.y\n\tend\n\tfunc shift(flt64 dx, flt64 dy)\n\t\tself.x += dx\n\t\tself.y += dy\n\tend\n\tfunc this scaled(flt64 k)\n\t\treturn this(self.x * k, self.y * k)\n\tend\nend\n";
+
+    #[test]
+    fn test_structs_match_the_evaluator() {
+        let source = format!(
+            "{POINT}Point p = Point(1.0, 2.0)\np.shift(1, 1)\nprintln(p, p.dot(Point(1.0)), p.scaled(2.0))\nPoint a = Point()\nPoint b = a\nb.x = 5.0\nfunc bump(Point p)\n\tp.x = 9.0\nend\nbump(a)\nprintln(a.x, b.x, a == Point(), b == a)"
+        );
+        assert_eq!(
+            run_both(&source).unwrap(),
+            "Point(x = 2.0, y = 3.0) 2.0 Point(x = 4.0, y = 6.0)\n0.0 5.0 true false\n"
+        );
+        let constructor = "struct Counter\n\tint32 count\n\tstring label\n\tfunc new(string label)\n\t\tself.label = label\n\t\tself.count = 0\n\tend\nend\nCounter c = Counter(\"hits\")\nprintln(c)";
+        assert_eq!(
+            run_both(constructor).unwrap(),
+            "Counter(count = 0, label = \"hits\")\n"
+        );
+        let generic = "struct Box<T>\n\tT value\n\tfunc T get()\n\t\treturn self.value\n\tend\nend\nBox<uint8> b = Box(200)\nprintln(b.get() + 55)\nBox<int8> c = Box(200)";
+        assert_eq!(
+            run_both(generic).unwrap_err(),
+            "255\nToolip:9:19: Runtime error: Integer literal 200 does not fit in int8."
+        );
+        for source in [
+            "struct Pair\n\tint32 a\n\tint32 b\nend\nPair p = Pair(1)",
+            "struct Pair\n\tint32 a = 0\nend\nPair p = Pair()\np.a = \"one\"",
+            "struct Pair\n\tint32 a = 0\nend\nprintln(Pair().b)",
+            "struct Pair\n\tint32 a = 0\nend\nprintln(Pair(1, 2))",
+            "struct Pair\n\tint32 a = \"zero\"\nend\nprintln(Pair())",
+            "struct Pair\n\tint32 a = 0\n\tfunc new()\n\t\tself.a = 1 // 0\n\tend\nend\nprintln(Pair())",
+        ] {
+            assert!(run_both(source).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn test_enums_match_the_evaluator() {
+        let source = "enum Shape Circle(flt64) Rect(flt64, flt64) Dot end\nfunc flt64 area(Shape shape)\n\tgiven shape do\n\t\twhen Circle(r) => return 3.0 * r * r\n\t\twhen Shape.Rect(w, h) => return w * h\n\t\twhen Dot => return 0.0\n\tend\nend\nShape s = Shape.Rect(2, 3)\nprintln(s, area(s), area(Shape.Circle(1.0)), area(Shape.Dot), s == Shape.Rect(2.0, 3.0))\nenum Color Red Green Blue end\nColor c = Color.fromDiscriminant(2)\nprintln(c, discriminant(Color.Green), discriminant(c), Color.fromDiscriminant(3))";
+        assert_eq!(
+            run_both(source).unwrap(),
+            "Shape.Rect(2.0, 3.0) 6.0 3.0 0.0 true\nColor.Blue 1 2 nil\n"
+        );
+        for source in [
+            "enum Shape Circle(flt64) end\nShape s = Shape.Circle(1.0, 2.0)",
+            "enum Shape Circle(flt64) end\nprintln(Shape.Square)",
+            "enum Shape Circle(flt64) end\ngiven Shape.Circle(1.0) do\n\twhen Circle(a, b) => println(a)\nend",
+        ] {
+            assert!(run_both(source).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn test_struct_iterators_match_the_evaluator() {
+        let source = "struct Countdown\n\tint32 n = 3\n\tfunc int32? next()\n\t\tif self.n == 0 then\n\t\t\treturn nil\n\t\tend\n\t\tself.n -= 1\n\t\treturn self.n\n\tend\nend\nstruct Pair\n\tint32 a\n\tint32 b\n\tfunc array<int32> iter()\n\t\treturn [self.a, self.b]\n\tend\nend\nCountdown c = Countdown()\nfor int32 i in c do\n\tprint(i)\nend\nfor int32 i in Pair(7, 8) do\n\tprint(i)\nend\nprintln(c.n)";
+        assert_eq!(run_both(source).unwrap(), "210783\n");
+        assert!(run_both("struct Empty\nend\nfor x in Empty() do\nend").is_err());
+    }
+
+    #[test]
+    fn test_statics_match_the_evaluator() {
+        let source = "const LIMIT = 3\nfunc int32 counter()\n\tstatic int32 count = 0\n\tcount += 1\n\treturn count\nend\nfunc setup()\n\tglobal int32 total = 10\n\tglobal hits = 0\nend\nfunc record()\n\tglobal hits = hits + 1\n\ttotal += LIMIT\nend\nsetup()\nrecord()\nrecord()\ncounter()\nprintln(counter(), hits, total)\nstruct Box\n\tfunc int32 next()\n\t\tstatic int32 n = 100\n\t\tn += 1\n\t\treturn n\n\tend\nend\nBox().next()\nprintln(Box().next())";
+        assert_eq!(run_both(source).unwrap(), "2 2 16\n102\n");
+    }
+
+    #[test]
+    fn test_sets_and_tables_match_the_evaluator() {
+        let source = "set<int32> s = [3, 1, 3]\nfor int32 x in s do\n\tprint(x)\nend\nprintln(#s, s[1])\ntable<string, int32> t = [\"a\": 1, \"b\": 2]\nt[\"c\"] = 3\nt[\"a\"] += 9\nt[\"d\"] ??= 4\nt[\"d\"] ??= 5\nfor string k in t do\n\tprint(k)\nend\nfor string k, int32 v in t do\n\tprint(v)\nend\nprintln(t)\nconst u = [\"x\": 1.5, \"x\": 2.5]\nfor k, v in u do\n\tprintln(k, v)\nend\ntable<int32, string> e = []\nprintln(#e, e[7])";
+        let program = parse(source).unwrap();
+        assert!(!diagnostic::has_errors(&checker::check(&program)));
+        assert_eq!(
+            run_both(source).unwrap_err(),
+            "312 1\nabcd10234[\"a\": 10, \"b\": 2, \"c\": 3, \"d\": 4]\nx 2.5\nToolip:23:15: Runtime error: The table has no key `7`."
+        );
+        let entries = "for k, v in [1, 2] do\nend";
+        assert_eq!(
+            run_both(entries).unwrap_err(),
+            "Toolip:1:13: Runtime error: Only a table has a key and a value to iterate over, found array."
+        );
+    }
+
+    #[test]
+    fn test_ranges_match_the_evaluator() {
+        let source = "for i in 0 through 3 do\n\tprint(i, \"\")\nend\nprintln()\nrange<int8> r = 1 through 4\nfor x in r do\n\tprint(x, \"\")\nend\nprintln()\nprintln(3 in r, 5 in r, 4 in 0 to 4, int64(4) in 0 through 4)\nint32 n = 7\nprintln(n in 0 to 10, n in 0 to n)\nstring s = \"hello\"\nprintln(s[1 to 3], s[1 through 3])\narray<int32> xs = [10, 20, 30, 40]\nprintln(xs[0 to 2], xs[1 through 3], #xs[0 to 0])\nfor uint8 b in 250 through 255 do\n\tprint(b, \"\")\nend\nprintln()\nrange<int32> w = 2 to 5\nprintln(w)";
+        let program = parse(source).unwrap();
+        assert!(!diagnostic::has_errors(&checker::check(&program)));
+        assert_eq!(
+            run_both(source).unwrap(),
+            "0 1 2 3 \n1 2 3 4 \ntrue false false true\ntrue false\nel ell\n[10, 20] [20, 30, 40] 0\n250 251 252 253 254 255 \n2 to 5\n"
+        );
+        let source = "array<int32> xs = [1, 2]\nprintln(xs[1 through 2])";
+        assert_eq!(
+            run_both(source).unwrap_err(),
+            "Toolip:2:14: Runtime error: Index 2 is out of bounds for length 2."
+        );
+    }
+
+    #[test]
+    fn test_stepped_ranges_match_the_evaluator() {
+        let source = "for i in 0 to 10 step 3 do\n\tprint(i, \"\")\nend\nprintln()\nfor int8 i in 10 through 0 step -5 do\n\tprint(i, \"\")\nend\nprintln()\nfor uint8 b in 240 through 255 step 5 do\n\tprint(b, \"\")\nend\nprintln()\nrange<int64> r = 1 to 20 step 4\nprintln(r, 9 in r, 10 in r, 5 in 0 to 10 step 5)\narray<int32> xs = [1, 2, 3, 4, 5, 6]\nprintln(xs[0 to 6 step 2], xs[5 through 0 step -2], \"abcdef\"[1 to 6 step 2])\nint32 k = 2\nfor x in 0 to 7 step k do\n\tprint(x, \"\")\nend\nprintln()";
+        let program = parse(source).unwrap();
+        assert!(!diagnostic::has_errors(&checker::check(&program)));
+        assert_eq!(
+            run_both(source).unwrap(),
+            "0 3 6 9 \n10 5 0 \n240 245 250 255 \n1 to 20 step 4 true false true\n[1, 3, 5] [6, 4, 2] bdf\n0 2 4 6 \n"
+        );
+        let source = "int32 k = 0\nfor i in 0 to 5 step k do\nend";
+        assert_eq!(
+            run_both(source).unwrap_err(),
+            "Toolip:2:12: Runtime error: The step of a range cannot be 0."
+        );
+    }
+
+    #[test]
+    fn test_errors_are_caught_like_the_evaluator() {
+        let source = "func int64 half(int64 n)\n\tif n % 2 == 1 then\n\t\traise error(\"odd\", \"odd input\")\n\tend\n\treturn n / 2\nend\nfunc int64 quarter(int64 n)\n\tint64 h = try half(n)\n\treturn try half(h)\nend\nfunc int32 first(array<int32> xs)\n\ttry\n\t\treturn xs[0]\n\tcatch e\n\t\treturn -1\n\tend\nend\ntry\n\tprintln(quarter(8))\n\tprintln(quarter(6))\n\tprintln(\"unreachable\")\ncatch e\n\tprintln(e.kind .. \": \" .. e.message)\nend\ntry\n\tint8 x = 127\n\tx += 1\ncatch e\n\tprintln(e.kind, e.message)\nend\narray<int32> xs = [1, 2]\ntry\n\tprintln(1 + xs[2])\ncatch e\n\tprintln(e.kind, e.message)\nend\nprintln(first(xs), first([]))\nfor i in 0 to 5 do\n\ttry\n\t\ttry\n\t\t\tif i == 1 then\n\t\t\t\tcontinue\n\t\t\tend\n\t\t\tif i == 3 then\n\t\t\t\tbreak\n\t\t\tend\n\t\t\traise \"inner \" .. i\n\t\tcatch e\n\t\t\tprintln(e.message)\n\t\t\traise e\n\t\tend\n\tcatch e\n\t\tprintln(\"outer\", e.kind)\n\tend\nend\ntry\n\tprintln(xs[5])\ncatch\n\traise \"no index \" .. 5\nend";
+        let program = parse(source).unwrap();
+        assert!(!diagnostic::has_errors(&checker::check(&program)));
+        assert_eq!(
+            run_both(source).unwrap_err(),
+            "2\nodd: odd input\noverflow Integer overflow: `+` on int8 operands.\nindex Index 2 is out of bounds for length 2.\n1 -1\ninner 0\nouter error\ninner 2\nouter error\nToolip:59:2: Runtime error: no index 5"
+        );
+        let source = "try\n\traise \"first\"\ncatch e\n\ttry\n\t\tprintln(try 1, try e)\n\tcatch f\n\t\tprintln(f.message)\n\tend\nend";
+        assert_eq!(run_both(source).unwrap(), "first\n");
+        assert_eq!(
+            run_both("raise 3").unwrap_err(),
+            "Toolip:1:1: Runtime error: Only errors and strings can be raised, found int32."
+        );
+    }
+
+    #[test]
+    fn test_coroutines_match_the_evaluator() {
+        let source = "coroutine func int32 count(int32 n)\n\tfor int32 i in 0 to n do\n\t\tyield i\n\tend\nend\nfor int32 x in count(3) do\n\tprint(x)\nend\ncoroutine func int32 sum()\n\tint32 total = 0\n\twhile true do\n\t\tint32 x = yield total\n\t\tif x == 0 then\n\t\t\treturn total\n\t\tend\n\t\ttotal += x\n\tend\nend\ncoroutine<int32> c = sum()\nprintln()\nprintln(resume(c), status(c), resume(c, 5), resume(c, 7), resume(c, 0), status(c))";
+        let program = parse(source).unwrap();
+        assert!(!diagnostic::has_errors(&checker::check(&program)));
+        assert_eq!(run_both(source).unwrap(), "012\n0 suspended 5 12 12 dead\n");
+        let source = "coroutine func string steps()\n\ttry\n\t\tyield \"a\"\n\t\traise \"b\"\n\tcatch e\n\t\tyield e.message\n\tend\n\tgiven 2 do\n\t\twhen 2 => yield \"c\"\n\tend\n\tloop\n\t\tyield \"d\"\n\t\tbreak\n\tend\n\treturn \"e\"\nend\nfor string s in steps() do\n\tprint(s)\nend\nprintln()\nprintln(steps())";
+        assert_eq!(run_both(source).unwrap(), "abcd\n<coroutine steps>\n");
+        let source = "struct Odds\n\tint32 limit\n\tfunc coroutine<int32> iter()\n\t\treturn odds(self.limit)\n\tend\nend\ncoroutine func int32 odds(int32 limit)\n\tfor int32 i in 0 to limit do\n\t\tif i % 2 == 0 then\n\t\t\tcontinue\n\t\tend\n\t\tyield i\n\tend\nend\nfor int32 i in Odds(8) do\n\tprint(i)\nend\nfunc coroutine<int32> counter(int32 start)\n\tint32 n = start\n\tcoroutine func int32 next()\n\t\twhile true do\n\t\t\tn += 1\n\t\t\tyield n\n\t\tend\n\tend\n\treturn next()\nend\nglobal co = counter(10)\nprintln(resume(co), resume(co), status(co))\ntry\n\tresume(co, 1, 2)\ncatch e\n\tprintln(e.message)\nend";
+        assert_eq!(
+            run_both(source).unwrap(),
+            "135711 12 suspended\n`resume` expects 2 argument(s), found 3.\n"
+        );
+        assert_eq!(
+            run_both("coroutine func int32 f()\n\tresume(co)\nend\nglobal co = f()\nresume(co)")
+                .unwrap_err(),
+            "Toolip:2:8: Runtime error: Cannot resume a running coroutine."
+        );
+        assert_eq!(
+            run_both("coroutine func int32 f()\nend\nglobal co = f()\nresume(co)\nresume(co)")
+                .unwrap_err(),
+            "Toolip:5:7: Runtime error: Cannot resume a dead coroutine."
+        );
+        assert_eq!(
+            run_both("coroutine func int32 f()\n\tint32 x = 1\n\tyield x / (x - 1)\nend\nglobal co = f()\ntry\n\tresume(co)\ncatch e\n\tprintln(e.message, status(co))\nend\nresume(co)")
+                .unwrap_err(),
+            "Division by zero. dead\nToolip:11:7: Runtime error: Cannot resume a dead coroutine."
+        );
+        let source = "func int32 pick(array<int32> xs)\n\treturn xs[3]\nend\ncoroutine func int32 items()\n\tyield 1\n\tyield pick([1])\nend\nfunc sum()\n\tfor int32 x in items() do\n\t\tprint(x)\n\tend\nend\nsum()";
+        let program = parse(source).unwrap();
+        let error = Vm::new().run(&compile(&program).unwrap()).unwrap_err();
+        assert_eq!(Evaluator::new().run(&program).unwrap_err(), error);
+        assert_eq!(
+            error.report("items.tool"),
+            "Toolip:2:12: Runtime error: Index 3 is out of bounds for length 1.
+Stack trace (most recent call first):
+  in `pick` at items.tool:2:12
+  in `items` at items.tool:6:12
+  in `sum` at items.tool:9:22
+  in the program at items.tool:13:4"
+        );
+    }
+
+    #[test]
+    fn test_deep_recursion_overflows() {
+        let source = "func int32 f(int32 n)\n\tif n == 0 then\n\t\treturn 0\n\tend\n\treturn 1 + f(n - 1)\nend\nprintln(f(5000))";
+        assert_eq!(run(source), Ok("5000\n".to_owned()));
+        /* The evaluator would overflow the test thread's own stack first. */
+        assert_eq!(
+            run("func int32 f()\n\treturn 1 + f()\nend\nf()"),
+            Err("Toolip:2:14: Runtime error: Stack overflow.".to_owned())
+        );
+        assert_eq!(
+            run("func int32 f()\n\treturn 1 + f()\nend\ntry\n\tf()\ncatch e\n\tprintln(e.message)\nend\nprintln(f)"),
+            Ok("Stack overflow.\n<func f>\n".to_owned())
+        );
+    }
+}